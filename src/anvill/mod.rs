@@ -1,8 +1,12 @@
 #![allow(non_camel_case_types)]
 #![allow(clippy::upper_case_acronyms)]
-use crate::types::DwarfType;
+use crate::error::{DwarfWriterError, Result};
+use crate::target::Target;
+use crate::types::{DwarfType, Member};
 use crate::InputFile;
 use crate::Opt;
+use log::warn;
+use object::{Architecture, Endianness};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::collections::HashMap;
@@ -12,8 +16,43 @@ mod types;
 impl InputFile for AnvillInput {}
 
 impl AnvillInput {
+    /// Checks that the input's declared architecture matches the binary's.
+    /// Register locations are always mapped using the binary's architecture.
+    pub fn check_arch(&self, elf_arch: Architecture, cfg: &Opt) -> Result<()> {
+        let arch = self.arch.architecture();
+        if elf_arch != Architecture::Unknown && arch != elf_arch {
+            let err = DwarfWriterError::ArchMismatch {
+                input: format!("{:?}", self.arch),
+                binary: elf_arch,
+            };
+            if cfg.strict {
+                return Err(err)
+            }
+            warn!("{}. Using the binary's architecture instead", err);
+        }
+        Ok(())
+    }
+
+    /// The target described by the input's architecture. This is only used
+    /// for raw code which has no header to detect the target from.
+    pub fn target(&self) -> Target {
+        use Endianness::{Big, Little};
+
+        let (ptr_size, endianness) = match self.arch {
+            Arch::aarch64 | Arch::amd64 | Arch::amd64_avx | Arch::amd64_avx512 => (8, Little),
+            Arch::aarch32 | Arch::x86 | Arch::x86_avx | Arch::x86_avx512 => (4, Little),
+            Arch::sparc32 => (4, Big),
+            Arch::sparc64 => (8, Big),
+        };
+        Target {
+            arch: self.arch.architecture(),
+            ptr_size,
+            endianness,
+        }
+    }
+
     /// Anvill data in a format suitable for writing as DWARF debug info.
-    pub fn data(&self, cfg: &Opt) -> AnvillData {
+    pub fn data(&self, cfg: &Opt) -> AnvillData<'_> {
         let var_map = if cfg.omit_variables {
             HashMap::new()
         } else {
@@ -24,10 +63,31 @@ impl AnvillInput {
         } else {
             self.functions()
         };
+        let var_map = var_map
+            .into_iter()
+            .map(|(addr, var)| (addr + cfg.load_bias, var))
+            .collect();
+        let fn_map: AnvillFnMap = fn_map
+            .into_iter()
+            .map(|(addr, func)| (addr + cfg.load_bias, func))
+            .collect();
+        let common_blocks = if cfg.omit_variables {
+            HashMap::new()
+        } else {
+            self.common_blocks
+                .iter()
+                .flatten()
+                .map(|block| (block.address + cfg.load_bias, block))
+                .collect()
+        };
+        let mut types: Vec<DwarfType> = self.types().iter().map(|&t| t.into()).collect();
+        // Functions returning multiple values also need their return structs
+        types.extend(fn_map.values().filter_map(|f| f.func.return_type()));
         AnvillData {
             fn_map,
             var_map,
-            types: self.types().iter().map(|&t| t.into()).collect(),
+            common_blocks,
+            types,
         }
     }
 }
@@ -38,6 +98,8 @@ pub type AnvillVarMap<'a> = HashMap<u64, VarRef<'a>>;
 pub struct AnvillData<'a> {
     pub fn_map: AnvillFnMap<'a>,
     pub var_map: AnvillVarMap<'a>,
+    /// Fortran common blocks keyed by their address.
+    pub common_blocks: HashMap<u64, &'a CommonBlock>,
     pub types: Vec<DwarfType>,
 }
 
@@ -50,12 +112,22 @@ pub struct FunctionRef<'a> {
 pub struct VarRef<'a> {
     pub var: &'a Variable,
     pub name: Option<&'a str>,
+    /// The variable's value if it's known to be constant.
+    pub value: Option<ConstValue>,
+}
+
+/// A constant value for a variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstValue {
+    Integer(i64),
+    /// The variable's bytes in the target's byte order.
+    Bytes(Vec<u8>),
 }
 
 impl AnvillInput {
     /// Returns a map from addresses to functions, adding its name if it's
     /// provided.
-    fn functions(&self) -> AnvillFnMap {
+    fn functions(&self) -> AnvillFnMap<'_> {
         let mut res = HashMap::new();
         let funcs = self.functions.as_ref();
         let syms = self.symbols.as_ref();
@@ -71,7 +143,7 @@ impl AnvillInput {
         res
     }
 
-    fn variables(&self) -> AnvillVarMap {
+    fn variables(&self) -> AnvillVarMap<'_> {
         let mut res = HashMap::new();
         let vars = self.variables.as_ref();
         let syms = self.symbols.as_ref();
@@ -81,26 +153,57 @@ impl AnvillInput {
                     .iter()
                     .find(|&sym| sym.address == var.address)
                     .map(|s| s.name.as_str());
-                res.insert(var.address, VarRef { var, name });
+                let value = match &var.value {
+                    Some(VariableValue::Integer(v)) => Some(ConstValue::Integer(*v)),
+                    Some(VariableValue::Bytes(hex)) => {
+                        let bytes = parse_hex(hex);
+                        if bytes.is_none() {
+                            warn!(
+                                "Unable to parse value {:?} of variable {:#x}",
+                                hex, var.address
+                            );
+                        }
+                        bytes.map(ConstValue::Bytes)
+                    },
+                    None => self.read_only_bytes(var).map(ConstValue::Bytes),
+                };
+                res.insert(var.address, VarRef { var, name, value });
             }
         }
         res
     }
 
+    /// Reads a variable's bytes if it's stored in read-only data.
+    fn read_only_bytes(&self, var: &Variable) -> Option<Vec<u8>> {
+        let size = DwarfType::from(&var.r#type).byte_size()?;
+        let range = self.memory.as_ref()?.iter().find(|range| {
+            !range.is_writeable &&
+                !range.is_executable &&
+                range.address <= var.address &&
+                var.address + size <= range.address + range.data.len() as u64 / 2
+        })?;
+        let start = (var.address - range.address) as usize * 2;
+        parse_hex(range.data.get(start..start + size as usize * 2)?)
+    }
+
     /// Gets all unique types from variables, function parameters and return
     /// types.
     fn types(&self) -> Vec<&Type> {
         let mut res: Vec<_> = self
             .functions()
             .values()
-            .map(|f| f.func.types())
-            .flatten()
+            .flat_map(|f| f.func.types())
             .collect();
         if let Some(vars) = &self.variables {
             for var in vars {
                 res.push(&var.r#type);
             }
         }
+        for block in self.common_blocks.iter().flatten() {
+            for member in &block.members {
+                res.push(&member.r#type);
+            }
+        }
         res.sort();
         res.dedup();
         res
@@ -130,6 +233,50 @@ impl Function {
     }
 }
 
+impl Function {
+    /// The function's return type. Functions returning multiple values return
+    /// an anonymous struct with a member for each value.
+    pub fn return_type(&self) -> Option<DwarfType> {
+        match self.return_values.as_deref()? {
+            [] => None,
+            [ret_val] => Some(DwarfType::from(&ret_val.r#type)),
+            ret_vals => Some(return_struct(ret_vals)),
+        }
+    }
+}
+
+/// Creates a struct with a member for each return value. Members are named
+/// after the register holding the value if it's returned in one and are laid
+/// out with their natural alignment while their sizes are known.
+fn return_struct(ret_vals: &[Value<TaggedLocation>]) -> DwarfType {
+    let mut fields = Vec::new();
+    let mut end = Some(0u64);
+    let mut max_align = 1;
+    for (idx, ret_val) in ret_vals.iter().enumerate() {
+        let ty = DwarfType::from(&ret_val.r#type);
+        let name = match &ret_val.location {
+            Some(TaggedLocation::register(reg)) => reg.name().to_lowercase(),
+            _ => format!("ret{}", idx),
+        };
+        let offset = match (end, ty.byte_size()) {
+            (Some(end_offset), Some(size)) => {
+                let align = size.clamp(1, 8).next_power_of_two();
+                max_align = max_align.max(align);
+                let offset = end_offset.div_ceil(align) * align;
+                end = Some(offset + size);
+                Some(offset)
+            },
+            _ => {
+                end = None;
+                None
+            },
+        };
+        fields.push(Member::new(Some(name.into_bytes().into()), ty, offset));
+    }
+    let size = end.map(|end| end.div_ceil(max_align) * max_align);
+    DwarfType::new_struct(None, size, fields)
+}
+
 impl Arg {
     pub fn name(&self) -> Option<&str> {
         self.name.as_deref()
@@ -153,6 +300,7 @@ pub struct AnvillInput {
     variables: Option<Vec<Variable>>,
     symbols: Option<Vec<Symbol>>,
     memory: Option<Vec<MemoryRange>>,
+    common_blocks: Option<Vec<CommonBlock>>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -169,6 +317,20 @@ pub enum Arch {
     sparc64,
 }
 
+impl Arch {
+    /// The object file architecture corresponding to an anvill architecture.
+    fn architecture(&self) -> Architecture {
+        match self {
+            Arch::aarch64 => Architecture::Aarch64,
+            Arch::aarch32 => Architecture::Arm,
+            Arch::x86 | Arch::x86_avx | Arch::x86_avx512 => Architecture::I386,
+            Arch::amd64 | Arch::amd64_avx | Arch::amd64_avx512 => Architecture::X86_64,
+            Arch::sparc32 => Architecture::Unknown,
+            Arch::sparc64 => Architecture::Sparc64,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub enum OS {
     linux,
@@ -181,18 +343,88 @@ pub enum OS {
 pub struct Function {
     address: u64,
     pub return_address: Option<Value<TaggedLocation>>,
-    return_stack_pointer: Option<Value<UntaggedLocation>>,
+    /// The stack pointer's value after the function returns.
+    pub return_stack_pointer: Option<Value<UntaggedLocation>>,
     pub parameters: Option<Vec<Arg>>,
     pub return_values: Option<Vec<Value<TaggedLocation>>>,
     is_variadic: Option<bool>,
     pub is_noreturn: Option<bool>,
     calling_convention: Option<CallingConvention>,
+    /// Calls made by the function.
+    pub calls: Option<Vec<Call>>,
+    /// Whether `calls` lists every call made by the function.
+    pub has_all_calls: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Call {
+    /// The address of the instruction after the call.
+    pub return_address: u64,
+    /// The address of the called function if it's known.
+    pub target: Option<u64>,
+    /// The locations of the arguments passed to the called function.
+    pub arguments: Option<Vec<TaggedLocation>>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Variable {
     pub r#type: Type,
     pub address: u64,
+    /// Locations the variable lives in over the given PC ranges.
+    pub locations: Option<Vec<LocationRange>>,
+    pub file: Option<String>,
+    pub line: Option<u64>,
+    /// The variable's value if it's constant. Variables in read-only memory
+    /// ranges use the value in memory if this isn't given.
+    value: Option<VariableValue>,
+    /// Whether each thread has its own copy of the variable.
+    #[serde(default)]
+    pub is_thread_local: bool,
+}
+
+/// A Fortran common block of variables stored together starting at its
+/// address.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CommonBlock {
+    pub name: String,
+    pub address: u64,
+    pub members: Vec<CommonMember>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CommonMember {
+    pub name: String,
+    pub r#type: Type,
+    /// The member's offset from the start of the common block.
+    pub offset: u64,
+}
+
+/// A variable's constant value given either as an integer or as a string of
+/// hex encoded bytes.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+pub enum VariableValue {
+    Integer(i64),
+    Bytes(String),
+}
+
+/// Decodes a string of hex encoded bytes like the data in memory ranges.
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LocationRange {
+    pub begin: u64,
+    pub end: u64,
+    #[serde(flatten)]
+    pub location: TaggedLocation,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -271,10 +503,19 @@ pub enum Type {
     Bool, // _Bool or bool
     Primitive(PrimitiveType),
     Pointer(Box<Type>),
-    Array { inner_type: Box<Type>, len: u64 },
-    Vector { inner_type: Box<Type>, len: u64 },
+    Array {
+        inner_type: Box<Type>,
+        len: u64,
+    },
+    Vector {
+        inner_type: Box<Type>,
+        len: u64,
+    },
     Struct,
-    Function,
+    Function {
+        args: Vec<Type>,
+        return_type: Box<Type>,
+    },
 }
 
 #[derive(Deserialize_repr, Serialize_repr, Debug)]
@@ -290,6 +531,9 @@ pub enum Register {
     X86(X86Register),
     ARM(ARMRegister),
     SPARC(SPARCRegister),
+    /// A register dwarf-writer doesn't know which can only be used if its
+    /// number is given with `--register-map`.
+    Other(String),
 }
 
 // TODO: Add support for x86 registers (i.e. eax, ecx, etc.). Does anvill
@@ -469,13 +713,55 @@ pub enum ARMRegister {
 #[derive(Deserialize, Serialize, Clone, Copy, Debug)]
 pub enum SPARCRegister {}
 
-impl From<Register> for u16 {
-    fn from(r: Register) -> u16 {
-        match r {
-            Register::X86(r) => r as u16,
-            Register::ARM(r) => r as u16,
-            Register::SPARC(r) => r as u16,
+impl Register {
+    /// The register's name as it appears in the anvill JSON.
+    pub fn name(&self) -> String {
+        let name =
+            serde_json::to_string(self).expect("Couldn't serialize `anvill::Register` to `String`");
+        name.trim_matches('"').to_string()
+    }
+
+    /// The register's number in the DWARF register mapping of the x86-64
+    /// psABI or the ARM AAPCS. This is used for registers which gimli can't
+    /// map by name. Unknown registers have no number.
+    pub fn dwarf_number(&self) -> Option<u16> {
+        // Each group of registers is numbered consecutively both here and in
+        // the DWARF mapping
+        fn number(reg: u16, groups: &[(u16, u16)]) -> u16 {
+            let (first, first_number) = groups
+                .iter()
+                .rev()
+                .find(|&&(first, _)| first <= reg)
+                .copied()
+                .unwrap_or((0, 0));
+            first_number + reg - first
         }
+        let number = match *self {
+            Register::X86(r) => {
+                use X86Register::*;
+                let groups = [
+                    (RAX as u16, 0),
+                    (ST0 as u16, 33),
+                    (MM0 as u16, 41),
+                    (XMM0 as u16, 17),
+                    (XMM16 as u16, 67),
+                ];
+                number(r as u16, &groups)
+            },
+            Register::ARM(r) => {
+                use ARMRegister::*;
+                // Like gimli, single-precision registers are numbered as the
+                // double-precision register which contains them
+                if r as u16 >= S0 as u16 {
+                    256 + (r as u16 - S0 as u16) / 2
+                } else {
+                    number(r as u16, &[(R0 as u16, 0), (D0 as u16, 256)])
+                }
+            },
+            Register::SPARC(r) => r as u16,
+            Register::Other(_) => return None,
+        };
+        Some(number)
     }
 }
 
@@ -489,12 +775,10 @@ mod tests {
     fn get_tests() -> impl Iterator<Item = String> {
         let all_files = fs::read_dir(TEST_DIR).expect("Could not open test directory");
 
-        all_files.filter_map(|file| file.ok()).filter_map(|file| {
-            let name = file
-                .file_name()
+        all_files.filter_map(|file| file.ok()).map(|file| {
+            file.file_name()
                 .into_string()
-                .expect("Could not convert `OsString` to UTF-8");
-            Some(name)
+                .expect("Could not convert `OsString` to UTF-8")
         })
     }
 
@@ -503,10 +787,70 @@ mod tests {
         for test_name in get_tests() {
             println!("Running test case: {}", test_name);
             let file = fs::File::open(format!("{}/{}", TEST_DIR, test_name))
-                .expect(&format!("Could not open test {}", test_name));
+                .unwrap_or_else(|_| panic!("Could not open test {}", test_name));
             let reader = io::BufReader::new(file);
-            let _: AnvillInput =
-                serde_json::from_reader(reader).expect(&format!("Failed test {}", test_name));
+            let _: AnvillInput = serde_json::from_reader(reader)
+                .unwrap_or_else(|_| panic!("Failed test {}", test_name));
         }
     }
+
+    #[test]
+    fn mismatched_arch() {
+        use crate::into_gimli::{IntoGimli, RegisterMap};
+        use clap::Parser;
+
+        let input: AnvillInput =
+            serde_json::from_str(r#"{"arch": "aarch32", "os": "linux"}"#).unwrap();
+        let opt = Opt::parse_from(["dwarf-writer", "bin"]);
+        assert!(input.check_arch(Architecture::Arm, &opt).is_ok());
+        assert!(input.check_arch(Architecture::X86_64, &opt).is_ok());
+        let strict = Opt::parse_from(["dwarf-writer", "--strict", "bin"]);
+        assert!(input.check_arch(Architecture::Arm, &strict).is_ok());
+        assert!(input.check_arch(Architecture::X86_64, &strict).is_err());
+
+        // The binary's architecture takes precedence over the spec's
+        let sp = Register::ARM(ARMRegister::SP);
+        let no_map = RegisterMap::default();
+        assert_eq!(
+            (&sp, Architecture::Arm, &no_map).into_gimli(),
+            Some(gimli::Arm::SP)
+        );
+        assert_eq!(
+            (&sp, Architecture::Aarch64, &no_map).into_gimli(),
+            Some(gimli::AArch64::SP)
+        );
+    }
+
+    #[test]
+    fn missing_return_address() {
+        let func: Function = serde_json::from_str(
+            r#"{"address": 4096, "parameters": [{"register": "RDI", "type": "l"}]}"#,
+        )
+        .unwrap();
+        assert!(func.return_address.is_none());
+        let types: Vec<_> = func.types().into_iter().map(DwarfType::from).collect();
+        assert_eq!(
+            types,
+            vec![DwarfType::new_primitive(
+                b"int64_t".to_vec().into(),
+                Some(8)
+            )]
+        );
+    }
+
+    #[test]
+    fn packed_vector() {
+        let ty: Type = serde_json::from_str(r#""<bx3>""#).unwrap();
+        let int8 = DwarfType::new_primitive(b"int8_t".to_vec().into(), Some(1));
+        let vector = DwarfType::from(&ty);
+        assert_eq!(vector, DwarfType::new_vector(int8, Some(3)));
+        assert!(matches!(
+            vector,
+            DwarfType::Array {
+                stride: Some(1),
+                is_vector: true,
+                ..
+            }
+        ));
+    }
 }
@@ -1,6 +1,6 @@
 use super::{PrimitiveType, Type};
+use crate::error::Result;
 use crate::types::{CanonicalTypeName, DwarfType};
-use anyhow::Result;
 use log::debug;
 use serde::de;
 use serde::de::{Deserializer, Unexpected, Visitor};
@@ -29,7 +29,7 @@ impl Type {
             Type::Primitive(PrimitiveType::f) => b"float",
             Type::Primitive(PrimitiveType::d) => b"double",
             Type::Primitive(PrimitiveType::D) => b"long double",
-            //M, // uint64_t (x86 MMX vector type)
+            Type::Primitive(PrimitiveType::M) => b"uint64_t",
             Type::Primitive(PrimitiveType::Q) => b"__float128",
             Type::Primitive(PrimitiveType::v) => b"void",
             _ => panic!("Unexpected type {:?}", self),
@@ -56,10 +56,10 @@ impl Type {
             Type::Primitive(PrimitiveType::d) => 8,
             // TODO: `long double` can be 10 or 12 bytes. How should this be handled?
             Type::Primitive(PrimitiveType::D) => 12,
-            //M, // uint64_t (x86 MMX vector type)
+            Type::Primitive(PrimitiveType::M) => 8,
             Type::Primitive(PrimitiveType::Q) => 16,
             Type::Primitive(PrimitiveType::v) => 0,
-            _ => todo!("missing type {:?}", self),
+            _ => panic!("Unexpected type {:?}", self),
         }
     }
 }
@@ -71,26 +71,22 @@ impl From<&Type> for DwarfType {
             Type::Bool | Type::Primitive(_) => DwarfType::Primitive {
                 name: anvill_ty.name(),
                 size: Some(anvill_ty.size()),
+                bit_size: None,
             },
             Type::Pointer(referent_ty) => DwarfType::Pointer(Box::new(referent_ty.as_ref().into())),
-            Type::Array { inner_type, len } => DwarfType::Array {
-                inner_type: Box::new(inner_type.as_ref().into()),
-                len: Some(*len),
+            Type::Array { inner_type, len } => {
+                DwarfType::new_array(inner_type.as_ref().into(), Some(*len))
+            },
+            Type::Vector { inner_type, len } => {
+                DwarfType::new_vector(inner_type.as_ref().into(), Some(*len))
             },
             Type::Struct => {
                 debug!("Writing struct info provided by anvill is not supported yet");
-                DwarfType::Struct(Vec::new())
-            },
-            Type::Function => {
-                debug!("Writing function type info provided by anvill is not supported yet");
-                DwarfType::Function {
-                    return_type: Box::new(DwarfType::void()),
-                    args: Vec::new(),
-                }
+                DwarfType::new_struct(None, None, Vec::new())
             },
-            _ => todo!(
-                "Map missing type {:?} from an `anvill::Type` type to a `DwarfType`",
-                anvill_ty
+            Type::Function { args, return_type } => DwarfType::new_function(
+                return_type.as_ref().into(),
+                args.iter().map(DwarfType::from).collect(),
             ),
         }
     }
@@ -104,17 +100,41 @@ impl TypeVisitor {
     }
 
     fn parse_array<E: de::Error>(&self, s: &str) -> Result<(Box<Type>, u64), E> {
-        let inner_str = &s[1..s.len() - 1];
-        let (inner_str, len) = inner_str
-            .rsplit_once("x")
-            .expect("Array type did not specify a length");
+        let invalid = || de::Error::invalid_value(Unexpected::Str(s), self);
+        // The caller checked that `s` starts and ends with single byte brackets
+        let inner_str = s.get(1..s.len() - 1).ok_or_else(invalid)?;
+        let (inner_str, len) = inner_str.rsplit_once('x').ok_or_else(invalid)?;
         let inner_type = Box::new(self.parse_type(inner_str)?);
-        let len = len
-            .parse()
-            .map_err(|_| de::Error::invalid_value(Unexpected::Str(inner_str), self))?;
+        let len = len.parse().map_err(|_| invalid())?;
         Ok((inner_type, len))
     }
 
+    /// Parses a function type made up of its argument types followed by its
+    /// return type (e.g. `(*bi)`). Variadic functions have a `&` before the
+    /// return type.
+    fn parse_function<E: de::Error>(&self, s: &str) -> Result<Type, E> {
+        let invalid = || de::Error::invalid_value(Unexpected::Str(s), self);
+        // The caller checked that `s` starts and ends with single byte brackets
+        let inner_str = s.get(1..s.len() - 1).ok_or_else(invalid)?;
+        let mut types = Vec::new();
+        let mut rest = inner_str;
+        while !rest.is_empty() {
+            let len = type_len(rest).ok_or_else(invalid)?;
+            let (ty, next) = rest.split_at(len);
+            if ty == "&" {
+                debug!("Variadic function types are written without their variadic arguments");
+            } else {
+                types.push(self.parse_type(ty)?);
+            }
+            rest = next;
+        }
+        let return_type = Box::new(types.pop().ok_or_else(invalid)?);
+        Ok(Type::Function {
+            args: types,
+            return_type,
+        })
+    }
+
     fn parse_type<E: de::Error>(&self, s: &str) -> Result<Type, E> {
         fn is_bracketed(x: &str, left: &str, right: &str) -> bool {
             x.starts_with(left) && x.ends_with(right)
@@ -133,12 +153,16 @@ impl TypeVisitor {
         } else if is_bracketed(s, "{", "}") {
             Ok(Type::Struct)
         } else if is_bracketed(s, "(", ")") {
-            Ok(Type::Function)
+            self.parse_function(s)
         } else if let Some(referent_str) = s.strip_prefix('*') {
             let referent_ty = Box::new(self.parse_type(referent_str)?);
             Ok(Type::Pointer(referent_ty))
-        } else if s.starts_with('=') && is_bracketed(&s[2..], "{", "}") {
-            debug!("Anvill's identified structs aren't supported yet. {:?} will be treated as normal struct", &s[2..]);
+        } else if let Some(id_struct) = s
+            .strip_prefix('=')
+            .and_then(|rest| rest.get(1..))
+            .filter(|rest| is_bracketed(rest, "{", "}"))
+        {
+            debug!("Anvill's identified structs aren't supported yet. {:?} will be treated as normal struct", id_struct);
             Ok(Type::Struct)
         } else {
             Err(de::Error::invalid_value(Unexpected::Str(s), self))
@@ -146,6 +170,31 @@ impl TypeVisitor {
     }
 }
 
+/// Returns the length of the first type in a string of concatenated types or
+/// `None` if its brackets aren't balanced.
+fn type_len(s: &str) -> Option<usize> {
+    let pointers = s.len() - s.trim_start_matches('*').len();
+    let rest = &s[pointers..];
+    let first = rest.chars().next()?;
+    if !matches!(first, '[' | '<' | '{' | '(' | '=') {
+        return Some(pointers + first.len_utf8())
+    }
+    let mut depth = 0;
+    for (idx, c) in rest.char_indices() {
+        match c {
+            '[' | '<' | '{' | '(' => depth += 1,
+            ']' | '>' | '}' | ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(pointers + idx + 1)
+                }
+            },
+            _ => (),
+        }
+    }
+    None
+}
+
 impl<'de> Visitor<'de> for TypeVisitor {
     type Value = Type;
 
@@ -165,3 +214,71 @@ impl<'de> Deserialize<'de> for Type {
         deserializer.deserialize_str(TypeVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_anvill_type(s: &str) -> Result<Type, de::value::Error> {
+        TypeVisitor.parse_type(s)
+    }
+
+    #[test]
+    fn degenerate_brackets() {
+        for s in [
+            "", "[", "]", "[]", "<>", "[x]", "<x>", "[ix]", "[xi]", "[i4]", "=", "={", "=é{}",
+        ] {
+            assert!(parse_anvill_type(s).is_err(), "{:?} should not parse", s);
+        }
+        assert_eq!(
+            parse_anvill_type("[ix4]").unwrap(),
+            Type::Array {
+                inner_type: Box::new(Type::Primitive(PrimitiveType::i)),
+                len: 4
+            }
+        );
+        assert_eq!(parse_anvill_type("=0{ii}").unwrap(), Type::Struct);
+        for s in ["()", "(", "(i", "([i)", "(i&"] {
+            assert!(parse_anvill_type(s).is_err(), "{:?} should not parse", s);
+        }
+        assert!(serde_json::from_str::<Type>(r#""<>""#).is_err());
+    }
+
+    #[test]
+    fn mmx_type() {
+        let ty = parse_anvill_type("M").unwrap();
+        assert_eq!(ty.size(), 8);
+        assert_eq!(ty.name(), b"uint64_t".to_vec().into());
+    }
+
+    #[test]
+    fn function_types() {
+        use PrimitiveType::*;
+
+        let int = || Box::new(Type::Primitive(i));
+        assert_eq!(
+            parse_anvill_type("(iv)").unwrap(),
+            Type::Function {
+                args: vec![Type::Primitive(i)],
+                return_type: Box::new(Type::Primitive(v)),
+            }
+        );
+        assert_eq!(
+            parse_anvill_type("(*b[ix4](ii)&*i)").unwrap(),
+            Type::Function {
+                args: vec![
+                    Type::Pointer(Box::new(Type::Primitive(b))),
+                    Type::Array {
+                        inner_type: int(),
+                        len: 4
+                    },
+                    Type::Function {
+                        args: vec![Type::Primitive(i)],
+                        return_type: int(),
+                    },
+                ],
+                return_type: Box::new(Type::Pointer(int())),
+            }
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use cpp_demangle::{DemangleOptions, Symbol};
+use msvc_demangler::DemangleFlags;
+
+/// Demangles an Itanium or MSVC mangled name to its qualified name without
+/// parameters (e.g. `ns::foo`). Returns `None` if the name isn't mangled.
+pub fn demangle(name: &str) -> Option<String> {
+    if name.starts_with("_Z") {
+        let options = DemangleOptions::new().no_params().no_return_type();
+        Symbol::new(name).ok()?.demangle(&options).ok()
+    } else if name.starts_with('?') {
+        msvc_demangler::demangle(name, DemangleFlags::NAME_ONLY).ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demangle_names() {
+        assert_eq!(demangle("_ZN2ns3fooEi").as_deref(), Some("ns::foo"));
+        assert_eq!(demangle("_Z3barv").as_deref(), Some("bar"));
+        assert_eq!(demangle("?foo@ns@@YAHH@Z").as_deref(), Some("ns::foo"));
+        assert_eq!(demangle("main"), None);
+        assert_eq!(demangle("_Zinvalid"), None);
+    }
+}
@@ -1,20 +1,37 @@
 use crate::anvill;
 use crate::dwarf_entry::EntryRef;
-use crate::into_gimli::IntoGimli;
-use gimli::write::{Address, AttributeValue, Expression, StringTable, UnitEntryId};
+use crate::into_gimli::{IntoGimli, RegisterMap};
+use gimli::constants;
+use gimli::constants::{DW_AT_language, DW_AT_lo_user, DW_OP_call_frame_cfa,
+                       DW_OP_form_tls_address, DwAt, DwLang, DwTag};
+use gimli::write::{Address, AttributeValue, Expression, Location, StringTable, Unit, UnitEntryId};
+use object::Architecture;
 
-impl From<&anvill::TaggedLocation> for AttributeValue {
-    fn from(location: &anvill::TaggedLocation) -> AttributeValue {
+/// Converts a location to an expression or `None` if its register is unknown.
+impl IntoGimli<Option<Expression>> for (&anvill::TaggedLocation, Architecture, &RegisterMap) {
+    fn into_gimli(self) -> Option<Expression> {
         use anvill::TaggedLocation;
 
+        let (location, arch, map) = self;
         let mut expr = Expression::new();
         match location {
-            TaggedLocation::register(reg) => expr.op_reg(reg.into_gimli()),
+            TaggedLocation::register(reg) => expr.op_reg((reg, arch, map).into_gimli()?),
             TaggedLocation::memory { register, offset } => {
-                expr.op_breg(register.into_gimli(), *offset)
+                expr.op_breg((register, arch, map).into_gimli()?, *offset)
             },
         }
-        AttributeValue::Exprloc(expr)
+        Some(expr)
+    }
+}
+
+impl IntoGimli<Option<Location>> for (&anvill::LocationRange, Architecture, &RegisterMap) {
+    fn into_gimli(self) -> Option<Location> {
+        let (range, arch, map) = self;
+        Some(Location::StartEnd {
+            begin: Address::Constant(range.begin),
+            end: Address::Constant(range.end),
+            data: (&range.location, arch, map).into_gimli()?,
+        })
     }
 }
 
@@ -24,12 +41,83 @@ impl<'a> From<&EntryRef<'a>> for AttributeValue {
     }
 }
 
+/// Vendor attribute marking the entries created by dwarf-writer so later runs
+/// can tell them apart from compiler-generated entries.
+#[allow(non_upper_case_globals)]
+pub const DW_AT_dwarf_writer: DwAt = DW_AT_lo_user;
+
+/// The value of `DW_AT_dwarf_writer` identifying the version which created the
+/// entry. This is also the `DW_AT_producer` of units created by dwarf-writer.
+pub const MARKER: &str = concat!("dwarf-writer ", env!("CARGO_PKG_VERSION"));
+
+/// Adds a new entry marked with `DW_AT_dwarf_writer` to the unit.
+pub fn add_marked_entry(unit: &mut Unit, parent: UnitEntryId, tag: DwTag) -> UnitEntryId {
+    let id = unit.add(parent, tag);
+    mark_entry(unit, id);
+    id
+}
+
+pub fn mark_entry(unit: &mut Unit, id: UnitEntryId) {
+    unit.get_mut(id).set(
+        DW_AT_dwarf_writer,
+        AttributeValue::String(MARKER.as_bytes().to_vec()),
+    );
+}
+
 pub fn addr_to_attr(addr: u64) -> AttributeValue {
     let mut expr = Expression::new();
     expr.op_addr(Address::Constant(addr));
     AttributeValue::Exprloc(expr)
 }
 
+/// Creates a `DW_AT_frame_base` using the canonical frame address.
+pub fn cfa_frame_base() -> AttributeValue {
+    let mut cfa = Expression::new();
+    cfa.op(DW_OP_call_frame_cfa);
+    AttributeValue::Exprloc(cfa)
+}
+
+/// Creates the location of a thread-local variable at `offset` in the
+/// thread's TLS block.
+pub fn tls_offset_to_attr(offset: u64) -> AttributeValue {
+    let mut expr = Expression::new();
+    expr.op_constu(offset);
+    expr.op(DW_OP_form_tls_address);
+    AttributeValue::Exprloc(expr)
+}
+
+/// The lower bound of arrays without a `DW_AT_lower_bound` in a unit of the
+/// given language, per the DWARF 5 table of language defaults.
+pub fn default_lower_bound(language: Option<DwLang>) -> u64 {
+    match language {
+        Some(
+            constants::DW_LANG_Ada83 |
+            constants::DW_LANG_Ada95 |
+            constants::DW_LANG_Cobol74 |
+            constants::DW_LANG_Cobol85 |
+            constants::DW_LANG_Fortran77 |
+            constants::DW_LANG_Fortran90 |
+            constants::DW_LANG_Fortran95 |
+            constants::DW_LANG_Fortran03 |
+            constants::DW_LANG_Fortran08 |
+            constants::DW_LANG_Julia |
+            constants::DW_LANG_Modula2 |
+            constants::DW_LANG_Modula3 |
+            constants::DW_LANG_Pascal83 |
+            constants::DW_LANG_PLI,
+        ) => 1,
+        _ => 0,
+    }
+}
+
+/// Returns the unit's source language if it's known.
+pub fn unit_language(unit: &Unit) -> Option<DwLang> {
+    match unit.get(unit.root()).get(DW_AT_language)? {
+        AttributeValue::Language(language) => Some(*language),
+        _ => None,
+    }
+}
+
 pub fn name_as_bytes<'a>(attr: &'a AttributeValue, strings: &'a StringTable) -> &'a [u8] {
     // TODO: This is missing some cases
     match attr {
@@ -39,12 +127,21 @@ pub fn name_as_bytes<'a>(attr: &'a AttributeValue, strings: &'a StringTable) ->
     }
 }
 
-pub fn low_pc_to_u64(attr: &AttributeValue) -> u64 {
-    // TODO: Handle Address::Symbol
+/// Returns the address stored in a `DW_AT_low_pc` attribute. Address indices
+/// (`DW_FORM_addrx`) are resolved to constants when the DWARF is read so they
+/// end up as `AttributeValue::Address`.
+pub fn low_pc_to_u64(attr: &AttributeValue) -> Option<u64> {
     match attr {
-        AttributeValue::Address(Address::Constant(addr)) => *addr,
-        AttributeValue::Udata(addr) => *addr,
-        _ => panic!("Unhandled `AttributeValue` variant in `low_pc_to_u64`"),
+        AttributeValue::Address(Address::Constant(addr)) => Some(*addr),
+        // Symbolic addresses from relocatable objects hold the address in
+        // their addend
+        AttributeValue::Address(Address::Symbol { addend, .. }) => Some(*addend as u64),
+        AttributeValue::Udata(addr) => Some(*addr),
+        AttributeValue::Data1(addr) => Some(*addr as u64),
+        AttributeValue::Data2(addr) => Some(*addr as u64),
+        AttributeValue::Data4(addr) => Some(*addr as u64),
+        AttributeValue::Data8(addr) => Some(*addr),
+        _ => None,
     }
 }
 
@@ -73,6 +170,14 @@ pub fn attr_to_u64(attr: &AttributeValue) -> u64 {
     }
 }
 
+/// Converts a constant which may be signed, e.g. an enumerator's value.
+pub fn attr_to_i64(attr: &AttributeValue) -> i64 {
+    match attr {
+        AttributeValue::Sdata(b) => *b,
+        attr => attr_to_u64(attr) as i64,
+    }
+}
+
 pub fn attr_to_entry_id(attr: &AttributeValue) -> UnitEntryId {
     match attr {
         AttributeValue::UnitRef(r) => *r,
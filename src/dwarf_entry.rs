@@ -1,19 +1,30 @@
-use crate::anvill::{AnvillFnMap, AnvillVarMap};
+use crate::anvill::{AnvillFnMap, AnvillVarMap, CommonBlock, ConstValue, Function, UntaggedLocation};
+use crate::demangle::demangle;
 use crate::dwarf_attr::*;
+use crate::dwarf_unit::namespace_entry;
 use crate::elf::ELF;
-use crate::ghidra::GhidraData;
+use crate::ghidra::{GhidraData, StackVariable};
+use crate::into_gimli::{IntoGimli, RegisterMap};
+use crate::patch::PatchFnMap;
 use crate::str_bsi::StrFnMap;
-use crate::types::{DwarfType, TypeMap};
+use crate::target::Target;
+use crate::types::{split_qualified_name, DwarfType, Member, TypeMap, VariantPart};
+use crate::{NameKind, NamePolicy, Opt};
 use gimli::constants::*;
-use gimli::write::{Address, AttributeValue, DebuggingInformationEntry, Unit, UnitEntryId, UnitId};
-use log::trace;
-use object::Object;
+use gimli::write::{Address, AttributeValue, DebuggingInformationEntry, Expression, Location,
+                   LocationList, Reference, Unit, UnitEntryId, UnitId};
+use log::{trace, warn};
+use object::Architecture;
+use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
 
 /// Reference to an entry in a `gimli::write::Unit`.
 #[derive(Debug)]
 pub struct EntryRef<'a> {
     elf: &'a mut ELF,
+    cfg: &'a Opt,
+    // The ID of the unit containing the entry.
+    unit: UnitId,
     // The entry's ID.
     id: UnitEntryId,
 }
@@ -35,12 +46,12 @@ impl DerefMut for EntryRef<'_> {
 }
 
 impl<'a> EntryRef<'a> {
-    pub fn new(elf: &'a mut ELF, id: UnitEntryId) -> Self {
-        EntryRef { elf, id }
+    pub fn new(elf: &'a mut ELF, cfg: &'a Opt, unit: UnitId, id: UnitEntryId) -> Self {
+        EntryRef { elf, cfg, unit, id }
     }
 
     fn unit_id(&self) -> UnitId {
-        self.elf.dwarf.units.id(0)
+        self.unit
     }
 
     fn get_unit(&self) -> &Unit {
@@ -53,21 +64,190 @@ impl<'a> EntryRef<'a> {
         self.elf.dwarf.units.get_mut(root)
     }
 
-    fn new_sibling(&mut self, tag: DwTag) -> EntryRef {
+    /// The target of the binary being updated, unless overridden with
+    /// `--target`.
+    fn target(&self) -> Target {
+        self.cfg.target.unwrap_or_else(|| self.elf.target())
+    }
+
+    /// The architecture of the binary being updated.
+    fn arch(&self) -> Architecture {
+        self.target().arch
+    }
+
+    /// The register numbers given with `--register-map`.
+    fn register_map(&self) -> &RegisterMap {
+        &self.elf.register_map
+    }
+
+    /// The size of a pointer in the binary being updated.
+    fn ptr_size(&self) -> u64 {
+        self.target().ptr_size
+    }
+
+    /// The subprogram's start address or `None` if it has no `DW_AT_low_pc`
+    /// (e.g. declarations) or its `DW_AT_low_pc` has an unsupported form.
+    fn low_pc(&self) -> Option<u64> {
+        let low_pc_attr = match self.get(DW_AT_low_pc) {
+            Some(attr) => attr,
+            None => {
+                trace!("Skipping subprogram {:?} without DW_AT_low_pc", self.id);
+                return None
+            },
+        };
+        let addr = low_pc_to_u64(low_pc_attr);
+        if addr.is_none() {
+            warn!(
+                "Skipping subprogram with unsupported DW_AT_low_pc {:?}",
+                low_pc_attr
+            );
+        }
+        addr
+    }
+
+    /// Whether the entry was created by dwarf-writer rather than the compiler.
+    pub fn is_marked(&self) -> bool {
+        self.get(DW_AT_dwarf_writer).is_some()
+    }
+
+    /// Entries not created by dwarf-writer are left unchanged with
+    /// `--respect-existing`.
+    pub fn is_protected(&self) -> bool {
+        self.cfg.protects_existing() && !self.is_marked()
+    }
+
+    fn new_sibling(&mut self, tag: DwTag) -> EntryRef<'_> {
         let parent = self
             .parent()
             .expect("`new_sibling` cannot be called on root entry");
-        let sibling_id = self.get_mut_unit().add(parent, tag);
-        EntryRef::new(self.elf, sibling_id)
+        let sibling_id = add_marked_entry(self.get_mut_unit(), parent, tag);
+        EntryRef::new(self.elf, self.cfg, self.unit, sibling_id)
     }
 
-    fn new_child(&mut self, tag: DwTag) -> EntryRef {
+    /// Creates an entry for a type referenced by this entry. Types in
+    /// namespaces are created in their namespace and other types are created as
+    /// siblings of this entry.
+    fn new_type_entry(&mut self, ty: &DwarfType) -> EntryRef<'_> {
+        let path = ty.namespaces();
+        if path.is_empty() {
+            return self.new_sibling(ty.tag())
+        }
+        let unit_id = self.unit_id();
+        let dwarf = &mut self.elf.dwarf;
+        let unit = dwarf.units.get_mut(unit_id);
+        let parent = namespace_entry(unit, &dwarf.strings, &path);
+        let id = add_marked_entry(unit, parent, ty.tag());
+        EntryRef::new(self.elf, self.cfg, unit_id, id)
+    }
+
+    fn new_child(&mut self, tag: DwTag) -> EntryRef<'_> {
         let id = self.id;
-        let child_id = self.get_mut_unit().add(id, tag);
-        EntryRef::new(self.elf, child_id)
+        let child_id = add_marked_entry(self.get_mut_unit(), id, tag);
+        EntryRef::new(self.elf, self.cfg, self.unit, child_id)
+    }
+
+    /// Returns the IDs of a subprogram's first `count` parameters, creating
+    /// any which are missing. Existing parameters are kept, including any
+    /// beyond `count`, so each input only overrides the attributes it knows
+    /// about (e.g. names from Ghidra and locations from anvill).
+    fn positional_params(&mut self, count: usize) -> Vec<UnitEntryId> {
+        self.positional_children(DW_TAG_formal_parameter, count)
+    }
+
+    /// Returns the IDs of the first `count` children with the given tag,
+    /// creating any which are missing.
+    fn positional_children(&mut self, tag: DwTag, count: usize) -> Vec<UnitEntryId> {
+        let mut child_ids: Vec<_> = self
+            .children()
+            .filter(|&&child_id| self.get_unit().get(child_id).tag() == tag)
+            .cloned()
+            .collect();
+        child_ids.truncate(count);
+        while child_ids.len() < count {
+            let child_id = self.new_child(tag).id;
+            child_ids.push(child_id);
+        }
+        child_ids
+    }
+
+    /// Deletes the children with the given tag created by dwarf-writer after
+    /// the first `count`, e.g. parameters removed by a patch.
+    fn delete_extra_children(&mut self, tag: DwTag, count: usize) {
+        let extra: Vec<_> = self
+            .children()
+            .filter(|&&child_id| self.get_unit().get(child_id).tag() == tag)
+            .skip(count)
+            .filter(|&&child_id| {
+                self.get_unit()
+                    .get(child_id)
+                    .get(DW_AT_dwarf_writer)
+                    .is_some()
+            })
+            .cloned()
+            .collect();
+        for child_id in extra {
+            self.delete_child(child_id);
+        }
+    }
+
+    /// Names a subprogram's first parameters and sets their types, creating
+    /// any parameters which are missing. Variable-length array types are
+    /// added to `local_arrays` to be created once all parameters are named.
+    fn set_params(
+        &mut self, params: Vec<(&str, Option<DwarfType>)>,
+        local_arrays: &mut Vec<(UnitEntryId, DwarfType)>, type_map: &TypeMap,
+    ) -> Vec<UnitEntryId> {
+        let param_ids = self.positional_params(params.len());
+        for ((name, ty), &id) in params.into_iter().zip(&param_ids) {
+            let mut param_entry = EntryRef::new(self.elf, self.cfg, self.unit, id);
+            match ty {
+                Some(ty) if ty.is_variable_length() => local_arrays.push((id, ty)),
+                Some(ty) => {
+                    let ty_id = type_map.get(&ty).unwrap_or_else(|| {
+                        panic!("Parameter type {:?} not found in the type map", ty)
+                    });
+                    param_entry.set(DW_AT_type, AttributeValue::UnitRef(*ty_id));
+                },
+                None => {},
+            }
+            param_entry.set(DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
+        }
+        param_ids
+    }
+
+    /// Finds the parameter with the given name among the entry's siblings.
+    fn sibling_param(&self, name: &[u8]) -> Option<UnitEntryId> {
+        let unit = self.get_unit();
+        let parent = unit.get(unit.get(self.id).parent()?);
+        parent.children().cloned().find(|&id| {
+            let entry = unit.get(id);
+            entry.tag() == DW_TAG_formal_parameter &&
+                entry
+                    .get(DW_AT_name)
+                    .map(|param| name_as_bytes(param, &self.elf.dwarf.strings)) ==
+                    Some(name)
+        })
     }
 
-    pub fn init_ghidra_fn(&mut self, addr: u64, ghidra_data: &mut GhidraData, type_map: &TypeMap) {
+    /// Sets the types of a subprogram's parameters and local variables which
+    /// are variable-length arrays. Their array types are created in the
+    /// subprogram since their lengths refer to its parameters, so all of the
+    /// parameters must be named first.
+    fn set_local_array_types(
+        &mut self, entries: Vec<(UnitEntryId, DwarfType)>, type_map: &mut TypeMap,
+    ) {
+        for (id, ty) in entries {
+            let mut array_entry = self.new_child(ty.tag());
+            array_entry.init_type(&ty, type_map);
+            let array_id = array_entry.id;
+            EntryRef::new(self.elf, self.cfg, self.unit, id)
+                .set(DW_AT_type, AttributeValue::UnitRef(array_id));
+        }
+    }
+
+    pub fn init_ghidra_fn(
+        &mut self, addr: u64, ghidra_data: &mut GhidraData, type_map: &mut TypeMap,
+    ) {
         self.set(
             DW_AT_low_pc,
             AttributeValue::Address(Address::Constant(addr)),
@@ -75,61 +255,145 @@ impl<'a> EntryRef<'a> {
         self.update_ghidra_fn(ghidra_data, type_map)
     }
 
-    pub fn update_ghidra_fn(&mut self, ghidra_data: &mut GhidraData, type_map: &TypeMap) {
-        let low_pc_attr = self
-            .get(DW_AT_low_pc)
-            .expect("No DW_AT_low_pc found in DW_TAG_subprogram entry");
-        let start_address = low_pc_to_u64(low_pc_attr);
+    pub fn update_ghidra_fn(&mut self, ghidra_data: &mut GhidraData, type_map: &mut TypeMap) {
+        let start_address = match self.low_pc() {
+            Some(addr) => addr,
+            None => return,
+        };
 
         let fn_data = ghidra_data.fn_map.remove(&start_address);
+        if self.is_protected() {
+            return
+        }
         if let Some(fn_data) = fn_data {
-            self.set(DW_AT_high_pc, AttributeValue::Data8(fn_data.high_pc));
+            // A constant high_pc is the function's size rather than its end
+            if let Some(high_pc) = fn_data.high_pc {
+                let size = high_pc - start_address;
+                self.set(DW_AT_high_pc, AttributeValue::Data8(size));
+            }
+            if let Some(entry_pc) = fn_data.entry_pc.filter(|&pc| pc != start_address) {
+                self.set(
+                    DW_AT_entry_pc,
+                    AttributeValue::Address(Address::Constant(entry_pc)),
+                );
+            }
 
-            if let Some(name) = self.update_name(Option::from(fn_data.name), "FUN_", start_address)
-            {
+            if let Some(name) = self.update_name(
+                Option::from(fn_data.name),
+                NameKind::Function,
+                start_address,
+            ) {
                 self.set(DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
             }
-
-            if let Some(ret_ty) = &fn_data.return_ty {
-                let ret_type_entry_id = type_map.get(&ret_ty).unwrap_or_else(|| {
-                    panic!("Return type {:?} not found in the type map", ret_ty)
-                });
-                self.set(DW_AT_type, AttributeValue::UnitRef(*ret_type_entry_id));
+            self.set_external(start_address);
+            if self.cfg.names_only {
+                return
             }
 
-            let existing_params: Vec<_> = self
-                .children()
-                .filter_map(|&child_id| {
-                    if self.get_unit().get(child_id).tag() == DW_TAG_formal_parameter {
-                        Some(child_id)
-                    } else {
-                        None
-                    }
-                })
+            // Ghidra only marks functions which don't return
+            let is_noreturn = self.set_noreturn(fn_data.is_noreturn.then_some(true));
+            self.set_return_type(fn_data.return_ty.as_ref(), is_noreturn, type_map);
+
+            let params = fn_data
+                .parameters
+                .iter()
+                .map(|param| (param.name, param.ty.clone()))
                 .collect();
+            let mut local_arrays = Vec::new();
+            let param_ids = self.set_params(params, &mut local_arrays, type_map);
+            for (param, id) in fn_data.parameters.iter().zip(param_ids) {
+                if param.is_object_pointer {
+                    EntryRef::new(self.elf, self.cfg, self.unit, id)
+                        .set(DW_AT_artificial, AttributeValue::Flag(true));
+                    self.set(DW_AT_object_pointer, AttributeValue::UnitRef(id));
+                }
+            }
+            self.set_local_array_types(local_arrays, type_map);
 
-            for param in existing_params {
-                self.delete_child(param);
+            if !fn_data.stack_vars.is_empty() {
+                self.add_stack_vars(&fn_data.stack_vars, type_map);
             }
+        }
+    }
 
-            for param in fn_data.parameters {
-                let mut param_entry = self.new_child(DW_TAG_formal_parameter);
-                if let Some(param_ty) = &param.ty {
-                    let param_ty_id = type_map.get(&param_ty).unwrap_or_else(|| {
-                        panic!("Parameter type {:?} not found in the type map", param_ty)
-                    });
-                    param_entry.set(DW_AT_type, AttributeValue::UnitRef(*param_ty_id));
-                }
-                param_entry.set(
-                    DW_AT_name,
-                    AttributeValue::String(param.name.as_bytes().to_vec()),
+    /// Returns the frame base of a function given the location of the stack
+    /// pointer after it returns, i.e. the stack pointer register and its
+    /// offset from the value on entry. Without it the canonical frame address
+    /// is used.
+    fn anvill_frame_base(&self, ret_sp: Option<&UntaggedLocation>) -> AttributeValue {
+        let (reg, offset) = match ret_sp {
+            Some(UntaggedLocation::memory { register, offset }) => (register, *offset),
+            Some(UntaggedLocation::register(register)) => (register, 0),
+            None => return cfa_frame_base(),
+        };
+        let reg = match (reg, self.arch(), self.register_map()).into_gimli() {
+            Some(reg) => reg,
+            None => return cfa_frame_base(),
+        };
+        let mut expr = Expression::new();
+        expr.op_breg(reg, offset);
+        AttributeValue::Exprloc(expr)
+    }
+
+    /// Adds a function's local stack variables with locations relative to the
+    /// canonical frame address which is used as the function's frame base.
+    fn add_stack_vars(&mut self, vars: &[StackVariable], type_map: &TypeMap) {
+        let frame_base = cfa_frame_base();
+        match self.get(DW_AT_frame_base) {
+            None => self.set(DW_AT_frame_base, frame_base),
+            Some(existing) if *existing == frame_base => (),
+            Some(existing) => {
+                warn!(
+                    "Skipping stack variables of subprogram {:?} with DW_AT_frame_base {:?}",
+                    self.id, existing
                 );
+                return
+            },
+        }
+        // Ghidra's stack offsets are relative to the stack pointer on entry
+        // which is below the CFA if calls push the return address
+        let cfa_offset = match self.arch() {
+            Architecture::I386 | Architecture::X86_64 => self.ptr_size() as i64,
+            _ => 0,
+        };
+
+        // Replace existing variables with the same names
+        let existing_vars: Vec<_> = self
+            .children()
+            .filter_map(|&child_id| {
+                let child = self.get_unit().get(child_id);
+                let name = child.get(DW_AT_name)?;
+                let is_replaced = child.tag() == DW_TAG_variable &&
+                    vars.iter().any(|var| {
+                        name_as_bytes(name, &self.elf.dwarf.strings) == var.name.as_bytes()
+                    });
+                is_replaced.then_some(child_id)
+            })
+            .collect();
+        for var in existing_vars {
+            self.delete_child(var);
+        }
+
+        for var in vars {
+            let mut var_entry = self.new_child(DW_TAG_variable);
+            var_entry.set(
+                DW_AT_name,
+                AttributeValue::String(var.name.as_bytes().to_vec()),
+            );
+            if let Some(var_ty) = &var.ty {
+                let var_ty_id = type_map.get(var_ty).unwrap_or_else(|| {
+                    panic!("Variable type {:?} not found in the type map", var_ty)
+                });
+                var_entry.set(DW_AT_type, AttributeValue::UnitRef(*var_ty_id));
             }
+            let mut location = Expression::new();
+            location.op_fbreg(var.offset - cfa_offset);
+            var_entry.set(DW_AT_location, AttributeValue::Exprloc(location));
         }
     }
 
     /// Initializes a newly created subprogram entry with STR data.
-    pub fn init_str_fn(&mut self, addr: u64, str_data: &mut StrFnMap, type_map: &TypeMap) {
+    pub fn init_str_fn(&mut self, addr: u64, str_data: &mut StrFnMap, type_map: &mut TypeMap) {
         self.set(
             DW_AT_low_pc,
             AttributeValue::Address(Address::Constant(addr)),
@@ -138,81 +402,140 @@ impl<'a> EntryRef<'a> {
     }
 
     /// Updates an existing function's subprogram entry with STR data.
-    pub fn update_str_fn(&mut self, str_data: &mut StrFnMap, type_map: &TypeMap) {
+    pub fn update_str_fn(&mut self, str_data: &mut StrFnMap, type_map: &mut TypeMap) {
         // Get function address to see if there's disassembly data for it
-        let low_pc_attr = self
-            .get(DW_AT_low_pc)
-            .expect("No DW_AT_low_pc found in DW_TAG_subprogram entry");
-        let start_address = low_pc_to_u64(low_pc_attr);
+        let start_address = match self.low_pc() {
+            Some(addr) => addr,
+            None => return,
+        };
 
         let fn_data = str_data.remove(&start_address);
+        if self.is_protected() {
+            return
+        }
         if let Some(fn_data) = fn_data {
             // Update function name and source location
-            if let Some(name) =
-                self.update_name(fn_data.symbol_name.as_deref(), "FUN_", start_address)
-            {
+            if let Some(name) = self.update_name(
+                fn_data.symbol_name.as_deref(),
+                NameKind::Function,
+                start_address,
+            ) {
                 self.set(DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
             }
-            if let Some(file) = fn_data.file() {
-                self.set(
-                    DW_AT_decl_file,
-                    AttributeValue::String(file.as_bytes().to_vec()),
-                );
+            self.set_external(start_address);
+            self.set_decl_location(fn_data.file(), fn_data.line());
+            if self.cfg.names_only {
+                return
             }
-            if let Some(line) = fn_data.line() {
-                self.set(DW_AT_decl_line, AttributeValue::Data8(line));
+            if self.set_noreturn(fn_data.is_noreturn) {
+                self.set_return_type(None, true, type_map);
             }
 
             // Update function parameters
+            let mut local_arrays = Vec::new();
             if let Some(new_params) = &fn_data.parameters() {
-                // Delete all existing parameters
-                let existing_params: Vec<_> = self
-                    .children()
-                    .filter_map(|&child_id| {
-                        if self.get_unit().get(child_id).tag() == DW_TAG_formal_parameter {
-                            Some(child_id)
-                        } else {
-                            None
-                        }
+                let params = new_params
+                    .iter()
+                    .map(|param| {
+                        (
+                            param.name.as_str(),
+                            param.r#type.as_ref().map(DwarfType::from),
+                        )
                     })
                     .collect();
-                for param in existing_params {
-                    self.delete_child(param);
-                }
-
-                for param in new_params {
-                    let mut param_entry = self.new_child(DW_TAG_formal_parameter);
-                    if let Some(ref ty) = param.r#type {
-                        let param_ty = DwarfType::from(ty);
-                        let param_ty_id = type_map.get(&param_ty).unwrap_or_else(|| {
-                            panic!("Parameter type {:?} not found in the type map", param_ty)
-                        });
-                        param_entry.set(DW_AT_type, AttributeValue::UnitRef(*param_ty_id));
-                        param_entry.set(
-                            DW_AT_name,
-                            AttributeValue::String(param.name.as_bytes().to_vec()),
-                        );
-                    }
-                }
+                self.set_params(params, &mut local_arrays, type_map);
+            } else {
+                trace!(
+                    "STR data has no parameters for the function at {:#x}",
+                    start_address
+                );
             }
 
             // Update the function's local variables
-            if let Some(local_vars) = &fn_data.local_vars() {
+            let local_vars = if self.cfg.omit_variables {
+                None
+            } else {
+                fn_data.local_vars()
+            };
+            if let Some(local_vars) = &local_vars {
                 for var in local_vars {
                     let mut var_entry = self.new_child(DW_TAG_variable);
                     if let Some(ref ty) = var.r#type {
                         let var_ty = DwarfType::from(ty);
-                        let var_ty_id = type_map.get(&var_ty).unwrap_or_else(|| {
-                            panic!("Variable type {:?} not found in the type map", var_ty)
-                        });
-                        var_entry.set(DW_AT_type, AttributeValue::UnitRef(*var_ty_id));
+                        if var_ty.is_variable_length() {
+                            local_arrays.push((var_entry.id, var_ty));
+                        } else {
+                            let var_ty_id = type_map.get(&var_ty).unwrap_or_else(|| {
+                                panic!("Variable type {:?} not found in the type map", var_ty)
+                            });
+                            var_entry.set(DW_AT_type, AttributeValue::UnitRef(*var_ty_id));
+                        }
                         var_entry.set(
                             DW_AT_name,
                             AttributeValue::String(var.name.as_bytes().to_vec()),
                         );
                     }
+                    var_entry.set_decl_location(var.file.as_deref(), var.line);
                 }
             }
+            self.set_local_array_types(local_arrays, type_map);
+        }
+    }
+
+    /// Initializes a newly created subprogram entry with a function patch.
+    pub fn init_patch_fn(
+        &mut self, addr: u64, patch_data: &mut PatchFnMap, type_map: &mut TypeMap,
+    ) {
+        self.set(
+            DW_AT_low_pc,
+            AttributeValue::Address(Address::Constant(addr)),
+        );
+        self.update_patch_fn(patch_data, type_map)
+    }
+
+    /// Overrides an existing function's attributes with a function patch.
+    pub fn update_patch_fn(&mut self, patch_data: &mut PatchFnMap, type_map: &mut TypeMap) {
+        let start_address = match self.low_pc() {
+            Some(addr) => addr,
+            None => return,
+        };
+
+        let fn_data = patch_data.remove(&start_address);
+        if self.is_protected() {
+            return
+        }
+        if let Some(fn_data) = fn_data {
+            // Patched names take precedence over the name policy
+            if let Some(name) =
+                self.override_name(fn_data.name.as_deref(), NameKind::Function, start_address)
+            {
+                self.set(DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
+            }
+            self.set_external(start_address);
+            if self.cfg.names_only {
+                return
+            }
+
+            let ret_ty = fn_data.return_type.as_ref().map(DwarfType::from);
+            self.set_return_type(ret_ty.as_ref(), false, type_map);
+
+            // Patches replace the parameter list so parameters beyond the
+            // patched ones are removed
+            if let Some(new_params) = &fn_data.parameters {
+                let params = new_params
+                    .iter()
+                    .map(|param| {
+                        (
+                            param.name.as_str(),
+                            param.r#type.as_ref().map(DwarfType::from),
+                        )
+                    })
+                    .collect();
+                let mut local_arrays = Vec::new();
+                self.set_params(params, &mut local_arrays, type_map);
+                self.delete_extra_children(DW_TAG_formal_parameter, new_params.len());
+                self.set_local_array_types(local_arrays, type_map);
+            }
         }
     }
 
@@ -228,60 +551,61 @@ impl<'a> EntryRef<'a> {
     /// Updates an existing function's subprogram entry with Anvill data.
     pub fn update_anvill_fn(&mut self, anvill_data: &mut AnvillFnMap, type_map: &TypeMap) {
         // Get function address to see if there's disassembly data for it
-        let low_pc_attr = self
-            .get(DW_AT_low_pc)
-            .expect("No DW_AT_low_pc found in DW_TAG_subprogram entry");
-        let start_address = low_pc_to_u64(low_pc_attr);
+        let start_address = match self.low_pc() {
+            Some(addr) => addr,
+            None => return,
+        };
 
         let fn_data = anvill_data.remove(&start_address);
+        if self.is_protected() {
+            return
+        }
         if let Some(fn_data) = fn_data {
             // Update function name
-            if let Some(name) = self.update_name(fn_data.name, "FUN_", start_address) {
+            if let Some(name) = self.update_name(fn_data.name, NameKind::Function, start_address) {
                 self.set(DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
             }
+            self.set_external(start_address);
+            if self.cfg.names_only {
+                return
+            }
 
             if let Some(ret_addr) = &fn_data.func.return_address {
-                if let Some(loc) = &ret_addr.location {
-                    self.set(DW_AT_return_addr, AttributeValue::from(loc));
+                let expr = ret_addr
+                    .location
+                    .as_ref()
+                    .and_then(|loc| (loc, self.arch(), self.register_map()).into_gimli());
+                if let Some(expr) = expr {
+                    self.set(DW_AT_return_addr, AttributeValue::Exprloc(expr));
                 }
             }
 
-            if let Some(no_ret) = fn_data.func.is_noreturn {
-                self.set(DW_AT_noreturn, AttributeValue::Flag(no_ret));
+            // The compiler's frame base is kept since its variables' locations
+            // are relative to it
+            if self.get(DW_AT_frame_base).is_none() {
+                let ret_sp = fn_data.func.return_stack_pointer.as_ref();
+                let frame_base = self.anvill_frame_base(ret_sp.and_then(|sp| sp.location.as_ref()));
+                self.set(DW_AT_frame_base, frame_base);
             }
 
+            let is_noreturn = self.set_noreturn(fn_data.func.is_noreturn);
+
             self.set(DW_AT_prototyped, AttributeValue::Flag(true));
 
-            if let Some(ret_vals) = &fn_data.func.return_values {
-                if let Some(ret) = ret_vals.get(0) {
-                    let ret_type = DwarfType::from(&ret.r#type);
-                    let ret_type_entry_id = type_map.get(&ret_type).unwrap_or_else(|| {
-                        panic!("Return type {:?} not found in the type map", ret_type)
-                    });
-                    self.set(DW_AT_type, AttributeValue::UnitRef(*ret_type_entry_id));
-                }
-            }
+            let ret_type = fn_data.func.return_type();
+            self.set_return_type(ret_type.as_ref(), is_noreturn, type_map);
 
             if let Some(new_params) = &fn_data.func.parameters {
-                // Delete all existing parameters
-                let existing_params: Vec<_> = self
-                    .children()
-                    .filter_map(|&child_id| {
-                        if self.get_unit().get(child_id).tag() == DW_TAG_formal_parameter {
-                            Some(child_id)
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-                for param in existing_params {
-                    self.delete_child(param);
-                }
-
-                for param in new_params {
-                    let mut param_entry = self.new_child(DW_TAG_formal_parameter);
-                    if let Some(loc) = param.location() {
-                        param_entry.set(DW_AT_location, AttributeValue::from(loc));
+                let param_ids = self.positional_params(new_params.len());
+                for (param, id) in new_params.iter().zip(param_ids) {
+                    let mut param_entry = EntryRef::new(self.elf, self.cfg, self.unit, id);
+                    // Parameters are matched by position so unlocated ones
+                    // just leave the location as it was
+                    let expr = param.location().and_then(|loc| {
+                        (loc, param_entry.arch(), param_entry.register_map()).into_gimli()
+                    });
+                    if let Some(expr) = expr {
+                        param_entry.set(DW_AT_location, AttributeValue::Exprloc(expr));
                     }
                     let param_ty = DwarfType::from(param.ty());
                     let param_ty_id = type_map.get(&param_ty).unwrap_or_else(|| {
@@ -299,41 +623,287 @@ impl<'a> EntryRef<'a> {
         }
     }
 
-    fn update_name(&mut self, new_name: Option<&str>, prefix: &str, addr: u64) -> Option<String> {
+    /// Adds call site entries for the calls made by a function. DWARF 5 call
+    /// site tags are used for DWARF 5 units and the GNU extensions otherwise.
+    pub fn add_call_sites(
+        &mut self, func: &Function, subprograms: &HashMap<u64, (UnitId, UnitEntryId)>,
+    ) {
+        let calls = match &func.calls {
+            Some(calls) => calls,
+            None => return,
+        };
+        if self.is_protected() {
+            return
+        }
+        let is_dwarf5 = self.get_unit().encoding().version >= 5;
+        let (call_site, call_site_param, return_pc, origin, all_calls) = if is_dwarf5 {
+            (
+                DW_TAG_call_site,
+                DW_TAG_call_site_parameter,
+                DW_AT_call_return_pc,
+                DW_AT_call_origin,
+                DW_AT_call_all_calls,
+            )
+        } else {
+            (
+                DW_TAG_GNU_call_site,
+                DW_TAG_GNU_call_site_parameter,
+                DW_AT_low_pc,
+                DW_AT_abstract_origin,
+                DW_AT_GNU_all_call_sites,
+            )
+        };
+        if func.has_all_calls == Some(true) {
+            self.set(all_calls, AttributeValue::Flag(true));
+        }
+        let load_bias = self.cfg.load_bias;
+        for call in calls {
+            let call_pc = call.return_address + load_bias;
+            // Call sites from previous runs are updated instead of duplicated
+            let existing_call = self.children().cloned().find(|&child_id| {
+                let child = self.get_unit().get(child_id);
+                child.tag() == call_site &&
+                    child.get(return_pc).and_then(low_pc_to_u64) == Some(call_pc)
+            });
+            let mut call_entry = match existing_call {
+                Some(call_id) => EntryRef::new(self.elf, self.cfg, self.unit, call_id),
+                None => self.new_child(call_site),
+            };
+            call_entry.set(
+                return_pc,
+                AttributeValue::Address(Address::Constant(call_pc)),
+            );
+            let target = call.target.map(|target| target + load_bias);
+            if let Some(&(target_unit, target_id)) =
+                target.and_then(|target| subprograms.get(&target))
+            {
+                // Functions in other units are referenced through .debug_info
+                let target_ref = if target_unit == call_entry.unit {
+                    AttributeValue::UnitRef(target_id)
+                } else {
+                    AttributeValue::DebugInfoRef(Reference::Entry(target_unit, target_id))
+                };
+                call_entry.set(origin, target_ref);
+            } else {
+                // The origin from a previous run may refer to another function
+                call_entry.delete(origin);
+            }
+            // Calls without argument data leave existing parameters alone
+            let args = match &call.arguments {
+                Some(args) => args,
+                None => continue,
+            };
+            let param_ids = call_entry.positional_children(call_site_param, args.len());
+            call_entry.delete_extra_children(call_site_param, args.len());
+            for (arg, param_id) in args.iter().zip(param_ids) {
+                let expr = (arg, call_entry.arch(), call_entry.register_map()).into_gimli();
+                let mut param_entry =
+                    EntryRef::new(call_entry.elf, call_entry.cfg, call_entry.unit, param_id);
+                // Parameters keep their argument's position even if its
+                // location can't be described
+                match expr {
+                    Some(expr) => param_entry.set(DW_AT_location, AttributeValue::Exprloc(expr)),
+                    None => param_entry.delete(DW_AT_location),
+                }
+            }
+        }
+    }
+
+    /// Sets `DW_AT_noreturn` if the input knows whether the function returns.
+    /// Returns whether the function is known to never return.
+    fn set_noreturn(&mut self, is_noreturn: Option<bool>) -> bool {
+        if let Some(no_ret) = is_noreturn {
+            self.set(DW_AT_noreturn, AttributeValue::Flag(no_ret));
+        }
+        is_noreturn == Some(true)
+    }
+
+    /// Sets a function's return type. Functions that don't return have no
+    /// return type so a void or missing one removes the existing type.
+    fn set_return_type(
+        &mut self, ret_ty: Option<&DwarfType>, is_noreturn: bool, type_map: &TypeMap,
+    ) {
+        if is_noreturn && ret_ty.iter().all(|&ty| *ty == DwarfType::void()) {
+            self.delete(DW_AT_type);
+        } else if let Some(ret_ty) = ret_ty {
+            let ret_type_entry_id = type_map
+                .get(ret_ty)
+                .unwrap_or_else(|| panic!("Return type {:?} not found in the type map", ret_ty));
+            self.set(DW_AT_type, AttributeValue::UnitRef(*ret_type_entry_id));
+        }
+    }
+
+    /// Sets the source location where the entry was declared. Relative paths
+    /// are kept relative since they're resolved against the unit's
+    /// `DW_AT_comp_dir`.
+    fn set_decl_location(&mut self, file: Option<&str>, line: Option<u64>) {
+        if let Some(file) = file {
+            self.set(
+                DW_AT_decl_file,
+                AttributeValue::String(file.as_bytes().to_vec()),
+            );
+        }
+        if let Some(line) = line {
+            self.set(DW_AT_decl_line, AttributeValue::Data8(line));
+        }
+    }
+
+    /// Marks the subprogram as externally visible if it's exported through the
+    /// dynamic symbol table.
+    fn set_external(&mut self, addr: u64) {
+        if self.elf.exported_fns.contains(&addr) {
+            self.set(DW_AT_external, AttributeValue::Flag(true));
+        }
+    }
+
+    fn update_name(&mut self, new_name: Option<&str>, kind: NameKind, addr: u64) -> Option<String> {
+        if self.cfg.name_policy == NamePolicy::Fill && self.get(DW_AT_name).is_some() {
+            return None
+        }
+        self.override_name(new_name, kind, addr)
+    }
+
+    /// Like `update_name` but replaces existing names regardless of
+    /// `--name-policy`.
+    fn override_name(
+        &mut self, new_name: Option<&str>, kind: NameKind, addr: u64,
+    ) -> Option<String> {
         let old_name = self.get(DW_AT_name);
         match (old_name, new_name) {
-            (None, None) => Some(format!("{}{:08x}", prefix, addr)),
+            // Unnamed functions are named after their symbol if the binary has
+            // one before falling back to a generated name
+            (None, None) => {
+                let symbol = match kind {
+                    NameKind::Function => self.elf.fn_symbols.get(&addr).cloned(),
+                    _ => None,
+                };
+                match symbol {
+                    Some(symbol) => self.override_name(Some(&symbol), kind, addr),
+                    None => Some(self.cfg.generated_name(kind, addr)),
+                }
+            },
             (Some(_), None) => None,
-            (_, Some(name)) => Some(name.to_string()),
+            (_, Some(name)) => {
+                let demangled = if self.cfg.demangle {
+                    demangle(name)
+                } else {
+                    None
+                };
+                if demangled.is_some() {
+                    let linkage_name = AttributeValue::String(name.as_bytes().to_vec());
+                    if self.cfg.legacy_linkage_name {
+                        self.set(DW_AT_MIPS_linkage_name, linkage_name.clone());
+                    }
+                    self.set(DW_AT_linkage_name, linkage_name);
+                }
+                let name = demangled.as_deref().unwrap_or(name);
+                // Entries in a namespace only use their unqualified name
+                let in_namespace = self
+                    .parent()
+                    .map(|parent| self.get_unit().get(parent).tag() == DW_TAG_namespace)
+                    .unwrap_or(false);
+                if in_namespace {
+                    let (_, name) = split_qualified_name(name.as_bytes());
+                    Some(String::from_utf8_lossy(name).into_owned())
+                } else {
+                    Some(name.to_string())
+                }
+            },
         }
     }
 
     pub fn init_var(&mut self, addr: u64, anvill_data: &mut AnvillVarMap, type_map: &TypeMap) {
-        self.set(DW_AT_location, addr_to_attr(addr));
+        let is_thread_local = anvill_data[&addr].var.is_thread_local;
+        let location = self.var_location(addr, is_thread_local);
+        self.set(DW_AT_location, location);
+        // Global variables are visible outside their file unless the symbol
+        // table says otherwise
+        let is_local = self
+            .elf
+            .data_symbols
+            .get(&addr)
+            .is_some_and(|sym| sym.is_local);
+        if !is_local {
+            self.set(DW_AT_external, AttributeValue::Flag(true));
+        }
         self.update_var(anvill_data, type_map);
     }
 
+    /// Sets a common block entry's location and replaces its member variables.
+    pub fn update_common_block(&mut self, addr: u64, block: &CommonBlock, type_map: &TypeMap) {
+        if self.is_protected() {
+            return
+        }
+        let members: Vec<_> = self.children().cloned().collect();
+        for member in members {
+            self.delete_child(member);
+        }
+        self.set(
+            DW_AT_name,
+            AttributeValue::String(block.name.as_bytes().to_vec()),
+        );
+        self.set(DW_AT_location, addr_to_attr(addr));
+        for member in &block.members {
+            let mut member_entry = self.new_child(DW_TAG_variable);
+            member_entry.set(
+                DW_AT_name,
+                AttributeValue::String(member.name.as_bytes().to_vec()),
+            );
+            if !member_entry.cfg.names_only {
+                let member_ty = DwarfType::from(&member.r#type);
+                let member_ty_id = type_map.get(&member_ty).unwrap_or_else(|| {
+                    panic!("Variable type {:?} not found in the type map", member_ty)
+                });
+                member_entry.set(DW_AT_type, AttributeValue::UnitRef(*member_ty_id));
+            }
+            member_entry.set(DW_AT_location, addr_to_attr(addr + member.offset));
+        }
+    }
+
+    /// Returns the location of a variable at `addr`. Thread-local variables
+    /// are located by their offset in the TLS block instead.
+    fn var_location(&self, addr: u64, is_thread_local: bool) -> AttributeValue {
+        if is_thread_local {
+            tls_offset_to_attr(self.elf.tls_offset(addr))
+        } else {
+            addr_to_attr(addr)
+        }
+    }
+
+    /// Returns the address of the input variable this variable entry matches.
+    pub fn var_addr(&self, anvill_data: &AnvillVarMap) -> Option<u64> {
+        // The `gimli::write::Operations` which make up an `Expression` are
+        // intentionally kept private so the addresses of variables in the input
+        // binary are decoded when it's read. Variables created by an earlier
+        // input are matched on their location instead.
+        match self.elf.var_addrs.get(&(self.unit, self.id)) {
+            Some(addr) => anvill_data.contains_key(addr).then_some(*addr),
+            None => self.get(DW_AT_location).and_then(|location| {
+                anvill_data
+                    .iter()
+                    .find(|(&addr, var)| {
+                        self.var_location(addr, var.var.is_thread_local) == *location
+                    })
+                    .map(|(&addr, _)| addr)
+            }),
+        }
+    }
+
     /// Updates an existing variable's entry.
     pub fn update_var(&mut self, anvill_data: &mut AnvillVarMap, type_map: &TypeMap) {
-        let location = self
-            .get(DW_AT_location)
-            .expect("No DW_AT_location found in DW_TAG_variable entry");
-        // TODO: Ideally I'd get the address from `location` above then check if the
-        // key's in the map, but I have to do it this way because the
-        // `gimli::write::Operations` which make up an `Expression` are intentionally
-        // kept private. There should be a way to tweak gimli to get the address of an
-        // expression.
-        let var_data = anvill_data
-            .keys()
-            .find(|&addr| addr_to_attr(*addr) == *location)
-            .cloned()
-            .map(|addr| anvill_data.remove(&addr))
-            .flatten();
-        if let Some(var_data) = var_data {
+        let addr = self.var_addr(anvill_data);
+        let var_data = addr.and_then(|addr| Some((addr, anvill_data.remove(&addr)?)));
+        if self.is_protected() {
+            return
+        }
+        if let Some((addr, var_data)) = var_data {
             // Update variable name
-            if let Some(name) = self.update_name(var_data.name, "VAR_", var_data.var.address) {
+            if let Some(name) = self.update_name(var_data.name, NameKind::Variable, addr) {
                 self.set(DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
             }
+            if self.cfg.names_only {
+                return
+            }
 
             // Update variale type
             let var_type = DwarfType::from(&var_data.var.r#type);
@@ -341,17 +911,165 @@ impl<'a> EntryRef<'a> {
                 panic!("Variable type {:?} not found in the type map", var_type)
             });
             self.set(DW_AT_type, AttributeValue::UnitRef(*var_type_entry_id));
+            self.check_var_size(addr, &var_type);
+
+            let var = var_data.var;
+            self.set_decl_location(var.file.as_deref(), var.line);
+
+            // Constant values are emitted in addition to the location since
+            // the location identifies the variable's entry
+            match var_data.value {
+                Some(ConstValue::Integer(value)) => {
+                    self.set(DW_AT_const_value, AttributeValue::Sdata(value))
+                },
+                Some(ConstValue::Bytes(bytes)) => {
+                    self.set(DW_AT_const_value, AttributeValue::Block(bytes))
+                },
+                None => (),
+            }
+
+            // Variables which move over the function's lifetime need a location list
+            if let Some(ranges) = var.locations.as_ref().filter(|ranges| !ranges.is_empty()) {
+                let (arch, map) = (self.arch(), self.register_map());
+                let locs: Option<Vec<_>> =
+                    ranges.iter().map(|r| (r, arch, map).into_gimli()).collect();
+                if let Some(mut locs) = locs {
+                    // Ranges are offsets from the unit's base address, which is
+                    // the low_pc of compiler-produced units, so it's reset to
+                    // the load bias for the ranges' input addresses
+                    let base = Location::BaseAddress {
+                        address: Address::Constant(self.cfg.load_bias),
+                    };
+                    locs.insert(0, base);
+                    let loc_list_id = self.get_mut_unit().locations.add(LocationList(locs));
+                    self.set(DW_AT_location, AttributeValue::LocationListRef(loc_list_id));
+                }
+            }
+        }
+    }
+
+    /// Warns if a variable's type doesn't have the size of the data object in
+    /// the symbol table at its address, which usually means the input's type
+    /// or address is wrong.
+    fn check_var_size(&self, addr: u64, ty: &DwarfType) {
+        let sym_size = match self.elf.data_symbols.get(&addr) {
+            Some(sym) if sym.size != 0 => sym.size,
+            _ => return,
+        };
+        if let Some(ty_size) = self.type_size(ty) {
+            if ty_size != sym_size {
+                warn!(
+                    "Variable at {:#x} has a {} byte type but its symbol is {} bytes",
+                    addr, ty_size, sym_size
+                );
+            }
+        }
+    }
+
+    /// The size of a type in bytes on the binary's target.
+    fn type_size(&self, ty: &DwarfType) -> Option<u64> {
+        match ty {
+            DwarfType::Pointer(_) => Some(self.ptr_size()),
+            DwarfType::Primitive { name, size, .. } => {
+                size.or_else(|| name.size_for_target(self.ptr_size()))
+            },
+            DwarfType::Typedef { ref_type: ty, .. } |
+            DwarfType::Restrict(ty) |
+            DwarfType::Atomic(ty) => self.type_size(ty),
+            DwarfType::Enum {
+                underlying_type, ..
+            } => self.type_size(underlying_type),
+            ty => ty.byte_size(),
+        }
+    }
+
+    /// Returns the entry for a type referenced by this entry, creating it if
+    /// it's not in the type map.
+    fn type_entry(&mut self, ty: &DwarfType, type_map: &mut TypeMap) -> UnitEntryId {
+        if let Some(id) = type_map.get(ty) {
+            return *id
+        }
+        let mut ty_entry = self.new_type_entry(ty);
+        ty_entry.init_type(ty, type_map);
+        trace!("Mapping type {:?} to entry {:?}", ty, ty_entry.id);
+        type_map.insert(ty.clone(), ty_entry.id);
+        ty_entry.id
+    }
+
+    /// Sets the attributes of a struct's `DW_TAG_member` or
+    /// `DW_TAG_inheritance` entry.
+    fn init_member(&mut self, member: &Member, ty: UnitEntryId) {
+        if let Some(name) = &member.name {
+            self.set(DW_AT_name, AttributeValue::String(Vec::from(name.clone())));
+        }
+        self.set(DW_AT_type, AttributeValue::UnitRef(ty));
+        if let Some(offset) = member.offset {
+            self.set(DW_AT_data_member_location, AttributeValue::Udata(offset));
+        }
+        if let Some(access) = member.accessibility {
+            self.set(
+                DW_AT_accessibility,
+                AttributeValue::Accessibility(access.into()),
+            );
+        }
+    }
+
+    /// Adds a `DW_TAG_variant_part` to a struct with its discriminant member
+    /// and a `DW_TAG_variant` for each variant.
+    fn init_variant_part(&mut self, variant_part: &VariantPart, type_map: &mut TypeMap) {
+        // Member types are created from the struct so they're its siblings
+        // rather than being nested in the variant part
+        let discr_ty = self.type_entry(&variant_part.discriminant.ty, type_map);
+        let variant_tys: Vec<Vec<_>> = variant_part
+            .variants
+            .iter()
+            .map(|variant| {
+                variant
+                    .fields
+                    .iter()
+                    .map(|member| self.type_entry(&member.ty, type_map))
+                    .collect()
+            })
+            .collect();
+
+        let mut part = self.new_child(DW_TAG_variant_part);
+        let mut discr = part.new_child(DW_TAG_member);
+        discr.init_member(&variant_part.discriminant, discr_ty);
+        discr.set(DW_AT_artificial, AttributeValue::Flag(true));
+        let discr_id = discr.id;
+        part.set(DW_AT_discr, AttributeValue::UnitRef(discr_id));
+        for (variant, tys) in variant_part.variants.iter().zip(variant_tys) {
+            let mut variant_entry = part.new_child(DW_TAG_variant);
+            if let Some(value) = variant.discr_value {
+                variant_entry.set(DW_AT_discr_value, AttributeValue::Udata(value));
+            }
+            for (member, ty) in variant.fields.iter().zip(tys) {
+                variant_entry
+                    .new_child(DW_TAG_member)
+                    .init_member(member, ty);
+            }
         }
     }
 
-    pub fn init_type<'ty>(&mut self, ty: &'ty DwarfType, type_map: &mut TypeMap) {
+    pub fn init_type(&mut self, ty: &DwarfType, type_map: &mut TypeMap) {
         match ty {
-            DwarfType::Primitive { name, size } => {
+            DwarfType::Primitive {
+                name,
+                size,
+                bit_size,
+            } => {
                 assert_eq!(self.tag(), DW_TAG_base_type);
                 self.set(DW_AT_name, AttributeValue::String(Vec::from(name.clone())));
-                if let Some(size) = size {
-                    self.set(DW_AT_byte_size, AttributeValue::Udata(*size));
+                let ptr_size = self.ptr_size();
+                if let Some(size) = size.or_else(|| name.size_for_target(ptr_size)) {
+                    self.set(DW_AT_byte_size, AttributeValue::Udata(size));
                 };
+                if let Some(bit_size) = bit_size {
+                    self.set(DW_AT_bit_size, AttributeValue::Udata(*bit_size));
+                }
+                if let Some(encoding) = name.encoding() {
+                    self.set(DW_AT_encoding, AttributeValue::Encoding(encoding));
+                }
             },
             DwarfType::Pointer(pointee_type) => {
                 assert_eq!(self.tag(), DW_TAG_pointer_type);
@@ -361,7 +1079,7 @@ impl<'a> EntryRef<'a> {
                     None => {
                         // If the pointee has not been seen, create its type and add it to the type
                         // map
-                        let mut pointee_ty_entry = self.new_sibling(pointee_type.tag());
+                        let mut pointee_ty_entry = self.new_type_entry(pointee_type);
                         pointee_ty_entry.init_type(pointee_type, type_map);
                         trace!(
                             "Mapping type {:?} to entry {:?}",
@@ -373,49 +1091,178 @@ impl<'a> EntryRef<'a> {
                         pointee_ty_entry.id
                     },
                 };
-                let ptr_size = if self.elf.object().is_64() { 8 } else { 4 };
+                let ptr_size = self.ptr_size();
                 self.set(DW_AT_byte_size, AttributeValue::Udata(ptr_size));
                 self.set(DW_AT_type, AttributeValue::UnitRef(pointee));
             },
-            DwarfType::Typedef { .. } => {
+            DwarfType::SizedPointer { pointee, size } => {
+                assert_eq!(self.tag(), DW_TAG_pointer_type);
+                let pointee = self.type_entry(pointee, type_map);
+                self.set(DW_AT_byte_size, AttributeValue::Udata(*size));
+                self.set(DW_AT_type, AttributeValue::UnitRef(pointee));
+            },
+            DwarfType::Restrict(inner_type) | DwarfType::Atomic(inner_type) => {
+                let inner = self.type_entry(inner_type, type_map);
+                self.set(DW_AT_type, AttributeValue::UnitRef(inner));
+            },
+            DwarfType::Typedef { name, ref_type } => {
                 assert_eq!(self.tag(), DW_TAG_typedef);
+                let (_, name) = split_qualified_name(name.as_bytes());
+                self.set(DW_AT_name, AttributeValue::String(name.to_vec()));
+                let ref_type = self.type_entry(ref_type, type_map);
+                self.set(DW_AT_type, AttributeValue::UnitRef(ref_type));
             },
-            DwarfType::Array { inner_type, len } => {
+            DwarfType::Array {
+                inner_type,
+                len,
+                stride,
+                is_vector,
+                count_param,
+            } => {
                 assert_eq!(self.tag(), DW_TAG_array_type);
                 let inner = match type_map.get(inner_type) {
                     Some(id) => *id,
                     None => {
-                        let mut inner_ty_entry = self.new_sibling(inner_type.tag());
+                        let mut inner_ty_entry = self.new_type_entry(inner_type);
                         inner_ty_entry.init_type(inner_type, type_map);
                         type_map.insert(*inner_type.clone(), inner_ty_entry.id);
                         inner_ty_entry.id
                     },
                 };
                 self.set(DW_AT_type, AttributeValue::UnitRef(inner));
+                if let Some(stride) = stride {
+                    if Some(*stride) != inner_type.byte_size() {
+                        self.set(DW_AT_byte_stride, AttributeValue::Udata(*stride));
+                    }
+                }
+                if *is_vector {
+                    self.set(DW_AT_GNU_vector, AttributeValue::Flag(true));
+                }
+                // Variable-length arrays are created in their function so
+                // their length can refer to its parameter
+                let count = count_param.as_ref().and_then(|name| {
+                    let param = self.sibling_param(name.as_bytes());
+                    if param.is_none() {
+                        warn!("Array length parameter {:?} not found", name);
+                    }
+                    param
+                });
+                let lower_bound = default_lower_bound(unit_language(self.get_unit()));
                 let mut array_size = self.new_child(DW_TAG_subrange_type);
+                // The bound is implied by the language but given anyway for
+                // consumers which assume C's zero lower bound
+                if lower_bound != 0 {
+                    array_size.set(DW_AT_lower_bound, AttributeValue::Udata(lower_bound));
+                }
                 if let Some(len) = len {
-                    // TODO: Try encoding the size with less space
-                    array_size.set(DW_AT_upper_bound, AttributeValue::Data8(*len));
+                    // The count doesn't depend on the language's default lower
+                    // bound and can describe empty arrays
+                    array_size.set(DW_AT_count, AttributeValue::Udata(*len));
                 };
+                if let Some(param) = count {
+                    array_size.set(DW_AT_count, AttributeValue::UnitRef(param));
+                }
             },
-            DwarfType::Struct(_) => {
-                assert_eq!(self.tag(), DW_TAG_structure_type);
+            DwarfType::Struct {
+                name,
+                size,
+                fields,
+                variant_part,
+                ..
+            } => {
+                assert_eq!(self.tag(), ty.tag());
+                if let Some(name) = name {
+                    let (_, name) = split_qualified_name(name.as_bytes());
+                    self.set(DW_AT_name, AttributeValue::String(name.to_vec()));
+                }
+                if let Some(size) = size {
+                    self.set(DW_AT_byte_size, AttributeValue::Udata(*size));
+                }
+                // Structs without known fields are opaque so they're emitted as
+                // declarations
+                if fields.is_empty() && variant_part.is_none() {
+                    self.set(DW_AT_declaration, AttributeValue::Flag(true));
+                }
+                for member in fields {
+                    let member_ty = self.type_entry(&member.ty, type_map);
+                    let tag = if member.is_base {
+                        DW_TAG_inheritance
+                    } else {
+                        DW_TAG_member
+                    };
+                    self.new_child(tag).init_member(member, member_ty);
+                }
+                if let Some(variant_part) = variant_part {
+                    self.init_variant_part(variant_part, type_map);
+                }
             },
             DwarfType::Function {
                 return_type,
-                args: _,
+                args,
+                is_unprototyped,
             } => {
                 assert_eq!(self.tag(), DW_TAG_subroutine_type);
-                let ret = match type_map.get(return_type) {
-                    Some(ret_ty_id) => *ret_ty_id,
-                    None => {
-                        let mut ret_ty_entry = self.new_sibling(return_type.tag());
-                        ret_ty_entry.init_type(return_type, type_map);
-                        type_map.insert(*return_type.clone(), ret_ty_entry.id);
-                        ret_ty_entry.id
-                    },
-                };
-                self.set(DW_AT_type, AttributeValue::UnitRef(ret));
+                // Functions with unknown signatures are emitted as declarations
+                if *is_unprototyped {
+                    self.set(DW_AT_declaration, AttributeValue::Flag(true));
+                } else {
+                    self.set(DW_AT_prototyped, AttributeValue::Flag(true));
+                }
+                // Subroutine types returning void have no DW_AT_type
+                if **return_type != DwarfType::void() {
+                    let ret = self.type_entry(return_type, type_map);
+                    self.set(DW_AT_type, AttributeValue::UnitRef(ret));
+                }
+                for arg in args {
+                    let arg_ty = self.type_entry(arg, type_map);
+                    let mut param_entry = self.new_child(DW_TAG_formal_parameter);
+                    param_entry.set(DW_AT_type, AttributeValue::UnitRef(arg_ty));
+                }
+            },
+            DwarfType::Enum {
+                name,
+                underlying_type,
+                enumerators,
+                is_scoped,
+            } => {
+                assert_eq!(self.tag(), DW_TAG_enumeration_type);
+                if let Some(name) = name {
+                    let (_, name) = split_qualified_name(name.as_bytes());
+                    self.set(DW_AT_name, AttributeValue::String(name.to_vec()));
+                }
+                let size = self.type_size(underlying_type);
+                if let Some(size) = size {
+                    self.set(DW_AT_byte_size, AttributeValue::Udata(size));
+                }
+                let underlying = self.type_entry(underlying_type, type_map);
+                self.set(DW_AT_type, AttributeValue::UnitRef(underlying));
+                if *is_scoped {
+                    self.set(DW_AT_enum_class, AttributeValue::Flag(true));
+                }
+                let is_signed = underlying_type.is_signed();
+                for enumerator in enumerators {
+                    let misfit = size.filter(|&size| !enumerator.fits(size, is_signed));
+                    if let Some(size) = misfit {
+                        warn!(
+                            "Enumerator {:?} of enum {:?} has value {} which doesn't fit in its \
+                             {} byte underlying type",
+                            enumerator.name, name, enumerator.value, size
+                        );
+                    }
+                    // Negative values always use a signed form, even if they
+                    // don't fit in an unsigned underlying type
+                    let value = if is_signed || enumerator.value < 0 {
+                        AttributeValue::Sdata(enumerator.value)
+                    } else {
+                        AttributeValue::Udata(enumerator.value as u64)
+                    };
+                    let mut entry = self.new_child(DW_TAG_enumerator);
+                    entry.set(
+                        DW_AT_name,
+                        AttributeValue::String(enumerator.name.as_bytes().to_vec()),
+                    );
+                    entry.set(DW_AT_const_value, value);
+                }
             },
         }
     }
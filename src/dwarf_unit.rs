@@ -1,23 +1,40 @@
 use crate::anvill::AnvillData;
-use crate::dwarf_attr::{attr_to_entry_id, attr_to_u64, name_as_bytes};
+use crate::demangle::demangle;
+use crate::dwarf_attr::{add_marked_entry, attr_to_entry_id, attr_to_i64, attr_to_u64,
+                        default_lower_bound, low_pc_to_u64, mark_entry, name_as_bytes,
+                        unit_language, DW_AT_dwarf_writer, MARKER};
 use crate::dwarf_entry::EntryRef;
 use crate::elf::ELF;
+use crate::error::{DwarfWriterError, Result};
 use crate::ghidra::GhidraData;
+use crate::patch::PatchData;
 use crate::str_bsi::StrBsiData;
-use crate::types::{CanonicalTypeName, DwarfType, TypeMap};
+use crate::target::Target;
+use crate::types::{split_qualified_name, Accessibility, CanonicalTypeName, DwarfType, Enumerator,
+                   Member, TypeMap, Variant, VariantPart};
+use crate::Opt;
 use gimli::constants;
 use gimli::constants::*;
-use gimli::write::{DebuggingInformationEntry, LineProgram, StringTable, Unit, UnitEntryId, UnitId};
+use gimli::write::{Address, AttributeValue, DebuggingInformationEntry, Dwarf, LineProgram,
+                   StringTable, Unit, UnitEntryId, UnitId};
 use gimli::{Encoding, Format};
-use log::trace;
-use object::Object;
-use std::collections::HashMap;
+use log::{info, trace, warn};
+use object::{Architecture, Object, ObjectSection, SectionKind};
+use std::collections::{HashMap, HashSet};
+use std::io;
 use std::ops::{Deref, DerefMut};
+use std::path::Path;
 
 pub struct DwarfUnitRef<'a> {
     elf: &'a mut ELF,
+    cfg: &'a Opt,
     // The unit's ID.
     id: UnitId,
+    // The ID of the unit selected when the `DwarfUnitRef` was created.
+    home: UnitId,
+    /// The types of the input being processed, which are added to each unit
+    /// its entries are split across.
+    input_types: Vec<DwarfType>,
 }
 
 impl Deref for DwarfUnitRef<'_> {
@@ -36,58 +53,401 @@ impl DerefMut for DwarfUnitRef<'_> {
     }
 }
 
+/// Gets the entry for a path of nested namespaces, creating any namespaces
+/// which don't exist yet.
+pub fn namespace_entry(unit: &mut Unit, strings: &StringTable, path: &[&[u8]]) -> UnitEntryId {
+    let mut parent = unit.root();
+    for &ns in path {
+        let existing = unit.get(parent).children().cloned().find(|&id| {
+            let child = unit.get(id);
+            child.tag() == DW_TAG_namespace &&
+                child
+                    .get(DW_AT_name)
+                    .map(|name| name_as_bytes(name, strings)) ==
+                    Some(ns)
+        });
+        parent = match existing {
+            Some(id) => id,
+            None => {
+                let id = add_marked_entry(unit, parent, DW_TAG_namespace);
+                unit.get_mut(id)
+                    .set(DW_AT_name, AttributeValue::String(ns.to_vec()));
+                id
+            },
+        };
+    }
+    parent
+}
+
+/// Finds the unit whose root entry is named `name`. The name may omit leading
+/// directories of the unit's name.
+fn find_unit(dwarf: &Dwarf, name: &str) -> Option<UnitId> {
+    (0..dwarf.units.count())
+        .map(|idx| dwarf.units.id(idx))
+        .find(|&id| {
+            let unit = dwarf.units.get(id);
+            unit.get(unit.root())
+                .get(DW_AT_name)
+                .map(|unit_name| {
+                    let unit_name =
+                        String::from_utf8_lossy(name_as_bytes(unit_name, &dwarf.strings));
+                    Path::new(unit_name.as_ref()).ends_with(name)
+                })
+                .unwrap_or(false)
+        })
+}
+
+/// Creates an empty unit for new entries with an optional source file name.
+fn create_unit(elf: &mut ELF, cfg: &Opt, name: Option<&str>) -> UnitId {
+    // The DWARF format is independent of the target's word size and 32-bit
+    // DWARF is used unless the sections may exceed 4GB
+    let format = if cfg.dwarf64 {
+        Format::Dwarf64
+    } else {
+        Format::Dwarf32
+    };
+    let target = cfg.target.unwrap_or_else(|| elf.target());
+    let address_size = target.ptr_size as u8;
+    let encoding = Encoding {
+        address_size,
+        format,
+        version: 4,
+    };
+    let line_program = LineProgram::none();
+    let mut unit = Unit::new(encoding, line_program);
+    let root = unit.root();
+    mark_entry(&mut unit, root);
+    unit.get_mut(root).set(
+        DW_AT_producer,
+        AttributeValue::String(MARKER.as_bytes().to_vec()),
+    );
+    // Names are always written as the UTF-8 bytes of the inputs' strings
+    unit.get_mut(root)
+        .set(DW_AT_use_UTF8, AttributeValue::Flag(true));
+    if let Some(language) = cfg.language {
+        unit.get_mut(root).set(
+            DW_AT_language,
+            AttributeValue::Language(language.dwarf_language()),
+        );
+    }
+    if let Some(name) = name {
+        unit.get_mut(root)
+            .set(DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
+    }
+    elf.dwarf.units.add(unit)
+}
+
+/// Whether a unit's `DW_AT_producer` is dwarf-writer.
+fn produced_by_dwarf_writer(unit: &Unit, strings: &StringTable) -> bool {
+    let producer = match unit.get(unit.root()).get(DW_AT_producer) {
+        Some(AttributeValue::String(s)) => Some(&s[..]),
+        Some(AttributeValue::StringRef(id)) => Some(strings.get(*id)),
+        _ => None,
+    };
+    producer.is_some_and(|p| p.starts_with(b"dwarf-writer"))
+}
+
+/// Whether a previous run of dwarf-writer created or updated any of the units,
+/// i.e. a unit was produced by dwarf-writer or has entries marked as created
+/// by it. Units produced by a compiler may still contain marked entries.
+pub fn processed_before(dwarf: &Dwarf) -> bool {
+    (0..dwarf.units.count()).any(|idx| {
+        let unit = dwarf.units.get(dwarf.units.id(idx));
+        if produced_by_dwarf_writer(unit, &dwarf.strings) {
+            return true
+        }
+        let mut to_visit = vec![unit.root()];
+        while let Some(id) = to_visit.pop() {
+            let entry = unit.get(id);
+            if entry.get(DW_AT_dwarf_writer).is_some() {
+                return true
+            }
+            to_visit.extend(entry.children());
+        }
+        false
+    })
+}
+
+/// Prefixes an entry's name with the names of its enclosing namespaces.
+fn qualified_name(unit: &Unit, strings: &StringTable, id: UnitEntryId, name: &[u8]) -> Vec<u8> {
+    let mut res = name.to_vec();
+    let mut parent = unit.get(id).parent();
+    while let Some(parent_id) = parent {
+        let entry = unit.get(parent_id);
+        if entry.tag() != DW_TAG_namespace {
+            break
+        }
+        if let Some(ns) = entry.get(DW_AT_name) {
+            let mut prefixed = name_as_bytes(ns, strings).to_vec();
+            prefixed.extend_from_slice(b"::");
+            prefixed.append(&mut res);
+            res = prefixed;
+        }
+        parent = entry.parent();
+    }
+    res
+}
+
+/// Writes the functions and types in the binary's units (or only the unit
+/// selected with `--cu`) one per line with tab-separated fields. Functions are
+/// written as `function <low_pc> <high_pc> <name>` and types as `type <type>`,
+/// using `-` for missing fields. Lines are sorted so listings of different
+/// binaries can be diffed.
+pub fn write_listing<W: io::Write>(elf: &mut ELF, cfg: &Opt, mut out: W) -> io::Result<()> {
+    for line in listing(elf, cfg) {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Returns the lines written by `write_listing`.
+pub fn listing(elf: &mut ELF, cfg: &Opt) -> Vec<String> {
+    let ids: Vec<_> = match cfg.cu {
+        Some(_) => vec![DwarfUnitRef::new(elf, cfg).id],
+        None => (0..elf.dwarf.units.count())
+            .map(|idx| elf.dwarf.units.id(idx))
+            .collect(),
+    };
+    let mut functions = Vec::new();
+    let mut types = Vec::new();
+    for id in ids {
+        let dwarf = DwarfUnitRef {
+            elf,
+            cfg,
+            id,
+            home: id,
+            input_types: Vec::new(),
+        };
+        functions.append(&mut dwarf.function_listing());
+        types.extend(dwarf.create_type_map().keys().map(|ty| format!("{:?}", ty)));
+    }
+    functions.sort();
+    functions.dedup();
+    types.sort();
+    types.dedup();
+
+    let hex = |addr: Option<u64>| addr.map_or("-".to_string(), |addr| format!("{:#x}", addr));
+    let functions = functions.into_iter().map(|(low_pc, high_pc, name)| {
+        let name = name.as_deref().unwrap_or("-");
+        format!("function\t{}\t{}\t{}", hex(low_pc), hex(high_pc), name)
+    });
+    let types = types.into_iter().map(|ty| format!("type\t{}", ty));
+    functions.chain(types).collect()
+}
+
+/// Checks that the `listing` of the debug info before it was written is read
+/// back unchanged from the binary written to `path`. This catches debug info
+/// which objcopy or the section layout silently broke.
+pub fn verify_output(expected: &[String], cfg: &Opt, path: &Path) -> Result<()> {
+    let actual = listing(&mut ELF::new(path)?, cfg);
+    let difference = |a: &[String], b: &[String]| {
+        let b: HashSet<_> = b.iter().collect();
+        a.iter()
+            .filter(|line| !b.contains(line))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let missing = difference(expected, &actual);
+    let unexpected = difference(&actual, expected);
+    if missing.is_empty() && unexpected.is_empty() {
+        Ok(())
+    } else {
+        Err(DwarfWriterError::VerifyOutput {
+            path: path.to_path_buf(),
+            missing,
+            unexpected,
+        })
+    }
+}
+
 impl<'a> DwarfUnitRef<'a> {
-    /// Creates a DWARF unit if none exists in the `ELF`.
-    pub fn new(elf: &'a mut ELF) -> Self {
-        let num_units = elf.dwarf.units.count();
-        if num_units == 0 {
-            let is_64_bit = elf.object().is_64();
-            let format = if is_64_bit {
-                Format::Dwarf64
-            } else {
-                Format::Dwarf32
-            };
-            let encoding = Encoding {
-                address_size: format.word_size(),
-                format,
-                version: 4,
-            };
-            let line_program = LineProgram::none();
-            let unit = Unit::new(encoding, line_program);
-            elf.dwarf.units.add(unit);
+    /// Gets the unit selected with `--cu` or the first unit otherwise. The
+    /// unit is created if it doesn't exist in the `ELF`.
+    pub fn new(elf: &'a mut ELF, cfg: &'a Opt) -> Self {
+        let existing = match &cfg.cu {
+            Some(name) => find_unit(&elf.dwarf, name),
+            None if elf.dwarf.units.count() > 0 => Some(elf.dwarf.units.id(0)),
+            None => None,
+        };
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                if let Some(name) = &cfg.cu {
+                    info!("Creating a new unit for {}", name);
+                }
+                create_unit(elf, cfg, cfg.cu.as_deref())
+            },
+        };
+        if let Some(comp_dir) = &cfg.comp_dir {
+            let unit = elf.dwarf.units.get_mut(id);
+            let root = unit.root();
+            unit.get_mut(root).set(
+                DW_AT_comp_dir,
+                AttributeValue::String(comp_dir.to_string_lossy().as_bytes().to_vec()),
+            );
+        }
+        DwarfUnitRef {
+            elf,
+            cfg,
+            id,
+            home: id,
+            input_types: Vec::new(),
+        }
+    }
+
+    /// Whether new entries for functions at an address belong in this unit.
+    /// This only restricts units selected with `--cu` which existed in the
+    /// input binary and have address ranges.
+    fn in_unit(&self, addr: u64) -> bool {
+        if self.cfg.cu.is_none() {
+            return true
+        }
+        let units = &self.elf.dwarf.units;
+        let ranges = (0..units.count())
+            .find(|&idx| units.id(idx) == self.home)
+            .and_then(|idx| self.elf.unit_ranges.get(idx));
+        match ranges {
+            Some(ranges) if !ranges.is_empty() => ranges.iter().any(|r| r.contains(&addr)),
+            _ => true,
         }
-        let id = elf.dwarf.units.id(0);
-        DwarfUnitRef { elf, id }
     }
 
-    fn new_entry(&mut self, parent: UnitEntryId, tag: DwTag) -> EntryRef {
-        let id = self.add(parent, tag);
+    /// Whether an entry is created for an input function at `addr` which
+    /// didn't match an existing subprogram. Functions outside of the unit
+    /// selected with `--cu` aren't created and no functions are created with
+    /// `--update-only-existing`.
+    fn creates_function(&self, addr: u64) -> bool {
+        !self.cfg.update_only_existing && self.in_unit(addr)
+    }
+
+    /// Moves on to another unit for new entries once this unit has as many
+    /// entries as `--max-dies-per-cu` allows. The last unit created by
+    /// dwarf-writer is used if it has room and a new unit is created
+    /// otherwise. Units may exceed the limit by an input's types or a
+    /// function's entries.
+    fn ensure_room(&mut self, type_map: &mut TypeMap) {
+        let max = match self.cfg.max_dies_per_cu {
+            Some(max) if self.count() >= max => max,
+            _ => return,
+        };
+        let dwarf = &self.elf.dwarf;
+        let last = dwarf.units.id(dwarf.units.count() - 1);
+        let unit = dwarf.units.get(last);
+        let id = if unit.count() < max && produced_by_dwarf_writer(unit, &dwarf.strings) {
+            last
+        } else {
+            create_unit(self.elf, self.cfg, None)
+        };
+        // The unit moved on to is always the last one
+        info!(
+            "Adding new entries to unit {} since the current unit has {} entries",
+            self.elf.dwarf.units.count() - 1,
+            self.count()
+        );
+        self.switch_unit(id, type_map);
+    }
+
+    /// Switches to another unit. Entries can only refer to types in their own
+    /// unit, so the type map is replaced with the other unit's types and the
+    /// input's types are added to it.
+    fn switch_unit(&mut self, id: UnitId, type_map: &mut TypeMap) {
+        if id == self.id {
+            return
+        }
+        self.id = id;
+        if let Some(comp_dir) = &self.cfg.comp_dir {
+            let root = self.root();
+            self.get_mut(root).set(
+                DW_AT_comp_dir,
+                AttributeValue::String(comp_dir.to_string_lossy().as_bytes().to_vec()),
+            );
+        }
+        type_map.replace_entries(self.create_type_map());
+        self.add_input_types(type_map);
+    }
+
+    fn new_entry(&mut self, parent: UnitEntryId, tag: DwTag) -> EntryRef<'_> {
+        let id = add_marked_entry(self, parent, tag);
         self.entry_ref(id)
     }
 
-    fn entry_ref(&mut self, id: UnitEntryId) -> EntryRef {
-        EntryRef::new(self.elf, id)
+    fn entry_ref(&mut self, id: UnitEntryId) -> EntryRef<'_> {
+        EntryRef::new(self.elf, self.cfg, self.id, id)
+    }
+
+    /// The units searched for existing entries matching the inputs. These are
+    /// the unit selected in `new` and every unit produced by dwarf-writer, so
+    /// entries split across units with `--max-dies-per-cu` are updated rather
+    /// than duplicated by later inputs and runs.
+    fn searched_units(&self) -> Vec<UnitId> {
+        let dwarf = &self.elf.dwarf;
+        (0..dwarf.units.count())
+            .map(|idx| dwarf.units.id(idx))
+            .filter(|&id| {
+                id == self.home || produced_by_dwarf_writer(dwarf.units.get(id), &dwarf.strings)
+            })
+            .collect()
+    }
+
+    /// Calls `f` on existing entries in the searched units, switching to each
+    /// entry's unit first. The unit selected in `new` is used again afterwards.
+    fn for_each_matched<F: FnMut(EntryRef<'_>, &mut TypeMap)>(
+        &mut self, entries: Vec<(UnitId, UnitEntryId)>, type_map: &mut TypeMap, mut f: F,
+    ) {
+        for (unit, id) in entries {
+            self.switch_unit(unit, type_map);
+            f(EntryRef::new(self.elf, self.cfg, unit, id), type_map);
+        }
+        let home = self.home;
+        self.switch_unit(home, type_map);
     }
 
     fn strings(&self) -> &StringTable {
         &self.elf.dwarf.strings
     }
 
+    /// Gets the entry for the namespaces enclosing a qualified name.
+    fn namespace(&mut self, path: &[&[u8]]) -> UnitEntryId {
+        let dwarf = &mut self.elf.dwarf;
+        namespace_entry(dwarf.units.get_mut(self.id), &dwarf.strings, path)
+    }
+
+    /// The target of the binary being updated, unless overridden with
+    /// `--target`.
+    fn target(&self) -> Target {
+        self.cfg.target.unwrap_or_else(|| self.elf.target())
+    }
+
+    /// Gets the parent entry for a new function with the given name.
+    fn fn_parent(&mut self, name: Option<&str>) -> UnitEntryId {
+        let name = name.unwrap_or_default();
+        let demangled = if self.cfg.demangle {
+            demangle(name)
+        } else {
+            None
+        };
+        let name = demangled.as_deref().unwrap_or(name);
+        self.namespace(&split_qualified_name(name.as_bytes()).0)
+    }
+
     /// Creates a type map from existing DWARF debug info. Returns an empty map
     /// if no debug info exists.
     pub fn create_type_map(&self) -> TypeMap {
         /// Searches the type map for the pointee of a type entry referencing
         /// another type. Returns `None` if the pointee has not been inserted
-        /// into the type map.
+        /// into the type map. Entries inserted under several keys (e.g. arrays
+        /// with and without their implied stride) always give the smallest
+        /// one so aggregates containing them get the same key every time.
         fn get_type_pointee(
             entry: &DebuggingInformationEntry, type_map: &mut TypeMap,
         ) -> Option<DwarfType> {
             if let Some(pointee_type) = entry.get(DW_AT_type) {
                 let pointee_id = attr_to_entry_id(pointee_type);
-                let pointee =
-                    type_map
-                        .iter()
-                        .find_map(|(k, &v)| if v == pointee_id { Some(k) } else { None });
+                let pointee = type_map
+                    .iter()
+                    .filter(|&(_, &v)| v == pointee_id)
+                    .map(|(k, _)| k)
+                    .min();
                 match pointee {
                     Some(pointee) => {
                         trace!("Found pointee type {:?} in type map", pointee_type);
@@ -104,13 +464,15 @@ impl<'a> DwarfUnitRef<'a> {
         }
 
         trace!("Creating a type map");
-        let mut type_map = HashMap::new();
+        let mut type_map = TypeMap::for_unit(self.id);
         let root = self.root();
+        let ptr_size = self.target().ptr_size;
+        let lower_bound = default_lower_bound(unit_language(self));
 
         let mut children: Vec<_> = self.get(root).children().cloned().collect();
         while !children.is_empty() {
-            let current_iter: Vec<_> = children.drain(..).collect();
-            for child in current_iter {
+            let current_iter: Vec<_> = std::mem::take(&mut children);
+            for &child in &current_iter {
                 let entry = self.get(child);
 
                 match entry.tag() {
@@ -120,7 +482,12 @@ impl<'a> DwarfUnitRef<'a> {
                             let name = CanonicalTypeName::from(
                                 name_as_bytes(name, self.strings()).to_vec(),
                             );
-                            let size = entry.get(DW_AT_byte_size).map(|s| attr_to_u64(s));
+                            // Inputs don't know the target's pointer width so
+                            // pointer-width types are keyed without a size
+                            let size = match entry.get(DW_AT_byte_size).map(attr_to_u64) {
+                                Some(size) if name.is_pointer_width() && size == ptr_size => None,
+                                size => size,
+                            };
 
                             trace!(
                                 "Inserting base type named {:?} of size {:?} into type map",
@@ -132,100 +499,477 @@ impl<'a> DwarfUnitRef<'a> {
                     },
                     constants::DW_TAG_pointer_type => {
                         trace!("Found a pointer type entry");
-                        match get_type_pointee(entry, &mut type_map) {
+                        // Pointers without a DW_AT_type point to void
+                        let pointee = match entry.get(DW_AT_type) {
+                            Some(_) => get_type_pointee(entry, &mut type_map),
+                            None => Some(DwarfType::void()),
+                        };
+                        let size = entry.get(DW_AT_byte_size).map(attr_to_u64);
+                        match pointee {
                             Some(pointee) => {
-                                type_map.insert(DwarfType::new_pointer(pointee), child);
+                                let ty = match size {
+                                    Some(size) if size != ptr_size => {
+                                        DwarfType::new_sized_pointer(pointee, size)
+                                    },
+                                    _ => DwarfType::new_pointer(pointee),
+                                };
+                                type_map.insert(ty, child);
                             },
                             None => children.push(child),
                         };
                     },
+                    constants::DW_TAG_restrict_type | constants::DW_TAG_atomic_type => {
+                        trace!("Found a qualified type entry");
+                        match get_type_pointee(entry, &mut type_map) {
+                            Some(inner) if entry.tag() == DW_TAG_restrict_type => {
+                                type_map.insert(DwarfType::new_restrict(inner), child);
+                            },
+                            Some(inner) => {
+                                type_map.insert(DwarfType::new_atomic(inner), child);
+                            },
+                            None => children.push(child),
+                        };
+                    },
+                    constants::DW_TAG_namespace => {
+                        children.extend(entry.children());
+                    },
                     constants::DW_TAG_typedef => {
                         trace!("Found a typedef entry");
                         let name = entry
                             .get(DW_AT_name)
                             .expect("Typedef entry should have a name");
+                        let name = qualified_name(
+                            self,
+                            self.strings(),
+                            child,
+                            name_as_bytes(name, self.strings()),
+                        );
                         match get_type_pointee(entry, &mut type_map) {
                             Some(ref_type) => {
-                                type_map.insert(
-                                    DwarfType::new_typedef(
-                                        name_as_bytes(name, self.strings()).to_vec().into(),
-                                        ref_type,
-                                    ),
-                                    child,
-                                );
+                                type_map
+                                    .insert(DwarfType::new_typedef(name.into(), ref_type), child);
                             },
                             None => children.push(child),
                         }
                     },
                     constants::DW_TAG_array_type => {
                         trace!("Found an array type entry");
+                        // Compilers give either the count or the inclusive
+                        // upper bound
                         let len = entry.children().find_map(|&id| {
                             let child = self.get(id);
-                            if child.tag() == DW_TAG_subrange_type {
-                                child.get(DW_AT_upper_bound).map(attr_to_u64)
-                            } else {
-                                None
+                            if child.tag() != DW_TAG_subrange_type {
+                                return None
+                            }
+                            match child.get(DW_AT_count) {
+                                Some(AttributeValue::UnitRef(_)) => None,
+                                Some(count) => Some(attr_to_u64(count)),
+                                None => {
+                                    let lower = child
+                                        .get(DW_AT_lower_bound)
+                                        .map_or(lower_bound, attr_to_u64);
+                                    child.get(DW_AT_upper_bound).map(|bound| {
+                                        attr_to_u64(bound).wrapping_add(1).wrapping_sub(lower)
+                                    })
+                                },
                             }
                         });
+                        let stride = entry.get(DW_AT_byte_stride).map(attr_to_u64);
+                        let is_vector = entry.get(DW_AT_GNU_vector).is_some();
                         match get_type_pointee(entry, &mut type_map) {
-                            Some(pointee) => {
-                                type_map.insert(DwarfType::new_array(pointee, len), child);
+                            Some(pointee) if is_vector => {
+                                type_map.insert(DwarfType::new_vector(pointee, len), child);
+                            },
+                            Some(pointee) => match stride.or_else(|| pointee.byte_size()) {
+                                Some(stride) => {
+                                    // Strides matching the element size aren't
+                                    // written so these arrays match either key
+                                    if entry.get(DW_AT_byte_stride).is_none() {
+                                        type_map.insert(
+                                            DwarfType::new_array(pointee.clone(), len),
+                                            child,
+                                        );
+                                    }
+                                    type_map.insert(
+                                        DwarfType::new_strided_array(pointee, len, stride),
+                                        child,
+                                    );
+                                },
+                                None => {
+                                    type_map.insert(DwarfType::new_array(pointee, len), child);
+                                },
                             },
                             None => children.push(child),
                         }
                     },
-                    constants::DW_TAG_structure_type => {},
+                    constants::DW_TAG_structure_type |
+                    constants::DW_TAG_class_type |
+                    constants::DW_TAG_union_type => {
+                        trace!("Found a structure type entry");
+                        let name = entry.get(DW_AT_name).map(|name| {
+                            let name = name_as_bytes(name, self.strings());
+                            CanonicalTypeName::from(qualified_name(
+                                self,
+                                self.strings(),
+                                child,
+                                name,
+                            ))
+                        });
+                        let size = entry.get(DW_AT_byte_size).map(attr_to_u64);
+                        let member = |member: &DebuggingInformationEntry,
+                                      type_map: &mut TypeMap| {
+                            let ty = get_type_pointee(member, type_map)?;
+                            let name = member.get(DW_AT_name).map(|name| {
+                                CanonicalTypeName::from(
+                                    name_as_bytes(name, self.strings()).to_vec(),
+                                )
+                            });
+                            let offset = member.get(DW_AT_data_member_location).map(attr_to_u64);
+                            let accessibility = match member.get(DW_AT_accessibility) {
+                                Some(AttributeValue::Accessibility(access)) => {
+                                    Accessibility::from_dwarf(*access)
+                                },
+                                _ => None,
+                            };
+                            Some(Member {
+                                accessibility,
+                                is_base: member.tag() == DW_TAG_inheritance,
+                                ..Member::new(name, ty, offset)
+                            })
+                        };
+                        let members = |parent: &DebuggingInformationEntry,
+                                       type_map: &mut TypeMap|
+                         -> Option<Vec<_>> {
+                            parent
+                                .children()
+                                .map(|&id| self.get(id))
+                                .filter(|child| {
+                                    matches!(
+                                        child.tag(),
+                                        constants::DW_TAG_member | constants::DW_TAG_inheritance
+                                    )
+                                })
+                                .map(|child| member(child, type_map))
+                                .collect()
+                        };
+                        let variant_part =
+                            |part: &DebuggingInformationEntry, type_map: &mut TypeMap| {
+                                let discr = self.get(attr_to_entry_id(part.get(DW_AT_discr)?));
+                                let discriminant = member(discr, type_map)?;
+                                let variants = part
+                                    .children()
+                                    .map(|&id| self.get(id))
+                                    .filter(|child| child.tag() == DW_TAG_variant)
+                                    .map(|variant| {
+                                        Some(Variant {
+                                            discr_value: variant
+                                                .get(DW_AT_discr_value)
+                                                .map(attr_to_u64),
+                                            fields: members(variant, type_map)?,
+                                        })
+                                    })
+                                    .collect::<Option<_>>()?;
+                                Some(Box::new(VariantPart {
+                                    discriminant,
+                                    variants,
+                                }))
+                            };
+                        let fields = members(entry, &mut type_map);
+                        // Structs without a variant part have `Some(None)`
+                        let variant_part = match entry
+                            .children()
+                            .map(|&id| self.get(id))
+                            .find(|child| child.tag() == DW_TAG_variant_part)
+                        {
+                            Some(part) => variant_part(part, &mut type_map).map(Some),
+                            None => Some(None),
+                        };
+                        match (fields, variant_part) {
+                            (Some(fields), Some(part)) => {
+                                let mut ty = match entry.tag() {
+                                    constants::DW_TAG_class_type => {
+                                        DwarfType::new_class(name, size, fields)
+                                    },
+                                    constants::DW_TAG_union_type => {
+                                        DwarfType::new_union(name, size, fields)
+                                    },
+                                    _ => DwarfType::new_struct(name, size, fields),
+                                };
+                                if let DwarfType::Struct { variant_part, .. } = &mut ty {
+                                    *variant_part = part;
+                                }
+                                type_map.insert(ty, child);
+                            },
+                            _ => children.push(child),
+                        }
+                    },
                     constants::DW_TAG_subroutine_type => {
                         trace!("Found a subroutine type entry");
-                        match get_type_pointee(entry, &mut type_map) {
-                            Some(pointee) => {
-                                type_map
-                                    .insert(DwarfType::new_function(pointee, Vec::new()), child);
+                        // Subroutine types without a DW_AT_type return void
+                        let return_type = match entry.get(DW_AT_type) {
+                            Some(_) => get_type_pointee(entry, &mut type_map),
+                            None => Some(DwarfType::void()),
+                        };
+                        let args: Option<Vec<_>> = entry
+                            .children()
+                            .map(|&id| self.get(id))
+                            .filter(|param| param.tag() == DW_TAG_formal_parameter)
+                            .map(|param| get_type_pointee(param, &mut type_map))
+                            .collect();
+                        // Subroutine types without DW_AT_prototyped don't list their arguments
+                        let is_unprototyped = entry.get(DW_AT_prototyped).is_none();
+                        match (return_type, args) {
+                            (Some(return_type), Some(args)) => {
+                                let ty = if is_unprototyped && args.is_empty() {
+                                    DwarfType::new_unprototyped_function(return_type)
+                                } else {
+                                    DwarfType::new_function(return_type, args)
+                                };
+                                type_map.insert(ty, child);
                             },
-                            None => children.push(child),
+                            _ => children.push(child),
+                        }
+                    },
+                    constants::DW_TAG_enumeration_type => {
+                        trace!("Found an enumeration type entry");
+                        // Enums without a DW_AT_type don't give the type of
+                        // their values so they can't be keyed
+                        if entry.get(DW_AT_type).is_none() {
+                            continue
+                        }
+                        let name = entry.get(DW_AT_name).map(|name| {
+                            let name = name_as_bytes(name, self.strings());
+                            CanonicalTypeName::from(qualified_name(
+                                self,
+                                self.strings(),
+                                child,
+                                name,
+                            ))
+                        });
+                        let enumerators: Option<Vec<_>> = entry
+                            .children()
+                            .map(|&id| self.get(id))
+                            .filter(|child| child.tag() == DW_TAG_enumerator)
+                            .map(|enumerator| {
+                                let name =
+                                    name_as_bytes(enumerator.get(DW_AT_name)?, self.strings());
+                                let value = attr_to_i64(enumerator.get(DW_AT_const_value)?);
+                                Some(Enumerator::new(name.to_vec().into(), value))
+                            })
+                            .collect();
+                        match (get_type_pointee(entry, &mut type_map), enumerators) {
+                            (Some(underlying_type), Some(enumerators)) => {
+                                let ty = if entry.get(DW_AT_enum_class).is_some() {
+                                    DwarfType::new_scoped_enum(name, underlying_type, enumerators)
+                                } else {
+                                    DwarfType::new_enum(name, underlying_type, enumerators)
+                                };
+                                type_map.insert(ty, child);
+                            },
+                            (None, _) => children.push(child),
+                            _ => {},
                         }
                     },
                     _ => (),
                 }
             }
+            // Types referring to types which can't be recovered (e.g. `const`
+            // types) are never resolved
+            if children == current_iter {
+                trace!("Unable to recover {} types", children.len());
+                break
+            }
         }
 
         trace!("Created a type map from {} existing types", type_map.len());
         type_map
     }
 
+    /// Overwrites the attributes of an existing base type with the same
+    /// canonical name as `ty`. Returns `false` if there is no such base type.
+    fn overwrite_base_type(&mut self, ty: &DwarfType, type_map: &mut TypeMap) -> bool {
+        let (name, size, bit_size) = match ty {
+            DwarfType::Primitive {
+                name,
+                size,
+                bit_size,
+            } => (name, size, bit_size),
+            _ => return false,
+        };
+        let old_ty = type_map
+            .keys()
+            .find(|&k| matches!(k, DwarfType::Primitive { name: n, .. } if n == name))
+            .cloned();
+        let old_ty = match old_ty {
+            Some(old_ty) => old_ty,
+            None => return false,
+        };
+        info!(
+            "Overwriting existing type {:?} with input type {:?}",
+            old_ty, ty
+        );
+        let id = type_map[&old_ty];
+        if self.cfg.protects_existing() && self.get(id).get(DW_AT_dwarf_writer).is_none() {
+            return false
+        }
+        // Attributes the input type doesn't know are left unchanged
+        let size = size.or_else(|| name.size_for_target(self.target().ptr_size));
+        let entry = self.get_mut(id);
+        if let Some(size) = size {
+            entry.set(DW_AT_byte_size, AttributeValue::Udata(size));
+        }
+        if let Some(bit_size) = bit_size {
+            entry.set(DW_AT_bit_size, AttributeValue::Udata(*bit_size));
+        }
+        if let Some(encoding) = name.encoding() {
+            entry.set(DW_AT_encoding, AttributeValue::Encoding(encoding));
+        }
+
+        // Types referencing the old type must be rekeyed to refer to the new one
+        let entries: Vec<_> = type_map.drain().collect();
+        for (k, v) in entries {
+            type_map.insert(k.substitute(&old_ty, ty), v);
+        }
+        true
+    }
+
+    /// Adds entries for the types in a type library. Inputs which refer to the
+    /// library's structs by name use their definitions instead of declaring
+    /// opaque structs.
+    pub fn add_type_library(&mut self, types: Vec<DwarfType>, type_map: &mut TypeMap) {
+        for ty in &types {
+            type_map.define(ty);
+        }
+        self.update_types(types, type_map)
+    }
+
+    /// Returns the low_pc, high_pc and qualified name of each of the unit's
+    /// subprograms.
+    fn function_listing(&self) -> Vec<(Option<u64>, Option<u64>, Option<String>)> {
+        let mut functions = Vec::new();
+        let mut to_visit = vec![self.root()];
+        while let Some(id) = to_visit.pop() {
+            let entry = self.get(id);
+            to_visit.extend(entry.children());
+            if entry.tag() != DW_TAG_subprogram {
+                continue
+            }
+            let low_pc = entry.get(DW_AT_low_pc).and_then(low_pc_to_u64);
+            let high_pc = low_pc.and_then(|low_pc| high_pc(entry, low_pc));
+            let name = entry.get(DW_AT_name).map(|name| {
+                let name = name_as_bytes(name, self.strings());
+                String::from_utf8_lossy(&qualified_name(self, self.strings(), id, name))
+                    .into_owned()
+            });
+            functions.push((low_pc, high_pc, name));
+        }
+        functions
+    }
+
     /// Update the map from `DwarfType`s to DWARF entry IDs.
+    /// The type map is first replaced with this unit's types if it was created
+    /// for another unit, e.g. a unit split off by an earlier input.
     fn update_types(&mut self, types: Vec<DwarfType>, type_map: &mut TypeMap) {
+        if type_map.unit().is_some_and(|unit| unit != self.id) {
+            type_map.replace_entries(self.create_type_map());
+        }
+        if self.cfg.names_only {
+            return
+        }
+        self.input_types = types;
+        self.ensure_room(type_map);
+        self.add_input_types(type_map);
+    }
+
+    /// Creates entries for the input's types which aren't in the type map.
+    fn add_input_types(&mut self, type_map: &mut TypeMap) {
         trace!("Processing anvill types");
-        for ty in types {
-            if !type_map.contains_key(&ty) {
+        let types = std::mem::take(&mut self.input_types);
+        for ty in &types {
+            // Variable-length arrays are created in the function using them
+            if ty.is_variable_length() {
+                continue
+            }
+            if !type_map.contains_key(ty) {
+                if self.cfg.prefer_input_types && self.overwrite_base_type(ty, type_map) {
+                    continue
+                }
                 // TypeMap::new initializes the map with existing DWARF debug
                 // info. After that if a type isn't in the map we have to create
-                // a DWARF entry for it.
-                let mut ty_entry = self.new_entry(self.root(), ty.tag());
-                ty_entry.init_type(&ty, type_map);
+                // a DWARF entry for it. Structs only referred to by name are
+                // created with their seeded definitions.
+                let parent = self.namespace(&ty.namespaces());
+                let mut ty_entry = self.new_entry(parent, ty.tag());
+                ty_entry.init_type(&type_map.key(ty), type_map);
 
                 // Update the type map with the new type
                 trace!("Mapping type {:?} to entry {:?}", ty, ty_entry.id());
-                type_map.insert(ty, ty_entry.id());
+                type_map.insert(ty.clone(), ty_entry.id());
             }
         }
+        self.input_types = types;
     }
 
-    fn for_each_entry<F: FnMut(&mut Self, &UnitEntryId)>(&mut self, mut f: F) {
-        let root = self.root();
-        let mut children: Vec<_> = self.get(root).children().cloned().collect();
-
-        while !children.is_empty() {
-            let current_generation: Vec<_> = children.drain(..).collect();
-            for entry_id in current_generation {
-                let entry = self.get(entry_id);
-
-                let mut next_generation = entry.children().cloned().collect();
-                children.append(&mut next_generation);
-
-                f(self, &entry_id);
+    /// Moves existing subprograms to the address of an input function with
+    /// the same name when `--match-by-name` is used so they're updated instead
+    /// of being duplicated. Subprograms are only moved if neither address is
+    /// already matched and the name is unique in the unit.
+    fn match_by_name<'n, I: IntoIterator<Item = (u64, Option<&'n str>)>>(&mut self, inputs: I) {
+        if !self.cfg.match_by_name {
+            return
+        }
+        let existing = self.subprograms();
+        let mut by_name: HashMap<Vec<u8>, Vec<(u64, UnitId, UnitEntryId)>> = HashMap::new();
+        for (&addr, &(unit, id)) in &existing {
+            let entry = self.elf.dwarf.units.get(unit).get(id);
+            for attr in [DW_AT_name, DW_AT_linkage_name, DW_AT_MIPS_linkage_name] {
+                if let Some(name) = entry.get(attr) {
+                    let name = name_as_bytes(name, self.strings()).to_vec();
+                    by_name.entry(name).or_default().push((addr, unit, id));
+                }
+            }
+        }
+        let inputs: Vec<_> = inputs
+            .into_iter()
+            .filter_map(|(addr, name)| Some((addr, name?)))
+            .collect();
+        let input_addrs: HashSet<_> = inputs.iter().map(|&(addr, _)| addr).collect();
+        for (addr, name) in inputs {
+            if existing.contains_key(&addr) {
+                continue
+            }
+            let (old_addr, unit, id) = match by_name.get(name.as_bytes()).map(Vec::as_slice) {
+                Some(&[entry]) => entry,
+                Some([_, _, ..]) => {
+                    warn!(
+                        "Not moving function {} to {:#x} since several functions have its name",
+                        name, addr
+                    );
+                    continue
+                },
+                _ => continue,
+            };
+            let mut entry = EntryRef::new(self.elf, self.cfg, unit, id);
+            if input_addrs.contains(&old_addr) || entry.is_protected() {
+                continue
+            }
+            info!(
+                "Moving function {} from {:#x} to {:#x}",
+                name, old_addr, addr
+            );
+            entry.set(
+                DW_AT_low_pc,
+                AttributeValue::Address(Address::Constant(addr)),
+            );
+            // An absolute high_pc is shifted so the function keeps its size
+            if let Some(&AttributeValue::Address(Address::Constant(high_pc))) =
+                entry.get(DW_AT_high_pc)
+            {
+                let high_pc = Address::Constant(high_pc.wrapping_sub(old_addr).wrapping_add(addr));
+                entry.set(DW_AT_high_pc, AttributeValue::Address(high_pc));
             }
+            // Later inputs with the same name are matched by address
+            by_name.remove(name.as_bytes());
         }
     }
 
@@ -234,19 +978,25 @@ impl<'a> DwarfUnitRef<'a> {
         types.sort();
         types.dedup();
         self.update_types(types, type_map);
+        let names = ghidra_data
+            .fn_map
+            .iter()
+            .map(|(&addr, f)| (addr, Some(f.name)));
+        self.match_by_name(names.collect::<Vec<_>>());
 
-        self.for_each_entry(|dwarf, &entry_id| {
-            let entry = dwarf.get(entry_id);
-            if entry.tag() == constants::DW_TAG_subprogram {
-                let mut fn_entry = dwarf.entry_ref(entry_id);
-                fn_entry.update_ghidra_fn(&mut ghidra_data, type_map);
-            }
+        let addrs: Vec<_> = ghidra_data.fn_map.keys().cloned().collect();
+        self.for_each_function(addrs, type_map, |mut fn_entry, type_map| {
+            fn_entry.update_ghidra_fn(&mut ghidra_data, type_map);
         });
 
-        let root = self.root();
+        ghidra_data
+            .fn_map
+            .retain(|&addr, _| self.creates_function(addr));
         let remaining_fn_addrs: Vec<_> = ghidra_data.fn_map.keys().cloned().collect();
         for addr in remaining_fn_addrs {
-            let mut fn_entry = self.new_entry(root, DW_TAG_subprogram);
+            self.ensure_room(type_map);
+            let parent = self.fn_parent(Some(ghidra_data.fn_map[&addr].name));
+            let mut fn_entry = self.new_entry(parent, DW_TAG_subprogram);
             fn_entry.init_ghidra_fn(addr, &mut ghidra_data, type_map);
         }
     }
@@ -258,37 +1008,189 @@ impl<'a> DwarfUnitRef<'a> {
             types,
             mut var_map,
             mut fn_map,
+            common_blocks,
         } = anvill;
         self.update_types(types, type_map);
+        let callers: Vec<_> = fn_map
+            .iter()
+            .filter(|(_, f)| f.func.calls.is_some())
+            .map(|(&addr, f)| (addr, f.func))
+            .collect();
+        self.match_by_name(fn_map.iter().map(|(&addr, f)| (addr, f.name)));
 
-        self.for_each_entry(|dwarf, &entry_id| {
-            let entry = dwarf.get(entry_id);
-            match entry.tag() {
-                constants::DW_TAG_variable => {
-                    let mut var_entry = dwarf.entry_ref(entry_id);
-                    var_entry.update_var(&mut var_map, type_map);
-                },
-                constants::DW_TAG_subprogram => {
-                    let mut fn_entry = dwarf.entry_ref(entry_id);
-                    fn_entry.update_anvill_fn(&mut fn_map, type_map);
-                },
-                _ => (),
-            }
+        let vars = self
+            .searched_entries(DW_TAG_variable)
+            .into_iter()
+            .filter(|&(unit, id)| {
+                let var_entry = EntryRef::new(self.elf, self.cfg, unit, id);
+                var_entry.var_addr(&var_map).is_some()
+            })
+            .collect();
+        self.for_each_matched(vars, type_map, |mut var_entry, type_map| {
+            var_entry.update_var(&mut var_map, type_map);
+        });
+        let addrs: Vec<_> = fn_map.keys().cloned().collect();
+        self.for_each_function(addrs, type_map, |mut fn_entry, type_map| {
+            fn_entry.update_anvill_fn(&mut fn_map, type_map);
         });
 
-        let root = self.root();
+        fn_map.retain(|&addr, _| self.creates_function(addr));
         let remaining_fn_addrs: Vec<_> = fn_map.keys().cloned().collect();
         for addr in remaining_fn_addrs {
-            let mut fn_entry = self.new_entry(root, DW_TAG_subprogram);
+            self.ensure_room(type_map);
+            let parent = self.fn_parent(fn_map[&addr].name);
+            let mut fn_entry = self.new_entry(parent, DW_TAG_subprogram);
             fn_entry.init_anvill_fn(addr, &mut fn_map, type_map);
         }
 
+        if self.cfg.update_only_existing {
+            var_map.clear();
+        }
         let remaining_var_addrs: Vec<_> = var_map.keys().cloned().collect();
         for addr in remaining_var_addrs {
+            self.ensure_room(type_map);
+            let root = self.root();
             let mut var_entry = self.new_entry(root, DW_TAG_variable);
             var_entry.init_var(addr, &mut var_map, type_map);
         }
+        for (addr, block) in common_blocks {
+            let root = self.root();
+            // Common blocks added by an earlier run are replaced
+            let existing_id = self.get(root).children().cloned().find(|&id| {
+                let entry = self.get(id);
+                entry.tag() == DW_TAG_common_block &&
+                    entry
+                        .get(DW_AT_name)
+                        .map(|name| name_as_bytes(name, self.strings())) ==
+                        Some(block.name.as_bytes())
+            });
+            let mut block_entry = match existing_id {
+                Some(id) => self.entry_ref(id),
+                None if self.cfg.update_only_existing => continue,
+                None => {
+                    self.ensure_room(type_map);
+                    let root = self.root();
+                    self.new_entry(root, DW_TAG_common_block)
+                },
+            };
+            block_entry.update_common_block(addr, block, type_map);
+        }
         assert!(fn_map.is_empty());
+
+        // Call sites are added last since they may refer to any function
+        if self.cfg.emit_call_sites && !callers.is_empty() {
+            let subprograms = self.subprograms();
+            for (addr, func) in callers {
+                if let Some(&(unit, id)) = subprograms.get(&addr) {
+                    EntryRef::new(self.elf, self.cfg, unit, id).add_call_sites(func, &subprograms);
+                }
+            }
+        }
+    }
+
+    /// Warns about functions whose start address isn't in an executable
+    /// section or isn't aligned to the architecture's instructions. This
+    /// usually means the inputs' addresses don't match the binary's base
+    /// address. Returns the addresses of these functions.
+    pub fn check_alignment(&mut self) -> Vec<u64> {
+        let alignment = instruction_alignment(self.target().arch);
+        let obj = self.elf.object();
+        let text_ranges: Vec<_> = obj
+            .sections()
+            .filter(|section| section.kind() == SectionKind::Text)
+            .map(|section| section.address()..section.address() + section.size())
+            .collect();
+
+        let mut addrs: Vec<_> = self.subprograms().into_keys().collect();
+        addrs.sort_unstable();
+        addrs.retain(|addr| {
+            if !text_ranges.iter().any(|range| range.contains(addr)) {
+                warn!("Function at {:#x} is not in an executable section", addr);
+            } else if addr % alignment != 0 {
+                warn!(
+                    "Function at {:#x} is not aligned to a {} byte instruction boundary",
+                    addr, alignment
+                );
+            } else {
+                return false
+            }
+            true
+        });
+        addrs
+    }
+
+    /// Returns the entries with the given tag in the searched units.
+    fn searched_entries(&self, tag: DwTag) -> Vec<(UnitId, UnitEntryId)> {
+        let mut entries = Vec::new();
+        for unit_id in self.searched_units() {
+            let unit = self.elf.dwarf.units.get(unit_id);
+            let mut to_visit = vec![unit.root()];
+            while let Some(id) = to_visit.pop() {
+                let entry = unit.get(id);
+                if entry.tag() == tag {
+                    entries.push((unit_id, id));
+                }
+                to_visit.extend(entry.children().rev());
+            }
+        }
+        entries
+    }
+
+    /// Maps the start addresses of functions to their subprogram entries in
+    /// the searched units. Overlapping subprograms are logged since inputs may
+    /// claim a function and an inlined helper at overlapping addresses.
+    /// Subprograms starting at the same address map to the innermost one with
+    /// the smallest range.
+    fn subprograms(&self) -> HashMap<u64, (UnitId, UnitEntryId)> {
+        let mut ranges = Vec::new();
+        for (unit, id) in self.searched_entries(DW_TAG_subprogram) {
+            let entry = self.elf.dwarf.units.get(unit).get(id);
+            if let Some(low_pc) = entry.get(DW_AT_low_pc).and_then(low_pc_to_u64) {
+                ranges.push((low_pc, high_pc(entry, low_pc), (unit, id)));
+            }
+        }
+        // Subprograms without a high_pc have an unknown size so they're
+        // outermost among the ones starting at the same address
+        ranges.sort_by_key(|&(low_pc, high_pc, _)| (low_pc, high_pc.unwrap_or(u64::MAX)));
+        for (idx, &(low_pc, high_pc, (_, id))) in ranges.iter().enumerate() {
+            let end = high_pc.unwrap_or(low_pc + 1);
+            for &(other_low_pc, _, (_, other_id)) in ranges[idx + 1..]
+                .iter()
+                .take_while(|&&(other_low_pc, ..)| other_low_pc < end)
+            {
+                warn!(
+                    "Subprogram {:?} at {:#x} overlaps subprogram {:?} at {:#x}",
+                    id, low_pc, other_id, other_low_pc
+                );
+            }
+        }
+        let mut res = HashMap::new();
+        for (low_pc, _, id) in ranges {
+            res.entry(low_pc).or_insert(id);
+        }
+        res
+    }
+
+    /// Calls `f` on the subprogram entries the input functions' start
+    /// addresses map to. Entries are visited one unit at a time.
+    fn for_each_function<I, F>(&mut self, addrs: I, type_map: &mut TypeMap, f: F)
+    where
+        I: IntoIterator<Item = u64>,
+        F: FnMut(EntryRef<'_>, &mut TypeMap), {
+        let subprograms = self.subprograms();
+        let order: HashMap<_, _> = self
+            .searched_units()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, id)| (id, idx))
+            .collect();
+        let mut functions: Vec<_> = addrs
+            .into_iter()
+            .filter_map(|addr| Some((addr, *subprograms.get(&addr)?)))
+            .collect();
+        functions.sort_unstable_by_key(|&(addr, (unit, _))| (order[&unit], addr));
+        let entries = functions.into_iter().map(|(_, entry)| entry).collect();
+        self.for_each_matched(entries, type_map, f);
     }
 
     /// Writes the STR BSI data as DWARF debug info and updates the type map
@@ -296,20 +1198,73 @@ impl<'a> DwarfUnitRef<'a> {
     pub fn process_str_bsi(&mut self, str_bsi: StrBsiData, type_map: &mut TypeMap) {
         let StrBsiData { types, mut fn_map } = str_bsi;
         self.update_types(types, type_map);
+        self.match_by_name(
+            fn_map
+                .iter()
+                .map(|(&addr, f)| (addr, f.symbol_name.as_deref())),
+        );
 
-        self.for_each_entry(|dwarf, &entry_id| {
-            let entry = dwarf.get(entry_id);
-            if let constants::DW_TAG_subprogram = entry.tag() {
-                let mut fn_entry = dwarf.entry_ref(entry_id);
-                fn_entry.update_str_fn(&mut fn_map, type_map);
-            };
+        let addrs: Vec<_> = fn_map.keys().cloned().collect();
+        self.for_each_function(addrs, type_map, |mut fn_entry, type_map| {
+            fn_entry.update_str_fn(&mut fn_map, type_map);
         });
 
-        let root = self.root();
+        fn_map.retain(|&addr, _| self.creates_function(addr));
         let remaining_fn_addrs: Vec<_> = fn_map.keys().cloned().collect();
         for addr in remaining_fn_addrs {
-            let mut fn_entry = self.new_entry(root, DW_TAG_subprogram);
+            self.ensure_room(type_map);
+            let parent = self.fn_parent(fn_map[&addr].symbol_name.as_deref());
+            let mut fn_entry = self.new_entry(parent, DW_TAG_subprogram);
             fn_entry.init_str_fn(addr, &mut fn_map, type_map);
         }
     }
+
+    /// Applies the function patches on top of the existing debug info and
+    /// updates the type map with new type entries.
+    pub fn process_patch(&mut self, patch: PatchData, type_map: &mut TypeMap) {
+        let PatchData { types, mut fn_map } = patch;
+        self.update_types(types, type_map);
+        self.match_by_name(fn_map.iter().map(|(&addr, f)| (addr, f.name.as_deref())));
+
+        let addrs: Vec<_> = fn_map.keys().cloned().collect();
+        self.for_each_function(addrs, type_map, |mut fn_entry, type_map| {
+            fn_entry.update_patch_fn(&mut fn_map, type_map);
+        });
+
+        fn_map.retain(|&addr, _| self.creates_function(addr));
+        let remaining_fn_addrs: Vec<_> = fn_map.keys().cloned().collect();
+        for addr in remaining_fn_addrs {
+            self.ensure_room(type_map);
+            let parent = self.fn_parent(fn_map[&addr].name.as_deref());
+            let mut fn_entry = self.new_entry(parent, DW_TAG_subprogram);
+            fn_entry.init_patch_fn(addr, &mut fn_map, type_map);
+        }
+    }
 }
+
+/// Returns the end address of a subprogram starting at `low_pc`. A constant
+/// high_pc is an offset from the low_pc.
+fn high_pc(entry: &DebuggingInformationEntry, low_pc: u64) -> Option<u64> {
+    match entry.get(DW_AT_high_pc)? {
+        addr @ AttributeValue::Address(_) => low_pc_to_u64(addr),
+        offset => low_pc_to_u64(offset).map(|len| low_pc + len),
+    }
+}
+
+/// The alignment of instructions on an architecture. 32-bit ARM allows 2 byte
+/// alignment since Thumb code can't be told apart from ARM code by address.
+fn instruction_alignment(arch: Architecture) -> u64 {
+    match arch {
+        Architecture::Aarch64 |
+        Architecture::Mips |
+        Architecture::Mips64 |
+        Architecture::PowerPc |
+        Architecture::PowerPc64 |
+        Architecture::Sparc64 => 4,
+        Architecture::Arm | Architecture::Riscv32 | Architecture::Riscv64 => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests;
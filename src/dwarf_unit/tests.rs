@@ -0,0 +1,3103 @@
+use super::*;
+use crate::anvill::AnvillInput;
+use crate::dwarf_attr::{addr_to_attr, cfa_frame_base, tls_offset_to_attr};
+use crate::elf::DataSymbol;
+use crate::ghidra::GhidraInput;
+use crate::patch::PatchInput;
+use crate::str_bsi::StrBsiInput;
+use crate::types::{Accessibility, Enumerator, Member, Variant, VariantPart};
+use crate::NameKind;
+use clap::Parser;
+use gimli::write::Expression;
+
+const TEST_BIN: &str = "tests/strip_bin/empty.c.elf";
+
+/// The options and binary shared by a test's `DwarfUnitRef`s.
+struct Fixture {
+    opt: Opt,
+    elf: ELF,
+}
+
+impl Fixture {
+    /// Loads the empty test binary with the given command line arguments.
+    fn new(args: &[&str]) -> Self {
+        Self::with_binary(TEST_BIN, args)
+    }
+
+    fn with_binary(bin: &str, args: &[&str]) -> Self {
+        Fixture {
+            opt: Opt::parse_from(["dwarf-writer", bin].iter().chain(args)),
+            elf: ELF::new(bin).unwrap(),
+        }
+    }
+
+    /// Writes the updated binary and reads it back like a later run would.
+    fn reload(&mut self) {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output.elf");
+        self.elf
+            .update_binary_in_process(Some(output.clone()), false)
+            .unwrap();
+        self.elf = ELF::new(&output).unwrap();
+    }
+
+    fn dwarf(&mut self) -> DwarfUnitRef<'_> {
+        DwarfUnitRef::new(&mut self.elf, &self.opt)
+    }
+
+    /// Returns the binary's unit and its type map along with the options
+    /// used to read the inputs.
+    fn unit(&mut self) -> (DwarfUnitRef<'_>, TypeMap, &Opt) {
+        let dwarf = DwarfUnitRef::new(&mut self.elf, &self.opt);
+        let type_map = dwarf.create_type_map();
+        (dwarf, type_map, &self.opt)
+    }
+}
+
+const GHIDRA_CSV: &str = r#""Name","Location","Function Signature","Function Size"
+"main","00001000","int main(int argc)","16"
+"#;
+
+const STR_JSON: &str = r#"{
+    "functions": {
+        "0x1000": {
+            "symbol_name": "main",
+            "calling_convention": null,
+            "return_registers": [],
+            "clobbered_registers": [],
+            "source_match": {
+                "confidence": 1,
+                "file": "main.c",
+                "line": 3,
+                "function": "main",
+                "return_value": {"type": "int"},
+                "parameters": null,
+                "local_variables": {"0": {"name": "x", "type": "int"}}
+            }
+        }
+    }
+}"#;
+
+const ANVILL_JSON: &str = r#"{
+    "arch": "amd64",
+    "os": "linux",
+    "functions": [{"address": 4096, "return_values": [{"register": "RAX", "type": "i"}]}],
+    "variables": [{"address": 16392, "type": "i"}],
+    "symbols": [[4096, "main"], [16392, "global"]]
+}"#;
+
+/// Runs dwarf-writer with the given arguments on each of the inputs and
+/// returns the resulting unit's entry count for each tag.
+fn run(
+    args: &[&str], ghidra: Option<&str>, anvill: Option<&str>, str_bsi: Option<&str>,
+) -> HashMap<DwTag, usize> {
+    let mut fixture = Fixture::new(args);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    if let Some(csv) = ghidra {
+        let input = GhidraInput::from_reader(csv.as_bytes()).unwrap();
+        dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+    }
+    if let Some(json) = anvill {
+        let input: AnvillInput = serde_json::from_str(json).unwrap();
+        dwarf.process_anvill(input.data(opt), &mut type_map);
+    }
+    if let Some(json) = str_bsi {
+        let input: StrBsiInput = serde_json::from_str(json).unwrap();
+        dwarf.process_str_bsi(input.data(opt), &mut type_map);
+    }
+    let mut counts = HashMap::new();
+    for tag in [DW_TAG_subprogram, DW_TAG_variable, DW_TAG_formal_parameter] {
+        counts.insert(tag, entries_with_tag(&dwarf, tag).len());
+    }
+    counts
+}
+
+/// Returns the IDs of all entries in the unit with the given tag.
+fn entries_with_tag(unit: &Unit, tag: DwTag) -> Vec<UnitEntryId> {
+    let mut res = Vec::new();
+    let mut to_visit = vec![unit.root()];
+    while let Some(id) = to_visit.pop() {
+        let entry = unit.get(id);
+        if entry.tag() == tag {
+            res.push(id);
+        }
+        to_visit.extend(entry.children());
+    }
+    res
+}
+
+/// Counts the entries with the given tag in all of the units.
+fn entries_with_tag_in_units(dwarf: &Dwarf, tag: DwTag) -> usize {
+    (0..dwarf.units.count())
+        .map(|idx| entries_with_tag(dwarf.units.get(dwarf.units.id(idx)), tag).len())
+        .sum()
+}
+
+/// Returns the number of entries in the unit.
+fn entry_count(unit: &Unit) -> usize {
+    let mut count = 0;
+    let mut to_visit = vec![unit.root()];
+    while let Some(id) = to_visit.pop() {
+        count += 1;
+        to_visit.extend(unit.get(id).children());
+    }
+    count
+}
+
+#[test]
+fn variable_location_list() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "variables": [{
+                "address": 16392,
+                "type": "i",
+                "locations": [
+                    {"begin": 4096, "end": 4104, "register": "RAX"},
+                    {"begin": 4104, "end": 4118, "memory": {"register": "RSP", "offset": 8}}
+                ]
+            }],
+            "symbols": [[16392, "moving"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+
+    let vars = entries_with_tag(&dwarf, DW_TAG_variable);
+    assert_eq!(vars.len(), 1);
+    let location = dwarf.get(vars[0]).get(DW_AT_location);
+    assert!(matches!(location, Some(AttributeValue::LocationListRef(_))));
+
+    let sections = fixture.elf.sections().unwrap();
+    assert!(!sections.debug_loc.slice().is_empty());
+}
+
+/// Writes the debug info and reads back the address ranges of the location
+/// lists of the first unit's variables.
+fn location_ranges(elf: &mut ELF) -> Vec<gimli::Range> {
+    let mut section_data = HashMap::new();
+    elf.sections()
+        .unwrap()
+        .for_each(|id, data| {
+            section_data.insert(id, data.slice().to_vec());
+            Ok::<_, anyhow::Error>(())
+        })
+        .unwrap();
+    let dwarf = gimli::read::Dwarf::load(|id| -> anyhow::Result<_> {
+        let data = section_data.get(&id).map(Vec::as_slice).unwrap_or(&[]);
+        Ok(gimli::EndianSlice::new(data, gimli::LittleEndian))
+    })
+    .unwrap();
+    let header = dwarf.units().next().unwrap().unwrap();
+    let unit = dwarf.unit(header).unwrap();
+    let mut entries = unit.entries();
+    let mut ranges = Vec::new();
+    while let Some((_, entry)) = entries.next_dfs().unwrap() {
+        let location = match entry.attr_value(DW_AT_location).unwrap() {
+            Some(location) if entry.tag() == DW_TAG_variable => location,
+            _ => continue,
+        };
+        if let Some(mut locations) = dwarf.attr_locations(&unit, location).unwrap() {
+            while let Some(location) = locations.next().unwrap() {
+                ranges.push(location.range);
+            }
+        }
+    }
+    ranges
+}
+
+#[test]
+fn location_list_base_address() {
+    // The compiler's unit in the DECL_BIN has a low_pc of 0x1020
+    for bin in [TEST_BIN, DECL_BIN] {
+        let mut fixture = Fixture::with_binary(bin, &[]);
+        let (mut dwarf, mut type_map, opt) = fixture.unit();
+        let input: AnvillInput = serde_json::from_str(
+            r#"{
+                "arch": "amd64",
+                "os": "linux",
+                "variables": [{
+                    "address": 16392,
+                    "type": "i",
+                    "locations": [{"begin": 4128, "end": 4132, "register": "RAX"}]
+                }],
+                "symbols": [[16392, "moving"]]
+            }"#,
+        )
+        .unwrap();
+        dwarf.process_anvill(input.data(opt), &mut type_map);
+
+        // A single range is still written as a location list
+        assert_eq!(
+            location_ranges(&mut fixture.elf),
+            vec![gimli::Range {
+                begin: 0x1020,
+                end: 0x1024
+            }],
+            "{}",
+            bin
+        );
+    }
+}
+
+#[test]
+fn const_variables() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "variables": [
+                {"address": 8196, "type": "i"},
+                {"address": 16392, "type": "i"},
+                {"address": 16400, "type": "l", "value": -1}
+            ],
+            "symbols": [[8196, "answer"], [16392, "counter"], [16400, "sentinel"]],
+            "memory": [
+                {"address": 8192, "is_writeable": false, "is_executable": false, "data": "010000002a000000"},
+                {"address": 16392, "is_writeable": true, "is_executable": false, "data": "07000000"}
+            ]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+
+    let mut values = HashMap::new();
+    for id in entries_with_tag(&dwarf, DW_TAG_variable) {
+        let var = dwarf.get(id);
+        assert!(var.get(DW_AT_location).is_some());
+        let name = name_as_bytes(var.get(DW_AT_name).unwrap(), dwarf.strings()).to_vec();
+        values.insert(name, var.get(DW_AT_const_value).cloned());
+    }
+    assert_eq!(
+        values[&b"answer"[..]],
+        Some(AttributeValue::Block(vec![0x2a, 0, 0, 0]))
+    );
+    assert_eq!(values[&b"counter"[..]], None);
+    assert_eq!(values[&b"sentinel"[..]], Some(AttributeValue::Sdata(-1)));
+}
+
+#[test]
+fn omit_functions() {
+    let ghidra = run(&["--omit-functions"], Some(GHIDRA_CSV), None, None);
+    assert_eq!(ghidra[&DW_TAG_subprogram], 0);
+    assert_eq!(ghidra[&DW_TAG_formal_parameter], 0);
+    let anvill = run(&["--omit-functions"], None, Some(ANVILL_JSON), None);
+    assert_eq!(anvill[&DW_TAG_subprogram], 0);
+    assert_eq!(anvill[&DW_TAG_variable], 1);
+    let str_bsi = run(&["--omit-functions"], None, None, Some(STR_JSON));
+    assert_eq!(str_bsi[&DW_TAG_subprogram], 0);
+
+    let ghidra = run(&[], Some(GHIDRA_CSV), None, None);
+    assert_eq!(ghidra[&DW_TAG_subprogram], 1);
+    assert_eq!(ghidra[&DW_TAG_formal_parameter], 1);
+}
+
+#[test]
+fn omit_variables() {
+    let anvill = run(&["--omit-variables"], None, Some(ANVILL_JSON), None);
+    assert_eq!(anvill[&DW_TAG_variable], 0);
+    assert_eq!(anvill[&DW_TAG_subprogram], 1);
+    let str_bsi = run(&["--omit-variables"], None, None, Some(STR_JSON));
+    assert_eq!(str_bsi[&DW_TAG_variable], 0);
+    assert_eq!(str_bsi[&DW_TAG_subprogram], 1);
+
+    let str_bsi = run(&[], None, None, Some(STR_JSON));
+    assert_eq!(str_bsi[&DW_TAG_variable], 1);
+
+    // Ghidra's variables are the stack variables of its functions
+    let mut fixture = Fixture::new(&["--omit-variables"]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let mut input = GhidraInput::from_reader(GHIDRA_CSV.as_bytes()).unwrap();
+    input
+        .add_stack_vars(
+            r#""Function","Name","Data Type","Offset"
+"00001000","count","int","-8"
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+    assert!(entries_with_tag(&dwarf, DW_TAG_variable).is_empty());
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_subprogram).len(), 1);
+}
+
+#[test]
+fn opaque_struct_size() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let mut input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"process","00001000","void process(Opaque * obj)","16"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    input
+        .add_struct_sizes("\"Name\",\"Length\"\n\"Opaque\",16\n".as_bytes())
+        .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    let structs = entries_with_tag(&dwarf, DW_TAG_structure_type);
+    assert_eq!(structs.len(), 1);
+    let entry = dwarf.get(structs[0]);
+    assert_eq!(entry.get(DW_AT_byte_size), Some(&AttributeValue::Udata(16)));
+    assert_eq!(
+        entry.get(DW_AT_declaration),
+        Some(&AttributeValue::Flag(true))
+    );
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_pointer_type).len(), 1);
+
+    // The opaque struct should be recovered from the existing DWARF
+    let type_map = dwarf.create_type_map();
+    let opaque = DwarfType::new_struct(Some(b"Opaque".to_vec().into()), Some(16), Vec::new());
+    assert_eq!(type_map.get(&opaque), Some(&structs[0]));
+}
+
+#[test]
+fn opaque_union_size() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let mut input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"process","00001000","void process(union Value * value)","16"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    input
+        .add_struct_sizes("\"Name\",\"Length\"\n\"Value\",8\n".as_bytes())
+        .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    assert!(entries_with_tag(&dwarf, DW_TAG_structure_type).is_empty());
+    let unions = entries_with_tag(&dwarf, DW_TAG_union_type);
+    assert_eq!(unions.len(), 1);
+    let entry = dwarf.get(unions[0]);
+    assert_eq!(entry.get(DW_AT_byte_size), Some(&AttributeValue::Udata(8)));
+    assert_eq!(
+        entry.get(DW_AT_declaration),
+        Some(&AttributeValue::Flag(true))
+    );
+
+    // The opaque union should be recovered from the existing DWARF
+    let type_map = dwarf.create_type_map();
+    let opaque = DwarfType::new_union(Some(b"Value".to_vec().into()), Some(8), Vec::new());
+    assert_eq!(type_map.get(&opaque), Some(&unions[0]));
+}
+
+#[test]
+fn class_member_accessibility() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+    let counter = DwarfType::new_class(
+        Some(b"Counter".to_vec().into()),
+        Some(8),
+        vec![
+            Member {
+                accessibility: Some(Accessibility::Private),
+                ..Member::new(Some(b"count".to_vec().into()), int.clone(), Some(0))
+            },
+            Member::new(Some(b"limit".to_vec().into()), int, Some(4)),
+        ],
+    );
+    dwarf.update_types(vec![counter], &mut type_map);
+
+    let classes = entries_with_tag(&dwarf, DW_TAG_class_type);
+    assert_eq!(classes.len(), 1);
+    let class = dwarf.get(classes[0]);
+    assert_eq!(class.get(DW_AT_byte_size), Some(&AttributeValue::Udata(8)));
+    assert!(class.get(DW_AT_declaration).is_none());
+    let members: Vec<_> = class.children().map(|&id| dwarf.get(id)).collect();
+    assert_eq!(members.len(), 2);
+    assert!(members.iter().all(|m| m.tag() == DW_TAG_member));
+    assert_eq!(
+        members[0].get(DW_AT_accessibility),
+        Some(&AttributeValue::Accessibility(DW_ACCESS_private))
+    );
+    assert_eq!(
+        members[1].get(DW_AT_data_member_location),
+        Some(&AttributeValue::Udata(4))
+    );
+    // Members without an accessibility keep the default
+    assert!(members[1].get(DW_AT_accessibility).is_none());
+    let int = dwarf.get(attr_to_entry_id(members[0].get(DW_AT_type).unwrap()));
+    assert_eq!(int.tag(), DW_TAG_base_type);
+    assert_eq!(int.get(DW_AT_byte_size), Some(&AttributeValue::Udata(4)));
+}
+
+#[test]
+fn class_inheritance() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+    let base = DwarfType::new_class(
+        Some(b"Base".to_vec().into()),
+        Some(4),
+        vec![Member::new(
+            Some(b"id".to_vec().into()),
+            int.clone(),
+            Some(0),
+        )],
+    );
+    let derived = DwarfType::new_class(
+        Some(b"Derived".to_vec().into()),
+        Some(8),
+        vec![
+            Member::new_base(base.clone(), Some(0)),
+            Member::new(Some(b"extra".to_vec().into()), int, Some(4)),
+        ],
+    );
+    dwarf.update_types(vec![derived.clone()], &mut type_map);
+
+    let classes = entries_with_tag(&dwarf, DW_TAG_class_type);
+    assert_eq!(classes.len(), 2);
+    let derived_id = type_map[&derived];
+    let children: Vec<_> = dwarf
+        .get(derived_id)
+        .children()
+        .map(|&id| dwarf.get(id))
+        .collect();
+    assert_eq!(children.len(), 2);
+    assert_eq!(children[0].tag(), DW_TAG_inheritance);
+    assert!(children[0].get(DW_AT_name).is_none());
+    assert_eq!(
+        children[0].get(DW_AT_type),
+        Some(&AttributeValue::UnitRef(type_map[&base]))
+    );
+    assert_eq!(
+        children[0].get(DW_AT_data_member_location),
+        Some(&AttributeValue::Udata(0))
+    );
+    assert_eq!(children[1].tag(), DW_TAG_member);
+
+    // Base classes are recovered from the existing DWARF
+    let type_map = dwarf.create_type_map();
+    assert_eq!(type_map.get(&derived), Some(&derived_id));
+}
+
+#[test]
+fn uint8_enum() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let uint8 = DwarfType::new_primitive(b"uint8_t".to_vec().into(), None);
+    let color = DwarfType::new_enum(
+        Some(b"Color".to_vec().into()),
+        uint8.clone(),
+        vec![
+            Enumerator::new(b"RED".to_vec().into(), 0),
+            Enumerator::new(b"GREEN".to_vec().into(), 1),
+            Enumerator::new(b"BLUE".to_vec().into(), 255),
+        ],
+    );
+    dwarf.update_types(vec![color.clone()], &mut type_map);
+
+    let color_id = type_map[&color];
+    let entry = dwarf.get(color_id);
+    assert_eq!(entry.tag(), DW_TAG_enumeration_type);
+    assert_eq!(entry.get(DW_AT_byte_size), Some(&AttributeValue::Udata(1)));
+    assert_eq!(
+        entry.get(DW_AT_type),
+        Some(&AttributeValue::UnitRef(type_map[&uint8]))
+    );
+    let values: Vec<_> = entry
+        .children()
+        .map(|&id| dwarf.get(id))
+        .map(|enumerator| {
+            assert_eq!(enumerator.tag(), DW_TAG_enumerator);
+            enumerator.get(DW_AT_const_value).cloned()
+        })
+        .collect();
+    assert_eq!(
+        values,
+        [0, 1, 255].map(|value| Some(AttributeValue::Udata(value)))
+    );
+
+    // Values which don't fit in the underlying type are still written
+    let enumerator = Enumerator::new(b"OVERFLOW".to_vec().into(), 256);
+    assert!(!enumerator.fits(1, false));
+    assert!(enumerator.fits(2, false));
+    assert!(!Enumerator::new(b"NEG".to_vec().into(), -1).fits(1, false));
+    assert!(!Enumerator::new(b"BIG".to_vec().into(), 128).fits(1, true));
+
+    // Enums with an underlying type are recovered from the existing DWARF
+    let type_map = dwarf.create_type_map();
+    assert_eq!(type_map.get(&color), Some(&color_id));
+}
+
+#[test]
+fn scoped_enum() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let uint32 = DwarfType::new_primitive(b"uint32_t".to_vec().into(), None);
+    // enum class Flags : uint32_t { Read = 1, Write = 2, Exec = 4 }
+    let flags = DwarfType::new_scoped_enum(
+        Some(b"Flags".to_vec().into()),
+        uint32.clone(),
+        vec![
+            Enumerator::new(b"Read".to_vec().into(), 1),
+            Enumerator::new(b"Write".to_vec().into(), 2),
+            Enumerator::new(b"Exec".to_vec().into(), 4),
+        ],
+    );
+    let plain = DwarfType::new_enum(
+        Some(b"Mode".to_vec().into()),
+        uint32,
+        vec![Enumerator::new(b"MODE_DEFAULT".to_vec().into(), 0)],
+    );
+    dwarf.update_types(vec![flags.clone(), plain.clone()], &mut type_map);
+
+    let entry = dwarf.get(type_map[&flags]);
+    assert_eq!(
+        entry.get(DW_AT_enum_class),
+        Some(&AttributeValue::Flag(true))
+    );
+    assert_eq!(entry.get(DW_AT_byte_size), Some(&AttributeValue::Udata(4)));
+    assert!(dwarf.get(type_map[&plain]).get(DW_AT_enum_class).is_none());
+
+    let type_map = dwarf.create_type_map();
+    assert!(type_map.get(&flags).is_some());
+    assert!(type_map.get(&plain).is_some());
+}
+
+#[test]
+fn negative_enumerators() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let int8 = DwarfType::new_primitive(b"int8_t".to_vec().into(), None);
+    let uint8 = DwarfType::new_primitive(b"uint8_t".to_vec().into(), None);
+    let enumerators = vec![
+        Enumerator::new(b"LESS".to_vec().into(), -1),
+        Enumerator::new(b"EQUAL".to_vec().into(), 0),
+        Enumerator::new(b"GREATER".to_vec().into(), 1),
+    ];
+    let ordering =
+        DwarfType::new_enum(Some(b"Ordering".to_vec().into()), int8, enumerators.clone());
+    // Negative values are signed even if the underlying type isn't
+    let misfit = DwarfType::new_enum(Some(b"Misfit".to_vec().into()), uint8, enumerators);
+    dwarf.update_types(vec![ordering.clone(), misfit.clone()], &mut type_map);
+
+    let values = |ty: &DwarfType| -> Vec<_> {
+        dwarf
+            .get(type_map[ty])
+            .children()
+            .map(|&id| dwarf.get(id).get(DW_AT_const_value).cloned().unwrap())
+            .collect()
+    };
+    assert_eq!(
+        values(&ordering),
+        [-1, 0, 1].map(AttributeValue::Sdata).to_vec()
+    );
+    assert_eq!(
+        values(&misfit),
+        vec![
+            AttributeValue::Sdata(-1),
+            AttributeValue::Udata(0),
+            AttributeValue::Udata(1)
+        ]
+    );
+
+    // The values are recovered with their sign
+    let type_map = dwarf.create_type_map();
+    assert!(type_map.get(&ordering).is_some());
+    assert!(type_map.get(&misfit).is_some());
+}
+
+#[test]
+fn anonymous_union_member() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+    let float = DwarfType::new_primitive(b"float".to_vec().into(), None);
+    // struct Value { int tag; union { int i; float f; }; }
+    let union = DwarfType::new_union(
+        None,
+        Some(4),
+        vec![
+            Member::new(Some(b"i".to_vec().into()), int.clone(), Some(0)),
+            Member::new(Some(b"f".to_vec().into()), float, Some(0)),
+        ],
+    );
+    let value = DwarfType::new_struct(
+        Some(b"Value".to_vec().into()),
+        Some(8),
+        vec![
+            Member::new(Some(b"tag".to_vec().into()), int, Some(0)),
+            Member::new(None, union.clone(), Some(4)),
+        ],
+    );
+    dwarf.update_types(vec![value.clone()], &mut type_map);
+
+    let structs = entries_with_tag(&dwarf, DW_TAG_structure_type);
+    assert_eq!(structs.len(), 1);
+    let members: Vec<_> = dwarf
+        .get(structs[0])
+        .children()
+        .map(|&id| dwarf.get(id))
+        .collect();
+    assert_eq!(members.len(), 2);
+    assert!(members[1].get(DW_AT_name).is_none());
+    assert_eq!(
+        members[1].get(DW_AT_data_member_location),
+        Some(&AttributeValue::Udata(4))
+    );
+    let union_entry = dwarf.get(attr_to_entry_id(members[1].get(DW_AT_type).unwrap()));
+    assert_eq!(union_entry.tag(), DW_TAG_union_type);
+    assert!(union_entry.get(DW_AT_name).is_none());
+    assert_eq!(union_entry.children().count(), 2);
+
+    // The anonymous members round-trip without a synthesized name
+    let type_map = dwarf.create_type_map();
+    assert!(type_map.contains_key(&value));
+    assert!(type_map.contains_key(&union));
+}
+
+#[test]
+fn tagged_union() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let u32_ty = DwarfType::new_primitive(b"uint32_t".to_vec().into(), None);
+    let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+    // enum Option { None, Some(i32) }
+    let option = DwarfType::new_tagged_union(
+        Some(b"Option".to_vec().into()),
+        Some(8),
+        VariantPart {
+            discriminant: Member::new(None, u32_ty, Some(0)),
+            variants: vec![
+                Variant {
+                    discr_value: Some(0),
+                    fields: Vec::new(),
+                },
+                Variant {
+                    discr_value: Some(1),
+                    fields: vec![Member::new(Some(b"Some".to_vec().into()), int, Some(4))],
+                },
+            ],
+        },
+    );
+    dwarf.update_types(vec![option.clone()], &mut type_map);
+
+    let structs = entries_with_tag(&dwarf, DW_TAG_structure_type);
+    assert_eq!(structs.len(), 1);
+    let entry = dwarf.get(structs[0]);
+    assert!(entry.get(DW_AT_declaration).is_none());
+    let parts: Vec<_> = entry.children().map(|&id| dwarf.get(id)).collect();
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].tag(), DW_TAG_variant_part);
+    let discr = dwarf.get(attr_to_entry_id(parts[0].get(DW_AT_discr).unwrap()));
+    assert_eq!(discr.tag(), DW_TAG_member);
+    assert_eq!(
+        discr.get(DW_AT_artificial),
+        Some(&AttributeValue::Flag(true))
+    );
+    let variants: Vec<_> = parts[0]
+        .children()
+        .map(|&id| dwarf.get(id))
+        .filter(|child| child.tag() == DW_TAG_variant)
+        .collect();
+    assert_eq!(variants.len(), 2);
+    assert_eq!(
+        variants[1].get(DW_AT_discr_value),
+        Some(&AttributeValue::Udata(1))
+    );
+    let some = dwarf.get(*variants[1].children().next().unwrap());
+    assert_eq!(
+        some.get(DW_AT_data_member_location),
+        Some(&AttributeValue::Udata(4))
+    );
+
+    // The tagged union is recovered from the updated binary
+    fixture.reload();
+    let type_map = fixture.dwarf().create_type_map();
+    assert!(type_map.contains_key(&option));
+}
+
+#[test]
+fn odd_width_base_type() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let json = STR_JSON.replace(
+        r#"{"name": "x", "type": "int"}"#,
+        r#"{"name": "x", "type": "uint24_t"}"#,
+    );
+    let input: StrBsiInput = serde_json::from_str(&json).unwrap();
+    dwarf.process_str_bsi(input.data(opt), &mut type_map);
+
+    let var = entries_with_tag(&dwarf, DW_TAG_variable)[0];
+    let ty = dwarf.get(attr_to_entry_id(dwarf.get(var).get(DW_AT_type).unwrap()));
+    assert_eq!(ty.tag(), DW_TAG_base_type);
+    assert_eq!(ty.get(DW_AT_byte_size), Some(&AttributeValue::Udata(3)));
+    assert_eq!(ty.get(DW_AT_bit_size), Some(&AttributeValue::Udata(24)));
+
+    let uint24 = DwarfType::new_primitive(b"uint24_t".to_vec().into(), None);
+    assert!(dwarf.create_type_map().contains_key(&uint24));
+}
+
+#[test]
+fn character_encodings() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let char_ty = DwarfType::new_primitive(b"char".to_vec().into(), None);
+    let char16 = DwarfType::new_primitive(b"char16_t".to_vec().into(), None);
+    dwarf.update_types(vec![char_ty.clone(), char16.clone()], &mut type_map);
+
+    let encoding = |ty: &DwarfType| dwarf.get(type_map[ty]).get(DW_AT_encoding).cloned();
+    assert_eq!(
+        encoding(&char_ty),
+        Some(AttributeValue::Encoding(DW_ATE_signed_char))
+    );
+    assert_eq!(
+        encoding(&char16),
+        Some(AttributeValue::Encoding(DW_ATE_UTF))
+    );
+}
+
+#[test]
+fn zero_size_ghidra_function() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let csv = GHIDRA_CSV.replace("\"16\"", "\"0\"");
+    let input = GhidraInput::from_reader(csv.as_bytes()).unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    let subprograms = entries_with_tag(&dwarf, DW_TAG_subprogram);
+    assert_eq!(subprograms.len(), 1);
+    let main = dwarf.get(subprograms[0]);
+    assert!(main.get(DW_AT_low_pc).is_some());
+    assert!(main.get(DW_AT_high_pc).is_none());
+}
+
+#[test]
+fn simd_vector() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "variables": [{"address": 16392, "type": "<fx4>"}]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+
+    let arrays = entries_with_tag(&dwarf, DW_TAG_array_type);
+    assert_eq!(arrays.len(), 1);
+    let vector = dwarf.get(arrays[0]);
+    assert_eq!(
+        vector.get(DW_AT_GNU_vector),
+        Some(&AttributeValue::Flag(true))
+    );
+    assert!(vector.get(DW_AT_byte_stride).is_none());
+
+    // Vectors are recovered as vectors rather than arrays
+    let float = DwarfType::new_primitive(b"float".to_vec().into(), None);
+    let type_map = dwarf.create_type_map();
+    assert!(type_map.contains_key(&DwarfType::new_vector(float.clone(), Some(4))));
+    assert!(!type_map.contains_key(&DwarfType::new_array(float, Some(4))));
+}
+
+#[test]
+fn type_library() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let library: Vec<DwarfType> = serde_json::from_str(
+        r#"[{"Struct": {"name": "point", "size": 8, "fields": [
+            {"name": "x", "ty": {"Primitive": {"name": "int", "size": 4}}, "offset": 0},
+            {"name": "y", "ty": {"Primitive": {"name": "int", "size": 4}}, "offset": 4}
+        ]}}]"#,
+    )
+    .unwrap();
+    dwarf.add_type_library(library, &mut type_map);
+    let input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"draw","00001000","void draw(struct point * p)","16"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    // The parameter points to the library's struct rather than a declaration
+    let structs = entries_with_tag(&dwarf, DW_TAG_structure_type);
+    assert_eq!(structs.len(), 1);
+    assert!(dwarf.get(structs[0]).get(DW_AT_declaration).is_none());
+    assert_eq!(dwarf.get(structs[0]).children().count(), 2);
+    let param = entries_with_tag(&dwarf, DW_TAG_formal_parameter)[0];
+    let ptr = dwarf.get(attr_to_entry_id(dwarf.get(param).get(DW_AT_type).unwrap()));
+    assert_eq!(ptr.tag(), DW_TAG_pointer_type);
+    assert_eq!(
+        ptr.get(DW_AT_type),
+        Some(&AttributeValue::UnitRef(structs[0]))
+    );
+}
+
+#[test]
+fn seed_types() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let seed: Vec<DwarfType> = serde_json::from_str(
+        r#"[{"Struct": {"name": "point", "size": 8, "fields": [
+            {"name": "x", "ty": {"Primitive": {"name": "int", "size": 4}}, "offset": 0},
+            {"name": "y", "ty": {"Primitive": {"name": "int", "size": 4}}, "offset": 4}
+        ]}}, {"Struct": {"name": "unused", "size": 4, "fields": [
+            {"name": "z", "ty": {"Primitive": {"name": "int", "size": 4}}, "offset": 0}
+        ]}}]"#,
+    )
+    .unwrap();
+    type_map.seed(&seed);
+    assert!(entries_with_tag(&dwarf, DW_TAG_structure_type).is_empty());
+
+    // Only the seeded structs used by the inputs are written
+    let input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"draw","00001000","void draw(struct point * p)","16"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+    let structs = entries_with_tag(&dwarf, DW_TAG_structure_type);
+    assert_eq!(structs.len(), 1);
+    assert_eq!(
+        name_as_bytes(
+            dwarf.get(structs[0]).get(DW_AT_name).unwrap(),
+            dwarf.strings()
+        ),
+        b"point"
+    );
+    assert_eq!(dwarf.get(structs[0]).children().count(), 2);
+}
+
+#[test]
+fn load_bias() {
+    let mut fixture = Fixture::new(&["--load-bias", "0x10000"]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let ghidra = GhidraInput::from_reader(GHIDRA_CSV.as_bytes()).unwrap();
+    dwarf.process_ghidra(ghidra.data(opt).unwrap(), &mut type_map);
+    let anvill: AnvillInput = serde_json::from_str(ANVILL_JSON).unwrap();
+    dwarf.process_anvill(anvill.data(opt), &mut type_map);
+
+    // The anvill function matches the one created from the Ghidra input
+    let subprograms = entries_with_tag(&dwarf, DW_TAG_subprogram);
+    assert_eq!(subprograms.len(), 1);
+    let main = dwarf.get(subprograms[0]);
+    assert_eq!(
+        main.get(DW_AT_low_pc).and_then(low_pc_to_u64),
+        Some(0x11000)
+    );
+    let vars = entries_with_tag(&dwarf, DW_TAG_variable);
+    assert_eq!(vars.len(), 1);
+    assert_eq!(
+        dwarf.get(vars[0]).get(DW_AT_location),
+        Some(&addr_to_attr(0x14008))
+    );
+
+    // Location list ranges are biased through their base address
+    let anvill: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "variables": [{
+                "address": 16392,
+                "type": "i",
+                "locations": [{"begin": 4096, "end": 4104, "register": "RAX"}]
+            }],
+            "symbols": [[16392, "moving"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(anvill.data(opt), &mut type_map);
+    let vars = entries_with_tag(&dwarf, DW_TAG_variable);
+    assert_eq!(vars.len(), 1);
+    assert_eq!(
+        location_ranges(&mut fixture.elf),
+        vec![gimli::Range {
+            begin: 0x11000,
+            end: 0x11008
+        }]
+    );
+}
+
+#[test]
+fn thread_local_variable() {
+    let json = r#"{
+        "arch": "amd64",
+        "os": "linux",
+        "variables": [{"address": 16, "type": "i", "is_thread_local": true}],
+        "symbols": [[16, "counter"]]
+    }"#;
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let anvill: AnvillInput = serde_json::from_str(json).unwrap();
+    dwarf.process_anvill(anvill.data(opt), &mut type_map);
+    let vars = entries_with_tag(&dwarf, DW_TAG_variable);
+    assert_eq!(vars.len(), 1);
+    // The binary has no TLS segment so the address is used as the offset
+    assert_eq!(
+        dwarf.get(vars[0]).get(DW_AT_location),
+        Some(&tls_offset_to_attr(16))
+    );
+
+    // The DW_OP_form_tls_address location is decoded when the binary is
+    // read back so later inputs update the same variable
+    fixture.reload();
+    assert_eq!(
+        fixture.elf.var_addrs.values().collect::<Vec<_>>(),
+        vec![&16]
+    );
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    dwarf.process_anvill(anvill.data(opt), &mut type_map);
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_variable).len(), 1);
+}
+
+#[test]
+fn common_block() {
+    let json = r#"{
+        "arch": "amd64",
+        "os": "linux",
+        "common_blocks": [{
+            "name": "coords",
+            "address": 16384,
+            "members": [
+                {"name": "x", "type": "d", "offset": 0},
+                {"name": "n", "type": "i", "offset": 8}
+            ]
+        }]
+    }"#;
+    let anvill: AnvillInput = serde_json::from_str(json).unwrap();
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    // Running the same input again keeps a single common block
+    for _ in 0..2 {
+        dwarf.process_anvill(anvill.data(opt), &mut type_map);
+    }
+
+    let blocks = entries_with_tag(&dwarf, DW_TAG_common_block);
+    assert_eq!(blocks.len(), 1);
+    let block = dwarf.get(blocks[0]);
+    assert_eq!(block.parent(), Some(dwarf.root()));
+    assert_eq!(
+        name_as_bytes(block.get(DW_AT_name).unwrap(), dwarf.strings()),
+        b"coords"
+    );
+    assert_eq!(block.get(DW_AT_location), Some(&addr_to_attr(0x4000)));
+
+    let members = entries_with_tag(&dwarf, DW_TAG_variable);
+    assert_eq!(members.len(), 2);
+    for id in members {
+        let member = dwarf.get(id);
+        assert_eq!(member.parent(), Some(blocks[0]));
+        let (addr, ty) = match name_as_bytes(member.get(DW_AT_name).unwrap(), dwarf.strings()) {
+            b"x" => (0x4000, b"double".as_slice()),
+            b"n" => (0x4008, b"int32_t".as_slice()),
+            name => panic!("Unexpected member {:?}", name),
+        };
+        assert_eq!(member.get(DW_AT_location), Some(&addr_to_attr(addr)));
+        let ty_id = attr_to_entry_id(member.get(DW_AT_type).unwrap());
+        assert_eq!(
+            name_as_bytes(dwarf.get(ty_id).get(DW_AT_name).unwrap(), dwarf.strings()),
+            ty
+        );
+    }
+}
+
+#[test]
+fn overlapping_functions() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    // The enclosing function is visited before the inlined helper
+    let root = dwarf.root();
+    let mut add_subprogram = |size| {
+        let id = dwarf.add(root, DW_TAG_subprogram);
+        let entry = dwarf.get_mut(id);
+        entry.set(
+            DW_AT_low_pc,
+            AttributeValue::Address(Address::Constant(0x1000)),
+        );
+        entry.set(DW_AT_high_pc, AttributeValue::Udata(size));
+        id
+    };
+    let outer = add_subprogram(0x40);
+    let inner = add_subprogram(0x10);
+    let input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"helper","00001000","int helper(void)","10"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_subprogram).len(), 2);
+    assert_eq!(dwarf.get(outer).get(DW_AT_name), None);
+    assert_eq!(
+        name_as_bytes(dwarf.get(inner).get(DW_AT_name).unwrap(), dwarf.strings()),
+        b"helper"
+    );
+    assert_eq!(dwarf.subprograms()[&0x1000].1, inner);
+}
+
+#[test]
+fn names_only() {
+    let mut fixture = Fixture::new(&["--names-only"]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let ghidra = GhidraInput::from_reader(GHIDRA_CSV.as_bytes()).unwrap();
+    dwarf.process_ghidra(ghidra.data(opt).unwrap(), &mut type_map);
+    let anvill: AnvillInput = serde_json::from_str(ANVILL_JSON).unwrap();
+    dwarf.process_anvill(anvill.data(opt), &mut type_map);
+    let str_bsi: StrBsiInput = serde_json::from_str(STR_JSON).unwrap();
+    dwarf.process_str_bsi(str_bsi.data(opt), &mut type_map);
+
+    // Only the function and variable are added without any type entries
+    let root = dwarf.root();
+    let mut tags: Vec<_> = dwarf
+        .get(root)
+        .children()
+        .map(|&id| dwarf.get(id).tag())
+        .collect();
+    tags.sort_by_key(|tag| tag.0);
+    assert_eq!(tags, vec![DW_TAG_subprogram, DW_TAG_variable]);
+    assert_eq!(entry_count(&dwarf), 3);
+    for tag in tags {
+        let entry = dwarf.get(entries_with_tag(&dwarf, tag)[0]);
+        assert!(entry.get(DW_AT_name).is_some());
+        assert!(entry.get(DW_AT_type).is_none());
+    }
+    let main = dwarf.get(entries_with_tag(&dwarf, DW_TAG_subprogram)[0]);
+    assert_eq!(
+        main.get(DW_AT_low_pc),
+        Some(&AttributeValue::Address(Address::Constant(0x1000)))
+    );
+    assert!(type_map.is_empty());
+}
+
+#[test]
+fn variable_length_array_param() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    // void f(int n, int a[n])
+    let json = STR_JSON
+        .replace(r#""symbol_name": "main""#, r#""symbol_name": "f""#)
+        .replace(
+            r#""parameters": null"#,
+            r#""parameters": {"0": {"name": "n", "type": "int"}, "1": {"name": "a", "type": "int[n]"}}"#,
+        );
+    let input: StrBsiInput = serde_json::from_str(&json).unwrap();
+    dwarf.process_str_bsi(input.data(opt), &mut type_map);
+
+    let f = entries_with_tag(&dwarf, DW_TAG_subprogram)[0];
+    let params = entries_with_tag(&dwarf, DW_TAG_formal_parameter);
+    assert_eq!(params.len(), 2);
+    let param = |name: &[u8]| {
+        *params
+            .iter()
+            .find(|&&id| {
+                name_as_bytes(dwarf.get(id).get(DW_AT_name).unwrap(), dwarf.strings()) == name
+            })
+            .unwrap()
+    };
+    let (n, a) = (param(b"n"), param(b"a"));
+
+    // The array is created in the function with its count referring to `n`
+    let arrays = entries_with_tag(&dwarf, DW_TAG_array_type);
+    assert_eq!(arrays.len(), 1);
+    let array = dwarf.get(arrays[0]);
+    assert_eq!(array.parent(), Some(f));
+    assert_eq!(
+        dwarf.get(a).get(DW_AT_type),
+        Some(&AttributeValue::UnitRef(arrays[0]))
+    );
+    assert_eq!(array.get(DW_AT_type), dwarf.get(n).get(DW_AT_type));
+    let subrange = dwarf.get(*array.children().next().unwrap());
+    assert_eq!(subrange.tag(), DW_TAG_subrange_type);
+    assert_eq!(subrange.get(DW_AT_count), Some(&AttributeValue::UnitRef(n)));
+    assert!(subrange.get(DW_AT_upper_bound).is_none());
+    assert!(!type_map.keys().any(DwarfType::is_variable_length));
+}
+
+#[test]
+fn ghidra_typedefs() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let mut input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"count","00001000","DWORD count(DWORD * counts)","16"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    input.add_typedefs("DWORD=uint32_t\n".as_bytes()).unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    let typedefs = entries_with_tag(&dwarf, DW_TAG_typedef);
+    assert_eq!(typedefs.len(), 1);
+    let typedef = dwarf.get(typedefs[0]);
+    assert_eq!(
+        name_as_bytes(typedef.get(DW_AT_name).unwrap(), dwarf.strings()),
+        b"DWORD"
+    );
+    let uint32 = attr_to_entry_id(typedef.get(DW_AT_type).unwrap());
+    assert_eq!(
+        name_as_bytes(dwarf.get(uint32).get(DW_AT_name).unwrap(), dwarf.strings()),
+        b"uint32_t"
+    );
+    assert_eq!(
+        dwarf.get(uint32).get(DW_AT_byte_size),
+        Some(&AttributeValue::Udata(4))
+    );
+    let subprogram = dwarf.get(entries_with_tag(&dwarf, DW_TAG_subprogram)[0]);
+    assert_eq!(
+        subprogram.get(DW_AT_type),
+        Some(&AttributeValue::UnitRef(typedefs[0]))
+    );
+
+    // The typedef should be recovered from the existing DWARF
+    let type_map = dwarf.create_type_map();
+    let dword = DwarfType::new_typedef(
+        b"DWORD".to_vec().into(),
+        DwarfType::new_primitive(b"uint32_t".to_vec().into(), None),
+    );
+    assert_eq!(type_map.get(&dword), Some(&typedefs[0]));
+
+    let mut input = GhidraInput::from_reader(&b""[..]).unwrap();
+    assert!(input.add_typedefs("A=B\nB=A\n".as_bytes()).is_err());
+}
+
+#[test]
+fn ghidra_stack_vars() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let mut input = GhidraInput::from_reader(GHIDRA_CSV.as_bytes()).unwrap();
+    input
+        .add_stack_vars(
+            r#""Function","Name","Data Type","Offset"
+"00001000","count","int","Stack[-0x14]"
+"00001000","buf","char *","-0x20"
+"00002000","unused","int","-8"
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    let main = entries_with_tag(&dwarf, DW_TAG_subprogram)[0];
+    let mut cfa = Expression::new();
+    cfa.op(DW_OP_call_frame_cfa);
+    assert_eq!(
+        dwarf.get(main).get(DW_AT_frame_base),
+        Some(&AttributeValue::Exprloc(cfa))
+    );
+    let vars = entries_with_tag(&dwarf, DW_TAG_variable);
+    assert_eq!(vars.len(), 2);
+    for id in vars {
+        let var = dwarf.get(id);
+        assert_eq!(var.parent(), Some(main));
+        // The CFA is 8 bytes above the stack pointer on entry on x86-64
+        let offset = match name_as_bytes(var.get(DW_AT_name).unwrap(), dwarf.strings()) {
+            b"count" => -0x1c,
+            b"buf" => -0x28,
+            name => panic!("Unexpected variable {:?}", name),
+        };
+        let mut location = Expression::new();
+        location.op_fbreg(offset);
+        assert_eq!(
+            var.get(DW_AT_location),
+            Some(&AttributeValue::Exprloc(location))
+        );
+        assert!(var.get(DW_AT_type).is_some());
+    }
+}
+
+#[test]
+fn ghidra_default_type() {
+    let csv = r#""Name","Location","Function Signature","Function Size"
+"main","00001000","undefined main(undefined * argv)","16"
+"#;
+    for default_type in [None, Some("uint64_t")] {
+        let args = match default_type {
+            Some(ty) => vec!["--default-type", ty],
+            None => vec![],
+        };
+        let mut fixture = Fixture::new(&args);
+        let (mut dwarf, mut type_map, opt) = fixture.unit();
+        let input = GhidraInput::from_reader(csv.as_bytes()).unwrap();
+        dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+        let main = dwarf.get(entries_with_tag(&dwarf, DW_TAG_subprogram)[0]);
+        let ret_ty = main.get(DW_AT_type).map(attr_to_entry_id);
+        let param = dwarf.get(entries_with_tag(&dwarf, DW_TAG_formal_parameter)[0]);
+        let ptr = attr_to_entry_id(param.get(DW_AT_type).unwrap());
+        let pointee = attr_to_entry_id(dwarf.get(ptr).get(DW_AT_type).unwrap());
+        let pointee_name =
+            name_as_bytes(dwarf.get(pointee).get(DW_AT_name).unwrap(), dwarf.strings());
+        match default_type {
+            // Undefined types are left out and pointers to them are void pointers
+            None => {
+                assert_eq!(ret_ty, None);
+                assert_eq!(pointee_name, b"void");
+            },
+            Some(ty) => {
+                assert_eq!(ret_ty, Some(pointee));
+                assert_eq!(pointee_name, ty.as_bytes());
+            },
+        }
+    }
+}
+
+#[test]
+fn prefer_input_types() {
+    let short_int = DwarfType::new_primitive(b"int".to_vec().into(), Some(2));
+    let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+    let int_ptr = DwarfType::new_pointer(int.clone());
+    for (args, num_base_types) in [(vec![], 2), (vec!["--prefer-input-types"], 1)] {
+        let mut fixture = Fixture::new(&args);
+        let (mut dwarf, mut type_map, _) = fixture.unit();
+        dwarf.update_types(
+            vec![DwarfType::new_pointer(short_int.clone())],
+            &mut type_map,
+        );
+
+        let mut type_map = dwarf.create_type_map();
+        dwarf.update_types(vec![int.clone(), int_ptr.clone()], &mut type_map);
+        let base_types = entries_with_tag(&dwarf, DW_TAG_base_type);
+        assert_eq!(base_types.len(), num_base_types);
+        assert_eq!(
+            dwarf.get(type_map[&int]).get(DW_AT_byte_size),
+            Some(&AttributeValue::Udata(4))
+        );
+        let pointers = entries_with_tag(&dwarf, DW_TAG_pointer_type);
+        assert_eq!(pointers.len(), num_base_types);
+    }
+}
+
+#[test]
+fn prefer_input_types_keeps_unknown_attrs() {
+    let mut fixture = Fixture::new(&["--prefer-input-types"]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let short_int = DwarfType::new_primitive(b"int".to_vec().into(), Some(2));
+    let short_widget = DwarfType::new_primitive(b"widget".to_vec().into(), Some(2));
+    dwarf.update_types(vec![short_int.clone(), short_widget], &mut type_map);
+    dwarf.get_mut(type_map[&short_int]).set(
+        DW_AT_encoding,
+        AttributeValue::Encoding(constants::DW_ATE_float),
+    );
+
+    let mut type_map = dwarf.create_type_map();
+    let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+    let widget = DwarfType::new_primitive(b"widget".to_vec().into(), None);
+    dwarf.update_types(vec![int.clone(), widget.clone()], &mut type_map);
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_base_type).len(), 2);
+
+    // The encoding and target size implied by the name are updated
+    let int = dwarf.get(type_map[&int]);
+    assert_eq!(int.get(DW_AT_byte_size), Some(&AttributeValue::Udata(4)));
+    assert_eq!(
+        int.get(DW_AT_encoding),
+        Some(&AttributeValue::Encoding(constants::DW_ATE_signed))
+    );
+    // but the size of a type without a known size is kept
+    assert_eq!(
+        dwarf.get(type_map[&widget]).get(DW_AT_byte_size),
+        Some(&AttributeValue::Udata(2))
+    );
+}
+
+#[test]
+fn pointer_width_types() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let size_t = DwarfType::new_primitive(b"size_t".to_vec().into(), None);
+    dwarf.update_types(vec![size_t.clone()], &mut type_map);
+    assert_eq!(
+        dwarf.get(type_map[&size_t]).get(DW_AT_byte_size),
+        Some(&AttributeValue::Udata(8))
+    );
+
+    // `usize` should map onto the existing `size_t` entry
+    let mut type_map = dwarf.create_type_map();
+    let usize = DwarfType::new_primitive(b"usize".to_vec().into(), None);
+    dwarf.update_types(vec![usize.clone()], &mut type_map);
+    assert_eq!(type_map[&usize], type_map[&size_t]);
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_base_type).len(), 1);
+}
+
+#[test]
+fn long_target_width() {
+    for (bin, size) in [(TEST_BIN, 8), ("tests/bin/debug.enum.c.sparc.o", 4)] {
+        let mut fixture = Fixture::with_binary(bin, &[]);
+        let (mut dwarf, mut type_map, _) = fixture.unit();
+        let long = DwarfType::new_primitive(b"long".to_vec().into(), None);
+        let ulong = DwarfType::new_primitive(b"unsigned long".to_vec().into(), None);
+        dwarf.update_types(vec![long.clone(), ulong.clone()], &mut type_map);
+        for ty in [&long, &ulong] {
+            assert_eq!(
+                dwarf.get(type_map[ty]).get(DW_AT_byte_size),
+                Some(&AttributeValue::Udata(size))
+            );
+        }
+    }
+}
+
+#[test]
+fn array_stride() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let int8 = DwarfType::new_primitive(b"int8_t".to_vec().into(), None);
+    let int32 = DwarfType::new_primitive(b"int32_t".to_vec().into(), None);
+    let packed = DwarfType::new_strided_array(int8, Some(3), 1);
+    let padded = DwarfType::new_strided_array(int32, Some(2), 8);
+    dwarf.update_types(vec![packed.clone(), padded.clone()], &mut type_map);
+
+    // The stride is only emitted when it differs from the element size
+    assert_eq!(dwarf.get(type_map[&packed]).get(DW_AT_byte_stride), None);
+    assert_eq!(
+        dwarf.get(type_map[&padded]).get(DW_AT_byte_stride),
+        Some(&AttributeValue::Udata(8))
+    );
+}
+
+#[test]
+fn ghidra_entry_pc() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size","Entry Point"
+"reordered","00001000","void reordered(void)","20","00001008"
+"plain","00001020","void plain(void)","10","00001020"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    let entry_pcs: Vec<_> = entries_with_tag(&dwarf, DW_TAG_subprogram)
+        .into_iter()
+        .filter_map(|id| dwarf.get(id).get(DW_AT_entry_pc).cloned())
+        .collect();
+    assert_eq!(
+        entry_pcs,
+        vec![AttributeValue::Address(gimli::write::Address::Constant(
+            0x1008
+        ))]
+    );
+}
+
+#[test]
+fn restrict_pointer_parameter() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"copy","00001000","void copy(char * restrict dst, _Atomic int * src)","16"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    let restricts = entries_with_tag(&dwarf, DW_TAG_restrict_type);
+    assert_eq!(restricts.len(), 1);
+    let restricted = attr_to_entry_id(dwarf.get(restricts[0]).get(DW_AT_type).unwrap());
+    assert_eq!(dwarf.get(restricted).tag(), DW_TAG_pointer_type);
+    let atomics = entries_with_tag(&dwarf, DW_TAG_atomic_type);
+    assert_eq!(atomics.len(), 1);
+    let atomic = attr_to_entry_id(dwarf.get(atomics[0]).get(DW_AT_type).unwrap());
+    assert_eq!(dwarf.get(atomic).tag(), DW_TAG_base_type);
+
+    // Qualified types should be recovered from the existing DWARF
+    let type_map = dwarf.create_type_map();
+    let char_ptr = DwarfType::new_pointer(DwarfType::new_primitive(b"char".to_vec().into(), None));
+    assert_eq!(
+        type_map.get(&DwarfType::new_restrict(char_ptr)),
+        Some(&restricts[0])
+    );
+}
+
+#[test]
+fn noreturn_without_return_type() {
+    for ret_vals in [
+        "",
+        r#", "return_values": [{"register": "RAX", "type": "v"}]"#,
+    ] {
+        let mut fixture = Fixture::new(&[]);
+        let (mut dwarf, mut type_map, opt) = fixture.unit();
+        let json = format!(
+            r#"{{
+                "arch": "amd64",
+                "os": "linux",
+                "functions": [{{"address": 4096, "is_noreturn": true{}}}],
+                "symbols": [[4096, "abort"]]
+            }}"#,
+            ret_vals
+        );
+        let input: AnvillInput = serde_json::from_str(&json).unwrap();
+        dwarf.process_anvill(input.data(opt), &mut type_map);
+
+        let subprograms = entries_with_tag(&dwarf, DW_TAG_subprogram);
+        assert_eq!(subprograms.len(), 1);
+        let abort = dwarf.get(subprograms[0]);
+        assert_eq!(abort.get(DW_AT_noreturn), Some(&AttributeValue::Flag(true)));
+        assert_eq!(abort.get(DW_AT_type), None);
+    }
+}
+
+#[test]
+fn ghidra_noreturn() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"exit","00001000","noreturn void exit(int status)","16"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    let exit = dwarf.get(entries_with_tag(&dwarf, DW_TAG_subprogram)[0]);
+    assert_eq!(exit.get(DW_AT_noreturn), Some(&AttributeValue::Flag(true)));
+    assert_eq!(exit.get(DW_AT_type), None);
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_formal_parameter).len(), 1);
+}
+
+#[test]
+fn sized_pointer() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    // A 16-bit near pointer alongside one with the target's size
+    let input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"copy","00001000","void copy(char *16 near, char * far)","16"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    for &param in &entries_with_tag(&dwarf, DW_TAG_formal_parameter) {
+        let param = dwarf.get(param);
+        let size = match param.get(DW_AT_name) {
+            Some(AttributeValue::String(name)) if name == b"near" => 2,
+            _ => 8,
+        };
+        let ptr = dwarf.get(attr_to_entry_id(param.get(DW_AT_type).unwrap()));
+        assert_eq!(ptr.tag(), DW_TAG_pointer_type);
+        assert_eq!(ptr.get(DW_AT_byte_size), Some(&AttributeValue::Udata(size)));
+    }
+
+    let char_ty = DwarfType::new_primitive(b"char".to_vec().into(), None);
+    let type_map = dwarf.create_type_map();
+    assert!(type_map
+        .get(&DwarfType::new_sized_pointer(char_ty.clone(), 2))
+        .is_some());
+    assert!(type_map.get(&DwarfType::new_pointer(char_ty)).is_some());
+}
+
+#[test]
+fn str_noreturn() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let json = STR_JSON.replace(
+        r#""symbol_name": "main","#,
+        r#""symbol_name": "main", "is_noreturn": true,"#,
+    );
+    let input: StrBsiInput = serde_json::from_str(&json).unwrap();
+    dwarf.process_str_bsi(input.data(opt), &mut type_map);
+
+    let main = dwarf.get(entries_with_tag(&dwarf, DW_TAG_subprogram)[0]);
+    assert_eq!(main.get(DW_AT_noreturn), Some(&AttributeValue::Flag(true)));
+}
+
+#[test]
+fn anvill_missing_return_address() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "functions": [
+                {
+                    "address": 4096,
+                    "return_address": {"memory": {"register": "RSP", "offset": 0}, "type": "L"},
+                    "return_values": [{"register": "RAX", "type": "i"}]
+                },
+                {
+                    "address": 4128,
+                    "parameters": [{"register": "RDI", "type": "l"}],
+                    "return_values": [{"register": "RAX", "type": "i"}]
+                }
+            ],
+            "symbols": [[4096, "with_ret"], [4128, "without_ret"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+
+    let subprograms = dwarf.subprograms();
+    let with_ret = dwarf.get(subprograms[&4096].1);
+    assert!(with_ret.get(DW_AT_return_addr).is_some());
+    let without_ret = dwarf.get(subprograms[&4128].1);
+    assert!(without_ret.get(DW_AT_return_addr).is_none());
+    assert!(without_ret.get(DW_AT_type).is_some());
+    assert_eq!(without_ret.children().count(), 1);
+}
+
+#[test]
+fn function_pointer_variable() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    // void (*handler)(int)
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "variables": [{"address": 16392, "type": "*(iv)"}],
+            "symbols": [[16392, "handler"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+
+    let vars = entries_with_tag(&dwarf, DW_TAG_variable);
+    assert_eq!(vars.len(), 1);
+    let ptr = dwarf.get(attr_to_entry_id(
+        dwarf.get(vars[0]).get(DW_AT_type).unwrap(),
+    ));
+    assert_eq!(ptr.tag(), DW_TAG_pointer_type);
+    assert_eq!(ptr.get(DW_AT_byte_size), Some(&AttributeValue::Udata(8)));
+    let func = dwarf.get(attr_to_entry_id(ptr.get(DW_AT_type).unwrap()));
+    assert_eq!(func.tag(), DW_TAG_subroutine_type);
+    assert_eq!(
+        func.get(DW_AT_prototyped),
+        Some(&AttributeValue::Flag(true))
+    );
+    assert!(func.get(DW_AT_type).is_none());
+    let params: Vec<_> = func.children().map(|&id| dwarf.get(id)).collect();
+    assert_eq!(params.len(), 1);
+    assert_eq!(params[0].tag(), DW_TAG_formal_parameter);
+    let int = dwarf.get(attr_to_entry_id(params[0].get(DW_AT_type).unwrap()));
+    assert_eq!(int.get(DW_AT_byte_size), Some(&AttributeValue::Udata(4)));
+
+    // The function pointer type is recovered from the updated binary
+    fixture.reload();
+    let type_map = fixture.dwarf().create_type_map();
+    let int = DwarfType::new_primitive(b"int32_t".to_vec().into(), Some(4));
+    let handler = DwarfType::new_pointer(DwarfType::new_function(DwarfType::void(), vec![int]));
+    assert!(type_map.contains_key(&handler));
+}
+
+#[test]
+fn incomplete_types_are_declarations() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+    let empty = DwarfType::new_struct(Some(b"Empty".to_vec().into()), None, Vec::new());
+    let callback = DwarfType::new_unprototyped_function(int.clone());
+    let full = DwarfType::new_function(int.clone(), vec![int]);
+    dwarf.update_types(
+        vec![empty.clone(), callback.clone(), full.clone()],
+        &mut type_map,
+    );
+
+    let declaration = |ty: &DwarfType| dwarf.get(type_map[ty]).get(DW_AT_declaration).cloned();
+    assert_eq!(declaration(&empty), Some(AttributeValue::Flag(true)));
+    assert_eq!(declaration(&callback), Some(AttributeValue::Flag(true)));
+    assert_eq!(declaration(&full), None);
+    let callback_entry = dwarf.get(type_map[&callback]);
+    assert!(callback_entry.get(DW_AT_prototyped).is_none());
+    assert_eq!(callback_entry.children().count(), 0);
+
+    // Both incomplete types are recovered from the updated binary
+    fixture.reload();
+    let type_map = fixture.dwarf().create_type_map();
+    for ty in [empty, callback, full] {
+        assert!(type_map.contains_key(&ty), "{:?} was not recovered", ty);
+    }
+}
+
+#[test]
+fn reuse_written_types() {
+    let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+    let long = DwarfType::new_primitive(b"long".to_vec().into(), None);
+    let int_ptr = DwarfType::new_pointer(int.clone());
+    let node = DwarfType::new_struct(
+        Some(b"ns::node".to_vec().into()),
+        Some(16),
+        vec![
+            Member::new(Some(b"value".to_vec().into()), int.clone(), Some(0)),
+            Member::new(Some(b"next".to_vec().into()), int_ptr.clone(), Some(8)),
+        ],
+    );
+    let counter = DwarfType::new_class(
+        Some(b"Counter".to_vec().into()),
+        Some(8),
+        vec![Member {
+            accessibility: Some(Accessibility::Private),
+            ..Member::new(Some(b"count".to_vec().into()), long.clone(), Some(0))
+        }],
+    );
+    let types = vec![
+        DwarfType::new_pointer(DwarfType::void()),
+        DwarfType::new_restrict(int_ptr.clone()),
+        DwarfType::new_atomic(int.clone()),
+        DwarfType::new_typedef(b"ns::handle_t".to_vec().into(), int_ptr),
+        DwarfType::new_array(long, Some(4)),
+        DwarfType::new_strided_array(int.clone(), Some(4), 4),
+        node,
+        counter,
+        DwarfType::new_struct(Some(b"opaque".to_vec().into()), None, Vec::new()),
+        DwarfType::new_pointer(DwarfType::new_function(DwarfType::void(), vec![int])),
+    ];
+
+    // The first invocation writes the types to a new binary
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    dwarf.update_types(types.clone(), &mut type_map);
+
+    // The second invocation reads the first's output and reuses its types
+    fixture.reload();
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    for ty in &types {
+        assert!(type_map.contains_key(ty), "{:?} was not recovered", ty);
+    }
+    let count = entry_count(&dwarf);
+    dwarf.update_types(types, &mut type_map);
+    assert_eq!(entry_count(&dwarf), count);
+}
+
+#[test]
+fn language() {
+    let ints = DwarfType::new_array(DwarfType::from(&String::from("int")), Some(4));
+    let subrange = |dwarf: &DwarfUnitRef, type_map: &TypeMap| {
+        dwarf
+            .get(type_map[&ints])
+            .children()
+            .next()
+            .copied()
+            .unwrap()
+    };
+
+    let mut fixture = Fixture::new(&["--language", "c"]);
+    let mut dwarf = fixture.dwarf();
+    assert_eq!(unit_language(&dwarf), Some(DW_LANG_C99));
+    let mut type_map = dwarf.create_type_map();
+    dwarf.update_types(vec![ints.clone()], &mut type_map);
+    let c_subrange = subrange(&dwarf, &type_map);
+    assert!(dwarf.get(c_subrange).get(DW_AT_lower_bound).is_none());
+
+    // Fortran arrays start at 1
+    let mut fixture = Fixture::new(&["--language", "fortran"]);
+    let mut dwarf = fixture.dwarf();
+    assert_eq!(
+        dwarf.get(dwarf.root()).get(DW_AT_language),
+        Some(&AttributeValue::Language(DW_LANG_Fortran90))
+    );
+    let mut type_map = dwarf.create_type_map();
+    dwarf.update_types(vec![ints.clone()], &mut type_map);
+    let id = subrange(&dwarf, &type_map);
+    let entry = dwarf.get_mut(id);
+    assert_eq!(
+        entry.get(DW_AT_lower_bound),
+        Some(&AttributeValue::Udata(1))
+    );
+    assert_eq!(entry.get(DW_AT_count), Some(&AttributeValue::Udata(4)));
+
+    // Upper bounds without a lower bound are relative to the language's
+    entry.delete(DW_AT_lower_bound);
+    entry.delete(DW_AT_count);
+    entry.set(DW_AT_upper_bound, AttributeValue::Udata(4));
+    assert!(dwarf.create_type_map().contains_key(&ints));
+}
+
+#[test]
+fn nested_aggregates() {
+    let int = DwarfType::from(&String::from("int"));
+    let ints = DwarfType::new_array(int.clone(), Some(4));
+    // struct Holder { int a[4]; }
+    let holder = DwarfType::new_struct(
+        Some(b"Holder".to_vec().into()),
+        Some(16),
+        vec![Member::new(
+            Some(b"a".to_vec().into()),
+            ints.clone(),
+            Some(0),
+        )],
+    );
+    let point = DwarfType::new_struct(
+        Some(b"Point".to_vec().into()),
+        Some(8),
+        vec![
+            Member::new(Some(b"x".to_vec().into()), int.clone(), Some(0)),
+            Member::new(Some(b"y".to_vec().into()), int.clone(), Some(4)),
+        ],
+    );
+    // struct Point pts[8]
+    let points = DwarfType::new_array(point.clone(), Some(8));
+    // struct Shape { int count; struct Point pts[8]; }
+    let shape = DwarfType::new_struct(
+        Some(b"Shape".to_vec().into()),
+        Some(68),
+        vec![
+            Member::new(Some(b"count".to_vec().into()), int, Some(0)),
+            Member::new(Some(b"pts".to_vec().into()), points.clone(), Some(4)),
+        ],
+    );
+    let types = vec![holder.clone(), points.clone(), shape.clone()];
+
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    dwarf.update_types(types.clone(), &mut type_map);
+
+    // Nested types are shared by their containers and sized as declared
+    let member_type = |dwarf: &DwarfUnitRef, ty: &DwarfType, idx: usize| {
+        let member = dwarf
+            .get(type_map[ty])
+            .children()
+            .nth(idx)
+            .copied()
+            .unwrap();
+        attr_to_entry_id(dwarf.get(member).get(DW_AT_type).unwrap())
+    };
+    assert_eq!(member_type(&dwarf, &holder, 0), type_map[&ints]);
+    assert_eq!(member_type(&dwarf, &shape, 1), type_map[&points]);
+    assert_eq!(
+        attr_to_entry_id(dwarf.get(type_map[&points]).get(DW_AT_type).unwrap()),
+        type_map[&point]
+    );
+    let pts = dwarf
+        .get(type_map[&shape])
+        .children()
+        .nth(1)
+        .copied()
+        .unwrap();
+    assert_eq!(
+        dwarf.get(pts).get(DW_AT_data_member_location),
+        Some(&AttributeValue::Udata(4))
+    );
+    let subrange = dwarf
+        .get(type_map[&points])
+        .children()
+        .next()
+        .copied()
+        .unwrap();
+    assert_eq!(
+        dwarf.get(subrange).get(DW_AT_count),
+        Some(&AttributeValue::Udata(8))
+    );
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_structure_type).len(), 3);
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_array_type).len(), 2);
+
+    // The containers read back from the entries match the inputs' types so
+    // they aren't duplicated
+    fixture.reload();
+    let (mut dwarf, mut type_map, _) = fixture.unit();
+    for ty in &types {
+        assert!(type_map.contains_key(ty), "{:?} was not recovered", ty);
+    }
+    let count = entry_count(&dwarf);
+    dwarf.update_types(types, &mut type_map);
+    assert_eq!(entry_count(&dwarf), count);
+}
+
+#[test]
+fn multiple_return_values() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "functions": [{
+                "address": 4096,
+                "return_values": [
+                    {"register": "RAX", "type": "i"},
+                    {"register": "RDX", "type": "l"}
+                ]
+            }],
+            "symbols": [[4096, "divmod"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+
+    let subprograms = entries_with_tag(&dwarf, DW_TAG_subprogram);
+    assert_eq!(subprograms.len(), 1);
+    let ret_type = attr_to_entry_id(dwarf.get(subprograms[0]).get(DW_AT_type).unwrap());
+    let ret_struct = dwarf.get(ret_type);
+    assert_eq!(ret_struct.tag(), DW_TAG_structure_type);
+    assert!(ret_struct.get(DW_AT_name).is_none());
+    assert_eq!(
+        ret_struct.get(DW_AT_byte_size),
+        Some(&AttributeValue::Udata(16))
+    );
+    let members: Vec<_> = ret_struct.children().map(|&id| dwarf.get(id)).collect();
+    assert_eq!(members.len(), 2);
+    let names: Vec<_> = members
+        .iter()
+        .map(|m| name_as_bytes(m.get(DW_AT_name).unwrap(), dwarf.strings()))
+        .collect();
+    assert_eq!(names, vec![&b"rax"[..], &b"rdx"[..]]);
+    let offsets: Vec<_> = members
+        .iter()
+        .map(|m| m.get(DW_AT_data_member_location))
+        .collect();
+    assert_eq!(
+        offsets,
+        vec![
+            Some(&AttributeValue::Udata(0)),
+            Some(&AttributeValue::Udata(8))
+        ]
+    );
+    for (member, size) in members.iter().zip([4, 8]) {
+        let ty = dwarf.get(attr_to_entry_id(member.get(DW_AT_type).unwrap()));
+        assert_eq!(ty.get(DW_AT_byte_size), Some(&AttributeValue::Udata(size)));
+    }
+}
+
+#[test]
+fn names_from_elf_symbols() {
+    // Defines `just_loop` at 0x1000 and `main` at 0x1020
+    let mut fixture = Fixture::with_binary("tests/bin/no_return_fn.c.elf", &[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "functions": [{"address": 4096}, {"address": 4112}, {"address": 4128}],
+            "symbols": [[4128, "entry"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+
+    let subprograms = dwarf.subprograms();
+    let name = |addr| {
+        let func = dwarf.get(subprograms[&addr].1);
+        name_as_bytes(func.get(DW_AT_name).unwrap(), dwarf.strings()).to_vec()
+    };
+    assert_eq!(name(0x1000), b"just_loop");
+    assert_eq!(
+        name(0x1010),
+        opt.generated_name(NameKind::Function, 0x1010).as_bytes()
+    );
+    // Input names take precedence over symbols
+    assert_eq!(name(0x1020), b"entry");
+}
+
+#[test]
+fn demangle_names() {
+    let json = r#"{
+        "arch": "amd64",
+        "os": "linux",
+        "functions": [{"address": 4096}],
+        "symbols": [[4096, "_ZN2ns3fooEi"]]
+    }"#;
+    for (args, name, linkage_name) in [
+        (&[][..], &b"_ZN2ns3fooEi"[..], None),
+        (
+            &["--demangle"][..],
+            &b"foo"[..],
+            Some(AttributeValue::String(b"_ZN2ns3fooEi".to_vec())),
+        ),
+        (
+            &["--demangle", "--legacy-linkage-name"][..],
+            &b"foo"[..],
+            Some(AttributeValue::String(b"_ZN2ns3fooEi".to_vec())),
+        ),
+    ] {
+        let mut fixture = Fixture::new(args);
+        let (mut dwarf, mut type_map, opt) = fixture.unit();
+        let input: AnvillInput = serde_json::from_str(json).unwrap();
+        dwarf.process_anvill(input.data(opt), &mut type_map);
+
+        let subprograms = entries_with_tag(&dwarf, DW_TAG_subprogram);
+        assert_eq!(subprograms.len(), 1);
+        let func = dwarf.get(subprograms[0]);
+        assert_eq!(
+            name_as_bytes(func.get(DW_AT_name).unwrap(), dwarf.strings()),
+            name
+        );
+        assert_eq!(func.get(DW_AT_linkage_name), linkage_name.as_ref());
+        let legacy_linkage_name = linkage_name
+            .as_ref()
+            .filter(|_| args.contains(&"--legacy-linkage-name"));
+        assert_eq!(func.get(DW_AT_MIPS_linkage_name), legacy_linkage_name);
+        // Demangled names are placed in their namespace
+        let parent = dwarf.get(func.parent().unwrap());
+        assert_eq!(parent.tag() == DW_TAG_namespace, linkage_name.is_some());
+    }
+}
+
+#[test]
+fn method_object_pointer() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"Widget::resize","00001000","void __thiscall resize(struct Widget * this, int width)","16"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    let subprograms = entries_with_tag(&dwarf, DW_TAG_subprogram);
+    assert_eq!(subprograms.len(), 1);
+    let method = dwarf.get(subprograms[0]);
+    let this_id = attr_to_entry_id(method.get(DW_AT_object_pointer).unwrap());
+    let this = dwarf.get(this_id);
+    assert_eq!(this.tag(), DW_TAG_formal_parameter);
+    assert_eq!(
+        this.get(DW_AT_name),
+        Some(&AttributeValue::String(b"this".to_vec()))
+    );
+    assert_eq!(
+        this.get(DW_AT_artificial),
+        Some(&AttributeValue::Flag(true))
+    );
+
+    let params = entries_with_tag(&dwarf, DW_TAG_formal_parameter);
+    assert_eq!(params.len(), 2);
+    let width = params.iter().find(|&&id| id != this_id).unwrap();
+    assert_eq!(dwarf.get(*width).get(DW_AT_artificial), None);
+    // The calling convention shouldn't end up in the return type
+    let ret_type = attr_to_entry_id(method.get(DW_AT_type).unwrap());
+    assert_eq!(
+        dwarf.get(ret_type).get(DW_AT_name),
+        Some(&AttributeValue::String(b"void".to_vec()))
+    );
+}
+
+#[test]
+fn anvill_frame_base() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "functions": [
+                {"address": 4096, "return_stack_pointer": {"register": "RSP", "offset": 8, "type": "L"}},
+                {"address": 4112}
+            ],
+            "symbols": [[4096, "main"], [4112, "helper"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+
+    let frame_bases: HashMap<_, _> = entries_with_tag(&dwarf, DW_TAG_subprogram)
+        .into_iter()
+        .map(|id| {
+            let entry = dwarf.get(id);
+            let name = name_as_bytes(entry.get(DW_AT_name).unwrap(), dwarf.strings());
+            (name.to_vec(), entry.get(DW_AT_frame_base).cloned())
+        })
+        .collect();
+    let mut sp = Expression::new();
+    sp.op_breg(gimli::X86_64::RSP, 8);
+    assert_eq!(frame_bases[&b"main"[..]], Some(AttributeValue::Exprloc(sp)));
+    assert_eq!(frame_bases[&b"helper"[..]], Some(cfa_frame_base()));
+}
+
+#[test]
+fn unlocated_anvill_params() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let params = |located: &str, unlocated: &str| {
+        format!(
+            r#"{{
+                "arch": "amd64",
+                "os": "linux",
+                "functions": [{{"address": 4096, "parameters": [
+                    {{"name": "{}", "register": "RDI", "type": "i"}},
+                    {{"name": "{}", "type": "l"}}
+                ]}}],
+                "symbols": [[4096, "main"]]
+            }}"#,
+            located, unlocated
+        )
+    };
+    let locations = |dwarf: &DwarfUnitRef| {
+        let main = entries_with_tag(dwarf, DW_TAG_subprogram)[0];
+        dwarf
+            .get(main)
+            .children()
+            .map(|&id| {
+                let param = dwarf.get(id);
+                let name = name_as_bytes(param.get(DW_AT_name).unwrap(), dwarf.strings());
+                (name.to_vec(), param.get(DW_AT_location).is_some())
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let input: AnvillInput = serde_json::from_str(&params("a", "b")).unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+    assert_eq!(
+        locations(&dwarf),
+        vec![(b"a".to_vec(), true), (b"b".to_vec(), false)]
+    );
+
+    // Existing parameters are matched by position whether or not either
+    // input located them
+    let input: AnvillInput = serde_json::from_str(&params("x", "y")).unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+    assert_eq!(
+        locations(&dwarf),
+        vec![(b"x".to_vec(), true), (b"y".to_vec(), false)]
+    );
+}
+
+#[test]
+fn anvill_call_sites() {
+    let json = r#"{
+        "arch": "amd64",
+        "os": "linux",
+        "functions": [
+            {
+                "address": 4096,
+                "has_all_calls": true,
+                "calls": [
+                    {"return_address": 4101, "target": 4128, "arguments": [{"register": "RDI"}]},
+                    {"return_address": 4110}
+                ]
+            },
+            {"address": 4128}
+        ],
+        "symbols": [[4096, "main"], [4128, "callee"]]
+    }"#;
+    for (args, num_call_sites) in [(vec![], 0), (vec!["--emit-call-sites"], 2)] {
+        let mut fixture = Fixture::new(&args);
+        let (mut dwarf, mut type_map, opt) = fixture.unit();
+        let input: AnvillInput = serde_json::from_str(json).unwrap();
+        dwarf.process_anvill(input.data(opt), &mut type_map);
+
+        let call_sites = entries_with_tag(&dwarf, DW_TAG_GNU_call_site);
+        assert_eq!(call_sites.len(), num_call_sites);
+        if num_call_sites == 0 {
+            continue
+        }
+        let subprograms = dwarf.subprograms();
+        let main = dwarf.get(subprograms[&0x1000].1);
+        assert_eq!(
+            main.get(DW_AT_GNU_all_call_sites),
+            Some(&AttributeValue::Flag(true))
+        );
+        let call = call_sites
+            .iter()
+            .map(|&id| dwarf.get(id))
+            .find(|call| call.get(DW_AT_abstract_origin).is_some())
+            .unwrap();
+        assert_eq!(
+            call.get(DW_AT_abstract_origin),
+            Some(&AttributeValue::UnitRef(subprograms[&0x1020].1))
+        );
+        assert_eq!(
+            call.get(DW_AT_low_pc),
+            Some(&AttributeValue::Address(gimli::write::Address::Constant(
+                4101
+            )))
+        );
+        assert_eq!(call.children().count(), 1);
+        assert_eq!(
+            entries_with_tag(&dwarf, DW_TAG_GNU_call_site_parameter).len(),
+            1
+        );
+
+        // Rerunning updates the existing call sites instead of duplicating them
+        let mut type_map = dwarf.create_type_map();
+        dwarf.process_anvill(input.data(opt), &mut type_map);
+        assert_eq!(entries_with_tag(&dwarf, DW_TAG_GNU_call_site), call_sites);
+        assert_eq!(
+            entries_with_tag(&dwarf, DW_TAG_GNU_call_site_parameter).len(),
+            1
+        );
+        assert!(fixture.elf.sections().is_ok());
+    }
+}
+
+#[test]
+fn call_site_updates() {
+    let json = |call: &str| {
+        format!(
+            r#"{{
+                "arch": "amd64",
+                "os": "linux",
+                "functions": [{{"address": 4096, "calls": [{}]}}, {{"address": 4128}}],
+                "symbols": [[4096, "main"], [4128, "callee"]]
+            }}"#,
+            call
+        )
+    };
+    let mut fixture = Fixture::new(&["--emit-call-sites"]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(&json(
+        r#"{"return_address": 4101, "target": 4128,
+            "arguments": [{"register": "RDI"}, {"register": "RSI"}, {"register": "RDX"}]}"#,
+    ))
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+    let call = entries_with_tag(&dwarf, DW_TAG_GNU_call_site)[0];
+    assert!(dwarf.get(call).get(DW_AT_abstract_origin).is_some());
+    assert_eq!(dwarf.get(call).children().count(), 3);
+
+    // Calls without argument data keep their parameters
+    let input: AnvillInput =
+        serde_json::from_str(&json(r#"{"return_address": 4101, "target": 4128}"#)).unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+    assert_eq!(dwarf.get(call).children().count(), 3);
+
+    // Unresolved targets drop the old origin, extra parameters are removed
+    // and arguments without a location keep their position
+    let input: AnvillInput = serde_json::from_str(&json(
+        r#"{"return_address": 4101, "target": 8192,
+            "arguments": [{"register": "XMM99"}, {"register": "RSI"}]}"#,
+    ))
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+    assert!(dwarf.get(call).get(DW_AT_abstract_origin).is_none());
+    let locations: Vec<_> = dwarf
+        .get(call)
+        .children()
+        .map(|&id| dwarf.get(id).get(DW_AT_location).cloned())
+        .collect();
+    let mut rsi = Expression::new();
+    rsi.op_reg(gimli::X86_64::RSI);
+    assert_eq!(locations, vec![None, Some(AttributeValue::Exprloc(rsi))]);
+    assert_eq!(
+        entries_with_tag(&dwarf, DW_TAG_GNU_call_site_parameter).len(),
+        2
+    );
+}
+
+#[test]
+fn utf8_names() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let ghidra = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"größe_λ","00001000","int größe_λ(int größe)","10"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(ghidra.data(opt).unwrap(), &mut type_map);
+
+    let mut section_data = HashMap::new();
+    fixture
+        .elf
+        .sections()
+        .unwrap()
+        .for_each(|id, data| {
+            section_data.insert(id, data.slice().to_vec());
+            Ok::<_, anyhow::Error>(())
+        })
+        .unwrap();
+    let dwarf = gimli::read::Dwarf::load(|id| -> anyhow::Result<_> {
+        let data = section_data.get(&id).map(Vec::as_slice).unwrap_or(&[]);
+        Ok(gimli::EndianSlice::new(data, gimli::LittleEndian))
+    })
+    .unwrap();
+    let header = dwarf.units().next().unwrap().unwrap();
+    let unit = dwarf.unit(header).unwrap();
+    let mut entries = unit.entries();
+    let (_, root) = entries.next_dfs().unwrap().unwrap();
+    assert_eq!(
+        root.attr_value(DW_AT_use_UTF8).unwrap(),
+        Some(gimli::read::AttributeValue::Flag(true))
+    );
+    let mut names = Vec::new();
+    while let Some((_, entry)) = entries.next_dfs().unwrap() {
+        if let Some(name) = entry.attr_value(DW_AT_name).unwrap() {
+            names.push(dwarf.attr_string(&unit, name).unwrap().to_vec());
+        }
+    }
+    assert!(names.contains(&"größe_λ".as_bytes().to_vec()));
+    assert!(names.contains(&"größe".as_bytes().to_vec()));
+}
+
+#[test]
+fn dwarf_format() {
+    for (args, format) in [
+        (vec![], Format::Dwarf32),
+        (vec!["--dwarf64"], Format::Dwarf64),
+    ] {
+        let mut fixture = Fixture::new(&args);
+        let encoding = fixture.dwarf().encoding();
+        assert_eq!(encoding.format, format);
+        assert_eq!(encoding.address_size, 8);
+    }
+}
+
+#[test]
+fn nested_namespaces() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"outer::inner::run","00001000","void run(struct outer::inner::Widget * w)","16"
+"outer::inner::stop","00001010","void stop(void)","16"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    let namespaces = entries_with_tag(&dwarf, DW_TAG_namespace);
+    assert_eq!(namespaces.len(), 2);
+    let outer = dwarf
+        .get(dwarf.root())
+        .children()
+        .cloned()
+        .find(|id| namespaces.contains(id));
+    let outer = dwarf.get(outer.unwrap());
+    assert_eq!(
+        outer.get(DW_AT_name),
+        Some(&AttributeValue::String(b"outer".to_vec()))
+    );
+    let inner = dwarf.get(outer.children().cloned().next().unwrap());
+    assert_eq!(
+        inner.get(DW_AT_name),
+        Some(&AttributeValue::String(b"inner".to_vec()))
+    );
+
+    let inner_names: Vec<_> = inner
+        .children()
+        .map(|&id| name_as_bytes(dwarf.get(id).get(DW_AT_name).unwrap(), dwarf.strings()))
+        .collect();
+    assert_eq!(inner_names.len(), 3);
+    assert!(inner_names.contains(&&b"run"[..]));
+    assert!(inner_names.contains(&&b"stop"[..]));
+    assert!(inner_names.contains(&&b"Widget"[..]));
+
+    // Namespaced types should be recovered with their qualified name
+    let type_map = dwarf.create_type_map();
+    let widget = DwarfType::new_struct(
+        Some(b"outer::inner::Widget".to_vec().into()),
+        None,
+        Vec::new(),
+    );
+    assert!(type_map.contains_key(&widget));
+}
+
+#[test]
+fn variable_decl_location() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let json = STR_JSON.replace(
+        r#"{"name": "x", "type": "int"}"#,
+        r#"{"name": "x", "type": "int", "file": "main.c", "line": 5}"#,
+    );
+    let input: StrBsiInput = serde_json::from_str(&json).unwrap();
+    dwarf.process_str_bsi(input.data(opt), &mut type_map);
+
+    let vars = entries_with_tag(&dwarf, DW_TAG_variable);
+    let var = dwarf.get(vars[0]);
+    assert_eq!(
+        var.get(DW_AT_decl_file),
+        Some(&AttributeValue::String(b"main.c".to_vec()))
+    );
+    assert_eq!(var.get(DW_AT_decl_line), Some(&AttributeValue::Data8(5)));
+}
+
+#[test]
+fn comp_dir_decl_location() {
+    let mut fixture = Fixture::new(&["--comp-dir", "/src"]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let json = STR_JSON.replace(
+        r#"{"name": "x", "type": "int"}"#,
+        r#"{"name": "x", "type": "int", "file": "/usr/include/x.h", "line": 5}"#,
+    );
+    let input: StrBsiInput = serde_json::from_str(&json).unwrap();
+    dwarf.process_str_bsi(input.data(opt), &mut type_map);
+
+    assert_eq!(
+        dwarf.get(dwarf.root()).get(DW_AT_comp_dir),
+        Some(&AttributeValue::String(b"/src".to_vec()))
+    );
+    // Relative paths are left for DW_AT_comp_dir to resolve
+    let main = entries_with_tag(&dwarf, DW_TAG_subprogram)[0];
+    assert_eq!(
+        dwarf.get(main).get(DW_AT_decl_file),
+        Some(&AttributeValue::String(b"main.c".to_vec()))
+    );
+    let var = entries_with_tag(&dwarf, DW_TAG_variable)[0];
+    assert_eq!(
+        dwarf.get(var).get(DW_AT_decl_file),
+        Some(&AttributeValue::String(b"/usr/include/x.h".to_vec()))
+    );
+}
+
+#[test]
+fn dwarf5_addrx_low_pc() {
+    // main's DW_AT_low_pc is a DW_FORM_addrx in this binary
+    let mut fixture = Fixture::with_binary("tests/bin/dwarf5.empty.c.elf", &[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input = GhidraInput::from_reader(GHIDRA_CSV.as_bytes()).unwrap();
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    let subprograms = entries_with_tag(&dwarf, DW_TAG_subprogram);
+    assert_eq!(subprograms.len(), 1);
+    let main = dwarf.get(subprograms[0]);
+    assert_eq!(
+        main.get(DW_AT_low_pc),
+        Some(&AttributeValue::Address(gimli::write::Address::Constant(
+            0x1000
+        )))
+    );
+    // The Ghidra data should replace the existing DW_FORM_data4 high_pc
+    assert!(matches!(
+        main.get(DW_AT_high_pc),
+        Some(AttributeValue::Data8(_))
+    ));
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_formal_parameter).len(), 1);
+    assert!(fixture.elf.sections().is_ok());
+}
+
+#[test]
+fn dwarf5_addrx_variable() {
+    // counter's DW_AT_location is a DW_OP_addrx in this binary
+    let mut fixture = Fixture::with_binary("tests/bin/dwarf5.var.c.elf", &[]);
+    assert_eq!(
+        fixture.elf.var_addrs.values().collect::<Vec<_>>(),
+        vec![&0x4000]
+    );
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "variables": [{"address": 16384, "type": "i"}],
+            "symbols": [[16384, "renamed"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+
+    let vars = entries_with_tag(&dwarf, DW_TAG_variable);
+    assert_eq!(vars.len(), 1);
+    let var = dwarf.get(vars[0]);
+    assert_eq!(
+        name_as_bytes(var.get(DW_AT_name).unwrap(), dwarf.strings()),
+        b"renamed"
+    );
+    assert!(fixture.elf.sections().is_ok());
+}
+
+#[test]
+fn loclist_variable() {
+    use gimli::write::{Address, Location, LocationList};
+
+    // Give the binary a variable whose location is a location list
+    let mut fixture = Fixture::new(&[]);
+    let mut dwarf = fixture.dwarf();
+    let mut expr = Expression::new();
+    expr.op_addr(Address::Constant(0x4008));
+    let list = dwarf.locations.add(LocationList(vec![Location::StartEnd {
+        begin: Address::Constant(0x1000),
+        end: Address::Constant(0x1010),
+        data: expr,
+    }]));
+    let root = dwarf.root();
+    let var = dwarf.add(root, DW_TAG_variable);
+    dwarf
+        .get_mut(var)
+        .set(DW_AT_location, AttributeValue::LocationListRef(list));
+    fixture.reload();
+    assert_eq!(
+        fixture.elf.var_addrs.values().collect::<Vec<_>>(),
+        vec![&0x4008]
+    );
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(ANVILL_JSON).unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+
+    // The existing variable is updated instead of adding a duplicate
+    let vars = entries_with_tag(&dwarf, DW_TAG_variable);
+    assert_eq!(vars.len(), 1);
+    let var = dwarf.get(vars[0]);
+    assert_eq!(
+        name_as_bytes(var.get(DW_AT_name).unwrap(), dwarf.strings()),
+        b"global"
+    );
+    assert!(matches!(
+        var.get(DW_AT_location),
+        Some(AttributeValue::LocationListRef(_))
+    ));
+}
+
+#[test]
+fn check_alignment() {
+    // The ARM object's .text section is 8 bytes at address 0
+    let mut fixture = Fixture::with_binary("tests/bin/empty.c.arm.o", &["--check-alignment"]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "aarch32",
+            "os": "linux",
+            "functions": [{"address": 0}, {"address": 3}, {"address": 4096}],
+            "symbols": [[0, "main"], [3, "misaligned"], [4096, "outside"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+    assert_eq!(dwarf.check_alignment(), vec![3, 4096]);
+}
+
+#[test]
+fn target_override() {
+    // TEST_BIN is a 64-bit x86 binary
+    let mut fixture = Fixture::new(&["--target", "i686-unknown-linux-gnu"]);
+    let mut dwarf = fixture.dwarf();
+    assert_eq!(dwarf.encoding().address_size, 4);
+    let mut type_map = dwarf.create_type_map();
+    let long = DwarfType::new_primitive(b"long".to_vec().into(), None);
+    dwarf.update_types(vec![DwarfType::new_pointer(long.clone())], &mut type_map);
+    let ptr = entries_with_tag(&dwarf, DW_TAG_pointer_type)[0];
+    assert_eq!(
+        dwarf.get(ptr).get(DW_AT_byte_size),
+        Some(&AttributeValue::Udata(4))
+    );
+    assert_eq!(
+        dwarf.get(type_map[&long]).get(DW_AT_byte_size),
+        Some(&AttributeValue::Udata(4))
+    );
+
+    // Instruction alignment follows the target's architecture
+    let mut fixture = Fixture::new(&["--target", "armv7-unknown-linux-gnueabihf"]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "aarch32",
+            "os": "linux",
+            "functions": [{"address": 4097}],
+            "symbols": [[4097, "misaligned"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+    assert_eq!(dwarf.check_alignment(), vec![4097]);
+}
+
+#[test]
+fn exported_functions() {
+    // `exported` is in the dynamic symbol table but `hidden` isn't
+    let mut fixture = Fixture::with_binary("tests/bin/export.c.so", &[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "functions": [{"address": 4096}, {"address": 4111}],
+            "symbols": [[4096, "exported"], [4111, "hidden"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+
+    let subprograms = dwarf.subprograms();
+    assert_eq!(
+        dwarf.get(subprograms[&0x1000].1).get(DW_AT_external),
+        Some(&AttributeValue::Flag(true))
+    );
+    assert_eq!(dwarf.get(subprograms[&0x100f].1).get(DW_AT_external), None);
+}
+
+#[test]
+fn declaration_without_low_pc() {
+    // `external` is a declaration-only subprogram in this binary
+    let mut fixture = Fixture::with_binary("tests/bin/debug.decl.c.elf", &[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let ghidra = GhidraInput::from_reader(GHIDRA_CSV.as_bytes()).unwrap();
+    dwarf.process_ghidra(ghidra.data(opt).unwrap(), &mut type_map);
+    let anvill: AnvillInput = serde_json::from_str(ANVILL_JSON).unwrap();
+    dwarf.process_anvill(anvill.data(opt), &mut type_map);
+    let str_bsi: StrBsiInput = serde_json::from_str(STR_JSON).unwrap();
+    dwarf.process_str_bsi(str_bsi.data(opt), &mut type_map);
+
+    let declarations: Vec<_> = entries_with_tag(&dwarf, DW_TAG_subprogram)
+        .into_iter()
+        .filter(|&id| dwarf.get(id).get(DW_AT_low_pc).is_none())
+        .collect();
+    assert_eq!(declarations.len(), 1);
+    let name = dwarf.get(declarations[0]).get(DW_AT_name).unwrap();
+    assert_eq!(name_as_bytes(name, dwarf.strings()), b"external");
+}
+
+#[test]
+fn merge_parameters() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let anvill: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "functions": [{"address": 4096, "parameters": [{"register": "RDI", "type": "i"}]}],
+            "symbols": [[4096, "main"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(anvill.data(opt), &mut type_map);
+    let ghidra = GhidraInput::from_reader(GHIDRA_CSV.as_bytes()).unwrap();
+    dwarf.process_ghidra(ghidra.data(opt).unwrap(), &mut type_map);
+
+    // The location from anvill is kept alongside Ghidra's name
+    let params = entries_with_tag(&dwarf, DW_TAG_formal_parameter);
+    assert_eq!(params.len(), 1);
+    let param = dwarf.get(params[0]);
+    assert_eq!(
+        name_as_bytes(param.get(DW_AT_name).unwrap(), dwarf.strings()),
+        b"argc"
+    );
+    assert!(matches!(
+        param.get(DW_AT_location),
+        Some(AttributeValue::Exprloc(_))
+    ));
+    assert_eq!(
+        param.get(DW_AT_type),
+        Some(&AttributeValue::UnitRef(
+            type_map[&DwarfType::from(&String::from("int"))]
+        ))
+    );
+}
+
+#[test]
+fn merge_str_parameters() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let anvill: AnvillInput = serde_json::from_str(
+        r#"{
+            "arch": "amd64",
+            "os": "linux",
+            "functions": [{"address": 4096, "parameters": [
+                {"register": "RDI", "type": "i"},
+                {"register": "RSI", "type": "i"},
+                {"register": "RDX", "type": "i"}
+            ]}],
+            "symbols": [[4096, "main"]]
+        }"#,
+    )
+    .unwrap();
+    dwarf.process_anvill(anvill.data(opt), &mut type_map);
+    let json = STR_JSON.replace(
+        r#""parameters": null"#,
+        r#""parameters": {"1": {"name": "b", "type": "int"}, "0": {"name": "a", "type": "int"}}"#,
+    );
+    let input: StrBsiInput = serde_json::from_str(&json).unwrap();
+    dwarf.process_str_bsi(input.data(opt), &mut type_map);
+
+    // STR names the first two parameters in ID order and the third is kept
+    let main = entries_with_tag(&dwarf, DW_TAG_subprogram)[0];
+    let params: Vec<_> = dwarf
+        .get(main)
+        .children()
+        .cloned()
+        .filter(|&id| dwarf.get(id).tag() == DW_TAG_formal_parameter)
+        .collect();
+    assert_eq!(params.len(), 3);
+    let names: Vec<_> = params
+        .iter()
+        .map(|&id| {
+            dwarf
+                .get(id)
+                .get(DW_AT_name)
+                .map(|name| name_as_bytes(name, dwarf.strings()))
+        })
+        .collect();
+    assert_eq!(names, [Some(&b"a"[..]), Some(&b"b"[..]), None]);
+    for id in params {
+        assert!(matches!(
+            dwarf.get(id).get(DW_AT_location),
+            Some(AttributeValue::Exprloc(_))
+        ));
+    }
+}
+
+#[test]
+fn str_parameters_without_header() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    // `helper` has structured parameters but no declaration in any header
+    let json = STR_JSON
+        .replace(r#""symbol_name": "main""#, r#""symbol_name": "helper""#)
+        .replace(r#""function": "main""#, r#""function": "helper""#)
+        .replace(
+            r#""parameters": null"#,
+            r#""parameters": {"0": {"name": "len", "type": "unsigned int"}}"#,
+        );
+    let input: StrBsiInput = serde_json::from_str(&json).unwrap();
+    dwarf.process_str_bsi(input.data(opt), &mut type_map);
+
+    let params = entries_with_tag(&dwarf, DW_TAG_formal_parameter);
+    assert_eq!(params.len(), 1);
+    let param = dwarf.get(params[0]);
+    assert_eq!(
+        name_as_bytes(param.get(DW_AT_name).unwrap(), dwarf.strings()),
+        b"len"
+    );
+    assert_eq!(
+        param.get(DW_AT_type),
+        Some(&AttributeValue::UnitRef(
+            type_map[&DwarfType::from(&String::from("unsigned int"))]
+        ))
+    );
+
+    // Rerunning without parameter data leaves the existing parameter alone
+    let input: StrBsiInput = serde_json::from_str(&STR_JSON.replace("main", "helper")).unwrap();
+    dwarf.process_str_bsi(input.data(opt), &mut type_map);
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_formal_parameter), params);
+    let param = dwarf.get(params[0]);
+    assert_eq!(
+        name_as_bytes(param.get(DW_AT_name).unwrap(), dwarf.strings()),
+        b"len"
+    );
+}
+
+#[test]
+fn patch_overrides_inputs() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let ghidra = GhidraInput::from_reader(GHIDRA_CSV.as_bytes()).unwrap();
+    dwarf.process_ghidra(ghidra.data(opt).unwrap(), &mut type_map);
+    let anvill: AnvillInput = serde_json::from_str(ANVILL_JSON).unwrap();
+    dwarf.process_anvill(anvill.data(opt), &mut type_map);
+    let patch: PatchInput = serde_json::from_str(
+        r#"{"functions": {
+            "0x1000": {"name": "patched", "return_type": "char *", "parameters": [{"name": "count"}]},
+            "0x1010": {"name": "added"}
+        }}"#,
+    )
+    .unwrap();
+    dwarf.process_patch(patch.data(opt).unwrap(), &mut type_map);
+
+    let subprograms = entries_with_tag(&dwarf, DW_TAG_subprogram);
+    assert_eq!(subprograms.len(), 2);
+    let names: Vec<_> = subprograms
+        .iter()
+        .map(|&id| name_as_bytes(dwarf.get(id).get(DW_AT_name).unwrap(), dwarf.strings()))
+        .collect();
+    assert!(names.contains(&&b"patched"[..]));
+    assert!(names.contains(&&b"added"[..]));
+
+    let patched = subprograms
+        .into_iter()
+        .find(|&id| dwarf.get(id).get(DW_AT_low_pc).and_then(low_pc_to_u64) == Some(0x1000))
+        .unwrap();
+    let char_ptr = DwarfType::from(&String::from("char *"));
+    assert_eq!(
+        dwarf.get(patched).get(DW_AT_type),
+        Some(&AttributeValue::UnitRef(type_map[&char_ptr]))
+    );
+    // The parameter keeps the type from the Ghidra input
+    let params = entries_with_tag(&dwarf, DW_TAG_formal_parameter);
+    assert_eq!(params.len(), 1);
+    let param = dwarf.get(params[0]);
+    assert_eq!(
+        name_as_bytes(param.get(DW_AT_name).unwrap(), dwarf.strings()),
+        b"count"
+    );
+    assert_eq!(
+        param.get(DW_AT_type),
+        Some(&AttributeValue::UnitRef(
+            type_map[&DwarfType::from(&String::from("int"))]
+        ))
+    );
+}
+
+#[test]
+fn patch_removes_params() {
+    let mut fixture = Fixture::new(&[]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let ghidra =
+        GhidraInput::from_reader(GHIDRA_CSV.replace("int argc", "int a, int b").as_bytes())
+            .unwrap();
+    dwarf.process_ghidra(ghidra.data(opt).unwrap(), &mut type_map);
+    assert_eq!(entries_with_tag(&dwarf, DW_TAG_formal_parameter).len(), 2);
+
+    let patch: PatchInput = serde_json::from_str(
+        r#"{"functions": {"0x1000": {"parameters": [{"name": "a", "type": "int"}]}}}"#,
+    )
+    .unwrap();
+    dwarf.process_patch(patch.data(opt).unwrap(), &mut type_map);
+    let params = entries_with_tag(&dwarf, DW_TAG_formal_parameter);
+    assert_eq!(params.len(), 1);
+    assert_eq!(
+        name_as_bytes(
+            dwarf.get(params[0]).get(DW_AT_name).unwrap(),
+            dwarf.strings()
+        ),
+        b"a"
+    );
+}
+
+const DECL_BIN: &str = "tests/bin/debug.decl.c.elf";
+
+const DECL_ANVILL_JSON: &str = r#"{
+    "arch": "amd64",
+    "os": "linux",
+    "functions": [
+        {"address": 4128, "parameters": [{"register": "RDI", "type": "l"}]},
+        {"address": 4096}
+    ],
+    "symbols": [[4128, "renamed"], [4096, "added"]]
+}"#;
+
+/// Processes `DECL_ANVILL_JSON` and returns the unit with its subprogram
+/// entries by name.
+fn process_decl(
+    fixture: &mut Fixture,
+) -> (DwarfUnitRef<'_>, HashMap<Vec<u8>, (UnitEntryId, bool)>) {
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let input: AnvillInput = serde_json::from_str(DECL_ANVILL_JSON).unwrap();
+    dwarf.process_anvill(input.data(opt), &mut type_map);
+    let subprograms = entries_with_tag(&dwarf, DW_TAG_subprogram)
+        .into_iter()
+        .map(|id| {
+            let entry = dwarf.get(id);
+            let name = name_as_bytes(entry.get(DW_AT_name).unwrap(), dwarf.strings());
+            let marked = entry.get(DW_AT_dwarf_writer).is_some();
+            (name.to_vec(), (id, marked))
+        })
+        .collect();
+    (dwarf, subprograms)
+}
+
+#[test]
+fn created_entries_marked() {
+    let mut fixture = Fixture::with_binary(DECL_BIN, &[]);
+    let (dwarf, subprograms) = process_decl(&mut fixture);
+
+    assert!(subprograms[&b"added"[..]].1);
+    // Existing entries aren't marked even when they're updated
+    let (renamed, marked) = subprograms[&b"renamed"[..]];
+    assert!(!marked);
+    assert!(!subprograms[&b"external"[..]].1);
+    assert!(dwarf.get(dwarf.root()).get(DW_AT_dwarf_writer).is_none());
+    // Parameters created in existing entries are marked
+    let param = dwarf.get(renamed).children().next().copied().unwrap();
+    assert!(dwarf.get(param).get(DW_AT_dwarf_writer).is_some());
+}
+
+#[test]
+fn match_by_name() {
+    // The existing `main` is at 0x1020
+    let csv = "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n\
+               \"main\",\"00001024\",\"int main(void)\",\"8\"\n";
+    for (args, count) in [(&[][..], 2), (&["--match-by-name"][..], 1)] {
+        let mut fixture = Fixture::with_binary(DECL_BIN, args);
+        let (mut dwarf, mut type_map, opt) = fixture.unit();
+        let input = GhidraInput::from_reader(csv.as_bytes()).unwrap();
+        dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+        let mains: Vec<_> = entries_with_tag(&dwarf, DW_TAG_subprogram)
+            .into_iter()
+            .map(|id| dwarf.get(id))
+            .filter(|entry| {
+                name_as_bytes(entry.get(DW_AT_name).unwrap(), dwarf.strings()) == b"main"
+            })
+            .collect();
+        assert_eq!(mains.len(), count);
+        if count == 1 {
+            assert_eq!(
+                mains[0].get(DW_AT_low_pc).and_then(low_pc_to_u64),
+                Some(0x1024)
+            );
+            // The existing entry is updated rather than replaced
+            assert!(mains[0].get(DW_AT_dwarf_writer).is_none());
+        }
+    }
+}
+
+#[test]
+fn name_policy() {
+    for (policy, name) in [("overwrite", &b"renamed"[..]), ("fill", b"main")] {
+        let mut fixture = Fixture::with_binary(DECL_BIN, &["--name-policy", policy]);
+        let (dwarf, subprograms) = process_decl(&mut fixture);
+
+        // The existing function at 0x1020 is named `main`
+        let (id, _) = subprograms[name];
+        assert_eq!(
+            dwarf.get(id).get(DW_AT_low_pc),
+            Some(&AttributeValue::Address(gimli::write::Address::Constant(
+                0x1020
+            )))
+        );
+        // Missing names are always filled in
+        assert!(subprograms.contains_key(&b"added"[..]));
+    }
+}
+
+#[test]
+fn name_template() {
+    let unnamed = r#"{
+        "arch": "amd64",
+        "os": "linux",
+        "functions": [{"address": 4096}],
+        "variables": [{"address": 16392, "type": "i"}],
+        "symbols": []
+    }"#;
+    let names = |args: &[&str]| {
+        let mut fixture = Fixture::new(args);
+        let (mut dwarf, mut type_map, opt) = fixture.unit();
+        let input: AnvillInput = serde_json::from_str(unnamed).unwrap();
+        dwarf.process_anvill(input.data(opt), &mut type_map);
+        [DW_TAG_subprogram, DW_TAG_variable].map(|tag| {
+            let ids = entries_with_tag(&dwarf, tag);
+            assert_eq!(ids.len(), 1);
+            let name = dwarf.get(ids[0]).get(DW_AT_name).unwrap();
+            String::from_utf8(name_as_bytes(name, dwarf.strings()).to_vec()).unwrap()
+        })
+    };
+    assert_eq!(names(&[]), ["FUN_00001000", "VAR_00004008"]);
+    assert_eq!(
+        names(&[
+            "--name-template",
+            "fn=sub_{addr:X}",
+            "--name-template",
+            "var=dword_{addr:x}"
+        ]),
+        ["sub_1000", "dword_4008"]
+    );
+    assert_eq!(
+        names(&["--name-template", "var=g_{addr}"]),
+        ["FUN_00001000", "g_00004008"]
+    );
+
+    for bad in ["sub_{addr}", "fn=sub_", "fn=sub_{address}", "type=t_{addr}"] {
+        assert!(Opt::try_parse_from(["dwarf-writer", "--name-template", bad, TEST_BIN]).is_err());
+    }
+}
+
+#[test]
+fn respect_existing() {
+    let mut fixture = Fixture::with_binary(DECL_BIN, &["--respect-existing"]);
+    let (dwarf, subprograms) = process_decl(&mut fixture);
+
+    let (main, _) = subprograms[&b"main"[..]];
+    assert!(!subprograms.contains_key(&b"renamed"[..]));
+    assert_eq!(dwarf.get(main).children().count(), 0);
+    assert!(subprograms[&b"added"[..]].1);
+}
+
+#[test]
+fn update_only_existing() {
+    let mut fixture = Fixture::with_binary(DECL_BIN, &["--update-only-existing"]);
+    let (_, subprograms) = process_decl(&mut fixture);
+    assert!(subprograms.contains_key(&b"renamed"[..]));
+    assert!(!subprograms.contains_key(&b"added"[..]));
+
+    // Nothing matches in a binary without debug info
+    let counts = run(
+        &["--update-only-existing"],
+        Some(GHIDRA_CSV),
+        Some(ANVILL_JSON),
+        Some(STR_JSON),
+    );
+    assert!(counts.values().all(|&count| count == 0), "{:?}", counts);
+}
+
+#[test]
+fn update_only() {
+    let mut fixture = Fixture::with_binary(DECL_BIN, &[]);
+    assert!(!processed_before(&fixture.elf.dwarf));
+    process_decl(&mut fixture);
+    assert!(processed_before(&fixture.elf.dwarf));
+
+    // The rerun only renames the function added by the first run
+    fixture.opt = Opt::parse_from(["dwarf-writer", "--update-only", DECL_BIN]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let ghidra = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"rerun_added","00001000","void rerun_added(void)","10"
+"rerun_main","00001020","int rerun_main(int x, int y)","10"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(ghidra.data(opt).unwrap(), &mut type_map);
+    let names: HashMap<_, _> = entries_with_tag(&dwarf, DW_TAG_subprogram)
+        .into_iter()
+        .map(|id| {
+            let entry = dwarf.get(id);
+            let name = name_as_bytes(entry.get(DW_AT_name).unwrap(), dwarf.strings());
+            (name.to_vec(), id)
+        })
+        .collect();
+    assert_eq!(names.len(), 3);
+    assert!(names.contains_key(&b"rerun_added"[..]));
+    assert!(names.contains_key(&b"external"[..]));
+    // The compiler's entry keeps the first run's changes but nothing more
+    let renamed = names[&b"renamed"[..]];
+    assert_eq!(dwarf.get(renamed).children().count(), 1);
+}
+
+#[test]
+fn single_cu() {
+    // `first` is in cu_a.c and `second` and `main` are in cu_b.c
+    let mut fixture = Fixture::with_binary("tests/bin/debug.cu.elf", &["--cu", "cu_b.c"]);
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    let ghidra = GhidraInput::from_reader(
+        r#""Name","Location","Function Signature","Function Size"
+"renamed_first","00001000","int renamed_first(int x)","f"
+"doubled","0000100f","int doubled(int x)","e"
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    dwarf.process_ghidra(ghidra.data(opt).unwrap(), &mut type_map);
+
+    let names = |unit: &Unit, strings: &StringTable| -> Vec<Vec<u8>> {
+        let mut names: Vec<_> = entries_with_tag(unit, DW_TAG_subprogram)
+            .into_iter()
+            .map(|id| name_as_bytes(unit.get(id).get(DW_AT_name).unwrap(), strings).to_vec())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(
+        names(&dwarf, dwarf.strings()),
+        vec![b"doubled".to_vec(), b"main".to_vec()]
+    );
+    let units = &fixture.elf.dwarf.units;
+    assert_eq!(units.count(), 2);
+    assert_eq!(
+        names(units.get(units.id(0)), &fixture.elf.dwarf.strings),
+        vec![b"first".to_vec()]
+    );
+}
+
+#[test]
+fn listing() {
+    let bin = "tests/bin/debug.cu.elf";
+    let listing = |args: &[&str]| {
+        let mut fixture = Fixture::with_binary(bin, args);
+        let mut out = Vec::new();
+        write_listing(&mut fixture.elf, &fixture.opt, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    let all = listing(&[]);
+    let functions: Vec<_> = all
+        .lines()
+        .filter(|l| l.starts_with("function\t"))
+        .collect();
+    assert_eq!(
+        functions,
+        vec![
+            "function\t0x1000\t0x100f\tfirst",
+            "function\t0x100f\t0x101d\tsecond",
+            "function\t0x101d\t0x102d\tmain",
+        ]
+    );
+    assert!(all.lines().any(|l| l.starts_with("type\t")));
+    assert!(!listing(&["--cu", "cu_b.c"]).contains("\tfirst"));
+}
+
+#[test]
+fn max_dies_per_cu() {
+    let mut fixture = Fixture::new(&["--max-dies-per-cu", "20"]);
+    let csv = |name: &str| {
+        let mut csv =
+            String::from("\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n");
+        for idx in 0..30 {
+            csv.push_str(&format!(
+                "\"{0}{1}\",\"{2:08x}\",\"int {0}{1}(int a)\",\"10\"\n",
+                name,
+                idx,
+                0x1000 + idx * 0x10
+            ));
+        }
+        GhidraInput::from_reader(csv.as_bytes()).unwrap()
+    };
+    let Fixture { opt, elf } = &mut fixture;
+    let mut dwarf = DwarfUnitRef::new(elf, opt);
+    let mut type_map = dwarf.create_type_map();
+    dwarf.process_ghidra(csv("f").data(opt).unwrap(), &mut type_map);
+    // A later input starts in the first unit and continues in the last one
+    let input = GhidraInput::from_reader(
+        "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n\
+         \"later\",\"00002000\",\"int later(int a)\",\"10\"\n"
+            .as_bytes(),
+    )
+    .unwrap();
+    let mut dwarf = DwarfUnitRef::new(elf, opt);
+    dwarf.process_ghidra(input.data(opt).unwrap(), &mut type_map);
+
+    let check_units = |elf: &ELF, name: &[u8]| {
+        let units = &elf.dwarf.units;
+        assert!(units.count() > 1);
+        let mut addrs = Vec::new();
+        for idx in 0..units.count() {
+            let unit = units.get(units.id(idx));
+            // Each function adds its subprogram and parameter to a unit with
+            // room for them
+            assert!(entry_count(unit) <= 21);
+            for id in entries_with_tag(unit, DW_TAG_subprogram) {
+                let entry = unit.get(id);
+                // Functions refer to the int type in their own unit
+                let ret = attr_to_entry_id(entry.get(DW_AT_type).unwrap());
+                assert!(entries_with_tag(unit, DW_TAG_base_type).contains(&ret));
+                let addr = entry.get(DW_AT_low_pc).and_then(low_pc_to_u64).unwrap();
+                if addr != 0x2000 {
+                    let fn_name = name_as_bytes(entry.get(DW_AT_name).unwrap(), &elf.dwarf.strings);
+                    assert!(fn_name.starts_with(name), "{:?}", fn_name);
+                }
+                addrs.push(addr);
+            }
+        }
+        addrs.sort_unstable();
+        addrs.dedup();
+        assert_eq!(addrs.len(), 31);
+        assert_eq!(entries_with_tag_in_units(&elf.dwarf, DW_TAG_subprogram), 31);
+    };
+    check_units(elf, b"f");
+
+    // Functions moved to the other units are updated instead of duplicated
+    let mut dwarf = DwarfUnitRef::new(elf, opt);
+    dwarf.process_ghidra(csv("g").data(opt).unwrap(), &mut type_map);
+    check_units(elf, b"g");
+    assert!(elf.sections().is_ok());
+
+    // The same goes for later runs on the output
+    fixture.reload();
+    let (mut dwarf, mut type_map, opt) = fixture.unit();
+    dwarf.process_ghidra(csv("h").data(opt).unwrap(), &mut type_map);
+    check_units(&fixture.elf, b"h");
+}
+
+#[test]
+fn external_global_variable() {
+    let process = |fixture: &mut Fixture| {
+        let (mut dwarf, mut type_map, opt) = fixture.unit();
+        let input: AnvillInput = serde_json::from_str(ANVILL_JSON).unwrap();
+        dwarf.process_anvill(input.data(opt), &mut type_map);
+        let vars = entries_with_tag(&dwarf, DW_TAG_variable);
+        assert_eq!(vars.len(), 1);
+        let var = dwarf.get(vars[0]);
+        let ty = dwarf.get(attr_to_entry_id(var.get(DW_AT_type).unwrap()));
+        assert_eq!(ty.tag(), DW_TAG_base_type);
+        assert_eq!(ty.get(DW_AT_byte_size), Some(&AttributeValue::Udata(4)));
+        var.get(DW_AT_external).cloned()
+    };
+
+    assert_eq!(
+        process(&mut Fixture::new(&[])),
+        Some(AttributeValue::Flag(true))
+    );
+
+    // Variables with local symbols, e.g. static variables, aren't external
+    let mut fixture = Fixture::new(&[]);
+    let symbol = DataSymbol {
+        size: 4,
+        is_local: true,
+    };
+    fixture.elf.data_symbols.insert(16392, symbol);
+    assert_eq!(process(&mut fixture), None);
+}
@@ -1,17 +1,33 @@
-use crate::into_gimli::IntoGimli;
+use crate::dwarf_attr::{low_pc_to_u64, DW_AT_dwarf_writer};
+use crate::error::{DwarfWriterError, Result};
+use crate::into_gimli::{IntoGimli, RegisterMap};
+use crate::reloc::{Layout, RelocSection, Relocate, RelocationEntry, RelocationFormat};
 use crate::symbols::Symbols;
-use anyhow::Result;
+use crate::target::Target;
 use gimli::read;
-use gimli::write::{Address, Dwarf, EndianVec, Sections};
-use gimli::{EndianSlice, RunTimeEndian, SectionId};
+use gimli::write::{self, Address, DebugInfoOffsets, Dwarf, Sections, Unit, UnitEntryId, UnitId,
+                   Writer};
+use gimli::{constants, EndianSlice, Reader, RunTimeEndian, SectionId};
 use log::warn;
-use object::{Object, ObjectSection, ObjectSymbol};
+use object::elf::{FileHeader32, FileHeader64, ELFCLASS32, ELFCLASS64, ELFDATA2LSB, ELFDATA2MSB,
+                  ELFMAG, EM_386, EM_AARCH64, EM_ARM, EM_MIPS, EM_NONE, EM_PPC, EM_PPC64,
+                  EM_RISCV, EM_S390, EM_SPARCV9, EM_X86_64, ET_EXEC, ET_REL, EV_CURRENT, PF_R,
+                  PF_X, PT_LOAD, PT_TLS, SHF_ALLOC, SHF_COMPRESSED, SHF_EXECINSTR, SHF_INFO_LINK,
+                  SHN_LORESERVE, SHT_PROGBITS, SHT_REL, SHT_RELA, SHT_STRTAB, SHT_SYMTAB};
+use object::read::elf::{CompressionHeader, FileHeader, ProgramHeader, SectionHeader};
+use object::{Architecture, Bytes, Endianness, Object, ObjectSection, ObjectSymbol, Section,
+             SectionFlags, SymbolKind};
+use ruzstd::decoding::StreamingDecoder;
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::{Read, Write};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, DerefMut, Range};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use tempfile::tempdir;
+use std::sync::Arc;
+use tempfile::{tempdir, NamedTempFile};
 
 /// An ELF and its DWARF debug data.
 #[derive(Debug)]
@@ -23,58 +39,429 @@ pub struct ELF {
     initial_buffer: Vec<u8>,
     /// Mutable DWARF debug data.
     pub dwarf: Dwarf,
+    /// The address ranges of each unit in the input binary in the same order
+    /// as the units in `dwarf`.
+    pub unit_ranges: Vec<Vec<Range<u64>>>,
+    /// The addresses of the variables in the input binary decoded from their
+    /// locations.
+    pub var_addrs: HashMap<(UnitId, UnitEntryId), u64>,
+    /// The addresses of the functions defined in the dynamic symbol table.
+    pub exported_fns: HashSet<u64>,
+    /// The data objects defined in the symbol tables by address.
+    pub data_symbols: HashMap<u64, DataSymbol>,
+    /// The names of the functions defined in the symbol tables by address.
+    /// Names from `.symtab` are preferred over those from `.dynsym`.
+    pub fn_symbols: HashMap<u64, String>,
+    /// The address range of the `PT_TLS` segment which thread-local
+    /// variables' offsets are relative to.
+    tls_segment: Option<Range<u64>>,
+    /// The section layout used for addresses if the binary is a relocatable
+    /// object.
+    layout: Option<Arc<Layout>>,
+    /// The target detected from the ELF header.
+    target: Target,
+    /// Whether a `.debug_aranges` section is written for the units'
+    /// functions.
+    pub emit_aranges: bool,
+    /// Whether entries with children get a `DW_AT_sibling` pointing past
+    /// their children.
+    pub emit_siblings: bool,
+    /// DWARF register numbers given with `--register-map`.
+    pub register_map: RegisterMap,
     elf_path: PathBuf,
 }
 
+/// A data object defined in the binary's symbol table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataSymbol {
+    /// The object's size in bytes or zero if it's unknown.
+    pub size: u64,
+    /// Whether the symbol is only visible in its object file, e.g. a `static`
+    /// variable in C.
+    pub is_local: bool,
+}
+
 impl ELF {
     /// Creates a new `ELF` from an input file path.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         let mut file = fs::File::open(path.as_ref())?;
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer)?;
+        Self::from_buffer(buffer, path)
+    }
+
+    /// Creates a new `ELF` from data which didn't necessarily come from `path`.
+    /// The binary is updated in place at `path` if no output path is given.
+    pub fn from_buffer<P: AsRef<Path>>(buffer: Vec<u8>, path: P) -> Result<Self> {
         let obj = object::File::parse(buffer.as_slice())?;
         let endianness = obj.endianness().into_gimli();
+        let (e_type, e_machine) = elf_kind(&buffer, obj.is_64())?;
+        let layout = (e_type == ET_REL).then(|| Layout::new(&obj, e_machine));
+        let tls_segment = tls_segment(&buffer, obj.is_64())?;
+        let target = Target::detect(&obj);
 
-        // Specify how to load an ELF section
-        let load_section = |id: SectionId| -> Result<Cow<[u8]>> {
+        // Specify how to load an ELF section and its relocations
+        let load_section = |id: SectionId| -> Result<_> {
             let empty = Cow::Borrowed(&[][..]);
-            let section = obj.section_by_name(id.name()).map(|ref section| {
-                section
-                    .uncompressed_data()
-                    .expect("Could not decompress section data")
-            });
-            Ok(section.unwrap_or(empty))
+            let section = obj.section_by_name(id.name());
+            let data = match &section {
+                Some(section) => Some(
+                    match zstd_section_data(section, obj.is_64(), obj.endianness())? {
+                        Some(data) => Cow::Owned(data),
+                        None => section
+                            .uncompressed_data()
+                            .expect("Could not decompress section data"),
+                    },
+                ),
+                None => None,
+            };
+            let relocations = match (&layout, &section) {
+                (Some(layout), Some(section)) => layout.relocations(&obj, section),
+                _ => HashMap::new(),
+            };
+            Ok((data.unwrap_or(empty), relocations))
         };
         let owned_dwarf = read::Dwarf::load(load_section)?;
-        let read_only_dwarf = owned_dwarf.borrow(|section| EndianSlice::new(section, endianness));
-        let dwarf = Dwarf::from(&read_only_dwarf, &|addr| Some(Address::Constant(addr)))?;
+        let read_only_dwarf = owned_dwarf.borrow(|(section, relocations)| {
+            Relocate::new(EndianSlice::new(section, endianness), relocations)
+        });
+        // Relocated addresses in relocatable objects are kept symbolic
+        let dwarf = Dwarf::from(&read_only_dwarf, &|addr| match &layout {
+            Some(layout) => Some(layout.address(addr)),
+            None => Some(Address::Constant(addr)),
+        })?;
+
+        // Unit address ranges and variable addresses can't be read back from
+        // the writable DWARF data
+        let mut unit_ranges = Vec::new();
+        let mut var_addrs = HashMap::new();
+        let mut headers = read_only_dwarf.units();
+        let mut unit_idx = 0;
+        while let Some(header) = headers.next()? {
+            let unit = read_only_dwarf.unit(header)?;
+            let unit_id = dwarf.units.id(unit_idx);
+            unit_idx += 1;
+            // Converted entries are added in the same depth-first order that
+            // they're read in
+            let mut entries = unit.entries();
+            let mut ids = vec![dwarf.units.get(unit_id).root()];
+            while let Some((_, entry)) = entries.next_dfs()? {
+                let id = ids
+                    .pop()
+                    .expect("Converted unit has fewer entries than the input");
+                let children = dwarf.units.get(unit_id).get(id).children();
+                ids.extend(children.rev());
+                if entry.tag() != constants::DW_TAG_variable {
+                    continue
+                }
+                let addr = location_address(&read_only_dwarf, &unit, entry, &tls_segment)?;
+                if let Some(addr) = addr {
+                    var_addrs.insert((unit_id, id), addr);
+                }
+            }
+            let mut ranges = Vec::new();
+            let mut range_iter = read_only_dwarf.unit_ranges(&unit)?;
+            while let Some(range) = range_iter.next()? {
+                ranges.push(range.begin..range.end);
+            }
+            unit_ranges.push(ranges);
+        }
+
+        let exported_fns = obj
+            .dynamic_symbols()
+            .filter(|sym| sym.is_definition() && sym.kind() == SymbolKind::Text)
+            .map(|sym| sym.address())
+            .collect();
+        let data_symbols = obj
+            .symbols()
+            .chain(obj.dynamic_symbols())
+            .filter(|sym| sym.is_definition() && sym.kind() == SymbolKind::Data)
+            .map(|sym| {
+                let data_symbol = DataSymbol {
+                    size: sym.size(),
+                    is_local: sym.is_local(),
+                };
+                (sym.address(), data_symbol)
+            })
+            .collect();
+        let mut fn_symbols = HashMap::new();
+        for sym in obj.symbols().chain(obj.dynamic_symbols()) {
+            if !sym.is_definition() || sym.kind() != SymbolKind::Text {
+                continue
+            }
+            match sym.name() {
+                Ok(name) if !name.is_empty() => {
+                    fn_symbols
+                        .entry(sym.address())
+                        .or_insert_with(|| name.to_string());
+                },
+                _ => {},
+            }
+        }
 
         Ok(Self {
             initial_buffer: buffer,
             dwarf,
+            unit_ranges,
+            var_addrs,
+            exported_fns,
+            data_symbols,
+            fn_symbols,
+            tls_segment,
+            layout: layout.map(Arc::new),
+            target,
+            emit_aranges: false,
+            emit_siblings: false,
+            register_map: RegisterMap::default(),
             elf_path: path.as_ref().to_path_buf(),
         })
     }
 
     /// Parses the ELF object data. Note this object data is not kept
     /// synchronized with changes to DWARF debug data.
-    pub fn object(&self) -> object::File {
+    pub fn object(&self) -> object::File<'_> {
         // The constructor ensures that the buffer is a valid object file
         object::File::parse(self.initial_buffer.as_slice()).unwrap()
     }
 
-    /// Write the DWARF debug data to ELF sections.
-    pub fn sections(&mut self) -> Result<Sections<EndianVec<RunTimeEndian>>> {
+    /// Converts the address of a thread-local variable to its offset in the
+    /// TLS block. Addresses outside the `PT_TLS` segment are assumed to
+    /// already be offsets.
+    pub fn tls_offset(&self, addr: u64) -> u64 {
+        match &self.tls_segment {
+            Some(segment) if segment.contains(&addr) => addr - segment.start,
+            _ => addr,
+        }
+    }
+
+    /// The target detected from the ELF header.
+    pub fn target(&self) -> Target {
+        self.target
+    }
+
+    /// Write the DWARF debug data to ELF sections along with the relocations
+    /// needed if the binary is a relocatable object.
+    pub fn sections(&mut self) -> Result<DebugSections<RelocSection>> {
         let endianness = self.object().endianness().into_gimli();
-        let mut sections = Sections::new(EndianVec::new(endianness));
-        self.dwarf.write(&mut sections)?;
+        let mut sections = DebugSections::new(RelocSection::new(endianness, self.layout.clone()));
+        self.write_dwarf(&mut sections)?;
         Ok(sections)
     }
 
+    /// Writes the DWARF debug data like `Dwarf::write` but keeps the units'
+    /// offsets which are needed for `.debug_aranges`.
+    fn write_dwarf<W: Writer + Clone>(&mut self, sections: &mut DebugSections<W>) -> Result<()> {
+        self.set_unit_ranges();
+        self.intern_strings();
+        if self.emit_siblings {
+            self.set_siblings();
+        }
+        let dwarf = &mut self.dwarf;
+        let line_strings = dwarf.line_strings.write(&mut sections.debug_line_str)?;
+        let strings = dwarf.strings.write(&mut sections.debug_str)?;
+        let offsets = dwarf
+            .units
+            .write(&mut sections.sections, &line_strings, &strings)?;
+        for line_program in &dwarf.line_programs {
+            line_program.write(
+                &mut sections.debug_line,
+                line_program.encoding(),
+                &line_strings,
+                &strings,
+            )?;
+        }
+        if self.emit_aranges {
+            self.write_aranges(&mut sections.debug_aranges, &offsets)?;
+        }
+        Ok(())
+    }
+
+    /// Writes an address range table for each unit with the ranges of its
+    /// functions.
+    fn write_aranges<W: Writer>(&self, w: &mut W, offsets: &DebugInfoOffsets) -> Result<()> {
+        let units = &self.dwarf.units;
+        for idx in 0..units.count() {
+            let id = units.id(idx);
+            let unit = units.get(id);
+            let ranges = function_ranges(unit);
+            if ranges.is_empty() {
+                continue
+            }
+            let encoding = unit.encoding();
+            let address_size = encoding.address_size;
+            let start = w.len();
+            let length_offset = w.write_initial_length(encoding.format)?;
+            let length_base = w.len();
+            w.write_u16(2)?;
+            w.write_offset(
+                offsets.unit(id).0,
+                SectionId::DebugInfo,
+                encoding.format.word_size(),
+            )?;
+            w.write_u8(address_size)?;
+            // Segment selector size
+            w.write_u8(0)?;
+            // The ranges are aligned to the size of a range
+            let range_size = 2 * address_size as usize;
+            let padding = (range_size - (w.len() - start) % range_size) % range_size;
+            w.write(&vec![0; padding])?;
+            for (begin, length) in ranges {
+                w.write_address(begin, address_size)?;
+                w.write_udata(length, address_size)?;
+            }
+            w.write_udata(0, address_size)?;
+            w.write_udata(0, address_size)?;
+            let length = (w.len() - length_base) as u64;
+            w.write_initial_length_at(length_offset, length, encoding.format)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the address range of units created by dwarf-writer and units
+    /// without one to the ranges of their functions so tools looking up the
+    /// unit containing an address find it. Contiguous functions are covered by
+    /// `DW_AT_low_pc` and `DW_AT_high_pc`, otherwise a `DW_AT_ranges` list is
+    /// used. Ranges written by the compiler are extended to cover functions
+    /// added to the unit outside of them.
+    fn set_unit_ranges(&mut self) {
+        let units = &mut self.dwarf.units;
+        for idx in 0..units.count() {
+            let unit = units.get_mut(units.id(idx));
+            let root = unit.get(unit.root());
+            let has_range = root.get(constants::DW_AT_low_pc).is_some() ||
+                root.get(constants::DW_AT_ranges).is_some();
+            if has_range && root.get(DW_AT_dwarf_writer).is_none() {
+                let existing = self.unit_ranges.get(idx).map_or(&[][..], |r| &r[..]);
+                extend_compiler_range(unit, existing, self.layout.as_deref());
+                continue
+            }
+            let ranges = merge_ranges(function_ranges(unit));
+            let root = unit.root();
+            match ranges[..] {
+                [] => {},
+                [(begin, length)] => {
+                    let root = unit.get_mut(root);
+                    root.delete(constants::DW_AT_ranges);
+                    root.set(
+                        constants::DW_AT_low_pc,
+                        write::AttributeValue::Address(begin),
+                    );
+                    root.set(
+                        constants::DW_AT_high_pc,
+                        write::AttributeValue::Udata(length),
+                    );
+                },
+                _ => {
+                    let ranges = ranges
+                        .into_iter()
+                        .map(|(begin, length)| write::Range::StartLength { begin, length })
+                        .collect();
+                    let id = unit.ranges.add(write::RangeList(ranges));
+                    let root = unit.get_mut(root);
+                    root.delete(constants::DW_AT_high_pc);
+                    // The base address of the unit's range list entries
+                    root.set(
+                        constants::DW_AT_low_pc,
+                        write::AttributeValue::Address(Address::Constant(0)),
+                    );
+                    root.set(
+                        constants::DW_AT_ranges,
+                        write::AttributeValue::RangeListRef(id),
+                    );
+                },
+            }
+        }
+    }
+
+    /// Moves strings stored inline in entries to `.debug_str` so strings used
+    /// by many entries (e.g. repeated names and the `DW_AT_dwarf_writer`
+    /// marker) are only stored once. Relocatable objects without a
+    /// `.debug_str` section symbol keep their inline strings since offsets
+    /// into a new `.debug_str` couldn't be relocated.
+    fn intern_strings(&mut self) {
+        if let Some(layout) = &self.layout {
+            if !layout.has_debug_symbol(SectionId::DebugStr) {
+                return
+            }
+        }
+        let Dwarf { units, strings, .. } = &mut self.dwarf;
+        for idx in 0..units.count() {
+            let unit = units.get_mut(units.id(idx));
+            let mut to_visit = vec![unit.root()];
+            while let Some(id) = to_visit.pop() {
+                let entry = unit.get_mut(id);
+                for attr in entry.attrs_mut() {
+                    if let write::AttributeValue::String(s) = attr.get() {
+                        let id = strings.add(s.clone());
+                        attr.set(write::AttributeValue::StringRef(id));
+                    }
+                }
+                to_visit.extend(entry.children());
+            }
+        }
+    }
+
+    /// Sets `DW_AT_sibling` on every entry below the units' roots which has
+    /// children. gimli fills in the offsets of the next siblings when the
+    /// units are written.
+    fn set_siblings(&mut self) {
+        let units = &mut self.dwarf.units;
+        for idx in 0..units.count() {
+            let unit = units.get_mut(units.id(idx));
+            let mut to_visit = unit
+                .get(unit.root())
+                .children()
+                .copied()
+                .collect::<Vec<_>>();
+            while let Some(id) = to_visit.pop() {
+                let entry = unit.get_mut(id);
+                if entry.children().next().is_some() {
+                    entry.set_sibling(true);
+                }
+                to_visit.extend(entry.children());
+            }
+        }
+    }
+
+    /// Warns that DWARF sections written on their own don't include the
+    /// relocations a relocatable object needs.
+    fn warn_unrelocated(&self) {
+        if self.layout.is_some() {
+            warn!("Relocations are only written when updating a relocatable object in-process");
+        }
+    }
+
+    /// Writes the DWARF debug data as GNU assembler directives instead of
+    /// updating the binary.
+    pub fn write_asm<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.warn_unrelocated();
+        let sections = self.sections()?;
+        let mut file = fs::File::create(path)?;
+        sections.for_each(|section, data| {
+            if !data.slice().is_empty() {
+                write_section_asm(&mut file, section.name(), data.slice())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Updates the symbols and DWARF sections in the output binary or in the
+    /// input binary if no output path is given. If `no_clobber` is set an
+    /// existing output binary is not overwritten.
     pub fn update_binary(
         mut self, output_path: Option<PathBuf>, objcopy_path: Option<PathBuf>,
-        output_dir: Option<PathBuf>, syms: Symbols,
+        output_dir: Option<PathBuf>, syms: Symbols, no_clobber: bool,
     ) -> Result<()> {
+        check_clobber(output_path.as_deref(), no_clobber)?;
+        // Symbols added by objcopy would shift the symbol indices that the
+        // debug info's relocations refer to
+        if self.layout.is_some() {
+            return Err(DwarfWriterError::UnsupportedBinary(
+                "Relocatable objects must be updated without objcopy to keep their debug info's \
+                 relocations"
+                    .to_string(),
+            ))
+        }
         let temp_dir = tempdir()?;
         let dir = match output_dir {
             Some(ref dir) => dir.as_path(),
@@ -99,13 +486,10 @@ impl ELF {
             .collect();
         for s in syms.0 {
             // If an existing symbol has a matching address, find its name
-            let addr_exists = existing_syms.iter().find_map(|&(name, addr)| {
-                if addr == s.value {
-                    name.map(|n| Some(n)).unwrap_or(None)
-                } else {
-                    None
-                }
-            });
+            let addr_exists =
+                existing_syms
+                    .iter()
+                    .find_map(|&(name, addr)| if addr == s.value { name.ok() } else { None });
             // If an existing symbol has a matching name, find its address
             let name_exists = existing_syms.iter().find_map(|&(name, addr)| {
                 name.map(|n| if n == s.name { Some(addr) } else { None })
@@ -124,7 +508,7 @@ impl ELF {
                 (None, Some(_)) => {
                     // If a symbol with the same name has a different address, update its address by
                     // first stripping the existing symbol then adding it again
-                    cmd.arg("--strip-symbol").arg(s.name.to_owned());
+                    cmd.arg("--strip-symbol").arg(&s.name);
                     cmd.arg("--add-symbol").arg(s.objcopy_add_cmd());
                 },
                 (Some(existing_name), Some(existing_addr)) => {
@@ -135,58 +519,1586 @@ impl ELF {
                 },
             };
         }
-        let output = cmd.arg(output_path.as_path()).output()?;
-        let stdout = std::str::from_utf8(&output.stdout)?;
-        let stderr = std::str::from_utf8(&output.stderr)?;
-        if !stdout.is_empty() {
-            warn!("{}", stdout);
-        }
-        if !stderr.is_empty() {
-            warn!("{}", stderr);
-        }
+        run_objcopy(cmd.arg(output_path.as_path()))?;
 
         // Update DWARF info
-        let updated_sections = &self.sections()?;
-
-        updated_sections.for_each(|section, data| {
-            if !data.slice().is_empty() {
-                // Remove leading '.' in section name to avoid creating dot files
-                let file_name = &section.name()[1..];
-                let section_path = &dir.join(file_name);
-                // Write section data to a file
-                let mut file = fs::File::create(section_path)?;
-                file.write_all(data.slice())?;
-
-                // Pass section file and binary through objcopy
-                let section_exists = self
-                    .object()
-                    .sections()
-                    .any(|s| s.name() == Ok(section.name()));
-                let objcopy_cmd = if section_exists {
-                    "--update-section"
-                } else {
-                    "--add-section"
-                };
+        for (section, section_path) in self.write_sections(dir)? {
+            // Pass section file and binary through objcopy
+            let section_exists = self
+                .object()
+                .sections()
+                .any(|s| s.name() == Ok(section.name()));
+            let objcopy_cmd = if section_exists {
+                "--update-section"
+            } else {
+                "--add-section"
+            };
 
-                let mut objcopy_arg = section.name().to_string();
-                objcopy_arg.push('=');
-                objcopy_arg.push_str(section_path.as_path().to_str().unwrap());
+            let mut objcopy_arg = section.name().to_string();
+            objcopy_arg.push('=');
+            objcopy_arg.push_str(section_path.as_path().to_str().unwrap());
 
-                let output = Command::new(objcopy)
+            run_objcopy(
+                Command::new(objcopy)
                     .arg(objcopy_cmd)
                     .arg(objcopy_arg.as_str())
-                    .arg(output_path.as_path())
-                    .output()?;
-                let stdout = std::str::from_utf8(&output.stdout)?;
-                let stderr = std::str::from_utf8(&output.stderr)?;
-                if !stdout.is_empty() {
-                    warn!("{}", stdout);
+                    .arg(output_path.as_path()),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the binary with the updated DWARF sections to the output path or
+    /// the input path if no output path is given without running objcopy.
+    pub fn update_binary_in_process(
+        &mut self, output_path: Option<PathBuf>, no_clobber: bool,
+    ) -> Result<()> {
+        check_clobber(output_path.as_deref(), no_clobber)?;
+        let path = output_path.unwrap_or_else(|| self.elf_path.clone());
+        // The input binary is already in memory so it can be overwritten
+        let file = fs::File::create(path)?;
+        self.write_binary(io::BufWriter::new(file))
+    }
+
+    /// Writes the binary with the updated DWARF sections to `out` without
+    /// running objcopy. The section data, a new section name table and a new
+    /// section header table are appended to the input binary so its segments
+    /// are left untouched. Replaced sections' old data is left in place.
+    pub fn write_binary<W: Write>(&mut self, mut out: W) -> Result<()> {
+        let mut sections = self.sections()?;
+        let object = self.object();
+        let is_64 = object.is_64();
+        let endianness = object.endianness();
+        let data = self.initial_buffer.as_slice();
+        let (mut headers, shstrndx) = if is_64 {
+            section_headers::<FileHeader64<Endianness>>(data)?
+        } else {
+            section_headers::<FileHeader32<Endianness>>(data)?
+        };
+        let format = self.layout.as_ref().and_then(|layout| layout.format());
+        sections.for_each_mut(|section, data| {
+            if data.slice().is_empty() {
+                return Ok(())
+            }
+            let name = section.name().as_bytes();
+            let relocations = std::mem::take(&mut data.relocations);
+            let data = Some(data.take());
+            let index = match headers.iter().position(|h| h.name == name) {
+                Some(index) => {
+                    let header = &mut headers[index];
+                    header.sh_type = SHT_PROGBITS;
+                    header.flags &= !u64::from(SHF_COMPRESSED);
+                    header.data = data;
+                    index
+                },
+                None => {
+                    headers.push(SectionData::new(name, data));
+                    headers.len() - 1
+                },
+            };
+            if self.layout.is_some() {
+                update_relocations(&mut headers, index, &relocations, format, is_64, endianness)?;
+            }
+            Ok::<_, DwarfWriterError>(())
+        })?;
+        if headers.len() >= usize::from(SHN_LORESERVE) || shstrndx == 0 {
+            return Err(DwarfWriterError::UnsupportedBinary(
+                "Binaries with extended section numbering or without section names must be \
+                 updated with objcopy"
+                    .to_string(),
+            ))
+        }
+        let mut names = vec![0];
+        for header in headers.iter_mut().filter(|h| !h.name.is_empty()) {
+            header.name_offset = names.len() as u32;
+            names.extend(&header.name);
+            names.push(0);
+        }
+        headers[shstrndx].data = Some(names);
+
+        // Lay out the appended data followed by the section headers
+        let mut offset = data.len() as u64;
+        for header in &mut headers {
+            if let Some(data) = &header.data {
+                offset = align(offset, header.addralign);
+                header.offset = offset;
+                header.size = data.len() as u64;
+                offset += header.size;
+            }
+        }
+        let shoff = align(offset, if is_64 { 8 } else { 4 });
+
+        let mut elf_header = FieldWriter::new(endianness, is_64);
+        elf_header.word(shoff);
+        let (shoff_pos, shnum_pos) = if is_64 { (0x28, 0x3c) } else { (0x20, 0x30) };
+        let mut prefix = data[..shnum_pos + 4].to_vec();
+        prefix[shoff_pos..shoff_pos + elf_header.buf.len()].copy_from_slice(&elf_header.buf);
+        let mut counts = FieldWriter::new(endianness, is_64);
+        counts.u16(headers.len() as u16);
+        counts.u16(shstrndx as u16);
+        prefix[shnum_pos..].copy_from_slice(&counts.buf);
+        out.write_all(&prefix)?;
+        out.write_all(&data[prefix.len()..])?;
+
+        let mut written = data.len() as u64;
+        for header in &headers {
+            if let Some(data) = &header.data {
+                out.write_all(&vec![0; (header.offset - written) as usize])?;
+                out.write_all(data)?;
+                written = header.offset + header.size;
+            }
+        }
+        out.write_all(&vec![0; (shoff - written) as usize])?;
+        for header in &headers {
+            let mut fields = FieldWriter::new(endianness, is_64);
+            header.write_header(&mut fields);
+            out.write_all(&fields.buf)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Writes each non-empty DWARF section to a file in `dir` named after the
+    /// section without touching any binary. Returns the sections written and
+    /// their paths.
+    pub fn write_sections<P: AsRef<Path>>(&mut self, dir: P) -> Result<Vec<(SectionId, PathBuf)>> {
+        self.warn_unrelocated();
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        // Sections are streamed to their files to avoid holding a second copy
+        // of the debug info in memory
+        let endianness = self.object().endianness().into_gimli();
+        let mut sections = DebugSections::new(SectionFile::new(dir, endianness));
+        let res = self.write_dwarf(&mut sections);
+        sections.for_each_mut(|_, file| match file.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        })?;
+        res?;
+        let mut written = Vec::new();
+        sections.for_each_mut(|section, file| {
+            // Remove leading '.' in section name to avoid creating dot files
+            let section_path = dir.join(&section.name()[1..]);
+            if file.persist(&section_path)? {
+                written.push((section, section_path));
+            }
+            Ok::<_, DwarfWriterError>(())
+        })?;
+        Ok(written)
+    }
+}
+
+/// The DWARF sections written by gimli along with the `.debug_aranges`
+/// section which gimli doesn't support writing.
+pub struct DebugSections<W: Writer> {
+    sections: Sections<W>,
+    pub debug_aranges: W,
+}
+
+impl<W: Writer + Clone> DebugSections<W> {
+    fn new(section: W) -> Self {
+        DebugSections {
+            sections: Sections::new(section.clone()),
+            debug_aranges: section,
+        }
+    }
+}
+
+impl<W: Writer> DebugSections<W> {
+    pub fn for_each<F, E>(&self, mut f: F) -> std::result::Result<(), E>
+    where F: FnMut(SectionId, &W) -> std::result::Result<(), E> {
+        self.sections.for_each(&mut f)?;
+        f(SectionId::DebugAranges, &self.debug_aranges)
+    }
+
+    pub fn for_each_mut<F, E>(&mut self, mut f: F) -> std::result::Result<(), E>
+    where F: FnMut(SectionId, &mut W) -> std::result::Result<(), E> {
+        self.sections.for_each_mut(&mut f)?;
+        f(SectionId::DebugAranges, &mut self.debug_aranges)
+    }
+}
+
+impl<W: Writer> Deref for DebugSections<W> {
+    type Target = Sections<W>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.sections
+    }
+}
+
+impl<W: Writer> DerefMut for DebugSections<W> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.sections
+    }
+}
+
+/// Returns the start address and length of each of a unit's functions.
+fn function_ranges(unit: &Unit) -> Vec<(Address, u64)> {
+    let mut ranges = Vec::new();
+    let mut to_visit = vec![unit.root()];
+    while let Some(id) = to_visit.pop() {
+        let entry = unit.get(id);
+        to_visit.extend(entry.children());
+        if entry.tag() != constants::DW_TAG_subprogram {
+            continue
+        }
+        let (low_pc, high_pc) = match (
+            entry.get(constants::DW_AT_low_pc),
+            entry.get(constants::DW_AT_high_pc),
+        ) {
+            (Some(write::AttributeValue::Address(low_pc)), Some(high_pc)) => (*low_pc, high_pc),
+            _ => continue,
+        };
+        let length = match high_pc {
+            write::AttributeValue::Address(high_pc) => {
+                let (low_pc, high_pc) = (address_value(low_pc), address_value(*high_pc));
+                high_pc.wrapping_sub(low_pc)
+            },
+            offset => match low_pc_to_u64(offset) {
+                Some(length) => length,
+                None => continue,
+            },
+        };
+        ranges.push((low_pc, length));
+    }
+    ranges.sort_by_key(|&(low_pc, _)| address_value(low_pc));
+    ranges
+}
+
+/// Extends the range of a unit written by the compiler to cover its functions.
+/// The unit's `DW_AT_low_pc` is kept since it's the base address of the
+/// compiler's location and range lists, so a `DW_AT_ranges` list is used
+/// unless the functions extend the end of a `DW_AT_low_pc`/`DW_AT_high_pc`
+/// range.
+fn extend_compiler_range(unit: &mut Unit, existing: &[Range<u64>], layout: Option<&Layout>) {
+    let address = |addr| match layout {
+        Some(layout) => layout.address(addr),
+        None => Address::Constant(addr),
+    };
+    let existing: Vec<_> = existing
+        .iter()
+        .map(|r| (address(r.start), r.end - r.start))
+        .collect();
+    let mut ranges = existing.clone();
+    ranges.append(&mut function_ranges(unit));
+    ranges.sort_by_key(|&(begin, _)| address_value(begin));
+    let ranges = merge_ranges(ranges);
+    if ranges == merge_ranges(existing) {
+        return
+    }
+    let root = unit.get(unit.root());
+    let low_pc = match (
+        root.get(constants::DW_AT_low_pc),
+        root.get(constants::DW_AT_high_pc),
+    ) {
+        (Some(write::AttributeValue::Address(low_pc)), Some(_)) => Some(*low_pc),
+        _ => None,
+    };
+    let root = unit.root();
+    match ranges[..] {
+        [(begin, length)] if Some(begin) == low_pc => {
+            unit.get_mut(root).set(
+                constants::DW_AT_high_pc,
+                write::AttributeValue::Udata(length),
+            );
+        },
+        _ => {
+            // The list's ranges aren't offsets from the unit's low_pc
+            let base = write::Range::BaseAddress {
+                address: Address::Constant(0),
+            };
+            let ranges = std::iter::once(base)
+                .chain(
+                    ranges
+                        .into_iter()
+                        .map(|(begin, length)| write::Range::StartLength { begin, length }),
+                )
+                .collect();
+            let id = unit.ranges.add(write::RangeList(ranges));
+            let root = unit.get_mut(root);
+            root.delete(constants::DW_AT_high_pc);
+            root.set(
+                constants::DW_AT_ranges,
+                write::AttributeValue::RangeListRef(id),
+            );
+        },
+    }
+}
+
+/// Merges sorted function ranges which overlap or are adjacent and drops
+/// empty ones.
+fn merge_ranges(ranges: Vec<(Address, u64)>) -> Vec<(Address, u64)> {
+    let mut merged: Vec<(Address, u64)> = Vec::new();
+    for (begin, length) in ranges {
+        if length == 0 {
+            continue
+        }
+        if let Some((last_begin, last_length)) = merged.last_mut() {
+            let same_base = match (*last_begin, begin) {
+                (Address::Constant(_), Address::Constant(_)) => true,
+                (Address::Symbol { symbol: a, .. }, Address::Symbol { symbol: b, .. }) => a == b,
+                _ => false,
+            };
+            let last_start = address_value(*last_begin);
+            let start = address_value(begin);
+            if same_base && start <= last_start + *last_length {
+                *last_length = (*last_length).max(start - last_start + length);
+                continue
+            }
+        }
+        merged.push((begin, length));
+    }
+    merged
+}
+
+/// The value of an address, which is held in the addend of symbolic
+/// addresses in relocatable objects.
+fn address_value(address: Address) -> u64 {
+    match address {
+        Address::Constant(addr) => addr,
+        Address::Symbol { addend, .. } => addend as u64,
+    }
+}
+
+/// A gimli `Writer` which writes a DWARF section to a temporary file instead of
+/// building it in memory. The file is only created once something is written
+/// and each clone writes to its own file.
+struct SectionFile {
+    dir: PathBuf,
+    endianness: RunTimeEndian,
+    file: Option<io::BufWriter<NamedTempFile>>,
+    len: usize,
+    /// The first I/O error hit while writing since gimli's errors can't hold
+    /// one.
+    error: Option<io::Error>,
+}
+
+impl SectionFile {
+    fn new(dir: &Path, endianness: RunTimeEndian) -> Self {
+        SectionFile {
+            dir: dir.to_path_buf(),
+            endianness,
+            file: None,
+            len: 0,
+            error: None,
+        }
+    }
+
+    /// Moves the section's file to `path`. Returns false if the section is
+    /// empty and no file was written.
+    fn persist(&mut self, path: &Path) -> Result<bool> {
+        match self.file.take() {
+            Some(file) => {
+                let file = file.into_inner().map_err(io::Error::from)?;
+                // Temporary files are only readable by their owner so use the
+                // permissions of a regular new file instead
+                if let Some(permissions) = new_file_permissions() {
+                    file.as_file().set_permissions(permissions)?;
+                }
+                file.persist(path).map_err(io::Error::from)?;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    fn try_write_at(&mut self, offset: Option<usize>, bytes: &[u8]) -> io::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self
+                .file
+                .insert(io::BufWriter::new(NamedTempFile::new_in(&self.dir)?)),
+        };
+        match offset {
+            Some(offset) => {
+                file.seek(SeekFrom::Start(offset as u64))?;
+                file.write_all(bytes)?;
+                file.seek(SeekFrom::End(0))?;
+            },
+            None => file.write_all(bytes)?,
+        }
+        Ok(())
+    }
+}
+
+impl Clone for SectionFile {
+    fn clone(&self) -> Self {
+        SectionFile::new(&self.dir, self.endianness)
+    }
+}
+
+impl Writer for SectionFile {
+    type Endian = RunTimeEndian;
+
+    fn endian(&self) -> Self::Endian {
+        self.endianness
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> write::Result<()> {
+        if bytes.is_empty() {
+            return Ok(())
+        }
+        self.try_write_at(None, bytes).map_err(|err| {
+            self.error.get_or_insert(err);
+            write::Error::LengthOutOfBounds
+        })?;
+        self.len += bytes.len();
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) -> write::Result<()> {
+        if offset + bytes.len() > self.len {
+            return Err(write::Error::LengthOutOfBounds)
+        }
+        self.try_write_at(Some(offset), bytes).map_err(|err| {
+            self.error.get_or_insert(err);
+            write::Error::LengthOutOfBounds
+        })
+    }
+
+    /// Section files can't hold relocations so symbolic addresses from
+    /// relocatable objects are written as their address.
+    fn write_address(&mut self, address: Address, size: u8) -> write::Result<()> {
+        match address {
+            Address::Constant(addr) => self.write_udata(addr, size),
+            Address::Symbol { addend, .. } => self.write_udata(addend as u64, size),
+        }
+    }
+}
+
+/// Decodes the address of a variable from its `DW_AT_location`. Only
+/// locations made up of a single `DW_OP_addr` or `DW_OP_addrx` are decoded
+/// since anything else (e.g. thread-local storage) isn't a plain address. For
+/// location lists the first such address is used.
+fn location_address<R: Reader<Offset = usize>>(
+    dwarf: &read::Dwarf<R>, unit: &read::Unit<R>, entry: &read::DebuggingInformationEntry<R>,
+    tls_segment: &Option<Range<u64>>,
+) -> Result<Option<u64>> {
+    let location = match entry.attr_value(constants::DW_AT_location)? {
+        Some(location) => location,
+        None => return Ok(None),
+    };
+    if let read::AttributeValue::Exprloc(expr) = location {
+        return expr_address(dwarf, unit, expr, tls_segment)
+    }
+    let mut locations = match dwarf.attr_locations(unit, location)? {
+        Some(locations) => locations,
+        None => return Ok(None),
+    };
+    while let Some(location) = locations.next()? {
+        if let Some(addr) = expr_address(dwarf, unit, location.data, tls_segment)? {
+            return Ok(Some(addr))
+        }
+    }
+    Ok(None)
+}
+
+/// Decodes an expression made up of a single `DW_OP_addr` or `DW_OP_addrx`,
+/// or of a constant TLS offset followed by `DW_OP_form_tls_address` (or the
+/// GNU equivalent) which is decoded to an address in the `PT_TLS` segment.
+fn expr_address<R: Reader<Offset = usize>>(
+    dwarf: &read::Dwarf<R>, unit: &read::Unit<R>, expr: read::Expression<R>,
+    tls_segment: &Option<Range<u64>>,
+) -> Result<Option<u64>> {
+    let mut ops = expr.operations(unit.encoding());
+    let addr = match ops.next()? {
+        Some(read::Operation::Address { address }) => address,
+        Some(read::Operation::AddressIndex { index }) => dwarf.address(unit, index)?,
+        Some(read::Operation::UnsignedConstant { value }) => match ops.next()? {
+            Some(read::Operation::TLS) => tls_segment
+                .as_ref()
+                .map_or(value, |segment| segment.start + value),
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    match ops.next()? {
+        None => Ok(Some(addr)),
+        Some(_) => Ok(None),
+    }
+}
+
+/// Fails if `no_clobber` is set and the output binary already exists.
+/// The permissions of a regular new file, i.e. read and write permissions
+/// without the bits cleared by the process's umask.
+#[cfg(unix)]
+fn new_file_permissions() -> Option<fs::Permissions> {
+    use std::os::unix::fs::PermissionsExt;
+    // The umask can only be read by replacing it so it's restored right away
+    let umask = unsafe {
+        let umask = libc::umask(0);
+        libc::umask(umask);
+        umask
+    };
+    Some(fs::Permissions::from_mode(0o666 & !(umask as u32)))
+}
+
+#[cfg(not(unix))]
+fn new_file_permissions() -> Option<fs::Permissions> {
+    None
+}
+
+fn check_clobber(output_path: Option<&Path>, no_clobber: bool) -> Result<()> {
+    match output_path {
+        Some(path) if no_clobber && path.exists() => {
+            Err(DwarfWriterError::OutputExists(path.to_path_buf()))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// A section header and the data to append for sections written by
+/// `write_binary`.
+struct SectionData {
+    name: Vec<u8>,
+    name_offset: u32,
+    sh_type: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    addralign: u64,
+    entsize: u64,
+    /// The section's new data if it's been replaced or added.
+    data: Option<Vec<u8>>,
+}
+
+impl SectionData {
+    fn new(name: &[u8], data: Option<Vec<u8>>) -> Self {
+        SectionData {
+            name: name.to_vec(),
+            name_offset: 0,
+            sh_type: SHT_PROGBITS,
+            flags: 0,
+            addr: 0,
+            offset: 0,
+            size: 0,
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+            data,
+        }
+    }
+
+    /// Encodes the section's header.
+    fn write_header(&self, fields: &mut FieldWriter) {
+        fields.u32(self.name_offset);
+        fields.u32(self.sh_type);
+        fields.word(self.flags);
+        fields.word(self.addr);
+        fields.word(self.offset);
+        fields.word(self.size);
+        fields.u32(self.link);
+        fields.u32(self.info);
+        fields.word(self.addralign);
+        fields.word(self.entsize);
+    }
+}
+
+/// The ELF machine type of an architecture.
+fn elf_machine(arch: Architecture) -> u16 {
+    match arch {
+        Architecture::Aarch64 => EM_AARCH64,
+        Architecture::Arm => EM_ARM,
+        Architecture::I386 => EM_386,
+        Architecture::X86_64 | Architecture::X86_64_X32 => EM_X86_64,
+        Architecture::Mips | Architecture::Mips64 => EM_MIPS,
+        Architecture::PowerPc => EM_PPC,
+        Architecture::PowerPc64 => EM_PPC64,
+        Architecture::Riscv32 | Architecture::Riscv64 => EM_RISCV,
+        Architecture::S390x => EM_S390,
+        Architecture::Sparc64 => EM_SPARCV9,
+        _ => EM_NONE,
+    }
+}
+
+/// Wraps raw code in a minimal executable with a single loadable segment and
+/// `.text` section mapping the code at `base` so debug info can be written
+/// for it.
+pub fn raw_elf(code: &[u8], base: u64, target: Target) -> Vec<u8> {
+    const PAGE_SIZE: u64 = 0x1000;
+    let is_64 = target.ptr_size == 8;
+    let (ehsize, phentsize, shentsize) = if is_64 { (64, 56, 64) } else { (52, 32, 40) };
+    // The code's file offset must be congruent to its address modulo the
+    // page size to be loadable
+    let code_offset = PAGE_SIZE + base % PAGE_SIZE;
+    let names = b"\0.text\0.shstrtab\0";
+    let names_offset = code_offset + code.len() as u64;
+    let shoff = align(names_offset + names.len() as u64, if is_64 { 8 } else { 4 });
+
+    let mut text = SectionData::new(b".text", None);
+    text.name_offset = 1;
+    text.flags = u64::from(SHF_ALLOC | SHF_EXECINSTR);
+    text.addr = base;
+    text.offset = code_offset;
+    text.size = code.len() as u64;
+    // The code's alignment is unknown so it's the largest power of two up to
+    // 16 which the base is a multiple of
+    text.addralign = 1 << base.trailing_zeros().min(4);
+    let mut shstrtab = SectionData::new(b".shstrtab", None);
+    shstrtab.name_offset = 7;
+    shstrtab.sh_type = SHT_STRTAB;
+    shstrtab.offset = names_offset;
+    shstrtab.size = names.len() as u64;
+    let headers = [SectionData::new(b"", None), text, shstrtab];
+
+    let mut elf = FieldWriter::new(target.endianness, is_64);
+    elf.buf.extend(ELFMAG);
+    elf.buf.push(if is_64 { ELFCLASS64 } else { ELFCLASS32 });
+    elf.buf.push(match target.endianness {
+        Endianness::Little => ELFDATA2LSB,
+        Endianness::Big => ELFDATA2MSB,
+    });
+    elf.buf.push(EV_CURRENT);
+    elf.buf.resize(16, 0);
+    elf.u16(ET_EXEC);
+    elf.u16(elf_machine(target.arch));
+    elf.u32(EV_CURRENT.into());
+    elf.word(base);
+    elf.word(ehsize);
+    elf.word(shoff);
+    elf.u32(0);
+    elf.u16(ehsize as u16);
+    elf.u16(phentsize);
+    elf.u16(1);
+    elf.u16(shentsize);
+    elf.u16(headers.len() as u16);
+    elf.u16(headers.len() as u16 - 1);
+
+    // The program header's fields are ordered differently in each class
+    let flags = PF_R | PF_X;
+    let size = code.len() as u64;
+    elf.u32(PT_LOAD);
+    if is_64 {
+        elf.u32(flags);
+    }
+    elf.word(code_offset);
+    elf.word(base);
+    elf.word(base);
+    elf.word(size);
+    elf.word(size);
+    if !is_64 {
+        elf.u32(flags);
+    }
+    elf.word(PAGE_SIZE);
+
+    elf.buf.resize(code_offset as usize, 0);
+    elf.buf.extend(code);
+    elf.buf.extend(names);
+    elf.buf.resize(shoff as usize, 0);
+    for header in &headers {
+        header.write_header(&mut elf);
+    }
+    elf.buf
+}
+
+/// Reads an ELF's section headers and the index of its section name table.
+fn section_headers<Elf: FileHeader<Endian = Endianness>>(
+    data: &[u8],
+) -> Result<(Vec<SectionData>, usize)> {
+    let header = Elf::parse(data)?;
+    let endian = header.endian()?;
+    let sections = header.sections(endian, data)?;
+    let headers = sections
+        .iter()
+        .map(|section| {
+            Ok(SectionData {
+                name: sections.section_name(endian, section)?.to_vec(),
+                name_offset: 0,
+                sh_type: section.sh_type(endian),
+                flags: section.sh_flags(endian).into(),
+                addr: section.sh_addr(endian).into(),
+                offset: section.sh_offset(endian).into(),
+                size: section.sh_size(endian).into(),
+                link: section.sh_link(endian),
+                info: section.sh_info(endian),
+                addralign: section.sh_addralign(endian).into(),
+                entsize: section.sh_entsize(endian).into(),
+                data: None,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok((headers, header.e_shstrndx(endian).into()))
+}
+
+/// Reads the object file type and machine type from an ELF header.
+fn elf_kind(data: &[u8], is_64: bool) -> Result<(u16, u16)> {
+    fn kind<Elf: FileHeader<Endian = Endianness>>(data: &[u8]) -> Result<(u16, u16)> {
+        let header = Elf::parse(data)?;
+        let endian = header.endian()?;
+        Ok((header.e_type(endian), header.e_machine(endian)))
+    }
+    if is_64 {
+        kind::<FileHeader64<Endianness>>(data)
+    } else {
+        kind::<FileHeader32<Endianness>>(data)
+    }
+}
+
+/// The `ch_type` of sections compressed with zstd.
+const ELFCOMPRESS_ZSTD: u32 = 2;
+
+/// Decompresses a section compressed with zstd since the object crate only
+/// supports zlib. Returns `None` if the section isn't compressed with zstd.
+fn zstd_section_data(
+    section: &Section, is_64: bool, endian: Endianness,
+) -> Result<Option<Vec<u8>>> {
+    fn decompress<Elf: FileHeader<Endian = Endianness>>(
+        data: &[u8], endian: Endianness,
+    ) -> Result<Option<Vec<u8>>> {
+        let mut data = Bytes(data);
+        let header = data.read::<Elf::CompressionHeader>().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, "Invalid compression header")
+        })?;
+        if header.ch_type(endian) != ELFCOMPRESS_ZSTD {
+            return Ok(None)
+        }
+        let size = header.ch_size(endian).into();
+        let mut res = Vec::with_capacity(size as usize);
+        StreamingDecoder::new(data.0)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+            .read_to_end(&mut res)?;
+        if res.len() as u64 != size {
+            let msg = "Decompressed section size doesn't match its compression header";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg).into())
+        }
+        Ok(Some(res))
+    }
+    let is_compressed = match section.flags() {
+        SectionFlags::Elf { sh_flags } => sh_flags & u64::from(SHF_COMPRESSED) != 0,
+        _ => false,
+    };
+    if !is_compressed {
+        return Ok(None)
+    }
+    let data = section.data()?;
+    if is_64 {
+        decompress::<FileHeader64<Endianness>>(data, endian)
+    } else {
+        decompress::<FileHeader32<Endianness>>(data, endian)
+    }
+}
+
+/// Reads the address range of the `PT_TLS` segment.
+fn tls_segment(data: &[u8], is_64: bool) -> Result<Option<Range<u64>>> {
+    fn segment<Elf: FileHeader<Endian = Endianness>>(data: &[u8]) -> Result<Option<Range<u64>>> {
+        let header = Elf::parse(data)?;
+        let endian = header.endian()?;
+        let segment = header
+            .program_headers(endian, data)?
+            .iter()
+            .find(|segment| segment.p_type(endian) == PT_TLS)
+            .map(|segment| {
+                let start = segment.p_vaddr(endian).into();
+                start..start + segment.p_memsz(endian).into()
+            });
+        Ok(segment)
+    }
+    if is_64 {
+        segment::<FileHeader64<Endianness>>(data)
+    } else {
+        segment::<FileHeader32<Endianness>>(data)
+    }
+}
+
+/// Replaces the relocation section for the section at `index` in a
+/// relocatable object, adding one if it has none.
+fn update_relocations(
+    headers: &mut Vec<SectionData>, index: usize, relocations: &[RelocationEntry],
+    format: Option<RelocationFormat>, is_64: bool, endianness: Endianness,
+) -> Result<()> {
+    let existing = headers
+        .iter()
+        .position(|h| matches!(h.sh_type, SHT_REL | SHT_RELA) && h.info as usize == index);
+    if relocations.is_empty() {
+        if let Some(existing) = existing {
+            headers[existing].data = Some(Vec::new());
+        }
+        return Ok(())
+    }
+    let format = format.ok_or_else(|| {
+        DwarfWriterError::UnsupportedBinary(
+            "Writing debug info relocations isn't supported for this architecture".to_string(),
+        )
+    })?;
+    let mut data = FieldWriter::new(endianness, is_64);
+    for reloc in relocations {
+        let r_type = format.r_type(reloc.size).ok_or_else(|| {
+            DwarfWriterError::UnsupportedBinary(format!(
+                "Unable to relocate a {}-byte value for this architecture",
+                reloc.size
+            ))
+        })?;
+        data.word(reloc.offset);
+        if is_64 {
+            data.word(((reloc.symbol as u64) << 32) | u64::from(r_type));
+        } else {
+            data.word(((reloc.symbol as u64) << 8) | u64::from(r_type));
+        }
+        if format.is_rela {
+            data.word(reloc.addend as u64);
+        }
+    }
+    let word_size = if is_64 { 8 } else { 4 };
+    let (sh_type, prefix, entsize) = if format.is_rela {
+        (SHT_RELA, &b".rela"[..], 3 * word_size)
+    } else {
+        (SHT_REL, &b".rel"[..], 2 * word_size)
+    };
+    let existing = match existing {
+        Some(existing) => existing,
+        None => {
+            let symtab = headers
+                .iter()
+                .position(|h| h.sh_type == SHT_SYMTAB)
+                .ok_or_else(|| {
+                    DwarfWriterError::UnsupportedBinary(
+                        "Relocatable objects without a symbol table can't be updated".to_string(),
+                    )
+                })?;
+            let name = [prefix, &headers[index].name].concat();
+            headers.push(SectionData {
+                sh_type,
+                flags: u64::from(SHF_INFO_LINK),
+                link: symtab as u32,
+                info: index as u32,
+                addralign: word_size,
+                entsize,
+                ..SectionData::new(&name, None)
+            });
+            headers.len() - 1
+        },
+    };
+    let header = &mut headers[existing];
+    header.sh_type = sh_type;
+    header.entsize = entsize;
+    header.data = Some(data.buf);
+    Ok(())
+}
+
+/// Rounds `offset` up to a multiple of `alignment`.
+fn align(offset: u64, alignment: u64) -> u64 {
+    let alignment = alignment.max(1);
+    offset.div_ceil(alignment) * alignment
+}
+
+/// Encodes ELF header fields with the binary's endianness and word size.
+struct FieldWriter {
+    endianness: Endianness,
+    is_64: bool,
+    buf: Vec<u8>,
+}
+
+impl FieldWriter {
+    fn new(endianness: Endianness, is_64: bool) -> Self {
+        FieldWriter {
+            endianness,
+            is_64,
+            buf: Vec::new(),
+        }
+    }
+
+    fn u16(&mut self, val: u16) {
+        match self.endianness {
+            Endianness::Little => self.buf.extend(val.to_le_bytes()),
+            Endianness::Big => self.buf.extend(val.to_be_bytes()),
+        }
+    }
+
+    fn u32(&mut self, val: u32) {
+        match self.endianness {
+            Endianness::Little => self.buf.extend(val.to_le_bytes()),
+            Endianness::Big => self.buf.extend(val.to_be_bytes()),
+        }
+    }
+
+    fn u64(&mut self, val: u64) {
+        match self.endianness {
+            Endianness::Little => self.buf.extend(val.to_le_bytes()),
+            Endianness::Big => self.buf.extend(val.to_be_bytes()),
+        }
+    }
+
+    /// Writes an address or offset sized for the ELF class.
+    fn word(&mut self, val: u64) {
+        if self.is_64 {
+            self.u64(val)
+        } else {
+            self.u32(val as u32)
+        }
+    }
+}
+
+/// Runs an objcopy command. Its output is logged as warnings unless it fails.
+fn run_objcopy(cmd: &mut Command) -> Result<()> {
+    let output = cmd.output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stdout.is_empty() {
+        warn!("{}", stdout);
+    }
+    if !output.status.success() {
+        return Err(DwarfWriterError::Objcopy {
+            status: output.status,
+            stderr: stderr.into_owned(),
+        })
+    }
+    if !stderr.is_empty() {
+        warn!("{}", stderr);
+    }
+    Ok(())
+}
+
+/// Renders a section's data as GNU assembler directives.
+fn write_section_asm<W: Write>(out: &mut W, name: &str, data: &[u8]) -> io::Result<()> {
+    writeln!(out, "\t.section {},\"\",%progbits", name)?;
+    for chunk in data.chunks(16) {
+        let bytes: Vec<_> = chunk.iter().map(|b| format!("0x{:02x}", b)).collect();
+        writeln!(out, "\t.byte {}", bytes.join(","))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anvill::AnvillInput;
+    use crate::dwarf_unit::{listing, DwarfUnitRef};
+    use crate::ghidra::GhidraInput;
+    use crate::{InputFile, Opt};
+    use clap::Parser;
+    use object::ObjectSegment;
+
+    #[test]
+    fn asm_matches_sections() {
+        let dir = tempdir().unwrap();
+        let asm_path = dir.path().join("debug.s");
+        let obj_path = dir.path().join("debug.o");
+
+        let opt = Opt::parse_from(["dwarf-writer", "tests/strip_bin/types.c.elf"]);
+        let mut elf = ELF::new(opt.input_path()).unwrap();
+        let input = AnvillInput::new("tests/anvill_json/strip.types.c.json").unwrap();
+        let mut dwarf = DwarfUnitRef::new(&mut elf, &opt);
+        let mut type_map = dwarf.create_type_map();
+        dwarf.process_anvill(input.data(&opt), &mut type_map);
+        elf.write_asm(&asm_path).unwrap();
+        let status = Command::new("as")
+            .arg(&asm_path)
+            .arg("-o")
+            .arg(&obj_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let obj_data = fs::read(&obj_path).unwrap();
+        let obj = object::File::parse(obj_data.as_slice()).unwrap();
+        let sections = elf.sections().unwrap();
+        assert!(!sections.debug_info.slice().is_empty());
+        sections
+            .for_each(|section, data| {
+                if !data.slice().is_empty() {
+                    let assembled = obj.section_by_name(section.name()).unwrap();
+                    assert_eq!(assembled.data().unwrap(), data.slice());
                 }
-                if !stderr.is_empty() {
-                    warn!("{}", stderr);
+                Ok::<_, anyhow::Error>(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn zstd_compressed_sections() {
+        let bin = "tests/bin/debug.types.c.zstd.elf";
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        let mut elf = ELF::new(bin).unwrap();
+        let expected = listing(&mut elf, &opt);
+        assert!(expected.iter().any(|line| line.ends_with("\tmain")));
+        assert!(expected
+            .iter()
+            .any(|line| line.starts_with("type\t") && line.contains("uint64_t")));
+
+        // The sections are written back uncompressed
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("output.elf");
+        elf.update_binary_in_process(Some(output.clone()), false)
+            .unwrap();
+        let mut elf = ELF::new(&output).unwrap();
+        assert_eq!(listing(&mut elf, &opt), expected);
+    }
+
+    #[test]
+    fn interned_strings() {
+        let bin = "tests/strip_bin/empty.c.elf";
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        let mut elf = ELF::new(bin).unwrap();
+        let input = GhidraInput::from_reader(
+            "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n\
+             \"handler\",\"00001000\",\"void handler(void)\",\"8\"\n\
+             \"handler\",\"00001008\",\"void handler(void)\",\"8\"\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut dwarf = DwarfUnitRef::new(&mut elf, &opt);
+        let mut type_map = dwarf.create_type_map();
+        dwarf.process_ghidra(input.data(&opt).unwrap(), &mut type_map);
+
+        let sections = elf.sections().unwrap();
+        let count = |data: &[u8], s: &[u8]| data.windows(s.len()).filter(|w| *w == s).count();
+        assert_eq!(count(sections.debug_str.slice(), b"handler\0"), 1);
+        assert_eq!(count(sections.debug_info.slice(), b"handler"), 0);
+        assert_eq!(count(sections.debug_str.slice(), b"dwarf-writer "), 1);
+    }
+
+    #[test]
+    fn aranges() {
+        let bin = "tests/strip_bin/empty.c.elf";
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        let mut elf = ELF::new(bin).unwrap();
+        let input = GhidraInput::from_reader(
+            "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n\
+             \"first\",\"00001000\",\"void first(void)\",\"8\"\n\
+             \"second\",\"00001010\",\"void second(void)\",\"c\"\n\
+             \"thunk\",\"00001020\",\"void thunk(void)\",\"0\"\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut dwarf = DwarfUnitRef::new(&mut elf, &opt);
+        let mut type_map = dwarf.create_type_map();
+        dwarf.process_ghidra(input.data(&opt).unwrap(), &mut type_map);
+        assert!(elf.sections().unwrap().debug_aranges.slice().is_empty());
+
+        elf.emit_aranges = true;
+        let sections = elf.sections().unwrap();
+        let aranges =
+            read::DebugAranges::new(sections.debug_aranges.slice(), RunTimeEndian::Little);
+        let mut headers = aranges.headers();
+        let header = headers.next().unwrap().unwrap();
+        assert_eq!(header.debug_info_offset().0, 0);
+        let mut ranges = Vec::new();
+        let mut entries = header.entries();
+        while let Some(entry) = entries.next().unwrap() {
+            ranges.push((entry.address(), entry.length()));
+        }
+        // Functions without a size aren't included
+        assert_eq!(ranges, vec![(0x1000, 8), (0x1010, 0xc)]);
+        assert!(headers.next().unwrap().is_none());
+    }
+
+    /// Reads back the range of each unit and of its subprograms.
+    fn unit_and_function_ranges(elf: &mut ELF) -> Vec<(Vec<read::Range>, Vec<read::Range>)> {
+        let sections = elf.sections().unwrap();
+        let mut data = HashMap::new();
+        sections
+            .for_each(|id, section| -> Result<()> {
+                data.insert(id, section.slice().to_vec());
+                Ok(())
+            })
+            .unwrap();
+        let dwarf = read::Dwarf::load(|id| -> std::result::Result<_, ()> {
+            let slice = data.get(&id).map_or(&[][..], |data| &data[..]);
+            Ok(EndianSlice::new(slice, RunTimeEndian::Little))
+        })
+        .unwrap();
+        let collect = |mut iter: read::RangeIter<_>| {
+            let mut ranges = Vec::new();
+            while let Some(range) = iter.next().unwrap() {
+                ranges.push(range);
+            }
+            ranges
+        };
+        let mut res = Vec::new();
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next().unwrap() {
+            let unit = dwarf.unit(header).unwrap();
+            let unit_ranges = collect(dwarf.unit_ranges(&unit).unwrap());
+            let mut functions = Vec::new();
+            let mut entries = unit.entries();
+            while let Some((_, entry)) = entries.next_dfs().unwrap() {
+                if entry.tag() == constants::DW_TAG_subprogram {
+                    functions.append(&mut collect(dwarf.die_ranges(&unit, entry).unwrap()));
                 }
             }
-            Ok(())
+            res.push((unit_ranges, functions));
+        }
+        res
+    }
+
+    #[test]
+    fn unit_ranges() {
+        let bin = "tests/strip_bin/empty.c.elf";
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        let process = |functions: &str| {
+            let mut elf = ELF::new(bin).unwrap();
+            let input = GhidraInput::from_reader(
+                format!(
+                    "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n{}",
+                    functions
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+            let mut dwarf = DwarfUnitRef::new(&mut elf, &opt);
+            let mut type_map = dwarf.create_type_map();
+            dwarf.process_ghidra(input.data(&opt).unwrap(), &mut type_map);
+            let mut ranges = unit_and_function_ranges(&mut elf);
+            assert_eq!(ranges.len(), 1);
+            ranges.remove(0)
+        };
+        let range = |begin, end| read::Range { begin, end };
+
+        // Adjacent functions are covered by the unit's low and high pc
+        let (unit, functions) = process(
+            "\"first\",\"00001000\",\"void first(void)\",\"10\"\n\
+             \"second\",\"00001010\",\"void second(void)\",\"c\"\n",
+        );
+        assert_eq!(unit, vec![range(0x1000, 0x101c)]);
+        assert_eq!(functions.len(), 2);
+
+        // Functions with gaps between them use a range list
+        let (unit, functions) = process(
+            "\"first\",\"00001000\",\"void first(void)\",\"8\"\n\
+             \"second\",\"00001010\",\"void second(void)\",\"c\"\n\
+             \"third\",\"00001018\",\"void third(void)\",\"10\"\n\
+             \"thunk\",\"00001040\",\"void thunk(void)\",\"0\"\n",
+        );
+        assert_eq!(unit, vec![range(0x1000, 0x1008), range(0x1010, 0x1028)]);
+        assert_eq!(functions.len(), 3);
+        for function in functions {
+            assert!(unit
+                .iter()
+                .any(|r| r.begin <= function.begin && function.end <= r.end));
+        }
+    }
+
+    #[test]
+    fn compiler_unit_ranges() {
+        let bin = "tests/bin/debug.decl.c.elf";
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        let process = |functions: &str| {
+            let mut elf = ELF::new(bin).unwrap();
+            let input = GhidraInput::from_reader(
+                format!(
+                    "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n{}",
+                    functions
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+            let mut dwarf = DwarfUnitRef::new(&mut elf, &opt);
+            let mut type_map = dwarf.create_type_map();
+            dwarf.process_ghidra(input.data(&opt).unwrap(), &mut type_map);
+            let mut ranges = unit_and_function_ranges(&mut elf);
+            assert_eq!(ranges.len(), 1);
+            ranges.remove(0)
+        };
+        let range = |begin, end| read::Range { begin, end };
+
+        // Functions inside of the compiler's range leave it alone
+        let (unit, _) = process("\"main\",\"00001020\",\"int main(void)\",\"10\"\n");
+        assert_eq!(unit, vec![range(0x1020, 0x1030)]);
+
+        // Functions at the end of the range extend its high pc
+        let (unit, _) = process("\"after\",\"00001030\",\"void after(void)\",\"8\"\n");
+        assert_eq!(unit, vec![range(0x1020, 0x1038)]);
+
+        // Other functions switch to a range list without moving the low pc
+        // which compiler-produced lists are relative to
+        let (unit, functions) = process(
+            "\"before\",\"00001000\",\"void before(void)\",\"8\"\n\
+             \"far\",\"00009000\",\"void far(void)\",\"10\"\n",
+        );
+        assert_eq!(
+            unit,
+            vec![
+                range(0x1000, 0x1008),
+                range(0x1020, 0x1030),
+                range(0x9000, 0x9010)
+            ]
+        );
+        assert!(functions.contains(&range(0x1020, 0x1030)));
+        assert!(functions.contains(&range(0x9000, 0x9010)));
+    }
+
+    #[test]
+    fn sibling_attrs() {
+        let bin = "tests/strip_bin/empty.c.elf";
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        let mut elf = ELF::new(bin).unwrap();
+        let input = GhidraInput::from_reader(
+            "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n\
+             \"first\",\"00001000\",\"int first(int a, int b)\",\"8\"\n\
+             \"second\",\"00001010\",\"void second(void)\",\"c\"\n\
+             \"third\",\"00001020\",\"void third(char c)\",\"4\"\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut dwarf = DwarfUnitRef::new(&mut elf, &opt);
+        let mut type_map = dwarf.create_type_map();
+        dwarf.process_ghidra(input.data(&opt).unwrap(), &mut type_map);
+        elf.emit_siblings = true;
+        let sections = elf.sections().unwrap();
+
+        let debug_info = read::DebugInfo::new(sections.debug_info.slice(), RunTimeEndian::Little);
+        let debug_abbrev =
+            read::DebugAbbrev::new(sections.debug_abbrev.slice(), RunTimeEndian::Little);
+        let unit = debug_info.units().next().unwrap().unwrap();
+        let abbrevs = unit.abbreviations(&debug_abbrev).unwrap();
+        let mut tree = unit.entries_tree(&abbrevs, None).unwrap();
+        let root = tree.root().unwrap();
+        assert!(root
+            .entry()
+            .attr(constants::DW_AT_sibling)
+            .unwrap()
+            .is_none());
+        // Each sibling attribute points to the entry after the last child
+        let mut children = root.children();
+        let mut siblings = 0;
+        let mut next_sibling = None;
+        while let Some(child) = children.next().unwrap() {
+            let entry = child.entry();
+            if let Some(offset) = next_sibling.take() {
+                assert_eq!(entry.offset(), offset);
+            }
+            match entry.attr_value(constants::DW_AT_sibling).unwrap() {
+                Some(read::AttributeValue::UnitRef(offset)) => {
+                    assert!(entry.has_children());
+                    next_sibling = Some(offset);
+                    siblings += 1;
+                },
+                Some(attr) => panic!("Unexpected DW_AT_sibling {:?}", attr),
+                None => assert!(!entry.has_children()),
+            }
+        }
+        assert_eq!(siblings, 2);
+    }
+
+    #[test]
+    fn raw_code_wrapper() {
+        let target: Target = "mips-unknown-linux-gnu".parse().unwrap();
+        let code = [0x03, 0xe0, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00];
+        let data = raw_elf(&code, 0x80001234, target);
+        let obj = object::File::parse(data.as_slice()).unwrap();
+        assert_eq!(Target::detect(&obj), target);
+        assert_eq!(obj.entry(), 0x80001234);
+        let text = obj.section_by_name(".text").unwrap();
+        assert_eq!(text.address(), 0x80001234);
+        assert_eq!(text.align(), 4);
+        assert_eq!(text.data().unwrap(), code);
+        assert_eq!(text.file_range().unwrap().0 % 0x1000, 0x234);
+        assert!(obj
+            .segments()
+            .any(|segment| segment.address() == 0x80001234));
+    }
+
+    /// Returns the address, line and end of sequence flag of each row in a
+    /// binary's line tables.
+    fn line_rows(path: &Path) -> Vec<(u64, Option<u64>, bool)> {
+        let data = fs::read(path).unwrap();
+        let obj = object::File::parse(data.as_slice()).unwrap();
+        let dwarf = read::Dwarf::load(|id| -> Result<_> {
+            let data = obj
+                .section_by_name(id.name())
+                .map(|section| section.uncompressed_data().unwrap())
+                .unwrap_or_default();
+            Ok(data)
         })
+        .unwrap();
+        let dwarf = dwarf.borrow(|data| EndianSlice::new(data, RunTimeEndian::Little));
+        let mut rows = Vec::new();
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next().unwrap() {
+            let unit = dwarf.unit(header).unwrap();
+            let mut program_rows = match unit.line_program {
+                Some(program) => program.rows(),
+                None => continue,
+            };
+            while let Some((_, row)) = program_rows.next_row().unwrap() {
+                rows.push((
+                    row.address(),
+                    row.line().map(|l| l.get()),
+                    row.end_sequence(),
+                ));
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn line_table_preserved() {
+        let bin = "tests/bin/debug.decl.c.elf";
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        let mut elf = ELF::new(bin).unwrap();
+        let input = GhidraInput::from_reader(
+            "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n\
+             \"renamed\",\"00001020\",\"int renamed(void)\",\"10\"\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut dwarf = DwarfUnitRef::new(&mut elf, &opt);
+        let mut type_map = dwarf.create_type_map();
+        dwarf.process_ghidra(input.data(&opt).unwrap(), &mut type_map);
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("output.elf");
+        elf.update_binary_in_process(Some(output.clone()), false)
+            .unwrap();
+
+        let rows = line_rows(Path::new(bin));
+        assert!(!rows.is_empty());
+        assert_eq!(line_rows(&output), rows);
+    }
+
+    #[test]
+    fn no_clobber() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("out.elf");
+        fs::write(&output_path, b"existing").unwrap();
+
+        let elf = ELF::new("tests/strip_bin/empty.c.elf").unwrap();
+        let res = elf.update_binary(Some(output_path.clone()), None, None, Symbols::new(), true);
+        assert!(res.is_err());
+        assert_eq!(fs::read(&output_path).unwrap(), b"existing");
+    }
+
+    #[test]
+    fn section_files_only() {
+        let dir = tempdir().unwrap();
+        let section_dir = dir.path().join("sections");
+        let bin = "tests/strip_bin/types.c.elf";
+        let original = fs::read(bin).unwrap();
+
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        let mut elf = ELF::new(bin).unwrap();
+        let input = AnvillInput::new("tests/anvill_json/strip.types.c.json").unwrap();
+        let mut dwarf = DwarfUnitRef::new(&mut elf, &opt);
+        let mut type_map = dwarf.create_type_map();
+        dwarf.process_anvill(input.data(&opt), &mut type_map);
+        let written = elf.write_sections(&section_dir).unwrap();
+
+        // The streamed section files should match the sections written in
+        // memory
+        let sections = elf.sections().unwrap();
+        assert!(written.iter().any(|&(id, _)| id == SectionId::DebugInfo));
+        sections
+            .for_each(|id, data| {
+                let path = section_dir.join(&id.name()[1..]);
+                if data.slice().is_empty() {
+                    assert!(!path.exists());
+                } else {
+                    assert_eq!(fs::read(path).unwrap(), data.slice());
+                }
+                Ok::<_, anyhow::Error>(())
+            })
+            .unwrap();
+        assert_eq!(fs::read(bin).unwrap(), original);
+
+        // Section files get the permissions of a regular new file
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode();
+            let new_file = dir.path().join("new");
+            fs::File::create(&new_file).unwrap();
+            assert_eq!(mode(&section_dir.join("debug_info")), mode(&new_file));
+        }
+    }
+
+    #[test]
+    fn write_binary_to_pipe() {
+        let bin = "tests/strip_bin/types.c.elf";
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        let mut elf = ELF::new(bin).unwrap();
+        let input = AnvillInput::new("tests/anvill_json/strip.types.c.json").unwrap();
+        let mut dwarf = DwarfUnitRef::new(&mut elf, &opt);
+        let mut type_map = dwarf.create_type_map();
+        dwarf.process_anvill(input.data(&opt), &mut type_map);
+
+        let (mut reader, writer) = io::pipe().unwrap();
+        let writer = std::thread::spawn(move || elf.write_binary(writer));
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        writer.join().unwrap().unwrap();
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("output.elf");
+        fs::write(&path, &output).unwrap();
+        let updated = ELF::new(&path).unwrap();
+        assert_eq!(updated.dwarf.units.count(), 1);
+        let unit = updated.dwarf.units.get(updated.dwarf.units.id(0));
+        assert!(unit.get(unit.root()).children().next().is_some());
+
+        // The input's sections and segments should be unchanged
+        let original = fs::read(bin).unwrap();
+        let original = object::File::parse(original.as_slice()).unwrap();
+        let object = updated.object();
+        // other than the section names
+        for section in original.sections().filter(|s| s.name() != Ok(".shstrtab")) {
+            let name = section.name().unwrap();
+            let updated = object.section_by_name(name).unwrap();
+            assert_eq!(updated.address(), section.address());
+            assert_eq!(updated.data().unwrap(), section.data().unwrap());
+        }
+        assert_eq!(
+            object
+                .segments()
+                .map(|s| (s.address(), s.size()))
+                .collect::<Vec<_>>(),
+            original
+                .segments()
+                .map(|s| (s.address(), s.size()))
+                .collect::<Vec<_>>()
+        );
+        assert!(object.section_by_name(".debug_info").is_some());
+    }
+
+    #[test]
+    fn big_endian_round_trip() {
+        let bin = "tests/bin/debug.enum.c.sparc.o";
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        let mut elf = ELF::new(bin).unwrap();
+        let input: AnvillInput = serde_json::from_str(
+            r#"{
+                "arch": "sparc32",
+                "os": "linux",
+                "variables": [{"address": 305419896, "type": "i"}],
+                "symbols": [[305419896, "added"]]
+            }"#,
+        )
+        .unwrap();
+        let mut dwarf = DwarfUnitRef::new(&mut elf, &opt);
+        let mut type_map = dwarf.create_type_map();
+        dwarf.process_anvill(input.data(&opt), &mut type_map);
+
+        let mut section_data = std::collections::HashMap::new();
+        elf.sections()
+            .unwrap()
+            .for_each(|id, data| {
+                section_data.insert(id, data.slice().to_vec());
+                Ok::<_, anyhow::Error>(())
+            })
+            .unwrap();
+        let dwarf = read::Dwarf::load(|id| -> Result<_> {
+            let data = section_data.get(&id).map(Vec::as_slice).unwrap_or(&[]);
+            Ok(EndianSlice::new(data, RunTimeEndian::Big))
+        })
+        .unwrap();
+        let header = dwarf.units().next().unwrap().unwrap();
+        let unit = dwarf.unit(header).unwrap();
+        let mut entries = unit.entries();
+        let (mut red, mut added, mut color_ty) = (None, None, None);
+        while let Some((_, entry)) = entries.next_dfs().unwrap() {
+            let name = match entry.attr_value(gimli::DW_AT_name).unwrap() {
+                Some(name) => dwarf.attr_string(&unit, name).unwrap().to_vec(),
+                None => continue,
+            };
+            match name.as_slice() {
+                b"RED" => red = entry.attr_value(gimli::DW_AT_const_value).unwrap(),
+                b"added" => added = entry.attr_value(gimli::DW_AT_location).unwrap(),
+                b"color" if entry.tag() == gimli::DW_TAG_variable => {
+                    color_ty = entry.attr_value(gimli::DW_AT_type).unwrap()
+                },
+                _ => (),
+            }
+        }
+
+        assert_eq!(red, Some(read::AttributeValue::Udata(0x12345678)));
+        match added {
+            Some(read::AttributeValue::Exprloc(expr)) => assert_eq!(
+                expr.0.slice(),
+                [gimli::DW_OP_addr.0, 0x12, 0x34, 0x56, 0x78]
+            ),
+            _ => panic!("Unexpected location {:?}", added),
+        }
+        match color_ty {
+            Some(read::AttributeValue::UnitRef(offset)) => {
+                let ty = unit.entry(offset).unwrap();
+                assert_eq!(ty.tag(), gimli::DW_TAG_enumeration_type);
+            },
+            _ => panic!("Unexpected type {:?}", color_ty),
+        }
+    }
+
+    #[test]
+    fn relocatable_object() {
+        // `main` is at .text+0 and `counter` is at .data+0, which is laid out
+        // after the 12 bytes of .text
+        let bin = "tests/bin/debug.var.c.o";
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        let mut elf = ELF::new(bin).unwrap();
+        let subprograms = |elf: &ELF| -> HashMap<Vec<u8>, write::AttributeValue> {
+            let unit = elf.dwarf.units.get(elf.dwarf.units.id(0));
+            unit.get(unit.root())
+                .children()
+                .map(|&id| unit.get(id))
+                .filter(|entry| entry.tag() == constants::DW_TAG_subprogram)
+                .map(|entry| {
+                    let name = match entry.get(constants::DW_AT_name).unwrap() {
+                        write::AttributeValue::StringRef(id) => elf.dwarf.strings.get(*id).to_vec(),
+                        write::AttributeValue::String(name) => name.clone(),
+                        name => panic!("Unexpected name {:?}", name),
+                    };
+                    (name, entry.get(constants::DW_AT_low_pc).unwrap().clone())
+                })
+                .collect()
+        };
+        let text_symbol =
+            |addend| write::AttributeValue::Address(Address::Symbol { symbol: 2, addend });
+        assert_eq!(subprograms(&elf)[&b"main"[..]], text_symbol(0));
+        assert_eq!(elf.var_addrs.values().collect::<Vec<_>>(), [&12]);
+
+        // Names are read through the relocations into .debug_str
+        let input: AnvillInput = serde_json::from_str(
+            r#"{
+                "arch": "amd64",
+                "os": "linux",
+                "functions": [{"address": 0}, {"address": 6}],
+                "symbols": [[0, "renamed"], [6, "added"]]
+            }"#,
+        )
+        .unwrap();
+        let mut dwarf = DwarfUnitRef::new(&mut elf, &opt);
+        let mut type_map = dwarf.create_type_map();
+        dwarf.process_anvill(input.data(&opt), &mut type_map);
+
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("output.o");
+        elf.update_binary_in_process(Some(output.clone()), false)
+            .unwrap();
+
+        // Addresses are relocated against .text and `counter`
+        let data = fs::read(&output).unwrap();
+        let object = object::File::parse(data.as_slice()).unwrap();
+        let debug_info = object.section_by_name(".debug_info").unwrap();
+        let mut addresses: Vec<_> = debug_info
+            .relocations()
+            .filter(|(_, reloc)| reloc.size() == 64)
+            .map(|(_, reloc)| {
+                let symbol = match reloc.target() {
+                    object::RelocationTarget::Symbol(idx) => object.symbol_by_index(idx).unwrap(),
+                    target => panic!("Unexpected target {:?}", target),
+                };
+                (symbol.name().unwrap().to_string(), reloc.addend())
+            })
+            .collect();
+        addresses.sort();
+        let expected = [("", 0), ("", 0), ("", 6), ("counter", 0)];
+        let expected: Vec<_> = expected.iter().map(|&(n, a)| (n.to_string(), a)).collect();
+        assert_eq!(addresses, expected);
+
+        let updated = ELF::new(&output).unwrap();
+        let subprograms = subprograms(&updated);
+        assert_eq!(subprograms.len(), 2);
+        assert_eq!(subprograms[&b"renamed"[..]], text_symbol(0));
+        assert_eq!(subprograms[&b"added"[..]], text_symbol(6));
+        assert_eq!(updated.var_addrs.values().collect::<Vec<_>>(), [&12]);
+
+        // Symbols added by objcopy would invalidate the relocations
+        let res = updated.update_binary(None, None, None, Symbols::new(), false);
+        assert!(matches!(res, Err(DwarfWriterError::UnsupportedBinary(_))));
     }
 }
@@ -0,0 +1,285 @@
+use object::Architecture;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+pub type Result<T, E = DwarfWriterError> = std::result::Result<T, E>;
+
+/// The ways processing the inputs and updating a binary can fail.
+#[derive(Debug)]
+pub enum DwarfWriterError {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// An input file could not be parsed. The path is missing if the input was
+    /// read from something other than a file.
+    Parse {
+        path: Option<PathBuf>,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// An input contains a malformed value.
+    InvalidInput(String),
+    /// An address in an input could not be parsed.
+    UnresolvedAddress(String),
+    /// A type referenced by an input never resolves to an actual type.
+    MissingType(String),
+    /// An input's architecture doesn't match the binary's in strict mode.
+    ArchMismatch { input: String, binary: Architecture },
+    /// The output binary exists and overwriting it was not allowed.
+    OutputExists(PathBuf),
+    /// objcopy exited with a failure status.
+    Objcopy { status: ExitStatus, stderr: String },
+    /// The binary could not be parsed.
+    Object(object::Error),
+    /// The binary can't be updated without objcopy.
+    UnsupportedBinary(String),
+    /// The binary's existing debug info could not be read.
+    ReadDwarf(gimli::Error),
+    /// The binary's existing debug info could not be converted for writing.
+    ConvertDwarf(gimli::write::ConvertError),
+    /// The debug info could not be written.
+    WriteDwarf(gimli::write::Error),
+    /// The debug info read back from the output binary differs from what was
+    /// written. Functions and types are given as `list` lines.
+    VerifyOutput {
+        path: PathBuf,
+        missing: Vec<String>,
+        unexpected: Vec<String>,
+    },
+}
+
+impl DwarfWriterError {
+    /// Creates a parse error for an input which wasn't read from a file.
+    pub fn parse<E: Into<Box<dyn Error + Send + Sync>>>(source: E) -> Self {
+        DwarfWriterError::Parse {
+            path: None,
+            source: source.into(),
+        }
+    }
+
+    /// Sets the path of a parse error to the file the input was read from.
+    pub fn in_file<P: AsRef<Path>>(self, file: P) -> Self {
+        match self {
+            DwarfWriterError::Parse { path: None, source } => DwarfWriterError::Parse {
+                path: Some(file.as_ref().to_path_buf()),
+                source,
+            },
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for DwarfWriterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DwarfWriterError::Io(err) => write!(f, "{}", err),
+            DwarfWriterError::Parse {
+                path: Some(path),
+                source,
+            } => write!(f, "Unable to parse {:?}: {}", path, source),
+            DwarfWriterError::Parse { path: None, source } => {
+                write!(f, "Unable to parse input: {}", source)
+            },
+            DwarfWriterError::InvalidInput(msg) => write!(f, "{}", msg),
+            DwarfWriterError::UnresolvedAddress(addr) => {
+                write!(f, "Unable to parse address {:?}", addr)
+            },
+            DwarfWriterError::MissingType(ty) => write!(f, "Type {} does not resolve", ty),
+            DwarfWriterError::ArchMismatch { input, binary } => write!(
+                f,
+                "Anvill input architecture {} does not match the binary's architecture {:?}",
+                input, binary
+            ),
+            DwarfWriterError::OutputExists(path) => {
+                write!(f, "Output binary {:?} already exists", path)
+            },
+            DwarfWriterError::Objcopy { status, stderr } => {
+                write!(f, "objcopy failed with {}: {}", status, stderr)
+            },
+            DwarfWriterError::Object(err) => write!(f, "Unable to parse the binary: {}", err),
+            DwarfWriterError::UnsupportedBinary(msg) => write!(f, "{}", msg),
+            DwarfWriterError::ReadDwarf(err) => write!(f, "Unable to read debug info: {}", err),
+            DwarfWriterError::ConvertDwarf(err) => {
+                write!(f, "Unable to convert debug info: {}", err)
+            },
+            DwarfWriterError::WriteDwarf(err) => write!(f, "Unable to write debug info: {}", err),
+            DwarfWriterError::VerifyOutput {
+                path,
+                missing,
+                unexpected,
+            } => {
+                write!(
+                    f,
+                    "Debug info read back from {:?} differs from what was written",
+                    path
+                )?;
+                for line in missing {
+                    write!(f, "\n  missing: {}", line)?;
+                }
+                for line in unexpected {
+                    write!(f, "\n  unexpected: {}", line)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Error for DwarfWriterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DwarfWriterError::Io(err) => Some(err),
+            DwarfWriterError::Parse { source, .. } => Some(source.as_ref()),
+            DwarfWriterError::Object(err) => Some(err),
+            DwarfWriterError::ReadDwarf(err) => Some(err),
+            DwarfWriterError::ConvertDwarf(err) => Some(err),
+            DwarfWriterError::WriteDwarf(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DwarfWriterError {
+    fn from(err: io::Error) -> Self {
+        DwarfWriterError::Io(err)
+    }
+}
+
+impl From<object::Error> for DwarfWriterError {
+    fn from(err: object::Error) -> Self {
+        DwarfWriterError::Object(err)
+    }
+}
+
+impl From<gimli::Error> for DwarfWriterError {
+    fn from(err: gimli::Error) -> Self {
+        DwarfWriterError::ReadDwarf(err)
+    }
+}
+
+impl From<gimli::write::ConvertError> for DwarfWriterError {
+    fn from(err: gimli::write::ConvertError) -> Self {
+        DwarfWriterError::ConvertDwarf(err)
+    }
+}
+
+impl From<gimli::write::Error> for DwarfWriterError {
+    fn from(err: gimli::write::Error) -> Self {
+        DwarfWriterError::WriteDwarf(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anvill::AnvillInput;
+    use crate::elf::ELF;
+    use crate::ghidra::GhidraInput;
+    use crate::patch::PatchInput;
+    use crate::symbols::Symbols;
+    use crate::{InputFile, Opt};
+    use clap::Parser;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn input_errors() {
+        let dir = tempdir().unwrap();
+        let csv = dir.path().join("functions.csv");
+        fs::write(&csv, "\"Name\",\"Location\"\n\"main\",\"00001000\"\n").unwrap();
+        match GhidraInput::new(&csv) {
+            Err(DwarfWriterError::Parse { path, .. }) => assert_eq!(path, Some(csv)),
+            res => panic!("Unexpected result {:?}", res),
+        }
+        assert!(matches!(
+            AnvillInput::new(dir.path().join("missing.json")),
+            Err(DwarfWriterError::Io(_))
+        ));
+
+        let opt = Opt::parse_from(["dwarf-writer", "tests/strip_bin/types.c.elf"]);
+        let input = GhidraInput::from_reader(
+            "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n\
+             \"main\",\"main\",\"int main(void)\",\"10\"\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert!(matches!(
+            input.data(&opt),
+            Err(DwarfWriterError::UnresolvedAddress(addr)) if addr == "main"
+        ));
+        let input = GhidraInput::from_reader(
+            "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n\
+             \"main\",\"00001000\",\"int main(void)\",\"1O\"\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert!(matches!(
+            input.data(&opt),
+            Err(DwarfWriterError::InvalidInput(msg)) if msg.contains("\"1O\" of function main")
+        ));
+        let input: PatchInput =
+            serde_json::from_str(r#"{"functions": {"0xmain": {"name": "start"}}}"#).unwrap();
+        assert!(matches!(
+            input.data(&opt),
+            Err(DwarfWriterError::UnresolvedAddress(_))
+        ));
+
+        let mut input = GhidraInput::from_reader(&b""[..]).unwrap();
+        assert!(matches!(
+            input.add_typedefs("HANDLE".as_bytes()),
+            Err(DwarfWriterError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            input.add_typedefs("DWORD=DWORD".as_bytes()),
+            Err(DwarfWriterError::MissingType(ty)) if ty == "DWORD"
+        ));
+    }
+
+    #[test]
+    fn arch_mismatch() {
+        let bin = "tests/strip_bin/types.c.elf";
+        let input: AnvillInput =
+            serde_json::from_str(r#"{"arch": "aarch64", "os": "linux"}"#).unwrap();
+        let opt = Opt::parse_from(["dwarf-writer", bin]);
+        assert!(input.check_arch(Architecture::X86_64, &opt).is_ok());
+        let opt = Opt::parse_from(["dwarf-writer", "--strict", bin]);
+        assert!(matches!(
+            input.check_arch(Architecture::X86_64, &opt),
+            Err(DwarfWriterError::ArchMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn output_errors() {
+        let dir = tempdir().unwrap();
+        let bin = "tests/strip_bin/types.c.elf";
+        let output = dir.path().join("output.elf");
+        fs::write(&output, b"").unwrap();
+        let res = ELF::new(bin).unwrap().update_binary(
+            Some(output.clone()),
+            None,
+            None,
+            Symbols::new(),
+            true,
+        );
+        assert!(matches!(res, Err(DwarfWriterError::OutputExists(path)) if path == output));
+
+        let res = ELF::new(bin).unwrap().update_binary(
+            Some(output),
+            Some("false".into()),
+            None,
+            Symbols::new(),
+            false,
+        );
+        match res {
+            Err(DwarfWriterError::Objcopy { status, .. }) => assert_eq!(status.code(), Some(1)),
+            res => panic!("Unexpected result {:?}", res),
+        }
+
+        assert!(matches!(
+            ELF::new("Cargo.toml"),
+            Err(DwarfWriterError::Object(_))
+        ));
+    }
+}
@@ -1,5 +1,6 @@
-use crate::types::{CanonicalTypeName, DwarfType};
-use anyhow::Result;
+use crate::error::{DwarfWriterError, Result};
+use crate::types::{CanonicalTypeName, DwarfType, Qualifier};
+use crate::Opt;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
@@ -8,29 +9,136 @@ use std::path::Path;
 
 impl GhidraInput {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let file = fs::File::open(path)?;
-        let reader = io::BufReader::new(file);
+        let file = fs::File::open(path.as_ref())?;
+        Self::from_reader(io::BufReader::new(file)).map_err(|err| err.in_file(path))
+    }
+
+    /// Parses Ghidra's exported CSV function data.
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Self> {
         let mut hints = csv::Reader::from_reader(reader);
         let mut functions = Vec::new();
         for h in hints.deserialize() {
-            functions.push(h?);
+            functions.push(h.map_err(DwarfWriterError::parse)?);
+        }
+        Ok(GhidraInput {
+            functions,
+            struct_sizes: HashMap::new(),
+            stack_vars: Vec::new(),
+            typedefs: HashMap::new(),
+        })
+    }
+
+    /// Loads the sizes of structs from a CSV of Ghidra data types.
+    pub fn load_struct_sizes<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let file = fs::File::open(path.as_ref())?;
+        self.add_struct_sizes(io::BufReader::new(file))
+            .map_err(|err| err.in_file(path))
+    }
+
+    pub fn add_struct_sizes<R: io::Read>(&mut self, reader: R) -> Result<()> {
+        let mut data_types = csv::Reader::from_reader(reader);
+        for dt in data_types.deserialize() {
+            let dt: DataTypeInput = dt.map_err(DwarfWriterError::parse)?;
+            self.struct_sizes.insert(dt.name, dt.length);
         }
-        Ok(GhidraInput { functions })
+        Ok(())
     }
 
-    pub fn data(&self) -> Result<GhidraData> {
+    /// Loads the local stack variables of functions from a CSV.
+    pub fn load_stack_vars<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let file = fs::File::open(path.as_ref())?;
+        self.add_stack_vars(io::BufReader::new(file))
+            .map_err(|err| err.in_file(path))
+    }
+
+    pub fn add_stack_vars<R: io::Read>(&mut self, reader: R) -> Result<()> {
+        let mut stack_vars = csv::Reader::from_reader(reader);
+        for var in stack_vars.deserialize() {
+            self.stack_vars.push(var.map_err(DwarfWriterError::parse)?);
+        }
+        Ok(())
+    }
+
+    /// Loads a file mapping typedef names to their underlying types with one
+    /// `NAME=TYPE` pair per line.
+    pub fn load_typedefs<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let file = fs::File::open(path.as_ref())?;
+        self.add_typedefs(io::BufReader::new(file))
+            .map_err(|err| err.in_file(path))
+    }
+
+    pub fn add_typedefs<R: io::BufRead>(&mut self, reader: R) -> Result<()> {
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let (name, ty) = line.split_once('=').ok_or_else(|| {
+                DwarfWriterError::InvalidInput(format!(
+                    "Expected a typedef of the form NAME=TYPE: {:?}",
+                    line
+                ))
+            })?;
+            self.typedefs
+                .insert(name.trim().to_string(), ty.trim().to_string());
+        }
+        // Typedefs referring back to themselves would never resolve to a type
+        for name in self.typedefs.keys() {
+            let mut ty = name;
+            for _ in 0..self.typedefs.len() {
+                match self.typedefs.get(ty) {
+                    Some(next) => ty = next,
+                    None => break,
+                }
+            }
+            if self.typedefs.contains_key(ty) {
+                return Err(DwarfWriterError::MissingType(name.clone()))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn data(&self, cfg: &Opt) -> Result<GhidraData<'_>> {
         let mut fn_map = HashMap::new();
+        if cfg.omit_functions {
+            return Ok(GhidraData { fn_map })
+        }
         for f in &self.functions {
-            let low_pc = u64::from_str_radix(&f.location, 16)?;
-            let high_pc = u64::from_str_radix(&f.size, 16)? + low_pc;
-            let (return_ty, parameters) = Self::parse_signature(&f.signature);
+            let location = parse_address(&f.location)?;
+            let low_pc = location + cfg.load_bias;
+            // Ghidra gives thunks and external functions a size of 0
+            let high_pc = match parse_size(f)? {
+                0 => None,
+                size => Some(low_pc + size),
+            };
+            let entry_pc = match &f.entry_point {
+                Some(entry) => Some(parse_address(entry)? + cfg.load_bias),
+                None => None,
+            };
+            let (return_ty, parameters, is_noreturn) = self.parse_signature(&f.signature, cfg);
+            let mut stack_vars = Vec::new();
+            if !cfg.omit_variables {
+                for var in &self.stack_vars {
+                    if parse_address(&var.function)? == location {
+                        stack_vars.push(StackVariable {
+                            name: &var.name,
+                            ty: self.parse_type(&var.data_type, cfg),
+                            offset: parse_stack_offset(&var.offset)?,
+                        });
+                    }
+                }
+            }
             fn_map.insert(
                 low_pc,
                 Function {
                     low_pc,
                     high_pc,
+                    entry_pc,
                     return_ty,
+                    is_noreturn,
                     parameters,
+                    stack_vars,
                     name: &f.name,
                 },
             );
@@ -38,25 +146,35 @@ impl GhidraInput {
         Ok(GhidraData { fn_map })
     }
 
-    /// Returns a tuple of (return_types, parameters). Ghidra currently only
-    /// provides a single return value, but it's inserted into a vector to
-    /// simplify the transformation to a `DwarfFunction`.
-    fn parse_signature(fn_sig: &str) -> (Option<DwarfType>, Vec<Parameter>) {
+    /// Returns a tuple of (return_type, parameters, is_noreturn). Functions
+    /// which never return have signatures starting with `noreturn`.
+    fn parse_signature<'a>(
+        &self, fn_sig: &'a str, cfg: &Opt,
+    ) -> (Option<DwarfType>, Vec<Parameter<'a>>, bool) {
         let mut sig_iter = fn_sig.split("(");
         let left_str = sig_iter.next().unwrap();
         let right_str = sig_iter.next().unwrap();
 
         let mut left_iter = left_str.rsplit(' ');
         let _fn_name = left_iter.next().unwrap();
-        let ret_str = left_iter.rfold(String::new(), |mut acc, s| {
-            acc.push(' ');
-            acc.push_str(s);
-            acc
-        });
-        let right_iter = right_str.split(',').map(|ty| &ty[..ty.len() - 1]);
+        let mut is_noreturn = false;
+        // Calling conventions like `__thiscall` are not part of the return type
+        let ret_str = left_iter
+            .filter(|&s| {
+                let is_calling_conv = s.starts_with("__") && s.ends_with("call");
+                is_noreturn |= s == "noreturn";
+                s != "noreturn" && !is_calling_conv
+            })
+            .rfold(String::new(), |mut acc, s| {
+                acc.push(' ');
+                acc.push_str(s);
+                acc
+            });
+        let right_str = right_str.trim_end().strip_suffix(')').unwrap_or(right_str);
+        let right_iter = right_str.split(',').map(str::trim);
         let mut params = Vec::new();
         for p in right_iter {
-            if p == "void" || p == "" {
+            if p == "void" || p.is_empty() {
                 break
             } else {
                 let mut param_iter = p.rsplit(' ');
@@ -68,23 +186,62 @@ impl GhidraInput {
                 });
                 let param = Parameter {
                     name,
-                    ty: Self::parse_type(&ty_name),
+                    ty: self.parse_type(&ty_name, cfg),
+                    // Ghidra names the implicit object pointer of methods `this`
+                    is_object_pointer: name == "this",
                 };
                 params.push(param);
             }
         }
-        let ret_ty = Self::parse_type(&ret_str);
-        (ret_ty, params)
+        let ret_ty = self.parse_type(&ret_str, cfg);
+        (ret_ty, params, is_noreturn)
     }
 
-    fn parse_type(ty: &str) -> Option<DwarfType> {
+    /// Parses a Ghidra type name. Ghidra's `undefined` type is replaced with
+    /// the `--default-type` if one is given and is `None` otherwise.
+    fn parse_type(&self, ty: &str, cfg: &Opt) -> Option<DwarfType> {
         let ty = ty.trim_end().trim_start();
-        if ty == "undefined" || ty == "thunk undefined" {
-            return None
+        if is_undefined(ty) {
+            let default = cfg.default_type.as_deref().filter(|ty| !is_undefined(ty));
+            return self.parse_type(default?, cfg)
         };
-        let res = match ty.strip_suffix("*") {
-            Some(inner_ty) => DwarfType::new_pointer(Self::parse_type(inner_ty).unwrap()),
-            None => DwarfType::new_primitive(
+        if let Some((qualifier, inner_ty)) = Qualifier::strip_suffix(ty) {
+            return self
+                .parse_type(inner_ty, cfg)
+                .map(|inner_ty| qualifier.apply(inner_ty))
+        }
+        if let Some((inner_ty, size)) = strip_sized_pointer(ty) {
+            let pointee = self
+                .parse_type(inner_ty, cfg)
+                .unwrap_or_else(DwarfType::void);
+            return Some(DwarfType::new_sized_pointer(pointee, size))
+        }
+        let union_name = ty.strip_prefix("union ").map(str::trim_start);
+        let struct_name = ty
+            .strip_prefix("struct ")
+            .map(str::trim_start)
+            .or(union_name);
+        let res = match (ty.strip_suffix("*"), Qualifier::strip_prefix(ty)) {
+            // Pointers to unknown types are void pointers
+            (Some(inner_ty), _) => DwarfType::new_pointer(
+                self.parse_type(inner_ty, cfg)
+                    .unwrap_or_else(DwarfType::void),
+            ),
+            (None, Some((qualifier, inner_ty))) => qualifier.apply(self.parse_type(inner_ty, cfg)?),
+            (None, None) if self.typedefs.contains_key(ty) => DwarfType::new_typedef(
+                ty.as_bytes().to_vec().into(),
+                self.parse_type(&self.typedefs[ty], cfg)?,
+            ),
+            (None, None) if struct_name.is_some() || self.struct_sizes.contains_key(ty) => {
+                let name = struct_name.unwrap_or(ty);
+                let size = self.struct_sizes.get(name).cloned();
+                if union_name.is_some() {
+                    DwarfType::new_union(Some(name.as_bytes().to_vec().into()), size, Vec::new())
+                } else {
+                    DwarfType::new_struct(Some(name.as_bytes().to_vec().into()), size, Vec::new())
+                }
+            },
+            (None, None) => DwarfType::new_primitive(
                 CanonicalTypeName::from(ty.trim_start().as_bytes().to_vec()),
                 None,
             ),
@@ -93,6 +250,59 @@ impl GhidraInput {
     }
 }
 
+/// Splits a pointer with a non-default size like `char *16` into its pointee
+/// and size in bytes. Ghidra appends the size in bits to these pointers' names.
+fn strip_sized_pointer(ty: &str) -> Option<(&str, u64)> {
+    let (inner_ty, bits) = ty.rsplit_once('*')?;
+    let bits = bits.trim_start();
+    if bits.is_empty() || !bits.bytes().all(|b| b.is_ascii_digit()) {
+        return None
+    }
+    let bits: u64 = bits.parse().ok()?;
+    bits.is_multiple_of(8).then_some((inner_ty, bits / 8))
+}
+
+fn is_undefined(ty: &str) -> bool {
+    ty == "undefined" || ty == "thunk undefined"
+}
+
+/// Parses an address or size given in hex without a prefix.
+fn parse_address(addr: &str) -> Result<u64> {
+    u64::from_str_radix(addr, 16).map_err(|_| DwarfWriterError::UnresolvedAddress(addr.to_string()))
+}
+
+/// Parses a function's size given in hex without a prefix.
+fn parse_size(f: &FunctionInput) -> Result<u64> {
+    u64::from_str_radix(&f.size, 16).map_err(|_| {
+        DwarfWriterError::InvalidInput(format!(
+            "Unable to parse size {:?} of function {} at {}",
+            f.size, f.name, f.location
+        ))
+    })
+}
+
+/// Parses a stack offset in Ghidra's `Stack[-0x18]` notation or as a plain hex
+/// or decimal integer.
+fn parse_stack_offset(offset: &str) -> Result<i64> {
+    let trimmed = offset.trim();
+    let trimmed = trimmed
+        .strip_prefix("Stack[")
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(trimmed);
+    let (is_negative, abs) = match trimmed.strip_prefix('-') {
+        Some(abs) => (true, abs),
+        None => (false, trimmed),
+    };
+    let abs = match abs.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => abs.parse(),
+    }
+    .map_err(|_| {
+        DwarfWriterError::InvalidInput(format!("Unable to parse stack offset {:?}", offset))
+    })?;
+    Ok(if is_negative { -abs } else { abs })
+}
+
 pub struct GhidraData<'a> {
     pub fn_map: HashMap<u64, Function<'a>>,
 }
@@ -109,6 +319,11 @@ impl<'a> GhidraData<'a> {
             if let Some(ret_ty) = &function.return_ty {
                 res.push(ret_ty.clone());
             }
+            for var in &function.stack_vars {
+                if let Some(var_ty) = &var.ty {
+                    res.push(var_ty.clone());
+                }
+            }
         }
         res
     }
@@ -116,20 +331,45 @@ impl<'a> GhidraData<'a> {
 
 pub struct Function<'a> {
     pub low_pc: u64,
-    pub high_pc: u64,
+    /// The address after the function's last instruction. This is `None` if
+    /// Ghidra doesn't know the function's size.
+    pub high_pc: Option<u64>,
+    /// The function's entry point if it isn't its lowest address.
+    pub entry_pc: Option<u64>,
     pub name: &'a str,
     pub return_ty: Option<DwarfType>,
+    pub is_noreturn: bool,
     pub parameters: Vec<Parameter<'a>>,
+    pub stack_vars: Vec<StackVariable<'a>>,
 }
 
 pub struct Parameter<'a> {
     pub name: &'a str,
     pub ty: Option<DwarfType>,
+    /// Whether this is the `this` parameter of a method.
+    pub is_object_pointer: bool,
+}
+
+pub struct StackVariable<'a> {
+    pub name: &'a str,
+    pub ty: Option<DwarfType>,
+    /// The variable's offset from the stack pointer on entry to the function.
+    pub offset: i64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GhidraInput {
     functions: Vec<FunctionInput>,
+    /// Sizes of structs which may be referenced in function signatures.
+    #[serde(skip)]
+    struct_sizes: HashMap<String, u64>,
+    /// Local stack variables of the functions.
+    #[serde(skip)]
+    stack_vars: Vec<StackVariableInput>,
+    /// Underlying types of typedef names which may be referenced in function
+    /// signatures.
+    #[serde(skip)]
+    typedefs: HashMap<String, String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -142,4 +382,26 @@ struct FunctionInput {
     location: String,
     #[serde(rename = "Function Signature")]
     signature: String,
+    #[serde(rename = "Entry Point", default)]
+    entry_point: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct DataTypeInput {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Length")]
+    length: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StackVariableInput {
+    #[serde(rename = "Function")]
+    function: String,
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Data Type")]
+    data_type: String,
+    #[serde(rename = "Offset")]
+    offset: String,
 }
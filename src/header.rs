@@ -0,0 +1,723 @@
+use crate::error::Result;
+use crate::types::{CanonicalTypeName, DwarfType, Member, TypeMap};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+/// Writes the types in a type map as C declarations to a header file so the
+/// reconstructed layouts can be reviewed.
+pub fn save_header<P: AsRef<Path>>(type_map: &TypeMap, path: P) -> Result<()> {
+    let out = io::BufWriter::new(fs::File::create(path)?);
+    write_header(type_map.keys(), out)?;
+    Ok(())
+}
+
+/// Writes C declarations for a set of types and the types they refer to.
+/// Structs are forward declared and then defined along with typedefs in an
+/// order where each type is complete before it's used by value. Member offsets
+/// and struct sizes are written as comments since the C layout of the members
+/// may differ.
+pub fn write_header<'a, I, W>(types: I, mut out: W) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a DwarfType>,
+    W: Write, {
+    let header = Header::new(types);
+    writeln!(out, "/* Types reconstructed by dwarf-writer */")?;
+    writeln!(out, "#pragma once")?;
+    writeln!(out)?;
+    for include in ["stdbool.h", "stddef.h", "stdint.h", "uchar.h"] {
+        writeln!(out, "#include <{}>", include)?;
+    }
+    if !header.primitives.is_empty() {
+        writeln!(out)?;
+    }
+    for (name, size) in &header.primitives {
+        writeln!(out, "{}", opaque_primitive(name, *size))?;
+    }
+    // Enums don't depend on other types so they're defined up front
+    for ty in header.enums.values() {
+        writeln!(out)?;
+        header.write_enum(ty, &mut out)?;
+    }
+    let declarations: BTreeSet<_> = header
+        .tags
+        .iter()
+        .filter(|(ty, _)| !matches!(ty, DwarfType::Enum { .. }))
+        .map(|(ty, tag)| (keyword(ty), tag))
+        .collect();
+    if !declarations.is_empty() {
+        writeln!(out)?;
+    }
+    for (keyword, tag) in &declarations {
+        writeln!(out, "{} {};", keyword, tag)?;
+    }
+    let mut emitted = BTreeSet::new();
+    let mut visiting = BTreeSet::new();
+    let items: Vec<_> = header
+        .typedefs
+        .keys()
+        .map(|name| Item::Typedef(name.clone()))
+        .chain(
+            header
+                .definitions
+                .keys()
+                .map(|tag| Item::Struct(tag.clone())),
+        )
+        .collect();
+    for item in items {
+        header.emit(item, &mut emitted, &mut visiting, &mut out)?;
+    }
+    Ok(())
+}
+
+/// A declaration in the header which other declarations may depend on.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Item {
+    Typedef(String),
+    Struct(String),
+}
+
+struct Header<'a> {
+    /// Primitive types without a C equivalent and their sizes.
+    primitives: BTreeMap<String, Option<u64>>,
+    /// The tag of each struct and enum, including generated tags for anonymous
+    /// ones.
+    tags: BTreeMap<&'a DwarfType, String>,
+    /// The struct defining each tag. Structs without fields aren't defined
+    /// and only the struct with the most fields is defined if several have
+    /// the same name.
+    definitions: BTreeMap<String, &'a DwarfType>,
+    /// The enum defining each tag. Enums without enumerators aren't defined
+    /// since C doesn't allow empty enums.
+    enums: BTreeMap<String, &'a DwarfType>,
+    typedefs: BTreeMap<String, &'a DwarfType>,
+}
+
+impl<'a> Header<'a> {
+    fn new<I: IntoIterator<Item = &'a DwarfType>>(types: I) -> Self {
+        let mut all = BTreeSet::new();
+        let mut to_visit: Vec<_> = types.into_iter().collect();
+        while let Some(ty) = to_visit.pop() {
+            if all.insert(ty) {
+                to_visit.extend(subtypes(ty));
+            }
+        }
+
+        let mut header = Header {
+            primitives: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            definitions: BTreeMap::new(),
+            enums: BTreeMap::new(),
+            typedefs: BTreeMap::new(),
+        };
+        let mut anonymous = 0;
+        for ty in all {
+            match ty {
+                DwarfType::Primitive { name, size, .. } if c_primitive(name).is_none() => {
+                    header.primitives.insert(identifier(name), *size);
+                },
+                DwarfType::Typedef { name, ref_type } if c_primitive(name).is_none() => {
+                    header.typedefs.entry(identifier(name)).or_insert(ref_type);
+                },
+                DwarfType::Struct {
+                    name,
+                    fields,
+                    variant_part,
+                    ..
+                } => {
+                    let tag = match name {
+                        Some(name) => identifier(name),
+                        None => {
+                            anonymous += 1;
+                            format!("anonymous_{}", anonymous)
+                        },
+                    };
+                    if !fields.is_empty() || variant_part.is_some() {
+                        let definition = header.definitions.entry(tag.clone()).or_insert(ty);
+                        if field_count(ty) > field_count(definition) {
+                            *definition = ty;
+                        }
+                    }
+                    header.tags.insert(ty, tag);
+                },
+                DwarfType::Enum {
+                    name, enumerators, ..
+                } => {
+                    let tag = match name {
+                        Some(name) => identifier(name),
+                        None => {
+                            anonymous += 1;
+                            format!("anonymous_{}", anonymous)
+                        },
+                    };
+                    if !enumerators.is_empty() {
+                        header.enums.entry(tag.clone()).or_insert(ty);
+                    }
+                    header.tags.insert(ty, tag);
+                },
+                _ => {},
+            }
+        }
+        header
+    }
+
+    /// Writes an item after the items it depends on. Dependency cycles can
+    /// only come from invalid layouts and are broken arbitrarily.
+    fn emit<W: Write>(
+        &self, item: Item, emitted: &mut BTreeSet<Item>, visiting: &mut BTreeSet<Item>, out: &mut W,
+    ) -> io::Result<()> {
+        if emitted.contains(&item) || !visiting.insert(item.clone()) {
+            return Ok(())
+        }
+        let mut deps = Vec::new();
+        match &item {
+            Item::Typedef(name) => self.declared_deps(self.typedefs[name], &mut deps),
+            Item::Struct(tag) => {
+                for member in members(self.definitions[tag]) {
+                    self.complete_deps(&member.ty, &mut deps);
+                }
+            },
+        }
+        for dep in deps {
+            self.emit(dep, emitted, visiting, out)?;
+        }
+        writeln!(out)?;
+        match &item {
+            Item::Typedef(name) => {
+                writeln!(
+                    out,
+                    "typedef {};",
+                    self.declaration(self.typedefs[name], name)
+                )?;
+            },
+            Item::Struct(tag) => self.write_struct(self.definitions[tag], out)?,
+        }
+        visiting.remove(&item);
+        emitted.insert(item);
+        Ok(())
+    }
+
+    /// The items which must be written before `ty` can be used by value.
+    fn complete_deps(&self, ty: &DwarfType, deps: &mut Vec<Item>) {
+        match ty {
+            DwarfType::Typedef { name, ref_type } => {
+                if self.typedefs.contains_key(&identifier(name)) {
+                    deps.push(Item::Typedef(identifier(name)));
+                }
+                self.complete_deps(ref_type, deps);
+            },
+            DwarfType::Restrict(ty) | DwarfType::Atomic(ty) => self.complete_deps(ty, deps),
+            DwarfType::Array { inner_type, .. } => self.complete_deps(inner_type, deps),
+            DwarfType::Struct { .. } => {
+                let tag = &self.tags[ty];
+                if self.definitions.contains_key(tag) {
+                    deps.push(Item::Struct(tag.clone()));
+                }
+            },
+            _ => self.declared_deps(ty, deps),
+        }
+    }
+
+    /// The items which must be written before `ty` can be named, e.g. behind a
+    /// pointer. Structs are forward declared so only typedefs and the elements
+    /// of arrays are needed.
+    fn declared_deps(&self, ty: &DwarfType, deps: &mut Vec<Item>) {
+        match ty {
+            DwarfType::Primitive { .. } | DwarfType::Struct { .. } | DwarfType::Enum { .. } => {},
+            DwarfType::Pointer(ty) |
+            DwarfType::SizedPointer { pointee: ty, .. } |
+            DwarfType::Restrict(ty) |
+            DwarfType::Atomic(ty) => self.declared_deps(ty, deps),
+            DwarfType::Typedef { name, .. } => {
+                if self.typedefs.contains_key(&identifier(name)) {
+                    deps.push(Item::Typedef(identifier(name)));
+                }
+            },
+            DwarfType::Array { inner_type, .. } => self.complete_deps(inner_type, deps),
+            DwarfType::Function {
+                return_type, args, ..
+            } => {
+                self.declared_deps(return_type, deps);
+                for arg in args {
+                    self.declared_deps(arg, deps);
+                }
+            },
+        }
+    }
+
+    fn write_struct<W: Write>(&self, ty: &DwarfType, out: &mut W) -> io::Result<()> {
+        let (size, fields, variant_part) = match ty {
+            DwarfType::Struct {
+                size,
+                fields,
+                variant_part,
+                ..
+            } => (size, fields, variant_part),
+            _ => unreachable!("Only structs are defined"),
+        };
+        if let Some(size) = size {
+            writeln!(out, "/* size {:#x} */", size)?;
+        }
+        writeln!(out, "{} {} {{", keyword(ty), self.tags[ty])?;
+        self.write_members(fields, "    ", out)?;
+        if let Some(part) = variant_part {
+            let discriminant = Member {
+                name: part
+                    .discriminant
+                    .name
+                    .clone()
+                    .or_else(|| Some(b"discriminant".to_vec().into())),
+                ..part.discriminant.clone()
+            };
+            self.write_members(&[discriminant], "    ", out)?;
+            // C doesn't allow empty structs so variants without fields are
+            // only listed in comments
+            let has_fields = part.variants.iter().any(|v| !v.fields.is_empty());
+            if has_fields {
+                writeln!(out, "    union {{")?;
+            }
+            for (idx, variant) in part.variants.iter().enumerate() {
+                let discr_value = match variant.discr_value {
+                    Some(value) => format!("discriminant {}", value),
+                    None => "default".to_string(),
+                };
+                if variant.fields.is_empty() {
+                    writeln!(out, "        /* {}: no fields */", discr_value)?;
+                    continue
+                }
+                writeln!(out, "        /* {} */", discr_value)?;
+                writeln!(out, "        struct {{")?;
+                self.write_members(&variant.fields, "            ", out)?;
+                writeln!(out, "        }} variant_{};", idx)?;
+            }
+            if has_fields {
+                writeln!(out, "    }} variants;")?;
+            }
+        }
+        writeln!(out, "}};")
+    }
+
+    /// Writes an enum with a comment giving its underlying type since C enums
+    /// may have a different size. C has no scoped enums so the enumerators of
+    /// an `enum class` are prefixed with its tag instead.
+    fn write_enum<W: Write>(&self, ty: &DwarfType, out: &mut W) -> io::Result<()> {
+        let (underlying_type, enumerators, is_scoped) = match ty {
+            DwarfType::Enum {
+                underlying_type,
+                enumerators,
+                is_scoped,
+                ..
+            } => (underlying_type, enumerators, *is_scoped),
+            _ => unreachable!("Only enums are written as enums"),
+        };
+        writeln!(
+            out,
+            "/* underlying type {} */",
+            self.declaration(underlying_type, "")
+        )?;
+        let tag = &self.tags[ty];
+        writeln!(out, "enum {} {{", tag)?;
+        for enumerator in enumerators {
+            let name = identifier(&enumerator.name);
+            if is_scoped {
+                writeln!(out, "    {}_{} = {},", tag, name, enumerator.value)?;
+            } else {
+                writeln!(out, "    {} = {},", name, enumerator.value)?;
+            }
+        }
+        writeln!(out, "}};")
+    }
+
+    /// Writes a member per line with its offset. Unnamed members are named
+    /// after their position since C only allows anonymous structs and unions
+    /// to be defined in place.
+    fn write_members<W: Write>(
+        &self, members: &[Member], indent: &str, out: &mut W,
+    ) -> io::Result<()> {
+        for (idx, member) in members.iter().enumerate() {
+            let name = match &member.name {
+                Some(name) => identifier(name),
+                None if member.is_base => format!("base_{}", idx),
+                None => format!("unnamed_{}", idx),
+            };
+            let offset = member
+                .offset
+                .map(|offset| format!(" /* {:#x} */", offset))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "{}{};{}",
+                indent,
+                self.declaration(&member.ty, &name),
+                offset
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns the C declaration of `declarator` with type `ty`, e.g.
+    /// `int (*name)[4]` for a pointer to an array.
+    fn declaration(&self, ty: &DwarfType, declarator: &str) -> String {
+        let with_specifier = |specifier: String| {
+            if declarator.is_empty() {
+                specifier
+            } else {
+                format!("{} {}", specifier, declarator)
+            }
+        };
+        match ty {
+            DwarfType::Primitive { name, .. } => {
+                with_specifier(c_primitive(name).unwrap_or_else(|| identifier(name)))
+            },
+            DwarfType::Typedef { name, .. } => {
+                with_specifier(c_primitive(name).unwrap_or_else(|| identifier(name)))
+            },
+            DwarfType::Struct { .. } => {
+                with_specifier(format!("{} {}", keyword(ty), self.tags[ty]))
+            },
+            DwarfType::Enum {
+                underlying_type, ..
+            } => {
+                let tag = &self.tags[ty];
+                if self.enums.contains_key(tag) {
+                    with_specifier(format!("enum {}", tag))
+                } else {
+                    self.declaration(underlying_type, declarator)
+                }
+            },
+            // C has no sized pointers so they're declared with the target's size
+            DwarfType::Pointer(pointee) | DwarfType::SizedPointer { pointee, .. } => {
+                self.pointer(pointee, "*", declarator)
+            },
+            DwarfType::Restrict(ty) => match &**ty {
+                DwarfType::Pointer(pointee) => self.pointer(pointee, "* restrict", declarator),
+                ty => self.declaration(ty, declarator),
+            },
+            DwarfType::Atomic(ty) => {
+                with_specifier(format!("_Atomic({})", self.declaration(ty, "")))
+            },
+            DwarfType::Array {
+                inner_type, len, ..
+            } => {
+                let len = len.map(|len| len.to_string()).unwrap_or_default();
+                self.declaration(inner_type, &format!("{}[{}]", declarator, len))
+            },
+            DwarfType::Function {
+                return_type,
+                args,
+                is_unprototyped,
+            } => {
+                let params = if *is_unprototyped {
+                    String::new()
+                } else if args.is_empty() {
+                    "void".to_string()
+                } else {
+                    let args: Vec<_> = args.iter().map(|arg| self.declaration(arg, "")).collect();
+                    args.join(", ")
+                };
+                self.declaration(return_type, &format!("{}({})", declarator, params))
+            },
+        }
+    }
+
+    /// Declares a pointer, parenthesizing the declarator if the pointee's
+    /// declarator binds more tightly than `*`.
+    fn pointer(&self, pointee: &DwarfType, star: &str, declarator: &str) -> String {
+        let declarator = if declarator.is_empty() {
+            star.to_string()
+        } else if star.ends_with('*') {
+            format!("{}{}", star, declarator)
+        } else {
+            format!("{} {}", star, declarator)
+        };
+        match pointee {
+            DwarfType::Array { .. } | DwarfType::Function { .. } => {
+                self.declaration(pointee, &format!("({})", declarator))
+            },
+            _ => self.declaration(pointee, &declarator),
+        }
+    }
+}
+
+/// The types a type refers to directly.
+fn subtypes(ty: &DwarfType) -> Vec<&DwarfType> {
+    match ty {
+        DwarfType::Primitive { .. } => Vec::new(),
+        DwarfType::Pointer(ty) |
+        DwarfType::SizedPointer { pointee: ty, .. } |
+        DwarfType::Restrict(ty) |
+        DwarfType::Atomic(ty) => vec![ty],
+        DwarfType::Typedef { ref_type, .. } => vec![ref_type],
+        DwarfType::Enum {
+            underlying_type, ..
+        } => vec![underlying_type],
+        DwarfType::Array { inner_type, .. } => vec![inner_type],
+        DwarfType::Struct { .. } => members(ty).map(|member| &member.ty).collect(),
+        DwarfType::Function {
+            return_type, args, ..
+        } => std::iter::once(&**return_type).chain(args).collect(),
+    }
+}
+
+/// The members of a struct including those of its variants.
+fn members(ty: &DwarfType) -> impl Iterator<Item = &Member> {
+    let (fields, variant_part) = match ty {
+        DwarfType::Struct {
+            fields,
+            variant_part,
+            ..
+        } => (&fields[..], variant_part.as_deref()),
+        _ => (&[][..], None),
+    };
+    let variant_members = variant_part.into_iter().flat_map(|part| {
+        std::iter::once(&part.discriminant).chain(part.variants.iter().flat_map(|v| &v.fields))
+    });
+    fields.iter().chain(variant_members)
+}
+
+fn field_count(ty: &DwarfType) -> usize {
+    members(ty).count()
+}
+
+fn keyword(ty: &DwarfType) -> &'static str {
+    match ty {
+        DwarfType::Struct { is_union: true, .. } => "union",
+        DwarfType::Enum { .. } => "enum",
+        _ => "struct",
+    }
+}
+
+/// The C spelling of a canonical primitive type name if C has one, either
+/// built in or from the included headers. Names made of C's type keywords
+/// (e.g. `long unsigned int` from GCC's debug info) are already C types.
+fn c_primitive(name: &CanonicalTypeName) -> Option<String> {
+    const KEYWORDS: [&str; 8] = [
+        "char", "double", "float", "int", "long", "short", "signed", "unsigned",
+    ];
+    let words = std::str::from_utf8(name.as_bytes()).ok()?;
+    if words.split(' ').all(|word| KEYWORDS.contains(&word)) {
+        return Some(words.to_string())
+    }
+    let c_name = match name.as_bytes() {
+        b"void" => "void",
+        b"bool" => "bool",
+        b"char" => "char",
+        b"int8_t" => "int8_t",
+        b"uint8_t" => "uint8_t",
+        b"int16_t" => "int16_t",
+        b"uint16_t" => "uint16_t",
+        b"int32_t" => "int32_t",
+        b"uint32_t" => "uint32_t",
+        b"int64_t" => "int64_t",
+        b"uint64_t" => "uint64_t",
+        b"int128_t" => "__int128",
+        b"uint128_t" => "unsigned __int128",
+        b"long" => "long",
+        b"unsigned long" => "unsigned long",
+        b"size_t" => "size_t",
+        b"ptrdiff_t" => "ptrdiff_t",
+        b"wchar_t" => "wchar_t",
+        b"char16_t" => "char16_t",
+        b"char32_t" => "char32_t",
+        b"float" => "float",
+        b"double" => "double",
+        b"long double" => "long double",
+        _ => return None,
+    };
+    Some(c_name.to_string())
+}
+
+/// Declares a primitive type without a C equivalent as an integer or byte
+/// array of the same size.
+fn opaque_primitive(name: &str, size: Option<u64>) -> String {
+    match size {
+        Some(size @ (1 | 2 | 4 | 8)) => format!("typedef uint{}_t {};", size * 8, name),
+        Some(16) => format!("typedef unsigned __int128 {};", name),
+        Some(size) => format!("typedef struct {{ uint8_t bytes[{}]; }} {};", size, name),
+        None => format!(
+            "typedef struct {{ uint8_t unknown; }} {}; /* unknown size */",
+            name
+        ),
+    }
+}
+
+/// Turns a type name into a C identifier, e.g. replacing the `::` separators
+/// of qualified C++ names.
+fn identifier(name: &CanonicalTypeName) -> String {
+    let name = String::from_utf8_lossy(name.as_bytes());
+    let mut res: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if res.is_empty() || res.starts_with(|c: char| c.is_ascii_digit()) {
+        res.insert(0, '_');
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Enumerator, Variant, VariantPart};
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    fn name(name: &str) -> Option<CanonicalTypeName> {
+        Some(name.as_bytes().to_vec().into())
+    }
+
+    fn header(types: &[DwarfType]) -> String {
+        let mut out = Vec::new();
+        write_header(types, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn declarators() {
+        let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+        let ptr_to_array = DwarfType::new_pointer(DwarfType::new_array(int.clone(), Some(4)));
+        let fn_ptr = DwarfType::new_pointer(DwarfType::new_function(
+            int.clone(),
+            vec![DwarfType::new_pointer(DwarfType::void())],
+        ));
+        let holder = DwarfType::new_struct(
+            name("holder"),
+            Some(24),
+            vec![
+                Member::new(name("rows"), ptr_to_array, Some(0)),
+                Member::new(name("callback"), fn_ptr, Some(8)),
+                Member::new(
+                    name("table"),
+                    DwarfType::new_array(DwarfType::new_pointer(int), Some(1)),
+                    Some(16),
+                ),
+            ],
+        );
+        let header = header(&[holder]);
+        assert!(header.contains("/* size 0x18 */\nstruct holder {\n"));
+        assert!(header.contains("    int32_t (*rows)[4]; /* 0x0 */\n"));
+        assert!(header.contains("    int32_t (*callback)(void *); /* 0x8 */\n"));
+        assert!(header.contains("    int32_t *table[1]; /* 0x10 */\n"));
+    }
+
+    #[test]
+    fn compiles() {
+        let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+        let uint24 = DwarfType::new_primitive(b"uint24_t".to_vec().into(), None);
+        let point = DwarfType::new_struct(
+            name("point"),
+            Some(8),
+            vec![
+                Member::new(name("x"), int.clone(), Some(0)),
+                Member::new(name("y"), int.clone(), Some(4)),
+            ],
+        );
+        let point_t = DwarfType::new_typedef(b"point_t".to_vec().into(), point.clone());
+        // Lists refer to themselves through a typedef declared before the
+        // struct is defined
+        let node_ptr = DwarfType::new_typedef(
+            b"node_ptr".to_vec().into(),
+            DwarfType::new_pointer(DwarfType::new_struct(name("node"), None, Vec::new())),
+        );
+        let node = DwarfType::new_struct(
+            name("node"),
+            Some(24),
+            vec![
+                Member::new(name("next"), node_ptr, Some(0)),
+                Member::new(
+                    name("points"),
+                    DwarfType::new_array(point_t, Some(2)),
+                    Some(8),
+                ),
+            ],
+        );
+        let value = DwarfType::new_union(
+            None,
+            Some(4),
+            vec![
+                Member::new(name("i"), int.clone(), Some(0)),
+                Member::new(name("color"), uint24, Some(0)),
+            ],
+        );
+        let derived = DwarfType::new_class(
+            name("ns::Derived"),
+            Some(12),
+            vec![
+                Member::new_base(point, Some(0)),
+                Member::new(None, value, Some(8)),
+            ],
+        );
+        let option = DwarfType::new_tagged_union(
+            name("Option"),
+            Some(8),
+            VariantPart {
+                discriminant: Member::new(None, int.clone(), Some(0)),
+                variants: vec![
+                    Variant {
+                        discr_value: Some(0),
+                        fields: Vec::new(),
+                    },
+                    Variant {
+                        discr_value: Some(1),
+                        fields: vec![Member::new(name("Some"), int, Some(4))],
+                    },
+                ],
+            },
+        );
+        let uint8 = DwarfType::new_primitive(b"uint8_t".to_vec().into(), None);
+        let shade = DwarfType::new_enum(
+            name("shade"),
+            uint8.clone(),
+            vec![
+                Enumerator::new(b"DARK".to_vec().into(), 0),
+                Enumerator::new(b"LIGHT".to_vec().into(), 1),
+            ],
+        );
+        // Scoped enumerators are prefixed with their tag so they don't clash
+        let direction = DwarfType::new_scoped_enum(
+            name("direction"),
+            DwarfType::new_primitive(b"int8_t".to_vec().into(), None),
+            vec![
+                Enumerator::new(b"DARK".to_vec().into(), -1),
+                Enumerator::new(b"LIGHT".to_vec().into(), 1),
+            ],
+        );
+        // Enums without enumerators are declared as their underlying type
+        let opaque = DwarfType::new_enum(name("opaque"), uint8, Vec::new());
+        let pixel = DwarfType::new_struct(
+            name("pixel"),
+            Some(3),
+            vec![
+                Member::new(name("shade"), shade, Some(0)),
+                Member::new(name("flags"), opaque, Some(1)),
+                Member::new(name("direction"), direction, Some(2)),
+            ],
+        );
+        let header = header(&[node, derived, option, pixel]);
+        assert!(header.contains("enum shade {\n    DARK = 0,\n    LIGHT = 1,\n};"));
+        assert!(header.contains("    direction_DARK = -1,\n    direction_LIGHT = 1,\n"));
+        assert!(header.contains("    enum shade shade; /* 0x0 */\n    uint8_t flags; /* 0x1 */\n"));
+        assert!(header.contains("typedef struct { uint8_t bytes[3]; } uint24_t;"));
+        assert!(header.contains("struct ns__Derived {\n    struct point base_0; /* 0x0 */\n"));
+        assert!(header.contains("    union anonymous_1 unnamed_1; /* 0x8 */\n"));
+
+        // The header is included so `#pragma once` isn't in the main file
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("types.h"), &header).unwrap();
+        let source = dir.path().join("types.c");
+        fs::write(&source, "#include \"types.h\"\n").unwrap();
+        let output = Command::new("cc")
+            .args(["-fsyntax-only", "-Wall", "-Werror"])
+            .arg(&source)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}\n{}",
+            header,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
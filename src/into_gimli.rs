@@ -1,4 +1,10 @@
 use crate::anvill;
+use crate::error::{DwarfWriterError, Result};
+use log::warn;
+use object::Architecture;
+use std::collections::HashMap;
+use std::path::Path;
+use std::{fs, io};
 
 /// Generic trait for converting to gimli-specific types.
 pub trait IntoGimli<T> {
@@ -16,28 +22,183 @@ impl IntoGimli<gimli::RunTimeEndian> for object::endian::Endianness {
     }
 }
 
-impl IntoGimli<gimli::Register> for &anvill::Register {
-    fn into_gimli(self) -> gimli::Register {
+/// DWARF register numbers given with `--register-map` for registers which
+/// dwarf-writer doesn't know or numbers differently than needed. Names are
+/// matched case-insensitively.
+#[derive(Default, Debug)]
+pub struct RegisterMap(HashMap<String, gimli::Register>);
+
+impl RegisterMap {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = fs::File::open(path.as_ref())?;
+        Self::from_reader(io::BufReader::new(file)).map_err(|err| err.in_file(path))
+    }
+
+    /// Reads a register map given as NAME=NUMBER lines.
+    pub fn from_reader<R: io::BufRead>(reader: R) -> Result<Self> {
+        let mut map = HashMap::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let number = line
+                .split_once('=')
+                .and_then(|(name, number)| Some((name, number.trim().parse().ok()?)));
+            let (name, number) = number.ok_or_else(|| {
+                DwarfWriterError::InvalidInput(format!(
+                    "Expected a register number of the form NAME=NUMBER: {:?}",
+                    line
+                ))
+            })?;
+            map.insert(name.trim().to_ascii_uppercase(), gimli::Register(number));
+        }
+        Ok(RegisterMap(map))
+    }
+
+    pub fn get(&self, name: &str) -> Option<gimli::Register> {
+        self.0.get(&name.to_ascii_uppercase()).copied()
+    }
+}
+
+/// Maps a register by name using the binary's architecture.
+fn arch_register(name: &str, arch: Architecture) -> Option<gimli::Register> {
+    match arch {
+        Architecture::X86_64 => gimli::X86_64::name_to_register(&name.to_ascii_lowercase()),
+        Architecture::I386 => gimli::X86::name_to_register(&name.to_ascii_lowercase()),
+        Architecture::Arm => gimli::Arm::name_to_register(&name.to_ascii_uppercase()),
+        Architecture::Aarch64 => gimli::AArch64::name_to_register(&name.to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+/// Maps a register using the register map, then the binary's architecture and
+/// finally the architecture implied by the register name if it's not known.
+/// Registers without a number are logged and give `None`.
+impl IntoGimli<Option<gimli::Register>> for (&anvill::Register, Architecture, &RegisterMap) {
+    fn into_gimli(self) -> Option<gimli::Register> {
+        let (reg, arch, map) = self;
+        let name = reg.name();
+        let number = map
+            .get(&name)
+            .or_else(|| arch_register(&name, arch))
+            .or_else(|| reg.into_gimli());
+        if number.is_none() {
+            warn!(
+                "Unknown register {} can't be used in locations without a number from \
+                 --register-map",
+                name
+            );
+        }
+        number
+    }
+}
+
+impl IntoGimli<Option<gimli::Register>> for &anvill::Register {
+    fn into_gimli(self) -> Option<gimli::Register> {
         use anvill::Register;
 
-        let name_to_register = match self {
-            Register::X86(_) => gimli::X86_64::name_to_register,
-            Register::ARM(_) => gimli::Arm::name_to_register,
-            Register::SPARC(r) => return gimli::Register(*r as u16),
+        let reg = match self {
+            Register::X86(_) => gimli::X86_64::name_to_register(&self.name().to_ascii_lowercase()),
+            Register::ARM(_) => gimli::Arm::name_to_register(&self.name().to_ascii_uppercase()),
+            // gimli doesn't know SPARC's register names
+            Register::SPARC(_) | Register::Other(_) => None,
         };
-        let lower_case = match self {
-            Register::X86(_) => true,
-            Register::ARM(_) => false,
-            _ => unreachable!("SPARC currently doesn't use `name_to_register`"),
-        };
-        let reg_string =
-            serde_json::to_string(self).expect("Couldn't serialize `anvill::Register` to `String`");
-        let reg_string = if lower_case {
-            reg_string.trim_matches('"').to_ascii_lowercase()
-        } else {
-            reg_string.trim_matches('"').to_ascii_uppercase()
-        };
-        name_to_register(&reg_string)
-            .unwrap_or_else(|| panic!("Couldn't map {:?} to `gimli::Register`", reg_string))
+        reg.or_else(|| self.dwarf_number().map(gimli::Register))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anvill::Register;
+
+    fn register(name: &str) -> Register {
+        serde_json::from_str(&format!("{:?}", name)).unwrap()
+    }
+
+    fn names(prefix: &str, count: usize) -> impl Iterator<Item = String> + '_ {
+        (0..count).map(move |n| format!("{}{}", prefix, n))
+    }
+
+    #[test]
+    fn x86_registers() {
+        let no_map = RegisterMap::default();
+        let gprs = [
+            "RAX", "RDX", "RCX", "RBX", "RSI", "RDI", "RBP", "RSP", "R8", "R9", "R10", "R11",
+            "R12", "R13", "R14", "R15",
+        ];
+        let all = gprs
+            .iter()
+            .map(|name| name.to_string())
+            .chain(names("ST", 8))
+            .chain(names("MM", 8))
+            .chain(names("XMM", 32));
+        for name in all {
+            let reg = register(&name);
+            assert_eq!(
+                (&reg, Architecture::X86_64, &no_map).into_gimli(),
+                reg.dwarf_number().map(gimli::Register),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            (&register("MM0"), Architecture::X86_64, &no_map).into_gimli(),
+            Some(gimli::X86_64::MM0)
+        );
+        assert_eq!(
+            (&register("XMM16"), Architecture::X86_64, &no_map).into_gimli(),
+            Some(gimli::X86_64::XMM16)
+        );
+        // 32-bit x86 numbers its registers differently
+        assert_eq!(
+            (&register("MM0"), Architecture::I386, &no_map).into_gimli(),
+            Some(gimli::X86::MM0)
+        );
+    }
+
+    #[test]
+    fn arm_registers() {
+        let all = names("R", 13)
+            .chain(["SP", "LR", "PC"].iter().map(|name| name.to_string()))
+            .chain(names("D", 32))
+            .chain(names("S", 32));
+        for name in all {
+            let reg = register(&name);
+            assert_eq!(
+                reg.into_gimli(),
+                reg.dwarf_number().map(gimli::Register),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn register_map() {
+        let map =
+            RegisterMap::from_reader(&b"# Vendor core\nrax=99\n\n  VREG0 = 300\n"[..]).unwrap();
+        // The map takes precedence over the built-in numbers
+        assert_eq!(
+            (&register("RAX"), Architecture::X86_64, &map).into_gimli(),
+            Some(gimli::Register(99))
+        );
+        assert_eq!(
+            (&register("RDX"), Architecture::X86_64, &map).into_gimli(),
+            Some(gimli::X86_64::RDX)
+        );
+        let vendor = register("vreg0");
+        assert!(matches!(vendor, Register::Other(_)));
+        assert_eq!(
+            (&vendor, Architecture::X86_64, &map).into_gimli(),
+            Some(gimli::Register(300))
+        );
+        assert_eq!(
+            (&vendor, Architecture::X86_64, &RegisterMap::default()).into_gimli(),
+            None
+        );
+        assert!(RegisterMap::from_reader(&b"RAX=rdx\n"[..]).is_err());
     }
 }
@@ -1,33 +1,61 @@
-use crate::anvill::AnvillInput;
-use crate::dwarf_unit::DwarfUnitRef;
-use crate::elf::ELF;
-use crate::ghidra::GhidraInput;
-use crate::str_bsi::StrBsiInput;
+use crate::dwarf_unit::{listing, processed_before, verify_output, write_listing, DwarfUnitRef};
+use crate::elf::{raw_elf, ELF};
+use crate::error::DwarfWriterError;
+use crate::header::save_header;
+use crate::into_gimli::RegisterMap;
+use crate::manifest::{Input, Manifest};
+use crate::patch::PatchInput;
 use crate::symbols::Symbols;
-use anyhow::{Error, Result};
-use clap::Parser;
+use crate::target::Target;
+use crate::types::{load_types, save_types};
+use anyhow::Result;
+use clap::{ArgEnum, Parser, Subcommand};
+use log::{warn, LevelFilter};
+use log4rs::append::console::{ConsoleAppender, Target as LogTarget};
+use log4rs::config::{Appender, Config, Root};
+use log4rs::encode::pattern::PatternEncoder;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 use serde::Deserialize;
-use simple_log::LogConfigBuilder;
 use std::path::Path;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::{fs, io};
 
 mod anvill;
+mod demangle;
 mod dwarf_attr;
 mod dwarf_entry;
 mod dwarf_unit;
 mod elf;
+mod error;
 mod ghidra;
+mod header;
 mod into_gimli;
+mod manifest;
+mod patch;
+mod reloc;
 mod str_bsi;
 mod symbols;
+mod target;
 mod types;
 
-#[derive(Parser, Debug)]
-#[clap(name = "dwarf-writer")]
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    name = "dwarf-writer",
+    subcommand_negates_reqs = true,
+    disable_help_subcommand = true
+)]
 pub struct Opt {
-    #[clap(name = "input", help = "Input binary", parse(from_os_str))]
-    input_binary_path: PathBuf,
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(
+        name = "input",
+        help = "Input binary",
+        required = true,
+        parse(from_os_str)
+    )]
+    input_binary_path: Option<PathBuf>,
     #[clap(name = "output", help = "Output binary", parse(from_os_str))]
     output_binary_path: Option<PathBuf>,
     #[clap(
@@ -54,12 +82,165 @@ pub struct Opt {
         parse(from_os_str)
     )]
     ghidra_paths: Vec<PathBuf>,
+    #[clap(
+        name = "ghidra-types",
+        long = "ghidra-types",
+        help = "Ghidra data type sizes used with Ghidra disassembly data",
+        parse(from_os_str)
+    )]
+    ghidra_types_path: Option<PathBuf>,
+    #[clap(
+        name = "ghidra-locals",
+        long = "ghidra-locals",
+        help = "Ghidra local stack variables used with Ghidra disassembly data",
+        parse(from_os_str)
+    )]
+    ghidra_locals_path: Option<PathBuf>,
+    #[clap(
+        long = "default-type",
+        help = "Type used for Ghidra's undefined types instead of leaving them out"
+    )]
+    default_type: Option<String>,
+    #[clap(
+        name = "typedef-map",
+        long = "typedef-map",
+        help = "Typedefs used with Ghidra disassembly data given as NAME=TYPE lines",
+        parse(from_os_str)
+    )]
+    typedef_map_path: Option<PathBuf>,
+    #[clap(
+        name = "register-map",
+        long = "register-map",
+        help = "DWARF register numbers given as NAME=NUMBER lines which take precedence over the \
+                built-in register mappings",
+        parse(from_os_str)
+    )]
+    register_map_path: Option<PathBuf>,
+    #[clap(
+        long = "cu",
+        help = "Only apply the inputs to the compilation unit with this source file name"
+    )]
+    cu: Option<String>,
+    #[clap(
+        long = "max-dies-per-cu",
+        help = "Add new entries to additional compilation units once a unit has this many entries"
+    )]
+    max_dies_per_cu: Option<usize>,
+    #[clap(
+        long = "language",
+        arg_enum,
+        help = "Source language of the unit created when the binary has no debug info"
+    )]
+    language: Option<Language>,
+    #[clap(
+        long = "comp-dir",
+        help = "Compilation directory of the unit which relative source file paths are resolved against",
+        parse(from_os_str)
+    )]
+    comp_dir: Option<PathBuf>,
+    #[clap(
+        name = "manifest",
+        long = "manifest",
+        help = "TOML or JSON file listing input files and their options",
+        parse(from_os_str)
+    )]
+    manifest_path: Option<PathBuf>,
+    #[clap(
+        name = "seed-types",
+        long = "seed-types",
+        help = "Types saved with --save-types whose struct definitions are used by the inputs",
+        parse(from_os_str)
+    )]
+    seed_types_path: Option<PathBuf>,
+    #[clap(
+        name = "types",
+        long = "types",
+        help = "Type library whose struct definitions are used by inputs referring to them by name",
+        parse(from_os_str)
+    )]
+    type_library_path: Option<PathBuf>,
+    #[clap(
+        name = "save-types",
+        long = "save-types",
+        help = "Save the binary's types after processing the inputs for use with --seed-types",
+        parse(from_os_str)
+    )]
+    save_types_path: Option<PathBuf>,
+    #[clap(
+        name = "emit-header",
+        long = "emit-header",
+        help = "Write the binary's types after processing the inputs as C declarations",
+        parse(from_os_str)
+    )]
+    emit_header_path: Option<PathBuf>,
+    #[clap(
+        name = "patch",
+        long = "patch",
+        help = "Function overrides applied after all other inputs",
+        parse(from_os_str)
+    )]
+    patch_path: Option<PathBuf>,
     #[clap(
         short = 'u',
         long = "use-all-str",
         help = "Use all entries in STR data regardless of confidence level"
     )]
     use_all_str: bool,
+    #[clap(
+        long = "respect-existing",
+        visible_alias = "update-only",
+        help = "Only modify debug info entries created by dwarf-writer, leaving the compiler's \
+                entries untouched when rerunning on a binary processed before"
+    )]
+    respect_existing: bool,
+    #[clap(
+        long = "update-only-existing",
+        help = "Only update the binary's existing functions and variables without creating entries \
+                for input functions and variables which don't match one"
+    )]
+    update_only_existing: bool,
+    #[clap(
+        long = "match-by-name",
+        help = "Move existing functions to the address of an input function with the same name \
+                instead of adding a duplicate"
+    )]
+    match_by_name: bool,
+    #[clap(
+        long = "name-policy",
+        arg_enum,
+        default_value = "overwrite",
+        help = "Whether input names overwrite existing names or only fill in missing ones"
+    )]
+    name_policy: NamePolicy,
+    #[clap(
+        long = "name-template",
+        help = "Name given to unnamed functions (`fn=...`) or variables (`var=...`), where {addr} is \
+                the padded hex address and {addr:x} or {addr:X} are unpadded",
+        multiple_occurrences = true
+    )]
+    name_templates: Vec<NameTemplate>,
+    #[clap(
+        long = "demangle",
+        help = "Demangle mangled input names, keeping the mangled name as DW_AT_linkage_name"
+    )]
+    demangle: bool,
+    #[clap(
+        long = "legacy-linkage-name",
+        help = "Also write mangled names as DW_AT_MIPS_linkage_name for debuggers which predate \
+                DW_AT_linkage_name",
+        requires = "demangle"
+    )]
+    legacy_linkage_name: bool,
+    #[clap(
+        long = "prefer-input-types",
+        help = "Overwrite existing base types' attributes when they differ from input types"
+    )]
+    prefer_input_types: bool,
+    #[clap(
+        long = "no-clobber",
+        help = "Fail instead of overwriting an existing output binary"
+    )]
+    no_clobber: bool,
     #[clap(
         name = "output-dir",
         short = 's',
@@ -68,6 +249,51 @@ pub struct Opt {
         parse(from_os_str)
     )]
     output_dir: Option<PathBuf>,
+    #[clap(
+        long = "dwarf64",
+        help = "Use the 64-bit DWARF format when creating new debug info"
+    )]
+    dwarf64: bool,
+    #[clap(
+        long = "emit-call-sites",
+        help = "Emit call site entries for calls listed in anvill data"
+    )]
+    emit_call_sites: bool,
+    #[clap(
+        long = "emit-aranges",
+        help = "Write a .debug_aranges section with the address ranges of each unit's functions"
+    )]
+    emit_aranges: bool,
+    #[clap(
+        long = "emit-sibling-attrs",
+        help = "Add DW_AT_sibling to entries with children so consumers can skip over them"
+    )]
+    emit_sibling_attrs: bool,
+    #[clap(
+        name = "asm-path",
+        long = "emit-asm",
+        help = "Write DWARF sections as assembler directives instead of updating the binary",
+        parse(from_os_str)
+    )]
+    asm_path: Option<PathBuf>,
+    #[clap(
+        long = "no-objcopy",
+        help = "Update the binary without objcopy. ELF symbols are not added in this mode"
+    )]
+    no_objcopy: bool,
+    #[cfg(unix)]
+    #[clap(
+        long = "output-fd",
+        help = "Write the output binary to this file descriptor. Implies --no-objcopy"
+    )]
+    output_fd: Option<i32>,
+    #[clap(
+        long = "verify-output",
+        help = "Read the output binary back and fail if its functions and types differ from the ones \
+                written",
+        conflicts_with_all = &["asm-path", "output-fd"]
+    )]
+    verify_output: bool,
     #[clap(
         name = "objcopy-path",
         short = 'x',
@@ -79,7 +305,7 @@ pub struct Opt {
     #[clap(
         name = "omit-variables",
         long = "omit-variables",
-        help = "Avoid emitting DW_TAG_variable entries for Anvill"
+        help = "Avoid emitting DW_TAG_variable entries"
     )]
     omit_variables: bool,
     #[clap(
@@ -88,10 +314,61 @@ pub struct Opt {
         help = "Avoid emitting DW_TAG_subprogram entries"
     )]
     omit_functions: bool,
+    #[clap(
+        long = "names-only",
+        help = "Only write the names and addresses of functions and variables without their types or \
+                parameters",
+        conflicts_with_all = &["seed-types", "types"]
+    )]
+    names_only: bool,
     #[clap(long = "omit-symbols", help = "Avoid adding ELF symbols")]
     omit_symbols: bool,
+    #[clap(
+        long = "strict",
+        help = "Treat inconsistencies between the inputs and the binary as errors"
+    )]
+    strict: bool,
+    #[clap(
+        long = "check-alignment",
+        help = "Warn about functions which don't start at an instruction boundary in executable code"
+    )]
+    check_alignment: bool,
+    #[clap(
+        long = "target",
+        help = "Target triple overriding the architecture and pointer size detected from the binary"
+    )]
+    target: Option<Target>,
+    #[clap(
+        long = "load-bias",
+        help = "Offset added to every input address, e.g. the load base that a position-independent \
+                binary's addresses were given relative to",
+        default_value = "0",
+        parse(try_from_str = parse_u64)
+    )]
+    load_bias: u64,
+    #[clap(
+        long = "raw-base",
+        help = "Treat the input as raw code loaded at this address and write a new ELF wrapping it. \
+                The architecture is taken from --target or the anvill input",
+        requires = "output",
+        parse(try_from_str = parse_u64)
+    )]
+    raw_base: Option<u64>,
+    #[clap(
+        short = 'j',
+        long = "threads",
+        help = "Number of threads used to parse input files (defaults to the number of CPUs)"
+    )]
+    threads: Option<usize>,
     #[clap(short = 'v', long = "verbose")]
     verbose: bool,
+    #[clap(
+        short = 'q',
+        long = "quiet",
+        help = "Only log errors",
+        conflicts_with_all = &["verbose", "level"]
+    )]
+    quiet: bool,
     // Has precedence over `verbose` flag
     #[clap(
         name = "level",
@@ -103,65 +380,393 @@ pub struct Opt {
     logging: Option<String>,
 }
 
+impl Opt {
+    /// Returns the binary being updated or listed.
+    fn input_path(&self) -> &Path {
+        match &self.command {
+            Some(Command::List { input_binary_path }) => input_binary_path,
+            None => self
+                .input_binary_path
+                .as_deref()
+                .expect("clap requires an input binary unless a subcommand is given"),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// List the functions and types in a binary's existing debug info
+    List {
+        #[clap(name = "input", help = "Input binary", parse(from_os_str))]
+        input_binary_path: PathBuf,
+    },
+}
+
+/// How names from the inputs are applied to existing entries.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NamePolicy {
+    /// Replace existing names.
+    Overwrite,
+    /// Only name entries which don't have a name.
+    Fill,
+}
+
+/// The source languages which can be given to units created by dwarf-writer.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    C,
+    Cpp,
+    Objc,
+    Fortran,
+    Ada,
+    Go,
+    Rust,
+    Swift,
+}
+
+impl Language {
+    pub fn dwarf_language(self) -> gimli::DwLang {
+        use gimli::constants::*;
+        match self {
+            Language::C => DW_LANG_C99,
+            Language::Cpp => DW_LANG_C_plus_plus,
+            Language::Objc => DW_LANG_ObjC,
+            Language::Fortran => DW_LANG_Fortran90,
+            Language::Ada => DW_LANG_Ada95,
+            Language::Go => DW_LANG_Go,
+            Language::Rust => DW_LANG_Rust,
+            Language::Swift => DW_LANG_Swift,
+        }
+    }
+}
+
+/// The kinds of entries given generated names when the inputs don't name them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameKind {
+    Function,
+    Variable,
+}
+
+/// A `--name-template` naming unnamed entries of one kind after their
+/// address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate {
+    kind: NameKind,
+    template: String,
+}
+
+impl NameTemplate {
+    const PLACEHOLDERS: [&'static str; 3] = ["{addr}", "{addr:x}", "{addr:X}"];
+
+    fn new(kind: NameKind, template: &str) -> Self {
+        NameTemplate {
+            kind,
+            template: template.to_string(),
+        }
+    }
+
+    /// The templates used when `--name-template` isn't given, which match
+    /// Ghidra's default names.
+    fn default_for(kind: NameKind) -> Self {
+        match kind {
+            NameKind::Function => NameTemplate::new(kind, "FUN_{addr}"),
+            NameKind::Variable => NameTemplate::new(kind, "VAR_{addr}"),
+        }
+    }
+
+    fn format(&self, addr: u64) -> String {
+        self.template
+            .replace("{addr}", &format!("{:08x}", addr))
+            .replace("{addr:x}", &format!("{:x}", addr))
+            .replace("{addr:X}", &format!("{:X}", addr))
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    /// Parses a template like `fn=sub_{addr:X}`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, template) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected `fn=TEMPLATE` or `var=TEMPLATE`, found {:?}", s))?;
+        let kind = match kind {
+            "fn" => NameKind::Function,
+            "var" => NameKind::Variable,
+            _ => {
+                return Err(format!(
+                    "Unknown entry kind {:?}, expected `fn` or `var`",
+                    kind
+                ))
+            },
+        };
+        // Without an address every generated name would be the same
+        if !Self::PLACEHOLDERS.iter().any(|p| template.contains(p)) {
+            return Err(format!("Template {:?} doesn't contain {{addr}}", template))
+        }
+        let rest = Self::PLACEHOLDERS
+            .iter()
+            .fold(template.to_string(), |rest, p| rest.replace(p, ""));
+        if rest.contains(['{', '}']) {
+            return Err(format!(
+                "Template {:?} has an unknown placeholder",
+                template
+            ))
+        }
+        Ok(NameTemplate::new(kind, template))
+    }
+}
+
+impl Opt {
+    /// Whether entries not created by dwarf-writer are left unchanged.
+    pub fn protects_existing(&self) -> bool {
+        self.respect_existing
+    }
+
+    /// Returns the name given to an unnamed entry of the given kind at `addr`.
+    pub fn generated_name(&self, kind: NameKind, addr: u64) -> String {
+        match self.name_templates.iter().rev().find(|t| t.kind == kind) {
+            Some(template) => template.format(addr),
+            None => NameTemplate::default_for(kind).format(addr),
+        }
+    }
+}
+
 pub trait InputFile: Sized + for<'de> Deserialize<'de> {
     /// Loads a file to create a new `AnvillInput`.
-    fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let file = fs::File::open(path)?;
+    fn new<P: AsRef<Path>>(path: P) -> error::Result<Self> {
+        let file = fs::File::open(path.as_ref())?;
         let reader = io::BufReader::new(file);
-        let hints = serde_json::from_reader(reader)?;
-        Ok(hints)
+        serde_json::from_reader(reader).map_err(|err| DwarfWriterError::parse(err).in_file(path))
     }
 }
 
+/// Parses an integer given in hex with a `0x` prefix or in decimal.
+fn parse_u64(s: &str) -> Result<u64, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Wraps the raw code given as the input binary in a new ELF which is written
+/// to the output path.
+fn raw_binary(opt: &Opt, inputs: &[Input], base: u64) -> Result<ELF> {
+    let anvill_target = inputs.iter().find_map(|input| match input {
+        Input::Anvill(input) => Some(input.target()),
+        _ => None,
+    });
+    let target = opt.target.or(anvill_target).ok_or_else(|| {
+        DwarfWriterError::InvalidInput(
+            "The architecture of raw code must be given with --target or an anvill input"
+                .to_string(),
+        )
+    })?;
+    let code = fs::read(opt.input_path())?;
+    let output_path = opt.output_binary_path.as_ref().ok_or_else(|| {
+        DwarfWriterError::InvalidInput("--raw-base requires an output binary".to_string())
+    })?;
+    Ok(ELF::from_buffer(raw_elf(&code, base, target), output_path)?)
+}
+
+/// Opens the file descriptor passed with `--output-fd`.
+#[cfg(unix)]
+fn output_fd(opt: &Opt) -> Option<fs::File> {
+    use std::os::unix::io::FromRawFd;
+    // The descriptor is inherited from the caller and isn't used anywhere else
+    // in this process so the file can take ownership of it
+    opt.output_fd.map(|fd| unsafe { fs::File::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+fn output_fd(_opt: &Opt) -> Option<fs::File> {
+    None
+}
+
+/// Parses input files in parallel, keeping the order of `inputs`.
+fn parse_inputs<S, T, F>(inputs: &[S], parse: F) -> error::Result<Vec<T>>
+where
+    S: Sync,
+    T: Send,
+    F: Fn(&S) -> error::Result<T> + Send + Sync, {
+    inputs.par_iter().map(parse).collect()
+}
+
+/// Logs to stderr so that stdout is left for machine-readable output.
+fn init_logging(opt: &Opt) -> Result<()> {
+    let level = match &opt.logging {
+        Some(level) => level.parse()?,
+        None if opt.quiet => LevelFilter::Error,
+        None if opt.verbose => LevelFilter::Trace,
+        None => LevelFilter::Info,
+    };
+    let console = ConsoleAppender::builder()
+        .target(LogTarget::Stderr)
+        .encoder(Box::new(PatternEncoder::new(
+            "{d(%Y-%m-%d %H:%M:%S.%f)} [{h({l})}] <{M}:{L}>:{m}{n}",
+        )))
+        .build();
+    let config = Config::builder()
+        .appender(Appender::builder().build("console", Box::new(console)))
+        .build(Root::builder().appender("console").build(level))?;
+    log4rs::init_config(config)?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let opt = Opt::parse();
-    let log_level = opt
-        .logging
-        .as_deref()
-        .unwrap_or(if opt.verbose { "trace" } else { "info" });
-    let log_config = LogConfigBuilder::builder()
-        .level(log_level)
-        .output_console()
-        .build();
-    simple_log::new(log_config).map_err(Error::msg)?;
+    init_logging(&opt)?;
+    if let Some(Command::List { .. }) = opt.command {
+        let mut elf = ELF::new(opt.input_path())?;
+        let stdout = io::stdout();
+        write_listing(&mut elf, &opt, io::BufWriter::new(stdout.lock()))?;
+        return Ok(())
+    }
 
-    let mut elf = ELF::new(&opt.input_binary_path)?;
+    // Each input is processed with the command line options overridden by its
+    // manifest options
+    let manifest = Manifest::from_cli(&opt)?;
+    let input_opts: Vec<_> = manifest
+        .inputs
+        .iter()
+        .map(|input| input.options(&opt))
+        .collect();
 
-    let mut dwarf = DwarfUnitRef::new(&mut elf);
+    // Input files are parsed in parallel, but applying them to the DWARF data
+    // must be done serially
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(opt.threads.unwrap_or(0))
+        .build()?;
+    let inputs = pool.install(|| {
+        let specs: Vec<_> = manifest.inputs.iter().zip(&input_opts).collect();
+        parse_inputs(&specs, |(input, cfg)| input.load(cfg))
+    })?;
+
+    let mut elf = match opt.raw_base {
+        Some(base) => raw_binary(&opt, &inputs, base)?,
+        None => ELF::new(opt.input_path())?,
+    };
+    if opt.respect_existing && !processed_before(&elf.dwarf) {
+        warn!(
+            "--respect-existing was given but dwarf-writer hasn't processed {} before so only new \
+             entries are written",
+            opt.input_path().display()
+        );
+    }
+    elf.emit_aranges = opt.emit_aranges;
+    elf.emit_siblings = opt.emit_sibling_attrs;
+    if let Some(map_path) = &opt.register_map_path {
+        elf.register_map = RegisterMap::load(map_path)?;
+    }
+    let elf_target = elf.target();
+    if let Some(target) = opt.target {
+        if target.endianness != elf_target.endianness {
+            warn!(
+                "Target {:?} is {:?} endian but the binary is {:?} endian. Debug info is \
+                 written in the binary's byte order",
+                target.arch, target.endianness, elf_target.endianness
+            );
+        }
+    }
+    let elf_arch = opt.target.unwrap_or(elf_target).arch;
 
     let mut syms = Symbols::new();
 
-    let mut type_map = dwarf.create_type_map();
+    let mut type_map = DwarfUnitRef::new(&mut elf, &opt).create_type_map();
+    if let Some(seed_path) = &opt.seed_types_path {
+        type_map.seed(&load_types(seed_path)?);
+    }
+    if let Some(library_path) = &opt.type_library_path {
+        DwarfUnitRef::new(&mut elf, &opt)
+            .add_type_library(load_types(library_path)?, &mut type_map);
+    }
 
-    for path in &opt.ghidra_paths {
-        let input = GhidraInput::new(path)?;
-        let ghidra_data = input.data()?;
-        if !opt.omit_symbols {
-            syms.add_ghidra(&ghidra_data);
+    for (input, cfg) in inputs.iter().zip(&input_opts) {
+        let mut dwarf = DwarfUnitRef::new(&mut elf, cfg);
+        match input {
+            Input::Ghidra(input) => {
+                let ghidra_data = input.data(cfg)?;
+                if !cfg.omit_symbols {
+                    syms.add_ghidra(&ghidra_data);
+                }
+                dwarf.process_ghidra(ghidra_data, &mut type_map);
+            },
+            Input::Anvill(input) => {
+                input.check_arch(elf_arch, cfg)?;
+                let anvill_data = input.data(cfg);
+                if !cfg.omit_symbols {
+                    syms.add_anvill(&anvill_data);
+                }
+                dwarf.process_anvill(anvill_data, &mut type_map);
+            },
+            Input::StrBsi(input) => {
+                let str_data = input.data(cfg);
+                if !cfg.omit_symbols {
+                    syms.add_str_bsi(&str_data);
+                }
+                dwarf.process_str_bsi(str_data, &mut type_map);
+            },
         }
-        dwarf.process_ghidra(ghidra_data, &mut type_map);
     }
 
-    for path in &opt.anvill_paths {
-        let input = AnvillInput::new(path)?;
-        let anvill_data = input.data(&opt);
-        if !opt.omit_symbols {
-            syms.add_anvill(&anvill_data);
-        }
-        dwarf.process_anvill(anvill_data, &mut type_map);
+    // Patches are applied last so they take precedence over all other inputs
+    if let Some(patch_path) = &opt.patch_path {
+        let patch = PatchInput::new(patch_path)?;
+        DwarfUnitRef::new(&mut elf, &opt).process_patch(patch.data(&opt)?, &mut type_map);
     }
 
-    for path in &opt.str_bsi_paths {
-        let input = StrBsiInput::new(path)?;
-        dwarf.process_str_bsi(input.data(&opt), &mut type_map);
+    if opt.check_alignment {
+        DwarfUnitRef::new(&mut elf, &opt).check_alignment();
     }
 
-    elf.update_binary(
-        opt.output_binary_path,
-        opt.objcopy_path,
-        opt.output_dir,
-        syms,
-    )?;
+    if let Some(types_path) = &opt.save_types_path {
+        save_types(&type_map, types_path)?;
+    }
+    if let Some(header_path) = &opt.emit_header_path {
+        save_header(&type_map, header_path)?;
+    }
+
+    // The debug info is listed before writing since objcopy consumes the `ELF`
+    let expected = opt.verify_output.then(|| listing(&mut elf, &opt));
+    let output_path = opt
+        .output_binary_path
+        .clone()
+        .unwrap_or_else(|| opt.input_path().to_path_buf());
+    let output_file = output_fd(&opt);
+    if let Some(asm_path) = &opt.asm_path {
+        elf.write_asm(asm_path)?;
+    } else if let (Some(dir), None, None) = (&opt.output_dir, &opt.output_binary_path, &output_file)
+    {
+        // Only dump the section files if no output binary was given
+        elf.write_sections(dir)?;
+        if expected.is_some() {
+            warn!("--verify-output only checks output binaries, not section files");
+        }
+        return Ok(())
+    } else if opt.no_objcopy || output_file.is_some() {
+        if !syms.0.is_empty() {
+            warn!("ELF symbols can only be added with objcopy");
+        }
+        if let Some(dir) = &opt.output_dir {
+            elf.write_sections(dir)?;
+        }
+        match output_file {
+            Some(file) => elf.write_binary(io::BufWriter::new(file))?,
+            None => elf.update_binary_in_process(opt.output_binary_path.clone(), opt.no_clobber)?,
+        }
+    } else {
+        elf.update_binary(
+            opt.output_binary_path.clone(),
+            opt.objcopy_path.clone(),
+            opt.output_dir.clone(),
+            syms,
+            opt.no_clobber,
+        )?;
+    }
+
+    if let Some(expected) = expected {
+        verify_output(&expected, &opt, &output_path)?;
+    }
 
     Ok(())
 }
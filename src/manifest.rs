@@ -0,0 +1,229 @@
+use crate::anvill::AnvillInput;
+use crate::error::{DwarfWriterError, Result};
+use crate::ghidra::GhidraInput;
+use crate::str_bsi::StrBsiInput;
+use crate::{InputFile, Opt};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A file listing the inputs to process and their options.
+#[derive(Deserialize, Debug, Default)]
+pub struct Manifest {
+    #[serde(default)]
+    pub inputs: Vec<ManifestInput>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputKind {
+    Anvill,
+    Ghidra,
+    StrBsi,
+}
+
+/// An input file and the options overriding the command line options while
+/// processing it.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ManifestInput {
+    #[serde(rename = "type")]
+    pub kind: InputKind,
+    pub path: PathBuf,
+    ghidra_types: Option<PathBuf>,
+    ghidra_locals: Option<PathBuf>,
+    typedef_map: Option<PathBuf>,
+    use_all_str: Option<bool>,
+    omit_functions: Option<bool>,
+    omit_variables: Option<bool>,
+    omit_symbols: Option<bool>,
+}
+
+/// A parsed input file.
+pub enum Input {
+    Anvill(AnvillInput),
+    Ghidra(GhidraInput),
+    StrBsi(StrBsiInput),
+}
+
+impl Manifest {
+    /// Loads a TOML or JSON manifest depending on the file extension. Relative
+    /// paths are resolved relative to the manifest's directory.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents).map_err(DwarfWriterError::parse),
+            _ => serde_json::from_str(&contents).map_err(DwarfWriterError::parse),
+        };
+        let mut manifest: Manifest = parsed.map_err(|err| err.in_file(path))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for input in &mut manifest.inputs {
+            input.path = dir.join(&input.path);
+            input.ghidra_types = input.ghidra_types.as_ref().map(|p| dir.join(p));
+            input.ghidra_locals = input.ghidra_locals.as_ref().map(|p| dir.join(p));
+            input.typedef_map = input.typedef_map.as_ref().map(|p| dir.join(p));
+        }
+        Ok(manifest)
+    }
+
+    /// Lists the inputs passed on the command line followed by the inputs in
+    /// the manifest if one was given.
+    pub fn from_cli(opt: &Opt) -> Result<Self> {
+        let mut inputs = Vec::new();
+        let cli_inputs = [
+            (InputKind::Ghidra, &opt.ghidra_paths),
+            (InputKind::Anvill, &opt.anvill_paths),
+            (InputKind::StrBsi, &opt.str_bsi_paths),
+        ];
+        for (kind, paths) in cli_inputs {
+            inputs.extend(paths.iter().map(|path| ManifestInput::new(kind, path)));
+        }
+        if let Some(path) = &opt.manifest_path {
+            inputs.extend(Manifest::new(path)?.inputs);
+        }
+        Ok(Manifest { inputs })
+    }
+}
+
+impl ManifestInput {
+    pub fn new<P: AsRef<Path>>(kind: InputKind, path: P) -> Self {
+        ManifestInput {
+            kind,
+            path: path.as_ref().to_path_buf(),
+            ghidra_types: None,
+            ghidra_locals: None,
+            typedef_map: None,
+            use_all_str: None,
+            omit_functions: None,
+            omit_variables: None,
+            omit_symbols: None,
+        }
+    }
+
+    /// Applies this input's options on top of the command line options.
+    pub fn options(&self, opt: &Opt) -> Opt {
+        let mut res = opt.clone();
+        if let Some(path) = &self.ghidra_types {
+            res.ghidra_types_path = Some(path.clone());
+        }
+        if let Some(path) = &self.ghidra_locals {
+            res.ghidra_locals_path = Some(path.clone());
+        }
+        if let Some(path) = &self.typedef_map {
+            res.typedef_map_path = Some(path.clone());
+        }
+        res.use_all_str = self.use_all_str.unwrap_or(opt.use_all_str);
+        res.omit_functions = self.omit_functions.unwrap_or(opt.omit_functions);
+        res.omit_variables = self.omit_variables.unwrap_or(opt.omit_variables);
+        res.omit_symbols = self.omit_symbols.unwrap_or(opt.omit_symbols);
+        res
+    }
+
+    /// Parses the input file using the input's options.
+    pub fn load(&self, cfg: &Opt) -> Result<Input> {
+        Ok(match self.kind {
+            InputKind::Anvill => Input::Anvill(AnvillInput::new(&self.path)?),
+            InputKind::Ghidra => {
+                let mut input = GhidraInput::new(&self.path)?;
+                if let Some(types_path) = &cfg.ghidra_types_path {
+                    input.load_struct_sizes(types_path)?;
+                }
+                if let Some(locals_path) = &cfg.ghidra_locals_path {
+                    input.load_stack_vars(locals_path)?;
+                }
+                if let Some(typedefs_path) = &cfg.typedef_map_path {
+                    input.load_typedefs(typedefs_path)?;
+                }
+                Input::Ghidra(input)
+            },
+            InputKind::StrBsi => Input::StrBsi(StrBsiInput::new(&self.path)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn mixed_manifest() {
+        let dir = tempdir().unwrap();
+        let mut csv = fs::File::create(dir.path().join("functions.csv")).unwrap();
+        csv.write_all(
+            b"\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n\
+              \"main\",\"00001000\",\"int main(void)\",\"10\"\n",
+        )
+        .unwrap();
+        let anvill = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/anvill_json/strip.types.c.json")
+            .canonicalize()
+            .unwrap();
+        let manifest_path = dir.path().join("inputs.toml");
+        fs::write(
+            &manifest_path,
+            format!(
+                r#"
+                [[inputs]]
+                type = "anvill"
+                path = "{}"
+                omit_variables = true
+
+                [[inputs]]
+                type = "ghidra"
+                path = "functions.csv"
+                "#,
+                anvill.display()
+            ),
+        )
+        .unwrap();
+
+        let opt = Opt::parse_from([
+            "dwarf-writer",
+            "--manifest",
+            manifest_path.to_str().unwrap(),
+            "-g",
+            "cli.csv",
+            "bin",
+        ]);
+        let manifest = Manifest::from_cli(&opt).unwrap();
+        let kinds: Vec<_> = manifest.inputs.iter().map(|input| input.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![InputKind::Ghidra, InputKind::Anvill, InputKind::Ghidra]
+        );
+
+        let anvill_input = &manifest.inputs[1];
+        let cfg = anvill_input.options(&opt);
+        assert!(cfg.omit_variables);
+        assert!(!opt.omit_variables);
+        assert!(matches!(anvill_input.load(&cfg), Ok(Input::Anvill(_))));
+
+        let ghidra_input = &manifest.inputs[2];
+        assert_eq!(ghidra_input.path, dir.path().join("functions.csv"));
+        let cfg = ghidra_input.options(&opt);
+        assert!(!cfg.omit_variables);
+        match ghidra_input.load(&cfg) {
+            Ok(Input::Ghidra(input)) => assert_eq!(input.data(&cfg).unwrap().fn_map.len(), 1),
+            _ => panic!("Failed to load the Ghidra input"),
+        }
+    }
+
+    #[test]
+    fn json_manifest() {
+        let dir = tempdir().unwrap();
+        let manifest_path = dir.path().join("inputs.json");
+        fs::write(
+            &manifest_path,
+            r#"{"inputs": [{"type": "str-bsi", "path": "str.json", "use_all_str": true}]}"#,
+        )
+        .unwrap();
+        let manifest = Manifest::new(&manifest_path).unwrap();
+        assert_eq!(manifest.inputs[0].kind, InputKind::StrBsi);
+        assert_eq!(manifest.inputs[0].path, dir.path().join("str.json"));
+        let opt = Opt::parse_from(["dwarf-writer", "bin"]);
+        assert!(manifest.inputs[0].options(&opt).use_all_str);
+    }
+}
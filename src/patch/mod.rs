@@ -0,0 +1,100 @@
+use crate::error::{DwarfWriterError, Result};
+use crate::str_bsi::{Address, Type};
+use crate::types::DwarfType;
+use crate::{InputFile, Opt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+impl InputFile for PatchInput {}
+
+impl PatchInput {
+    pub fn data(&self, cfg: &Opt) -> Result<PatchData<'_>> {
+        let fn_map = self
+            .functions
+            .iter()
+            .map(|(addr, f)| {
+                let parsed = match addr.strip_prefix("0x") {
+                    Some(hex_addr) => u64::from_str_radix(hex_addr, 16),
+                    None => u64::from_str(addr),
+                };
+                parsed
+                    .map(|addr| (addr + cfg.load_bias, f))
+                    .map_err(|_| DwarfWriterError::UnresolvedAddress(addr.clone()))
+            })
+            .collect::<Result<_>>()?;
+        let mut types: Vec<_> = self
+            .functions
+            .values()
+            .flat_map(|f| f.types())
+            .map(DwarfType::from)
+            .collect();
+        types.sort();
+        types.dedup();
+        Ok(PatchData { fn_map, types })
+    }
+}
+
+pub type PatchFnMap<'a> = HashMap<u64, &'a FunctionPatch>;
+
+pub struct PatchData<'a> {
+    pub fn_map: PatchFnMap<'a>,
+    pub types: Vec<DwarfType>,
+}
+
+/// Manual overrides for functions applied after all other inputs.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PatchInput {
+    functions: HashMap<Address, FunctionPatch>,
+}
+
+/// The attributes to override for a single function. Attributes which are not
+/// given are left as they were.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FunctionPatch {
+    pub name: Option<String>,
+    pub return_type: Option<Type>,
+    pub parameters: Option<Vec<ParameterPatch>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ParameterPatch {
+    pub name: String,
+    pub r#type: Option<Type>,
+}
+
+impl FunctionPatch {
+    fn types(&self) -> Vec<&Type> {
+        let mut types: Vec<_> = self.return_type.iter().collect();
+        if let Some(params) = &self.parameters {
+            types.extend(params.iter().filter_map(|p| p.r#type.as_ref()));
+        }
+        types
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn patch_addresses() {
+        let opt = Opt::parse_from(["dwarf-writer", "--load-bias", "0x100", "a.out"]);
+        let input: PatchInput = serde_json::from_str(
+            r#"{"functions": {
+                "0x1000": {"name": "start", "return_type": "int *"},
+                "4112": {"parameters": [{"name": "n", "type": "size_t"}]}
+            }}"#,
+        )
+        .unwrap();
+        let data = input.data(&opt).unwrap();
+        assert_eq!(data.fn_map[&0x1100].name.as_deref(), Some("start"));
+        assert!(data.fn_map[&0x1110].name.is_none());
+        assert_eq!(data.types.len(), 2);
+
+        let input: PatchInput =
+            serde_json::from_str(r#"{"functions": {"main": {"name": "start"}}}"#).unwrap();
+        assert!(input.data(&opt).is_err());
+    }
+}
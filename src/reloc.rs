@@ -0,0 +1,478 @@
+use gimli::write::{self, Address, EndianVec, Writer};
+use gimli::{ReaderOffset, RunTimeEndian, SectionId};
+use log::warn;
+use object::elf::*;
+use object::{Object, ObjectSection, ObjectSymbol, RelocationKind, RelocationTarget, SectionFlags,
+             SectionIndex, SymbolKind};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// The addresses given to the allocated sections of a relocatable object.
+///
+/// Sections in a relocatable object all start at address zero so they're laid
+/// out one after another, as a linker would, to give every address in the
+/// object a unique value. Addresses read from the object's debug info are
+/// converted to `Address::Symbol`s relative to the section symbol of the
+/// section they're in. The symbol only selects the section to relocate
+/// against while the addend holds the address in this layout, so the address
+/// can be used without looking up the section.
+#[derive(Debug)]
+pub struct Layout {
+    sections: Vec<SectionLayout>,
+    /// The section symbols of the DWARF sections which offsets are relative to.
+    debug_symbols: HashMap<String, usize>,
+    /// How relocations are encoded for the object's architecture if they can be
+    /// written.
+    format: Option<RelocationFormat>,
+    /// The address of each allocated section in this layout.
+    bases: HashMap<SectionIndex, u64>,
+}
+
+#[derive(Debug)]
+struct SectionLayout {
+    range: Range<u64>,
+    /// The symbol that addresses in the section are relocated against.
+    symbol: usize,
+    /// The offset of `symbol` in the section.
+    symbol_offset: u64,
+}
+
+/// How a relocatable object's relocations are encoded.
+#[derive(Clone, Copy, Debug)]
+pub struct RelocationFormat {
+    /// Whether relocations have explicit addends (`SHT_RELA`) rather than
+    /// storing them in the relocated data (`SHT_REL`).
+    pub is_rela: bool,
+    /// The relocation type for absolute 4-byte values.
+    abs32: u32,
+    /// The relocation type for absolute 8-byte values.
+    abs64: Option<u32>,
+}
+
+impl RelocationFormat {
+    /// The relocation format used for absolute values in debug info on each
+    /// supported architecture.
+    fn new(e_machine: u16, is_64: bool) -> Option<Self> {
+        let (is_rela, abs32, abs64) = match e_machine {
+            EM_X86_64 => (true, R_X86_64_32, Some(R_X86_64_64)),
+            EM_386 => (false, R_386_32, None),
+            EM_AARCH64 => (true, R_AARCH64_ABS32, Some(R_AARCH64_ABS64)),
+            EM_ARM => (false, R_ARM_ABS32, None),
+            EM_RISCV => (true, R_RISCV_32, Some(R_RISCV_64)),
+            EM_SPARC | EM_SPARC32PLUS | EM_SPARCV9 => (true, R_SPARC_UA32, Some(R_SPARC_UA64)),
+            EM_PPC => (true, R_PPC_ADDR32, None),
+            EM_PPC64 => (true, R_PPC64_ADDR32, Some(R_PPC64_ADDR64)),
+            // MIPS64 packs its relocation types differently
+            EM_MIPS if !is_64 => (false, R_MIPS_32, None),
+            _ => return None,
+        };
+        Some(RelocationFormat {
+            is_rela,
+            abs32,
+            abs64,
+        })
+    }
+
+    /// The relocation type for an absolute value of `size` bytes.
+    pub fn r_type(&self, size: u8) -> Option<u32> {
+        match size {
+            4 => Some(self.abs32),
+            8 => self.abs64,
+            _ => None,
+        }
+    }
+}
+
+impl Layout {
+    /// Lays out the allocated sections of a relocatable object.
+    pub fn new(obj: &object::File, e_machine: u16) -> Self {
+        // Addresses are relocated against section symbols when they exist,
+        // otherwise against the first symbol defined in the section
+        let mut section_symbols = HashMap::new();
+        for sym in obj.symbols() {
+            let section = match sym.section_index() {
+                Some(section) => section,
+                None => continue,
+            };
+            let candidate = (
+                sym.kind() != SymbolKind::Section,
+                sym.address(),
+                sym.index().0,
+            );
+            let best = section_symbols.entry(section).or_insert(candidate);
+            *best = candidate.min(*best);
+        }
+        let mut sections = Vec::new();
+        let mut debug_symbols = HashMap::new();
+        let mut bases = HashMap::new();
+        let mut next = 0u64;
+        for section in obj.sections() {
+            let symbol = section_symbols.get(&section.index()).copied();
+            let is_section_symbol = matches!(symbol, Some((false, _, _)));
+            let is_alloc = match section.flags() {
+                SectionFlags::Elf { sh_flags } => sh_flags & u64::from(SHF_ALLOC) != 0,
+                _ => false,
+            };
+            if is_alloc {
+                let align = section.align().max(1);
+                let base = next.div_ceil(align) * align;
+                next = base + section.size();
+                bases.insert(section.index(), base);
+                if let Some((_, symbol_offset, symbol)) = symbol {
+                    sections.push(SectionLayout {
+                        range: base..next,
+                        symbol,
+                        symbol_offset,
+                    });
+                }
+            } else if let (Ok(name), Some((_, _, symbol)), true) =
+                (section.name(), symbol, is_section_symbol)
+            {
+                debug_symbols.insert(name.to_string(), symbol);
+            }
+        }
+        // Existing relocation sections decide between REL and RELA
+        let format = RelocationFormat::new(e_machine, obj.is_64()).map(|mut format| {
+            let has_rel = obj.sections().any(|section| {
+                section
+                    .relocations()
+                    .next()
+                    .map(|(_, reloc)| reloc.has_implicit_addend())
+                    .unwrap_or(false)
+            });
+            format.is_rela &= !has_rel;
+            format
+        });
+        Layout {
+            sections,
+            debug_symbols,
+            format,
+            bases,
+        }
+    }
+
+    /// Whether offsets into a DWARF section can be relocated.
+    pub fn has_debug_symbol(&self, section: SectionId) -> bool {
+        self.debug_symbols.contains_key(section.name())
+    }
+
+    pub fn format(&self) -> Option<RelocationFormat> {
+        self.format
+    }
+
+    /// Converts an address read from the object's debug info. Addresses
+    /// outside of the object's sections are left as constants.
+    pub fn address(&self, addr: u64) -> Address {
+        match self.section(addr) {
+            Some(section) => Address::Symbol {
+                symbol: section.symbol,
+                addend: addr as i64,
+            },
+            None => Address::Constant(addr),
+        }
+    }
+
+    /// Finds the section containing `addr`. Addresses at the end of a section
+    /// (e.g. the end of the last function) belong to it if they aren't at the
+    /// start of the next section.
+    fn section(&self, addr: u64) -> Option<&SectionLayout> {
+        self.sections
+            .iter()
+            .find(|section| section.range.contains(&addr))
+            .or_else(|| {
+                self.sections
+                    .iter()
+                    .find(|section| section.range.end == addr && !section.range.is_empty())
+            })
+    }
+
+    /// Returns the symbol and addend to relocate an address against.
+    fn relocation(&self, address: Address) -> Option<(usize, i64)> {
+        let addr = match address {
+            Address::Constant(addr) => addr,
+            Address::Symbol { addend, .. } => addend as u64,
+        };
+        let section = self.section(addr)?;
+        let addend = (addr - section.range.start) as i64 - section.symbol_offset as i64;
+        Some((section.symbol, addend))
+    }
+
+    /// Reads the relocations of a DWARF section with their symbols' addresses
+    /// in this layout.
+    pub fn relocations(&self, obj: &object::File, section: &object::Section) -> RelocationMap {
+        let mut relocations = HashMap::new();
+        for (offset, reloc) in section.relocations() {
+            if reloc.kind() != RelocationKind::Absolute {
+                warn!(
+                    "Relocation {:?} in {} at {:#x} isn't supported and will not be kept",
+                    reloc.kind(),
+                    section.name().unwrap_or_default(),
+                    offset
+                );
+                continue
+            }
+            let target = match reloc.target() {
+                RelocationTarget::Symbol(idx) => obj.symbol_by_index(idx).ok().map(|sym| {
+                    let base = sym.section_index().and_then(|idx| self.bases.get(&idx));
+                    base.copied().unwrap_or_default() + sym.address()
+                }),
+                RelocationTarget::Section(idx) => self.bases.get(&idx).copied(),
+                _ => None,
+            };
+            let value = target
+                .unwrap_or_default()
+                .wrapping_add(reloc.addend() as u64);
+            relocations.insert(
+                offset as usize,
+                Relocation {
+                    value,
+                    is_implicit: reloc.has_implicit_addend(),
+                },
+            );
+        }
+        relocations
+    }
+}
+
+/// A relocation read from a DWARF section.
+#[derive(Debug)]
+pub struct Relocation {
+    /// The value of the symbol plus the explicit addend.
+    value: u64,
+    /// Whether the relocated data holds an addend.
+    is_implicit: bool,
+}
+
+/// The relocations of a DWARF section keyed by their offset.
+pub type RelocationMap = HashMap<usize, Relocation>;
+
+/// A gimli `Reader` which applies a relocatable object's relocations to the
+/// addresses and offsets that it reads.
+#[derive(Debug, Clone)]
+pub struct Relocate<'a, R: gimli::Reader<Offset = usize>> {
+    relocations: &'a RelocationMap,
+    section: R,
+    reader: R,
+}
+
+impl<'a, R: gimli::Reader<Offset = usize>> Relocate<'a, R> {
+    pub fn new(section: R, relocations: &'a RelocationMap) -> Self {
+        Relocate {
+            relocations,
+            section: section.clone(),
+            reader: section,
+        }
+    }
+
+    fn relocate(&self, offset: usize, value: u64) -> u64 {
+        match self.relocations.get(&offset) {
+            Some(reloc) if reloc.is_implicit => value.wrapping_add(reloc.value),
+            Some(reloc) => reloc.value,
+            None => value,
+        }
+    }
+}
+
+impl<'a, R: gimli::Reader<Offset = usize>> gimli::Reader for Relocate<'a, R> {
+    type Endian = R::Endian;
+    type Offset = R::Offset;
+
+    fn read_address(&mut self, address_size: u8) -> gimli::Result<u64> {
+        let offset = self.reader.offset_from(&self.section);
+        let value = self.reader.read_address(address_size)?;
+        Ok(self.relocate(offset, value))
+    }
+
+    fn read_length(&mut self, format: gimli::Format) -> gimli::Result<usize> {
+        let offset = self.reader.offset_from(&self.section);
+        let value = self.reader.read_length(format)?;
+        usize::from_u64(self.relocate(offset, value as u64))
+    }
+
+    fn read_offset(&mut self, format: gimli::Format) -> gimli::Result<usize> {
+        let offset = self.reader.offset_from(&self.section);
+        let value = self.reader.read_offset(format)?;
+        usize::from_u64(self.relocate(offset, value as u64))
+    }
+
+    fn read_sized_offset(&mut self, size: u8) -> gimli::Result<usize> {
+        let offset = self.reader.offset_from(&self.section);
+        let value = self.reader.read_sized_offset(size)?;
+        usize::from_u64(self.relocate(offset, value as u64))
+    }
+
+    fn split(&mut self, len: Self::Offset) -> gimli::Result<Self> {
+        let mut other = self.clone();
+        other.reader.truncate(len)?;
+        self.reader.skip(len)?;
+        Ok(other)
+    }
+
+    // The remaining methods delegate to `self.reader`
+
+    fn endian(&self) -> Self::Endian {
+        self.reader.endian()
+    }
+
+    fn len(&self) -> Self::Offset {
+        self.reader.len()
+    }
+
+    fn empty(&mut self) {
+        self.reader.empty()
+    }
+
+    fn truncate(&mut self, len: Self::Offset) -> gimli::Result<()> {
+        self.reader.truncate(len)
+    }
+
+    fn offset_from(&self, base: &Self) -> Self::Offset {
+        self.reader.offset_from(&base.reader)
+    }
+
+    fn offset_id(&self) -> gimli::ReaderOffsetId {
+        self.reader.offset_id()
+    }
+
+    fn lookup_offset_id(&self, id: gimli::ReaderOffsetId) -> Option<Self::Offset> {
+        self.reader.lookup_offset_id(id)
+    }
+
+    fn find(&self, byte: u8) -> gimli::Result<Self::Offset> {
+        self.reader.find(byte)
+    }
+
+    fn skip(&mut self, len: Self::Offset) -> gimli::Result<()> {
+        self.reader.skip(len)
+    }
+
+    fn to_slice(&self) -> gimli::Result<Cow<'_, [u8]>> {
+        self.reader.to_slice()
+    }
+
+    fn to_string(&self) -> gimli::Result<Cow<'_, str>> {
+        self.reader.to_string()
+    }
+
+    fn to_string_lossy(&self) -> gimli::Result<Cow<'_, str>> {
+        self.reader.to_string_lossy()
+    }
+
+    fn read_slice(&mut self, buf: &mut [u8]) -> gimli::Result<()> {
+        self.reader.read_slice(buf)
+    }
+}
+
+/// A relocation to write for a DWARF section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelocationEntry {
+    pub offset: u64,
+    pub size: u8,
+    pub symbol: usize,
+    pub addend: i64,
+}
+
+/// A gimli `Writer` which builds a DWARF section in memory and records the
+/// relocations needed by the addresses and offsets written to it when
+/// updating a relocatable object.
+#[derive(Clone, Debug)]
+pub struct RelocSection {
+    data: EndianVec<RunTimeEndian>,
+    layout: Option<Arc<Layout>>,
+    pub relocations: Vec<RelocationEntry>,
+}
+
+impl RelocSection {
+    pub fn new(endianness: RunTimeEndian, layout: Option<Arc<Layout>>) -> Self {
+        RelocSection {
+            data: EndianVec::new(endianness),
+            layout,
+            relocations: Vec::new(),
+        }
+    }
+
+    pub fn slice(&self) -> &[u8] {
+        self.data.slice()
+    }
+
+    pub fn take(&mut self) -> Vec<u8> {
+        self.data.take()
+    }
+
+    /// Records a relocation for an offset into another DWARF section if that
+    /// section has a symbol to relocate against.
+    fn relocate_offset(&mut self, offset: usize, val: usize, section: SectionId, size: u8) {
+        let layout = match &self.layout {
+            Some(layout) => layout,
+            None => return,
+        };
+        match layout.debug_symbols.get(section.name()) {
+            Some(&symbol) => self.relocations.push(RelocationEntry {
+                offset: offset as u64,
+                size,
+                symbol,
+                addend: val as i64,
+            }),
+            None => warn!(
+                "Offsets into {} can't be relocated since it has no section symbol",
+                section.name()
+            ),
+        }
+    }
+}
+
+impl Writer for RelocSection {
+    type Endian = RunTimeEndian;
+
+    fn endian(&self) -> Self::Endian {
+        self.data.endian()
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> write::Result<()> {
+        self.data.write(bytes)
+    }
+
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) -> write::Result<()> {
+        self.data.write_at(offset, bytes)
+    }
+
+    fn write_address(&mut self, address: Address, size: u8) -> write::Result<()> {
+        let relocation = self
+            .layout
+            .as_ref()
+            .and_then(|layout| layout.relocation(address));
+        match (relocation, address) {
+            (Some((symbol, addend)), _) => {
+                self.relocations.push(RelocationEntry {
+                    offset: self.len() as u64,
+                    size,
+                    symbol,
+                    addend,
+                });
+                // The addend is also written in place for relocations without
+                // explicit addends
+                self.write_udata(addend as u64, size)
+            },
+            (None, Address::Symbol { addend, .. }) => self.write_udata(addend as u64, size),
+            (None, Address::Constant(addr)) => self.write_udata(addr, size),
+        }
+    }
+
+    fn write_offset(&mut self, val: usize, section: SectionId, size: u8) -> write::Result<()> {
+        self.relocate_offset(self.len(), val, section, size);
+        self.write_udata(val as u64, size)
+    }
+
+    fn write_offset_at(
+        &mut self, offset: usize, val: usize, section: SectionId, size: u8,
+    ) -> write::Result<()> {
+        self.relocate_offset(offset, val, section, size);
+        self.write_udata_at(offset, val as u64, size)
+    }
+}
@@ -1,4 +1,4 @@
-use crate::types::{CanonicalTypeName, DwarfType};
+use crate::types::{CanonicalTypeName, DwarfType, Qualifier};
 use crate::InputFile;
 use crate::Opt;
 use serde::{Deserialize, Serialize};
@@ -8,7 +8,7 @@ use std::str::FromStr;
 impl InputFile for StrBsiInput {}
 
 impl StrBsiInput {
-    pub fn data(&self, cfg: &Opt) -> StrBsiData {
+    pub fn data(&self, cfg: &Opt) -> StrBsiData<'_> {
         let use_all_entries = cfg.use_all_str;
         let fn_map = if cfg.omit_functions {
             HashMap::new()
@@ -25,13 +25,13 @@ impl StrBsiInput {
                             None => u64::from_str(addr),
                         }
                         .unwrap_or_else(|_| panic!("Unable to parse {} into a u64", addr));
-                        Some((addr, f))
+                        Some((addr + cfg.load_bias, f))
                     }
                 })
                 .collect()
         };
         let dwarf_types = self
-            .types(use_all_entries)
+            .types(use_all_entries, !cfg.omit_variables)
             .iter()
             .map(|&t| t.into())
             .collect();
@@ -41,14 +41,14 @@ impl StrBsiInput {
         }
     }
 
-    fn types(&self, use_all_entries: bool) -> Vec<&Type> {
+    fn types(&self, use_all_entries: bool, include_local_vars: bool) -> Vec<&Type> {
         let mut types = Vec::new();
-        for (_, func) in &self.functions {
+        for func in self.functions.values() {
             if let Some(sm) = &func.source_match {
                 if !use_all_entries && sm.confidence != 1 {
                     continue
                 }
-                types.append(&mut sm.types());
+                types.append(&mut sm.types(include_local_vars));
             }
         }
         types.sort();
@@ -78,6 +78,7 @@ pub struct StrBsiInput {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Function {
     pub symbol_name: Option<String>,
+    pub is_noreturn: Option<bool>,
     calling_convention: Option<String>,
     return_registers: Vec<Register>,
     clobbered_registers: Vec<Register>,
@@ -87,11 +88,7 @@ pub struct Function {
 impl Function {
     pub fn parameters(&self) -> Option<Vec<&NamedVariable>> {
         if let Some(sm) = &self.source_match {
-            if let Some(params) = &sm.parameters {
-                Some(params.values().collect())
-            } else {
-                None
-            }
+            sm.parameters.as_ref().map(ordered_vars)
         } else {
             None
         }
@@ -99,28 +96,35 @@ impl Function {
 
     pub fn local_vars(&self) -> Option<Vec<&NamedVariable>> {
         if let Some(sm) = &self.source_match {
-            if let Some(params) = &sm.local_variables {
-                Some(params.values().collect())
-            } else {
-                None
-            }
+            sm.local_variables.as_ref().map(ordered_vars)
         } else {
             None
         }
     }
 
     pub fn file(&self) -> Option<&str> {
-        self.source_match
-            .as_ref()
-            .map(|sm| sm.file.as_ref().map(|file| file.as_str()))
-            .flatten()
+        self.source_match.as_ref().and_then(|sm| sm.file.as_deref())
     }
 
     pub fn line(&self) -> Option<u64> {
-        self.source_match.as_ref().map(|sm| sm.line).flatten()
+        self.source_match.as_ref().and_then(|sm| sm.line)
     }
 }
 
+/// Returns variables ordered by their IDs since parameters are matched by
+/// position. IDs are compared numerically when possible so `"10"` follows
+/// `"9"`.
+fn ordered_vars(vars: &HashMap<VarId, NamedVariable>) -> Vec<&NamedVariable> {
+    let mut vars: Vec<_> = vars.iter().collect();
+    vars.sort_by(
+        |(a, _), (b, _)| match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        },
+    );
+    vars.into_iter().map(|(_, var)| var).collect()
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SourceMatch {
     confidence: u32,
@@ -133,22 +137,24 @@ pub struct SourceMatch {
 }
 
 impl SourceMatch {
-    pub fn types(&self) -> Vec<&Type> {
+    pub fn types(&self, include_local_vars: bool) -> Vec<&Type> {
         let mut types = Vec::new();
         if let Some(parameters) = &self.parameters {
-            for (_, var) in parameters {
+            for var in parameters.values() {
                 if let Some(ty) = &var.r#type {
                     types.push(ty);
                 }
             }
         };
-        if let Some(local_variables) = &self.local_variables {
-            for (_, var) in local_variables {
-                if let Some(ty) = &var.r#type {
-                    types.push(ty);
+        if include_local_vars {
+            if let Some(local_variables) = &self.local_variables {
+                for var in local_variables.values() {
+                    if let Some(ty) = &var.r#type {
+                        types.push(ty);
+                    }
                 }
-            }
-        };
+            };
+        }
         if let Some(ty) = &self.return_value.r#type {
             types.push(ty);
         };
@@ -165,11 +171,15 @@ pub struct UnnamedVariable {
 pub struct NamedVariable {
     pub name: String,
     pub r#type: Option<Type>,
+    pub file: Option<String>,
+    pub line: Option<u64>,
 }
 
 impl From<&Type> for DwarfType {
     fn from(str_ty: &Type) -> DwarfType {
-        if let Some(referent_ty) = str_ty.strip_suffix("*") {
+        if let Some((qualifier, inner_ty)) = Qualifier::strip_suffix(str_ty) {
+            qualifier.apply(DwarfType::from(&String::from(inner_ty)))
+        } else if let Some(referent_ty) = str_ty.strip_suffix("*") {
             DwarfType::new_pointer(DwarfType::from(&String::from(referent_ty)))
         } else if let Some(inner_ty) = str_ty.strip_suffix("[]") {
             DwarfType::new_array(DwarfType::from(&String::from(inner_ty)), None)
@@ -177,11 +187,16 @@ impl From<&Type> for DwarfType {
             let mut inner_ty = inner_ty.split('[').collect::<Vec<_>>();
             let array_len = inner_ty
                 .pop()
-                .map(|ty| u64::from_str(ty).ok())
-                .flatten()
-                .unwrap_or_else(|| panic!("Unable to parse type {:?}", inner_ty));
-            let array_ty = inner_ty.join("");
-            DwarfType::new_array(DwarfType::from(&array_ty), Some(array_len))
+                .unwrap_or_else(|| panic!("Unable to parse type {:?}", inner_ty))
+                .trim();
+            let array_ty = DwarfType::from(&inner_ty.join(""));
+            match u64::from_str(array_len) {
+                Ok(len) => DwarfType::new_array(array_ty, Some(len)),
+                // Variable-length arrays are sized by another parameter
+                Err(_) => DwarfType::new_variable_length_array(array_ty, array_len.to_string()),
+            }
+        } else if let Some((qualifier, inner_ty)) = Qualifier::strip_prefix(str_ty) {
+            qualifier.apply(DwarfType::from(&String::from(inner_ty)))
         } else {
             DwarfType::new_primitive(CanonicalTypeName::from(str_ty.as_bytes().to_vec()), None)
         }
@@ -1,5 +1,6 @@
 use crate::anvill::AnvillData;
 use crate::ghidra::GhidraData;
+use crate::str_bsi::StrBsiData;
 
 pub enum SymbolFlag {
     Function,
@@ -61,4 +62,47 @@ impl Symbols {
             }
         }
     }
+
+    pub fn add_str_bsi(&mut self, str_data: &StrBsiData) {
+        for (&addr, func) in &str_data.fn_map {
+            if let Some(name) = &func.symbol_name {
+                self.0.push(Symbol {
+                    name: name.to_string(),
+                    value: addr,
+                    flags: SymbolFlag::Function,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::str_bsi::StrBsiInput;
+    use crate::Opt;
+    use clap::Parser;
+
+    #[test]
+    fn str_symbols_respect_omit_functions() {
+        let input: StrBsiInput = serde_json::from_str(
+            r#"{"functions": {"0x1000": {
+                "symbol_name": "main",
+                "calling_convention": null,
+                "return_registers": [],
+                "clobbered_registers": [],
+                "source_match": null
+            }}}"#,
+        )
+        .unwrap();
+        let mut syms = Symbols::new();
+        syms.add_str_bsi(&input.data(&Opt::parse_from(["dwarf-writer", "-u", "bin"])));
+        assert_eq!(syms.0.len(), 1);
+        assert_eq!(syms.0[0].objcopy_add_cmd(), "main=0x00001000,function");
+
+        let mut syms = Symbols::new();
+        let opt = Opt::parse_from(["dwarf-writer", "-u", "--omit-functions", "bin"]);
+        syms.add_str_bsi(&input.data(&opt));
+        assert!(syms.0.is_empty());
+    }
 }
@@ -0,0 +1,85 @@
+use object::{Architecture, Endianness, Object};
+use std::str::FromStr;
+
+/// The properties of the target a binary was built for which affect how the
+/// inputs are written as debug info.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Target {
+    /// The architecture used to map register names.
+    pub arch: Architecture,
+    /// The size of a pointer in bytes.
+    pub ptr_size: u64,
+    pub endianness: Endianness,
+}
+
+impl Target {
+    /// Detects the target from a binary's header.
+    pub fn detect(obj: &object::File) -> Self {
+        Target {
+            arch: obj.architecture(),
+            ptr_size: if obj.is_64() { 8 } else { 4 },
+            endianness: obj.endianness(),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    /// Parses the architecture of a target triple like
+    /// `x86_64-unknown-linux-gnu`.
+    fn from_str(triple: &str) -> Result<Self, Self::Err> {
+        use Endianness::{Big, Little};
+
+        let arch_name = triple.split('-').next().unwrap_or_default();
+        let is_x32 = triple.ends_with("x32");
+        let (arch, ptr_size, endianness) = match arch_name {
+            "x86_64" | "amd64" if is_x32 => (Architecture::X86_64_X32, 4, Little),
+            "x86_64" | "amd64" => (Architecture::X86_64, 8, Little),
+            "i386" | "i486" | "i586" | "i686" | "x86" => (Architecture::I386, 4, Little),
+            "aarch64" | "arm64" => (Architecture::Aarch64, 8, Little),
+            "aarch64_be" => (Architecture::Aarch64, 8, Big),
+            "mips" => (Architecture::Mips, 4, Big),
+            "mipsel" => (Architecture::Mips, 4, Little),
+            "mips64" => (Architecture::Mips64, 8, Big),
+            "mips64el" => (Architecture::Mips64, 8, Little),
+            "powerpc" => (Architecture::PowerPc, 4, Big),
+            "powerpc64" => (Architecture::PowerPc64, 8, Big),
+            "powerpc64le" => (Architecture::PowerPc64, 8, Little),
+            "riscv32" => (Architecture::Riscv32, 4, Little),
+            "riscv64" => (Architecture::Riscv64, 8, Little),
+            "s390x" => (Architecture::S390x, 8, Big),
+            "sparc" => (Architecture::Unknown, 4, Big),
+            "sparc64" | "sparcv9" => (Architecture::Sparc64, 8, Big),
+            arm if arm.starts_with("arm") || arm.starts_with("thumb") => {
+                let endianness = if arm.contains("eb") { Big } else { Little };
+                (Architecture::Arm, 4, endianness)
+            },
+            _ => return Err(format!("Unsupported target triple {:?}", triple)),
+        };
+        Ok(Target {
+            arch,
+            ptr_size,
+            endianness,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_triples() {
+        let target: Target = "x86_64-unknown-linux-gnu".parse().unwrap();
+        assert_eq!(target.arch, Architecture::X86_64);
+        assert_eq!(target.ptr_size, 8);
+        let target: Target = "x86_64-unknown-linux-gnux32".parse().unwrap();
+        assert_eq!(target.arch, Architecture::X86_64_X32);
+        assert_eq!(target.ptr_size, 4);
+        let target: Target = "armebv7-unknown-linux-gnueabi".parse().unwrap();
+        assert_eq!(target.arch, Architecture::Arm);
+        assert_eq!(target.endianness, Endianness::Big);
+        assert!("m68k-unknown-linux-gnu".parse::<Target>().is_err());
+    }
+}
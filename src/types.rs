@@ -1,7 +1,15 @@
+use crate::error::{DwarfWriterError, Result};
 use gimli::constants::*;
-use gimli::write::UnitEntryId;
+use gimli::write::{UnitEntryId, UnitId};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::hash_map;
 use std::collections::HashMap;
 use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::iter::FromIterator;
+use std::ops::Index;
+use std::path::Path;
 
 // Types may have various representations so `TypeName`s should be converted to
 // `CanonicalTypeName`s before being compared for equality.
@@ -11,7 +19,201 @@ pub type TypeName = Vec<u8>;
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct CanonicalTypeName(TypeName);
 
-pub type TypeMap = HashMap<DwarfType, UnitEntryId>;
+/// Maps types to their DWARF entries. Types which aren't already normalized are
+/// normalized before lookups and insertions so equivalent types share a single
+/// entry.
+#[derive(Debug, Default)]
+pub struct TypeMap {
+    types: HashMap<DwarfType, UnitEntryId>,
+    /// Definitions of named structs which replace references to structs with
+    /// the same name and no known fields.
+    definitions: HashMap<CanonicalTypeName, DwarfType>,
+    /// The unit containing the mapped entries if the map was created from one.
+    unit: Option<UnitId>,
+}
+
+impl TypeMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty map for the types in a unit.
+    pub fn for_unit(unit: UnitId) -> Self {
+        TypeMap {
+            unit: Some(unit),
+            ..Self::default()
+        }
+    }
+
+    pub fn unit(&self) -> Option<UnitId> {
+        self.unit
+    }
+
+    /// Replaces the mapped entries with those of another map, e.g. one created
+    /// for another unit. Struct definitions are kept.
+    pub fn replace_entries(&mut self, other: TypeMap) {
+        self.types = other.types;
+        self.unit = other.unit;
+    }
+
+    pub fn get(&self, ty: &DwarfType) -> Option<&UnitEntryId> {
+        if self.is_key(ty) {
+            self.types.get(ty)
+        } else {
+            self.types.get(&self.key(ty))
+        }
+    }
+
+    pub fn contains_key(&self, ty: &DwarfType) -> bool {
+        self.get(ty).is_some()
+    }
+
+    pub fn insert(&mut self, ty: DwarfType, id: UnitEntryId) -> Option<UnitEntryId> {
+        let key = if self.is_key(&ty) { ty } else { self.key(&ty) };
+        self.types.insert(key, id)
+    }
+
+    pub fn keys(&self) -> hash_map::Keys<'_, DwarfType, UnitEntryId> {
+        self.types.keys()
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, DwarfType, UnitEntryId> {
+        self.types.iter()
+    }
+
+    pub fn drain(&mut self) -> hash_map::Drain<'_, DwarfType, UnitEntryId> {
+        self.types.drain()
+    }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// Adds the definition of a named struct so inputs which only refer to it
+    /// by name use its full layout. Returns `false` if `ty` isn't a named
+    /// struct with fields.
+    pub fn define(&mut self, ty: &DwarfType) -> bool {
+        match ty.normalize() {
+            DwarfType::Struct {
+                name: Some(name),
+                ref fields,
+                ref variant_part,
+                ..
+            } if !fields.is_empty() || variant_part.is_some() => {
+                let def = self.key(ty);
+                self.definitions.insert(name, def);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Adds the struct definitions from a set of types saved from another
+    /// binary. No entries are created for them until an input uses them, so
+    /// binaries processed with the same seed only share the types they use.
+    pub fn seed(&mut self, types: &[DwarfType]) {
+        for ty in types {
+            self.define(ty);
+        }
+    }
+
+    /// Whether a type is already in the form used as its key so lookups can
+    /// skip building the key.
+    fn is_key(&self, ty: &DwarfType) -> bool {
+        !ty.any(&|ty| match ty {
+            DwarfType::Struct {
+                name: Some(name),
+                fields,
+                variant_part: None,
+                ..
+            } if fields.is_empty() && self.definitions.contains_key(name) => true,
+            ty => !ty.is_normalized(),
+        })
+    }
+
+    /// Returns the form of a type used as its key. References to structs
+    /// without known fields are replaced with their definitions.
+    pub fn key(&self, ty: &DwarfType) -> DwarfType {
+        let ty = ty.normalize();
+        if self.definitions.is_empty() {
+            return ty
+        }
+        ty.replace(&|ty| match ty {
+            DwarfType::Struct {
+                name: Some(name),
+                fields,
+                variant_part: None,
+                ..
+            } if fields.is_empty() => self.definitions.get(name).cloned(),
+            _ => None,
+        })
+    }
+}
+
+impl Index<&DwarfType> for TypeMap {
+    type Output = UnitEntryId;
+
+    fn index(&self, ty: &DwarfType) -> &UnitEntryId {
+        self.get(ty)
+            .unwrap_or_else(|| panic!("Type {:?} not found in the type map", ty))
+    }
+}
+
+impl FromIterator<(DwarfType, UnitEntryId)> for TypeMap {
+    fn from_iter<I: IntoIterator<Item = (DwarfType, UnitEntryId)>>(iter: I) -> Self {
+        let mut type_map = TypeMap::new();
+        for (ty, id) in iter {
+            type_map.insert(ty, id);
+        }
+        type_map
+    }
+}
+
+/// Saves the types in a type map as JSON so they can be used as a seed when
+/// processing other binaries. The entry IDs are not saved since they're
+/// specific to each binary's DWARF unit.
+pub fn save_types<P: AsRef<Path>>(type_map: &TypeMap, path: P) -> Result<()> {
+    let mut types: Vec<_> = type_map.keys().collect();
+    types.sort();
+    let writer = io::BufWriter::new(fs::File::create(path)?);
+    serde_json::to_writer(writer, &types).map_err(io::Error::from)?;
+    Ok(())
+}
+
+/// Loads a set of types saved with `save_types`.
+pub fn load_types<P: AsRef<Path>>(path: P) -> Result<Vec<DwarfType>> {
+    let reader = io::BufReader::new(fs::File::open(path.as_ref())?);
+    serde_json::from_reader(reader).map_err(|err| DwarfWriterError::parse(err).in_file(path))
+}
+
+// Names are saved as strings when possible to keep saved types readable
+impl Serialize for CanonicalTypeName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(&self.0) {
+            Ok(s) => serializer.serialize_str(s),
+            Err(_) => serializer.serialize_bytes(&self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CanonicalTypeName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Name {
+            Str(String),
+            Bytes(TypeName),
+        }
+        Ok(match Name::deserialize(deserializer)? {
+            Name::Str(s) => s.into_bytes().into(),
+            Name::Bytes(b) => b.into(),
+        })
+    }
+}
 
 impl std::fmt::Debug for CanonicalTypeName {
     fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
@@ -25,13 +227,24 @@ impl std::fmt::Debug for CanonicalTypeName {
 /// This enum directly maps onto the way type information is encoded as DWARF
 /// info. Derive an arbitrary PartialOrd and Ord to allow sorting and
 /// deduplicating.
-#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum DwarfType {
     Primitive {
         name: CanonicalTypeName,
         size: Option<u64>,
+        /// The width in bits of integers which don't fill their bytes.
+        #[serde(default)]
+        bit_size: Option<u64>,
     },
     Pointer(Box<DwarfType>),
+    /// A pointer whose size differs from the target's, e.g. a near or far
+    /// pointer.
+    SizedPointer {
+        pointee: Box<DwarfType>,
+        size: u64,
+    },
+    Restrict(Box<DwarfType>),
+    Atomic(Box<DwarfType>),
     Typedef {
         name: CanonicalTypeName,
         ref_type: Box<DwarfType>,
@@ -39,11 +252,48 @@ pub enum DwarfType {
     Array {
         inner_type: Box<DwarfType>,
         len: Option<u64>,
+        /// The distance in bytes between elements if it's known.
+        stride: Option<u64>,
+        /// Whether this is a SIMD vector rather than an array.
+        #[serde(default)]
+        is_vector: bool,
+        /// The name of the parameter holding the length of a variable-length
+        /// array. These arrays are created in their function since the length
+        /// refers to the parameter's entry.
+        #[serde(default)]
+        count_param: Option<String>,
+    },
+    Struct {
+        name: Option<CanonicalTypeName>,
+        size: Option<u64>,
+        fields: Vec<Member>,
+        /// Whether this is a C++ class rather than a struct.
+        #[serde(default)]
+        is_class: bool,
+        /// Whether this is a union whose members all overlap.
+        #[serde(default)]
+        is_union: bool,
+        /// The variants of a tagged union, e.g. a Rust enum.
+        #[serde(default)]
+        variant_part: Option<Box<VariantPart>>,
     },
-    Struct(Vec<DwarfType>),
     Function {
         return_type: Box<DwarfType>,
         args: Vec<DwarfType>,
+        /// Whether the argument types are unknown, as for C functions declared
+        /// without a prototype.
+        #[serde(default)]
+        is_unprototyped: bool,
+    },
+    Enum {
+        name: Option<CanonicalTypeName>,
+        /// The integer type which holds the enum's values.
+        underlying_type: Box<DwarfType>,
+        enumerators: Vec<Enumerator>,
+        /// Whether this is a C++ `enum class` whose enumerators are scoped to
+        /// the enum.
+        #[serde(default)]
+        is_scoped: bool,
     },
 }
 
@@ -52,19 +302,42 @@ impl DwarfType {
         DwarfType::Primitive {
             name: b"void".to_vec().into(),
             size: Some(0),
+            bit_size: None,
         }
     }
 
     /// Creates a new primitive type from a canonical type name.
     pub fn new_primitive(name: CanonicalTypeName, size: Option<u64>) -> Self {
-        let size = size.or(name.size());
-        DwarfType::Primitive { name, size }
+        let bit_size = name.bit_size();
+        let size = size
+            .or(name.size())
+            .or_else(|| bit_size.map(|bits| bits.div_ceil(8)));
+        DwarfType::Primitive {
+            name,
+            size,
+            bit_size,
+        }
     }
 
     pub fn new_pointer(pointee: DwarfType) -> Self {
         DwarfType::Pointer(Box::new(pointee))
     }
 
+    pub fn new_sized_pointer(pointee: DwarfType, size: u64) -> Self {
+        DwarfType::SizedPointer {
+            pointee: Box::new(pointee),
+            size,
+        }
+    }
+
+    pub fn new_restrict(ty: DwarfType) -> Self {
+        DwarfType::Restrict(Box::new(ty))
+    }
+
+    pub fn new_atomic(ty: DwarfType) -> Self {
+        DwarfType::Atomic(Box::new(ty))
+    }
+
     pub fn new_typedef(name: CanonicalTypeName, ref_ty: DwarfType) -> Self {
         DwarfType::Typedef {
             name,
@@ -76,34 +349,666 @@ impl DwarfType {
         DwarfType::Array {
             inner_type: Box::new(inner_type),
             len,
+            stride: None,
+            is_vector: false,
+            count_param: None,
         }
     }
 
-    pub fn new_struct(fields: Vec<DwarfType>) -> Self {
-        DwarfType::Struct(fields)
+    /// Creates a variable-length array whose length is the value of one of
+    /// the function's parameters.
+    pub fn new_variable_length_array(inner_type: DwarfType, count_param: String) -> Self {
+        DwarfType::Array {
+            inner_type: Box::new(inner_type),
+            len: None,
+            stride: None,
+            is_vector: false,
+            count_param: Some(count_param),
+        }
+    }
+
+    /// Creates an array whose elements are `stride` bytes apart.
+    pub fn new_strided_array(inner_type: DwarfType, len: Option<u64>, stride: u64) -> Self {
+        DwarfType::Array {
+            inner_type: Box::new(inner_type),
+            len,
+            stride: Some(stride),
+            is_vector: false,
+            count_param: None,
+        }
+    }
+
+    /// Creates a SIMD vector. Vector elements are packed so their stride is the
+    /// element size.
+    pub fn new_vector(inner_type: DwarfType, len: Option<u64>) -> Self {
+        DwarfType::Array {
+            stride: inner_type.byte_size(),
+            inner_type: Box::new(inner_type),
+            len,
+            is_vector: true,
+            count_param: None,
+        }
+    }
+
+    pub fn new_struct(
+        name: Option<CanonicalTypeName>, size: Option<u64>, fields: Vec<Member>,
+    ) -> Self {
+        DwarfType::Struct {
+            name,
+            size,
+            fields,
+            is_class: false,
+            is_union: false,
+            variant_part: None,
+        }
+    }
+
+    pub fn new_class(
+        name: Option<CanonicalTypeName>, size: Option<u64>, fields: Vec<Member>,
+    ) -> Self {
+        DwarfType::Struct {
+            name,
+            size,
+            fields,
+            is_class: true,
+            is_union: false,
+            variant_part: None,
+        }
+    }
+
+    pub fn new_union(
+        name: Option<CanonicalTypeName>, size: Option<u64>, fields: Vec<Member>,
+    ) -> Self {
+        DwarfType::Struct {
+            name,
+            size,
+            fields,
+            is_class: false,
+            is_union: true,
+            variant_part: None,
+        }
+    }
+
+    /// Creates a tagged union whose members are selected by the value of a
+    /// discriminant, e.g. a Rust enum.
+    pub fn new_tagged_union(
+        name: Option<CanonicalTypeName>, size: Option<u64>, variant_part: VariantPart,
+    ) -> Self {
+        DwarfType::Struct {
+            name,
+            size,
+            fields: Vec::new(),
+            is_class: false,
+            is_union: false,
+            variant_part: Some(Box::new(variant_part)),
+        }
     }
 
     pub fn new_function(return_type: DwarfType, args: Vec<DwarfType>) -> Self {
         DwarfType::Function {
             return_type: Box::new(return_type),
             args,
+            is_unprototyped: false,
+        }
+    }
+
+    /// Creates a function type with unknown argument types.
+    pub fn new_unprototyped_function(return_type: DwarfType) -> Self {
+        DwarfType::Function {
+            return_type: Box::new(return_type),
+            args: Vec::new(),
+            is_unprototyped: true,
+        }
+    }
+
+    pub fn new_enum(
+        name: Option<CanonicalTypeName>, underlying_type: DwarfType, enumerators: Vec<Enumerator>,
+    ) -> Self {
+        DwarfType::Enum {
+            name,
+            underlying_type: Box::new(underlying_type),
+            enumerators,
+            is_scoped: false,
+        }
+    }
+
+    /// Creates a C++ `enum class` whose enumerators are scoped to the enum.
+    pub fn new_scoped_enum(
+        name: Option<CanonicalTypeName>, underlying_type: DwarfType, enumerators: Vec<Enumerator>,
+    ) -> Self {
+        DwarfType::Enum {
+            name,
+            underlying_type: Box::new(underlying_type),
+            enumerators,
+            is_scoped: true,
+        }
+    }
+
+    /// Returns a copy of this type with every occurrence of `from` replaced
+    /// with `to`.
+    pub fn substitute(&self, from: &DwarfType, to: &DwarfType) -> DwarfType {
+        self.replace(&|ty| (ty == from).then(|| to.clone()))
+    }
+
+    /// Returns a copy of this type with the outermost types for which `f`
+    /// returns a replacement replaced. Replacements are not visited.
+    fn replace(&self, f: &dyn Fn(&DwarfType) -> Option<DwarfType>) -> DwarfType {
+        if let Some(replacement) = f(self) {
+            return replacement
+        }
+        let sub = |ty: &DwarfType| Box::new(ty.replace(f));
+        match self {
+            DwarfType::Primitive { .. } => self.clone(),
+            DwarfType::Pointer(pointee) => DwarfType::Pointer(sub(pointee)),
+            DwarfType::SizedPointer { pointee, size } => DwarfType::SizedPointer {
+                pointee: sub(pointee),
+                size: *size,
+            },
+            DwarfType::Restrict(ty) => DwarfType::Restrict(sub(ty)),
+            DwarfType::Atomic(ty) => DwarfType::Atomic(sub(ty)),
+            DwarfType::Typedef { name, ref_type } => DwarfType::Typedef {
+                name: name.clone(),
+                ref_type: sub(ref_type),
+            },
+            DwarfType::Array {
+                inner_type,
+                len,
+                stride,
+                is_vector,
+                count_param,
+            } => DwarfType::Array {
+                inner_type: sub(inner_type),
+                len: *len,
+                stride: *stride,
+                is_vector: *is_vector,
+                count_param: count_param.clone(),
+            },
+            DwarfType::Struct {
+                name,
+                size,
+                fields,
+                is_class,
+                is_union,
+                variant_part,
+            } => DwarfType::Struct {
+                name: name.clone(),
+                size: *size,
+                fields: fields
+                    .iter()
+                    .map(|field| field.map_type(|ty| ty.replace(f)))
+                    .collect(),
+                is_class: *is_class,
+                is_union: *is_union,
+                variant_part: variant_part
+                    .as_ref()
+                    .map(|part| Box::new(part.map_type(&|ty| ty.replace(f)))),
+            },
+            DwarfType::Function {
+                return_type,
+                args,
+                is_unprototyped,
+            } => DwarfType::Function {
+                return_type: sub(return_type),
+                args: args.iter().map(|a| a.replace(f)).collect(),
+                is_unprototyped: *is_unprototyped,
+            },
+            DwarfType::Enum {
+                name,
+                underlying_type,
+                enumerators,
+                is_scoped,
+            } => DwarfType::Enum {
+                name: name.clone(),
+                underlying_type: sub(underlying_type),
+                enumerators: enumerators.clone(),
+                is_scoped: *is_scoped,
+            },
+        }
+    }
+
+    /// Whether `f` returns true for this type or any type it refers to.
+    fn any(&self, f: &dyn Fn(&DwarfType) -> bool) -> bool {
+        if f(self) {
+            return true
+        }
+        let members_any = |members: &[Member]| members.iter().any(|member| member.ty.any(f));
+        match self {
+            DwarfType::Primitive { .. } => false,
+            DwarfType::Pointer(ty) |
+            DwarfType::SizedPointer { pointee: ty, .. } |
+            DwarfType::Restrict(ty) |
+            DwarfType::Atomic(ty) |
+            DwarfType::Typedef { ref_type: ty, .. } |
+            DwarfType::Array { inner_type: ty, .. } |
+            DwarfType::Enum {
+                underlying_type: ty,
+                ..
+            } => ty.any(f),
+            DwarfType::Struct {
+                fields,
+                variant_part,
+                ..
+            } => {
+                members_any(fields) ||
+                    variant_part.as_ref().is_some_and(|part| {
+                        part.discriminant.ty.any(f) ||
+                            part.variants
+                                .iter()
+                                .any(|variant| members_any(&variant.fields))
+                    })
+            },
+            DwarfType::Function {
+                return_type, args, ..
+            } => return_type.any(f) || args.iter().any(|arg| arg.any(f)),
         }
     }
 
+    /// Whether `normalize` leaves this type's own names and sizes unchanged.
+    /// The types it refers to aren't checked.
+    fn is_normalized(&self) -> bool {
+        match self {
+            DwarfType::Primitive {
+                name,
+                size,
+                bit_size,
+            } => {
+                name.is_canonical() &&
+                    *bit_size == name.bit_size() &&
+                    (size.is_some() || (name.size().is_none() && bit_size.is_none()))
+            },
+            DwarfType::Typedef { name, .. } => name.is_canonical(),
+            DwarfType::Struct { name, .. } | DwarfType::Enum { name, .. } => {
+                name.as_ref().is_none_or(CanonicalTypeName::is_canonical)
+            },
+            _ => true,
+        }
+    }
+
+    /// Returns the form of this type used as the type map's key. Names are
+    /// canonicalized and primitive sizes are filled in from their names.
+    pub fn normalize(&self) -> DwarfType {
+        let canonical = |name: &CanonicalTypeName| CanonicalTypeName::from(name.0.clone());
+        let norm = |ty: &DwarfType| Box::new(ty.normalize());
+        match self {
+            DwarfType::Primitive { name, size, .. } => {
+                DwarfType::new_primitive(canonical(name), *size)
+            },
+            DwarfType::Pointer(pointee) => DwarfType::Pointer(norm(pointee)),
+            DwarfType::SizedPointer { pointee, size } => DwarfType::SizedPointer {
+                pointee: norm(pointee),
+                size: *size,
+            },
+            DwarfType::Restrict(ty) => DwarfType::Restrict(norm(ty)),
+            DwarfType::Atomic(ty) => DwarfType::Atomic(norm(ty)),
+            DwarfType::Typedef { name, ref_type } => DwarfType::Typedef {
+                name: canonical(name),
+                ref_type: norm(ref_type),
+            },
+            DwarfType::Array {
+                inner_type,
+                len,
+                stride,
+                is_vector,
+                count_param,
+            } => DwarfType::Array {
+                inner_type: norm(inner_type),
+                len: *len,
+                stride: *stride,
+                is_vector: *is_vector,
+                count_param: count_param.clone(),
+            },
+            DwarfType::Struct {
+                name,
+                size,
+                fields,
+                is_class,
+                is_union,
+                variant_part,
+            } => DwarfType::Struct {
+                name: name.as_ref().map(canonical),
+                size: *size,
+                fields: fields
+                    .iter()
+                    .map(|f| f.map_type(DwarfType::normalize))
+                    .collect(),
+                is_class: *is_class,
+                is_union: *is_union,
+                variant_part: variant_part
+                    .as_ref()
+                    .map(|part| Box::new(part.map_type(&DwarfType::normalize))),
+            },
+            DwarfType::Function {
+                return_type,
+                args,
+                is_unprototyped,
+            } => DwarfType::Function {
+                return_type: norm(return_type),
+                args: args.iter().map(DwarfType::normalize).collect(),
+                is_unprototyped: *is_unprototyped,
+            },
+            DwarfType::Enum {
+                name,
+                underlying_type,
+                enumerators,
+                is_scoped,
+            } => DwarfType::Enum {
+                name: name.as_ref().map(canonical),
+                underlying_type: norm(underlying_type),
+                enumerators: enumerators.clone(),
+                is_scoped: *is_scoped,
+            },
+        }
+    }
+
+    /// The size of the type in bytes if it doesn't depend on the target.
+    pub fn byte_size(&self) -> Option<u64> {
+        match self {
+            DwarfType::Primitive { size, .. } => *size,
+            DwarfType::Typedef { ref_type, .. } => ref_type.byte_size(),
+            DwarfType::Restrict(ty) | DwarfType::Atomic(ty) => ty.byte_size(),
+            DwarfType::Enum {
+                underlying_type, ..
+            } => underlying_type.byte_size(),
+            DwarfType::Array {
+                inner_type,
+                len,
+                stride,
+                ..
+            } => {
+                let stride = stride.or_else(|| inner_type.byte_size())?;
+                len.map(|len| len * stride)
+            },
+            DwarfType::Struct { size, .. } => *size,
+            DwarfType::SizedPointer { size, .. } => Some(*size),
+            DwarfType::Pointer(_) | DwarfType::Function { .. } => None,
+        }
+    }
+
+    /// Whether this is a signed integer type, looking through typedefs and
+    /// qualifiers.
+    pub fn is_signed(&self) -> bool {
+        match self {
+            DwarfType::Primitive { name, .. } => {
+                matches!(
+                    name.encoding(),
+                    Some(gimli::constants::DW_ATE_signed | gimli::constants::DW_ATE_signed_char)
+                )
+            },
+            DwarfType::Typedef { ref_type: ty, .. } |
+            DwarfType::Restrict(ty) |
+            DwarfType::Atomic(ty) => ty.is_signed(),
+            DwarfType::Enum {
+                underlying_type, ..
+            } => underlying_type.is_signed(),
+            _ => false,
+        }
+    }
+
+    /// Whether this is a variable-length array created in its function.
+    pub fn is_variable_length(&self) -> bool {
+        matches!(
+            self,
+            DwarfType::Array {
+                count_param: Some(_),
+                ..
+            }
+        )
+    }
+
+    /// The namespaces enclosing a named type.
+    pub fn namespaces(&self) -> Vec<&[u8]> {
+        let name = match self {
+            DwarfType::Struct {
+                name: Some(name), ..
+            } => name,
+            DwarfType::Typedef { name, .. } => name,
+            DwarfType::Enum {
+                name: Some(name), ..
+            } => name,
+            _ => return Vec::new(),
+        };
+        split_qualified_name(name.as_bytes()).0
+    }
+
     pub fn tag(&self) -> DwTag {
         match self {
             DwarfType::Primitive { .. } => DW_TAG_base_type,
-            DwarfType::Pointer(_) => DW_TAG_pointer_type,
+            DwarfType::Pointer(_) | DwarfType::SizedPointer { .. } => DW_TAG_pointer_type,
+            DwarfType::Restrict(_) => DW_TAG_restrict_type,
+            DwarfType::Atomic(_) => DW_TAG_atomic_type,
             DwarfType::Typedef { .. } => DW_TAG_typedef,
             DwarfType::Array { .. } => DW_TAG_array_type,
-            DwarfType::Struct(_) => DW_TAG_structure_type,
+            DwarfType::Struct { is_class: true, .. } => DW_TAG_class_type,
+            DwarfType::Struct { is_union: true, .. } => DW_TAG_union_type,
+            DwarfType::Struct { .. } => DW_TAG_structure_type,
             // TODO: Double check that subroutine_type is correct
             DwarfType::Function { .. } => DW_TAG_subroutine_type,
+            DwarfType::Enum { .. } => DW_TAG_enumeration_type,
+        }
+    }
+}
+
+/// A data member or base class of a struct or class.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Member {
+    pub name: Option<CanonicalTypeName>,
+    pub ty: DwarfType,
+    /// The member's offset in bytes from the start of the struct.
+    pub offset: Option<u64>,
+    /// The member's visibility. Omitting it leaves the default of public for
+    /// structs and private for classes.
+    pub accessibility: Option<Accessibility>,
+    /// Whether this is a C++ base class of the struct rather than a data
+    /// member. Base classes are unnamed and `ty` is the base type.
+    #[serde(default)]
+    pub is_base: bool,
+}
+
+impl Member {
+    pub fn new(name: Option<CanonicalTypeName>, ty: DwarfType, offset: Option<u64>) -> Self {
+        Member {
+            name,
+            ty,
+            offset,
+            accessibility: None,
+            is_base: false,
+        }
+    }
+
+    /// Creates a base class located at `offset` in the derived struct.
+    pub fn new_base(ty: DwarfType, offset: Option<u64>) -> Self {
+        Member {
+            is_base: true,
+            ..Member::new(None, ty, offset)
+        }
+    }
+
+    /// Returns a copy of this member with its type transformed by `f`.
+    fn map_type<F: FnOnce(&DwarfType) -> DwarfType>(&self, f: F) -> Self {
+        Member {
+            ty: f(&self.ty),
+            ..self.clone()
+        }
+    }
+}
+
+/// The variants of a tagged union and the member used to select one.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct VariantPart {
+    pub discriminant: Member,
+    pub variants: Vec<Variant>,
+}
+
+impl VariantPart {
+    fn map_type(&self, f: &dyn Fn(&DwarfType) -> DwarfType) -> Self {
+        VariantPart {
+            discriminant: self.discriminant.map_type(f),
+            variants: self
+                .variants
+                .iter()
+                .map(|variant| Variant {
+                    fields: variant
+                        .fields
+                        .iter()
+                        .map(|field| field.map_type(f))
+                        .collect(),
+                    ..variant.clone()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// One of the variants of a tagged union.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Variant {
+    /// The discriminant value selecting this variant. A variant without one is
+    /// used when no other variant matches.
+    pub discr_value: Option<u64>,
+    pub fields: Vec<Member>,
+}
+
+/// A named value of an enum.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Enumerator {
+    pub name: CanonicalTypeName,
+    pub value: i64,
+}
+
+impl Enumerator {
+    pub fn new(name: CanonicalTypeName, value: i64) -> Self {
+        Enumerator { name, value }
+    }
+
+    /// Whether the value can be represented by an integer of `size` bytes.
+    pub fn fits(&self, size: u64, is_signed: bool) -> bool {
+        let bits = (size * 8).clamp(1, 64);
+        let value = self.value as i128;
+        if is_signed {
+            let limit = 1i128 << (bits - 1);
+            (-limit..limit).contains(&value)
+        } else {
+            (0..1i128 << bits).contains(&value)
         }
     }
 }
 
+/// The visibility of a C++ class member.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Accessibility {
+    Public,
+    Protected,
+    Private,
+}
+
+impl Accessibility {
+    /// Converts a `DW_AT_accessibility` value, returning `None` for unknown
+    /// values.
+    pub fn from_dwarf(access: DwAccess) -> Option<Self> {
+        match access {
+            gimli::constants::DW_ACCESS_public => Some(Accessibility::Public),
+            gimli::constants::DW_ACCESS_protected => Some(Accessibility::Protected),
+            gimli::constants::DW_ACCESS_private => Some(Accessibility::Private),
+            _ => None,
+        }
+    }
+}
+
+impl From<Accessibility> for DwAccess {
+    fn from(access: Accessibility) -> Self {
+        match access {
+            Accessibility::Public => DW_ACCESS_public,
+            Accessibility::Protected => DW_ACCESS_protected,
+            Accessibility::Private => DW_ACCESS_private,
+        }
+    }
+}
+
+/// Type qualifiers with their own DWARF tags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Qualifier {
+    Restrict,
+    Atomic,
+}
+
+impl Qualifier {
+    const SPELLINGS: [(&'static str, Qualifier); 4] = [
+        ("restrict", Qualifier::Restrict),
+        ("__restrict", Qualifier::Restrict),
+        ("_Atomic", Qualifier::Atomic),
+        ("atomic", Qualifier::Atomic),
+    ];
+
+    /// Splits a qualifier applying to the type before it (e.g. the pointer in
+    /// `int * restrict`) from a C type.
+    pub fn strip_suffix(ty: &str) -> Option<(Qualifier, &str)> {
+        let ty = ty.trim_end();
+        Self::SPELLINGS.iter().find_map(|&(spelling, qualifier)| {
+            let rest = ty.strip_suffix(spelling)?;
+            if rest.ends_with([' ', '*']) {
+                Some((qualifier, rest.trim_end()))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Splits a qualifier applying to the type after it (e.g. `_Atomic int`)
+    /// from a C type.
+    pub fn strip_prefix(ty: &str) -> Option<(Qualifier, &str)> {
+        let ty = ty.trim_start();
+        Self::SPELLINGS.iter().find_map(|&(spelling, qualifier)| {
+            let rest = ty.strip_prefix(spelling)?.strip_prefix(' ')?;
+            Some((qualifier, rest))
+        })
+    }
+
+    pub fn apply(self, ty: DwarfType) -> DwarfType {
+        match self {
+            Qualifier::Restrict => DwarfType::new_restrict(ty),
+            Qualifier::Atomic => DwarfType::new_atomic(ty),
+        }
+    }
+}
+
+/// Splits a qualified C++ name into its enclosing namespaces and its
+/// unqualified name. Separators inside template arguments are ignored.
+pub fn split_qualified_name(name: &[u8]) -> (Vec<&[u8]>, &[u8]) {
+    let mut namespaces = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut i = 0;
+    while i < name.len() {
+        match name[i] {
+            b'<' | b'(' => depth += 1,
+            b'>' | b')' => depth = depth.saturating_sub(1),
+            b':' if depth == 0 && name.get(i + 1) == Some(&b':') => {
+                if i > start {
+                    namespaces.push(&name[start..i]);
+                }
+                i += 2;
+                start = i;
+                continue
+            },
+            _ => (),
+        }
+        i += 1;
+    }
+    (namespaces, &name[start..])
+}
+
 impl CanonicalTypeName {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Whether the name is already in its canonical form. Names can only skip
+    /// canonicalization when they're constructed directly.
+    fn is_canonical(&self) -> bool {
+        canonical_name(&self.0) == self.0.as_slice()
+    }
+
     pub fn size(&self) -> Option<u64> {
         match self.0.as_slice() {
             b"bool" | b"_Bool" => Some(1),
@@ -117,6 +1022,9 @@ impl CanonicalTypeName {
             b"uint64_t" | b"unsigned long long" | b"u64" => Some(8),
             b"int128_t" | b"__int128" | b"i128" => Some(16),
             b"uint128_t" | b"__uint128" | b"u128" => Some(16),
+            b"char" => Some(1),
+            b"char16_t" => Some(2),
+            b"char32_t" | b"wchar_t" => Some(4),
             b"float16_t" | b"binary16" => Some(2),
             b"float" | b"f32" => Some(4),
             b"double" | b"f64" => Some(8),
@@ -124,32 +1032,112 @@ impl CanonicalTypeName {
             _ => None,
         }
     }
+
+    /// The width in bits of integer types like `uint24_t` or `_BitInt(24)`
+    /// which don't have one of the standard widths.
+    pub fn bit_size(&self) -> Option<u64> {
+        let name = std::str::from_utf8(&self.0).ok()?;
+        if let Some(bits) = name
+            .strip_prefix("unsigned ")
+            .unwrap_or(name)
+            .strip_prefix("_BitInt(")
+            .and_then(|bits| bits.strip_suffix(')'))
+        {
+            return bits.parse().ok()
+        }
+        let bits = name
+            .strip_prefix("uint")
+            .or_else(|| name.strip_prefix("int"))
+            .and_then(|bits| bits.strip_suffix("_t"))
+            .or_else(|| name.strip_prefix('u'))
+            .or_else(|| name.strip_prefix('i'))?;
+        match bits.parse() {
+            Ok(8 | 16 | 32 | 64 | 128) | Err(_) => None,
+            Ok(bits) => Some(bits),
+        }
+    }
+
+    /// How values of the type are encoded. Character types use the character
+    /// encodings so debuggers display them as characters rather than numbers.
+    pub fn encoding(&self) -> Option<DwAte> {
+        let encoding = match self.0.as_slice() {
+            b"bool" => DW_ATE_boolean,
+            b"char" | b"int8_t" => DW_ATE_signed_char,
+            b"uint8_t" => DW_ATE_unsigned_char,
+            b"char8_t" | b"char16_t" | b"char32_t" | b"wchar_t" => DW_ATE_UTF,
+            b"int16_t" | b"int32_t" | b"int64_t" | b"int128_t" | b"long" | b"ptrdiff_t" => {
+                DW_ATE_signed
+            },
+            b"uint16_t" | b"uint32_t" | b"uint64_t" | b"uint128_t" | b"unsigned long" |
+            b"size_t" => DW_ATE_unsigned,
+            b"float16_t" | b"float" | b"double" | b"long double" | b"__float128" => DW_ATE_float,
+            name => {
+                self.bit_size()?;
+                if name.starts_with(b"u") {
+                    DW_ATE_unsigned
+                } else {
+                    DW_ATE_signed
+                }
+            },
+        };
+        Some(encoding)
+    }
+
+    /// Whether the type's size is the target's pointer width. `long` is
+    /// assumed to be pointer width as on the ILP32 and LP64 ELF targets.
+    pub fn is_pointer_width(&self) -> bool {
+        matches!(
+            self.0.as_slice(),
+            b"size_t" | b"ptrdiff_t" | b"long" | b"unsigned long"
+        )
+    }
+
+    /// The type's size on a target with the given pointer width.
+    pub fn size_for_target(&self, ptr_size: u64) -> Option<u64> {
+        if self.is_pointer_width() {
+            Some(ptr_size)
+        } else {
+            self.size()
+        }
+    }
 }
 
 impl From<TypeName> for CanonicalTypeName {
     fn from(name: TypeName) -> CanonicalTypeName {
-        let canonical_name: &[u8] = match name.as_slice() {
-            b"bool" | b"_Bool" => b"bool",
-            b"int8_t" | b"signed char" | b"i8" => b"int8_t",
-            b"uint8_t" | b"unsigned char" | b"u8" => b"uint8_t",
-            b"int16_t" | b"short" | b"i16" => b"int16_t",
-            b"uint16_t" | b"unsigned short" | b"u16" => b"uint16_t",
-            b"int32_t" | b"int" | b"i32" => b"int32_t",
-            b"uint32_t" | b"unsigned" | b"u32" => b"uint32_t",
-            b"int64_t" | b"long long" | b"i64" => b"int64_t",
-            b"uint64_t" | b"unsigned long long" | b"u64" => b"uint64_t",
-            b"int128_t" | b"__int128" | b"i128" => b"int128_t",
-            b"uint128_t" | b"__uint128" | b"u128" => b"uint128_t",
-            b"float16_t" | b"binary16" => b"float16_t",
-            b"float" | b"f32" => b"float",
-            b"double" | b"f64" => b"double",
-            b"long double" => b"long double",
-            //M, // uint64_t (x86 MMX vector type)
-            b"__float128" => b"__float128",
-            b"void" => b"void",
-            s => s,
-        };
-        CanonicalTypeName(canonical_name.to_vec())
+        CanonicalTypeName(canonical_name(&name).to_vec())
+    }
+}
+
+/// Returns the name used for all of a type's alternate spellings.
+fn canonical_name(name: &[u8]) -> &[u8] {
+    match name {
+        b"bool" | b"_Bool" => b"bool",
+        b"int8_t" | b"signed char" | b"i8" => b"int8_t",
+        b"uint8_t" | b"unsigned char" | b"u8" => b"uint8_t",
+        b"int16_t" | b"short" | b"i16" => b"int16_t",
+        b"uint16_t" | b"unsigned short" | b"u16" => b"uint16_t",
+        b"int32_t" | b"int" | b"i32" => b"int32_t",
+        b"uint32_t" | b"unsigned" | b"u32" => b"uint32_t",
+        b"int64_t" | b"long long" | b"i64" => b"int64_t",
+        b"uint64_t" | b"unsigned long long" | b"u64" => b"uint64_t",
+        b"int128_t" | b"__int128" | b"i128" => b"int128_t",
+        b"uint128_t" | b"__uint128" | b"u128" => b"uint128_t",
+        b"size_t" | b"usize" => b"size_t",
+        b"ptrdiff_t" | b"isize" => b"ptrdiff_t",
+        b"long" | b"long int" | b"signed long" => b"long",
+        b"unsigned long" | b"unsigned long int" => b"unsigned long",
+        b"char" => b"char",
+        b"wchar_t" => b"wchar_t",
+        b"char16_t" => b"char16_t",
+        b"char32_t" => b"char32_t",
+        b"float16_t" | b"binary16" => b"float16_t",
+        b"float" | b"f32" => b"float",
+        b"double" | b"f64" => b"double",
+        b"long double" => b"long double",
+        //M, // uint64_t (x86 MMX vector type)
+        b"__float128" => b"__float128",
+        b"void" => b"void",
+        s => s,
     }
 }
 
@@ -158,3 +1146,224 @@ impl From<CanonicalTypeName> for Vec<u8> {
         name.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_names() {
+        assert_eq!(split_qualified_name(b"main"), (vec![], &b"main"[..]));
+        assert_eq!(
+            split_qualified_name(b"a::b::f"),
+            (vec![&b"a"[..], &b"b"[..]], &b"f"[..])
+        );
+        assert_eq!(
+            split_qualified_name(b"::std::vector<a::b>::size"),
+            (vec![&b"std"[..], &b"vector<a::b>"[..]], &b"size"[..])
+        );
+    }
+
+    #[test]
+    fn rust_and_cpp_spellings() {
+        let canonical = |name: &[u8]| CanonicalTypeName::from(name.to_vec());
+        assert_eq!(canonical(b"usize"), canonical(b"size_t"));
+        assert_eq!(canonical(b"usize").as_bytes(), b"size_t");
+        assert_eq!(canonical(b"isize"), canonical(b"ptrdiff_t"));
+        assert_eq!(canonical(b"isize").as_bytes(), b"ptrdiff_t");
+        assert_eq!(canonical(b"char").size(), Some(1));
+        assert_eq!(canonical(b"wchar_t").size(), Some(4));
+        assert_eq!(canonical(b"char16_t").size(), Some(2));
+        assert_eq!(canonical(b"char32_t").size(), Some(4));
+
+        for name in [&b"usize"[..], b"size_t", b"isize", b"ptrdiff_t"] {
+            let name = canonical(name);
+            assert!(name.is_pointer_width());
+            assert_eq!(name.size(), None);
+            assert_eq!(name.size_for_target(4), Some(4));
+            assert_eq!(name.size_for_target(8), Some(8));
+        }
+        assert_eq!(canonical(b"char32_t").size_for_target(8), Some(4));
+    }
+
+    #[test]
+    fn long_width() {
+        let canonical = |name: &[u8]| CanonicalTypeName::from(name.to_vec());
+        assert_eq!(canonical(b"long int"), canonical(b"long"));
+        assert_eq!(canonical(b"unsigned long int"), canonical(b"unsigned long"));
+        for name in [&b"long"[..], b"unsigned long"] {
+            assert_eq!(canonical(name).size(), None);
+            assert_eq!(canonical(name).size_for_target(4), Some(4));
+            assert_eq!(canonical(name).size_for_target(8), Some(8));
+        }
+        assert_eq!(canonical(b"long long").size_for_target(4), Some(8));
+    }
+
+    #[test]
+    fn odd_width_integers() {
+        let canonical = |name: &[u8]| CanonicalTypeName::from(name.to_vec());
+        assert_eq!(canonical(b"uint24_t").bit_size(), Some(24));
+        assert_eq!(canonical(b"i12").bit_size(), Some(12));
+        assert_eq!(canonical(b"unsigned _BitInt(17)").bit_size(), Some(17));
+        assert_eq!(canonical(b"uint32_t").bit_size(), None);
+        assert_eq!(canonical(b"u64").bit_size(), None);
+        assert_eq!(canonical(b"unsigned").bit_size(), None);
+        assert_eq!(
+            DwarfType::new_primitive(canonical(b"uint24_t"), None),
+            DwarfType::Primitive {
+                name: canonical(b"uint24_t"),
+                size: Some(3),
+                bit_size: Some(24),
+            }
+        );
+    }
+
+    #[test]
+    fn encodings() {
+        let encoding = |name: &[u8]| CanonicalTypeName::from(name.to_vec()).encoding();
+        assert_eq!(encoding(b"char"), Some(DW_ATE_signed_char));
+        assert_eq!(encoding(b"unsigned char"), Some(DW_ATE_unsigned_char));
+        assert_eq!(encoding(b"char16_t"), Some(DW_ATE_UTF));
+        assert_eq!(encoding(b"wchar_t"), Some(DW_ATE_UTF));
+        assert_eq!(encoding(b"unsigned long"), Some(DW_ATE_unsigned));
+        assert_eq!(encoding(b"i32"), Some(DW_ATE_signed));
+        assert_eq!(encoding(b"uint24_t"), Some(DW_ATE_unsigned));
+        assert_eq!(encoding(b"double"), Some(DW_ATE_float));
+        assert_eq!(encoding(b"void"), None);
+        assert_eq!(encoding(b"HANDLE"), None);
+    }
+
+    #[test]
+    fn type_set_round_trip() {
+        let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+        let int8 = DwarfType::new_primitive(b"int8_t".to_vec().into(), None);
+        let types = vec![
+            int.clone(),
+            DwarfType::new_pointer(int.clone()),
+            DwarfType::new_typedef(b"my_int".to_vec().into(), int.clone()),
+            DwarfType::new_strided_array(int8, Some(3), 1),
+            DwarfType::new_struct(Some(b"ns::Opaque".to_vec().into()), Some(16), Vec::new()),
+            DwarfType::new_class(
+                Some(b"Counter".to_vec().into()),
+                Some(4),
+                vec![Member {
+                    accessibility: Some(Accessibility::Private),
+                    ..Member::new(Some(b"count".to_vec().into()), int.clone(), Some(0))
+                }],
+            ),
+            DwarfType::new_primitive(b"\xffbad".to_vec().into(), Some(1)),
+            DwarfType::new_function(DwarfType::void(), vec![int]),
+        ];
+        // The entry IDs aren't saved so they can all be the same
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 8,
+        };
+        let unit = gimli::write::Unit::new(encoding, gimli::write::LineProgram::none());
+        let type_map: TypeMap = types.iter().map(|ty| (ty.clone(), unit.root())).collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("types.json");
+        save_types(&type_map, &path).unwrap();
+        let mut loaded = load_types(&path).unwrap();
+        let mut expected = types;
+        expected.sort();
+        loaded.sort();
+        assert_eq!(loaded, expected);
+    }
+
+    #[test]
+    fn qualifiers() {
+        assert_eq!(
+            Qualifier::strip_suffix("int * restrict"),
+            Some((Qualifier::Restrict, "int *"))
+        );
+        assert_eq!(
+            Qualifier::strip_suffix("char *__restrict"),
+            Some((Qualifier::Restrict, "char *"))
+        );
+        assert_eq!(Qualifier::strip_suffix("unrestrict"), None);
+        assert_eq!(
+            Qualifier::strip_prefix("_Atomic int"),
+            Some((Qualifier::Atomic, "int"))
+        );
+        assert_eq!(Qualifier::strip_prefix("atomicity"), None);
+    }
+
+    #[test]
+    fn normalized_type_map() {
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 8,
+        };
+        let mut unit = gimli::write::Unit::new(encoding, gimli::write::LineProgram::none());
+        let root = unit.root();
+        let first = unit.add(root, DW_TAG_base_type);
+        let second = unit.add(root, DW_TAG_base_type);
+
+        // Names that skipped canonicalization and missing sizes are normalized
+        let unsigned = DwarfType::Primitive {
+            name: CanonicalTypeName(b"unsigned".to_vec()),
+            size: None,
+            bit_size: None,
+        };
+        let u32 = DwarfType::new_primitive(b"u32".to_vec().into(), None);
+        let mut type_map = TypeMap::new();
+        type_map.insert(DwarfType::new_pointer(unsigned.clone()), first);
+        assert_eq!(
+            type_map.insert(DwarfType::new_pointer(u32.clone()), second),
+            Some(first)
+        );
+        assert_eq!(type_map.len(), 1);
+        assert_eq!(type_map[&DwarfType::new_pointer(unsigned)], second);
+
+        let int32 = DwarfType::Primitive {
+            name: CanonicalTypeName(b"i32".to_vec()),
+            size: Some(4),
+            bit_size: None,
+        };
+        type_map.insert(DwarfType::new_array(int32, Some(2)), first);
+        let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+        assert!(type_map.contains_key(&DwarfType::new_array(int, Some(2))));
+        assert_eq!(type_map.len(), 2);
+    }
+
+    #[test]
+    fn type_map_keys() {
+        let int = DwarfType::new_primitive(b"int".to_vec().into(), None);
+        let unsigned = DwarfType::Primitive {
+            name: CanonicalTypeName(b"unsigned".to_vec()),
+            size: Some(4),
+            bit_size: None,
+        };
+        let sized_int = DwarfType::Primitive {
+            name: CanonicalTypeName::from(b"int".to_vec()),
+            size: None,
+            bit_size: None,
+        };
+        let opaque = DwarfType::new_struct(Some(b"Counter".to_vec().into()), None, Vec::new());
+        let counter = DwarfType::new_struct(
+            Some(b"Counter".to_vec().into()),
+            Some(4),
+            vec![Member::new(
+                Some(b"count".to_vec().into()),
+                int.clone(),
+                Some(0),
+            )],
+        );
+
+        // Types built by the constructors are used as keys as is
+        let mut type_map = TypeMap::new();
+        assert!(type_map.is_key(&DwarfType::new_pointer(int.clone())));
+        assert!(type_map.is_key(&opaque));
+        assert!(!type_map.is_key(&DwarfType::new_pointer(unsigned)));
+        assert!(!type_map.is_key(&DwarfType::new_array(sized_int, Some(2))));
+
+        // References to defined structs must be replaced by their definitions
+        assert!(type_map.define(&counter));
+        assert!(!type_map.is_key(&DwarfType::new_pointer(opaque)));
+        assert!(type_map.is_key(&DwarfType::new_pointer(counter)));
+    }
+}
@@ -18,3 +18,27 @@ do
     strip $binary -o $stripped
     python3 -m anvill --bin_in $stripped --spec_out $spec
 done
+
+# DWARF 5 binary with DW_FORM_addrx low_pcs used by the unit tests
+clang $cflags -gdwarf-5 src/empty.c -o bin/dwarf5.empty.c.elf
+
+# Binary with a declaration-only subprogram used by the unit tests
+clang $cflags -gdwarf-4 src/decl.c -o bin/debug.decl.c.elf
+
+# Big-endian object with an enumeration used by the unit tests. Strings are
+# inlined since relocations in the object's debug sections aren't applied
+clang --target=sparc-unknown-linux-gnu -c -gdwarf-4 -mllvm -dwarf-inlined-strings=Enable src/enum.c -o bin/debug.enum.c.sparc.o
+# Two compilation units used to test --cu
+clang -nostdlib -gdwarf-4 src/cu_a.c src/cu_b.c -o bin/debug.cu.elf
+# DWARF 5 binary with a DW_OP_addrx variable location used by the unit tests
+clang $cflags -gdwarf-5 src/var.c -o bin/dwarf5.var.c.elf
+# 32-bit ARM object used to test --check-alignment
+clang --target=arm-linux-gnueabi -c src/empty.c -o bin/empty.c.arm.o
+# Shared library with an exported and a hidden function used by the unit tests
+clang $cflags -shared -fPIC src/export.c -o bin/export.c.so
+# Relocatable object whose debug info refers to .text and .data through
+# relocations used by the unit tests
+clang -c -gdwarf-4 src/var.c -o bin/debug.var.c.o
+# Binary with zstd compressed debug sections used by the unit tests
+clang $cflags -gdwarf-4 src/types.c -o bin/debug.types.c.zstd.elf
+objcopy --compress-debug-sections=zstd bin/debug.types.c.zstd.elf
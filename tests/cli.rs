@@ -0,0 +1,342 @@
+use object::{Object, ObjectSymbol};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use tempfile::tempdir;
+
+const ARM_OBJ: &str = "tests/bin/empty.c.arm.o";
+
+/// Runs dwarf-writer on an ARM object with a misaligned function so it has
+/// something to warn about.
+fn run_misaligned(args: &[&str]) -> Output {
+    let dir = tempdir().unwrap();
+    let csv = dir.path().join("functions.csv");
+    fs::write(
+        &csv,
+        "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n\
+         \"main\",\"00000003\",\"int main(void)\",\"4\"\n",
+    )
+    .unwrap();
+    Command::new(env!("CARGO_BIN_EXE_dwarf-writer"))
+        .args(args)
+        .args(["--check-alignment", "--no-objcopy", "--ghidra"])
+        .arg(&csv)
+        .arg(ARM_OBJ)
+        .arg(dir.path().join("output.o"))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn logs_go_to_stderr() {
+    let output = run_misaligned(&[]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Function at 0x3 is not aligned"));
+}
+
+#[test]
+fn quiet() {
+    let output = run_misaligned(&["--quiet"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn list() {
+    let output = Command::new(env!("CARGO_BIN_EXE_dwarf-writer"))
+        .args(["list", "tests/bin/debug.cu.elf"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines[0], "function\t0x1000\t0x100f\tfirst");
+    assert_eq!(lines[2], "function\t0x101d\t0x102d\tmain");
+    assert!(lines[3].starts_with("type\t"));
+}
+
+#[test]
+fn list_binary_named_list() {
+    let dir = tempdir().unwrap();
+    fs::copy("tests/bin/debug.cu.elf", dir.path().join("list")).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dwarf-writer"))
+        .current_dir(dir.path())
+        .args(["list", "list"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("function\t0x1000\t0x100f\tfirst"));
+}
+
+#[test]
+fn list_requires_input() {
+    let output = Command::new(env!("CARGO_BIN_EXE_dwarf-writer"))
+        .arg("list")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("dwarf-writer list <input>"));
+}
+
+#[test]
+fn verify_output() {
+    let dir = tempdir().unwrap();
+    let types = dir.path().join("types.json");
+    fs::write(
+        &types,
+        r#"[{"Struct": {"name": "Counter", "size": 4, "fields": [
+            {"name": "count", "ty": {"Primitive": {"name": "int", "size": 4}}, "offset": 0}
+        ]}}]"#,
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_dwarf-writer"))
+            .args(["--verify-output", "--types"])
+            .arg(&types)
+            .args(args)
+            .arg("tests/strip_bin/types.c.elf")
+            .arg(dir.path().join("output.elf"))
+            .output()
+            .unwrap()
+    };
+    assert!(run(&[]).status.success());
+    assert!(run(&["--no-objcopy"]).status.success());
+
+    // An objcopy which doesn't update the sections leaves the input's debug
+    // info in the output
+    let output = run(&["--objcopy", "true"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("differs from what was written"));
+    assert!(stderr.contains("missing: type\tStruct"));
+}
+
+#[test]
+fn function_pointer_member() {
+    let dir = tempdir().unwrap();
+    let types = dir.path().join("types.json");
+    // struct Handler { int id; int (*callback)(void *); }
+    fs::write(
+        &types,
+        r#"[{"Struct": {"name": "Handler", "size": 16, "fields": [
+            {"name": "id", "ty": {"Primitive": {"name": "int", "size": 4}}, "offset": 0},
+            {"name": "callback", "ty": {"Pointer": {"Function": {
+                "return_type": {"Primitive": {"name": "int", "size": 4}},
+                "args": [{"Pointer": {"Primitive": {"name": "void", "size": 0}}}]
+            }}}, "offset": 8}
+        ]}}]"#,
+    )
+    .unwrap();
+    let output_elf = dir.path().join("output.elf");
+    let output = Command::new(env!("CARGO_BIN_EXE_dwarf-writer"))
+        .args(["--verify-output", "--types"])
+        .arg(&types)
+        .arg("tests/strip_bin/types.c.elf")
+        .arg(&output_elf)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // The member's type is read back as a pointer to the subroutine type
+    let output = Command::new(env!("CARGO_BIN_EXE_dwarf-writer"))
+        .arg("list")
+        .arg(&output_elf)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let handler = stdout
+        .lines()
+        .find(|line| line.contains("name: Some(Handler)"))
+        .unwrap();
+    assert!(handler.contains(
+        "name: Some(callback), ty: Pointer(Function { return_type: Primitive { name: int32_t"
+    ));
+    assert!(handler.contains("args: [Pointer(Primitive { name: void"));
+}
+
+#[test]
+fn raw_code() {
+    let dir = tempdir().unwrap();
+    // push rbp; mov rbp, rsp; mov eax, 42; pop rbp; ret
+    let code = dir.path().join("code.bin");
+    fs::write(&code, b"\x55\x48\x89\xe5\xb8\x2a\x00\x00\x00\x5d\xc3").unwrap();
+    let spec = dir.path().join("spec.json");
+    fs::write(
+        &spec,
+        r#"{"arch": "amd64", "os": "linux",
+            "functions": [{"address": 4198400, "return_values": [{"register": "RAX", "type": "i"}]}],
+            "symbols": [[4198400, "answer"]]}"#,
+    )
+    .unwrap();
+    let output_elf = dir.path().join("output.elf");
+    let output = Command::new(env!("CARGO_BIN_EXE_dwarf-writer"))
+        .args(["--raw-base", "0x401000", "--anvill"])
+        .arg(&spec)
+        .arg(&code)
+        .arg(&output_elf)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dwarf-writer"))
+        .arg("list")
+        .arg(&output_elf)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("function\t0x401000\t"));
+    assert!(stdout.lines().next().unwrap().ends_with("\tanswer"));
+}
+
+#[test]
+fn raw_code_without_output() {
+    let dir = tempdir().unwrap();
+    let code = dir.path().join("code.bin");
+    fs::write(&code, b"\xc3").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dwarf-writer"))
+        .args([
+            "--raw-base",
+            "0x401000",
+            "--target",
+            "x86_64-unknown-linux-gnu",
+        ])
+        .arg(&code)
+        .output()
+        .unwrap();
+    // Rejected as a usage error rather than a panic
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("<output>"));
+}
+
+/// Returns the names of an ELF's symbols.
+fn symbol_names(path: &Path) -> Vec<String> {
+    let data = fs::read(path).unwrap();
+    let file = object::File::parse(&*data).unwrap();
+    file.symbols()
+        .filter_map(|sym| sym.name().ok().map(str::to_string))
+        .collect()
+}
+
+#[test]
+fn omit_symbols() {
+    let dir = tempdir().unwrap();
+    let inputs = [
+        (
+            "--ghidra",
+            "functions.csv",
+            "\"Name\",\"Location\",\"Function Signature\",\"Function Size\"\n\
+             \"ghidra_fn\",\"00001000\",\"int ghidra_fn(void)\",\"16\"\n",
+            "ghidra_fn",
+        ),
+        (
+            "--anvill",
+            "spec.json",
+            r#"{"arch": "amd64", "os": "linux",
+                "functions": [{"address": 4096, "return_values": [{"register": "RAX", "type": "i"}]}],
+                "variables": [{"address": 16392, "type": "i"}],
+                "symbols": [[4096, "anvill_fn"], [16392, "anvill_var"]]}"#,
+            "anvill_fn",
+        ),
+        (
+            "--str-bsi",
+            "str.json",
+            r#"{"functions": {"0x1000": {
+                "symbol_name": "str_fn",
+                "calling_convention": null,
+                "return_registers": [],
+                "clobbered_registers": [],
+                "source_match": null
+            }}}"#,
+            "str_fn",
+        ),
+    ];
+    for (flag, file_name, contents, symbol) in inputs {
+        let input = dir.path().join(file_name);
+        fs::write(&input, contents).unwrap();
+        for omit in [false, true] {
+            let output_elf = dir.path().join("output.elf");
+            let mut cmd = Command::new(env!("CARGO_BIN_EXE_dwarf-writer"));
+            if omit {
+                cmd.arg("--omit-symbols");
+            }
+            let output = cmd
+                .args(["--use-all-str", flag])
+                .arg(&input)
+                .arg("tests/strip_bin/empty.c.elf")
+                .arg(&output_elf)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            let names = symbol_names(&output_elf);
+            assert_eq!(names.iter().any(|name| name == symbol), !omit, "{}", flag);
+            if flag == "--anvill" {
+                assert_eq!(names.iter().any(|name| name == "anvill_var"), !omit);
+            }
+        }
+    }
+}
+
+#[test]
+fn threads_match_sequential_output() {
+    let dir = tempdir().unwrap();
+    let mut specs = Vec::new();
+    for i in 0..8 {
+        let spec = dir.path().join(format!("spec{}.json", i));
+        let addr = 0x1000 + i * 2;
+        fs::write(
+            &spec,
+            format!(
+                r#"{{"arch": "amd64", "os": "linux",
+                    "functions": [{{"address": {}, "parameters": [{{"register": "RDI", "type": "l"}}],
+                                   "return_values": [{{"register": "RAX", "type": "i"}}]}}],
+                    "symbols": [[{}, "fn{}"]]}}"#,
+                addr, addr, i
+            ),
+        )
+        .unwrap();
+        specs.push(spec);
+    }
+    let outputs: Vec<_> = ["1", "4"]
+        .iter()
+        .map(|threads| {
+            let output_elf = dir.path().join(format!("output{}.elf", threads));
+            let mut cmd = Command::new(env!("CARGO_BIN_EXE_dwarf-writer"));
+            cmd.args(["--no-objcopy", "--threads", threads]);
+            for spec in &specs {
+                cmd.arg("--anvill").arg(spec);
+            }
+            let output = cmd
+                .arg("tests/strip_bin/empty.c.elf")
+                .arg(&output_elf)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            fs::read(output_elf).unwrap()
+        })
+        .collect();
+    assert_eq!(outputs[0], outputs[1]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dwarf-writer"))
+        .arg("list")
+        .arg(dir.path().join("output4.elf"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout
+            .lines()
+            .filter(|line| line.starts_with("function\t"))
+            .count(),
+        8
+    );
+}
@@ -0,0 +1,3 @@
+int first(int x) {
+    return x + 1;
+}
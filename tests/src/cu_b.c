@@ -0,0 +1,7 @@
+int second(int x) {
+    return x * 2;
+}
+
+int main(void) {
+    return second(1);
+}
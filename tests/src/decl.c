@@ -0,0 +1,5 @@
+extern void external(void) __attribute__((weak));
+
+int main() {
+    external();
+}
@@ -0,0 +1,6 @@
+enum color {
+    RED = 0x12345678,
+    BLUE = 2,
+};
+
+enum color color = RED;
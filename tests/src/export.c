@@ -0,0 +1,7 @@
+int exported(int x) {
+    return x + 1;
+}
+
+__attribute__((visibility("hidden"))) int hidden(int x) {
+    return x - 1;
+}
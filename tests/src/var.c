@@ -0,0 +1,5 @@
+int counter = 1;
+
+int main() {
+    return counter;
+}